@@ -1,9 +1,20 @@
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 
-use clap::{arg, builder::PathBufValueParser, Args, FromArgMatches, Parser, Subcommand};
+use chrono::NaiveDate;
+use clap::{
+    builder::PathBufValueParser, error::ErrorKind, Args, FromArgMatches, Parser, Subcommand,
+};
 use regex::Regex;
 
-use crate::{ReplicatorKind, Template, TemplateParser};
+use photosort::encoding::NonUtf8Policy;
+use photosort::mount::MountPin;
+use photosort::portable::{PortableNames, UnicodeForm};
+use photosort::sort::ConflictPolicyKind;
+use photosort::streams::StreamsPolicy;
+use photosort::template::context::{self, DateOffset, DateSource, DateTimezone, Options};
+
+use crate::{dirs, value_parser, ReplicatorKind, Template, TemplateParser};
 
 /// A pictures/files organizer.
 #[derive(Parser, Debug)]
@@ -17,10 +28,29 @@ pub struct Cli {
 #[command(author = None, version, about)]
 pub enum Command {
     /// Sort all files once.
-    Sort(CliArgs),
+    Sort(SortCmd),
 
     /// Watch & sort files as their added.
     Watch(WatchCmd),
+
+    /// Write a default config file from the given arguments.
+    Init(InitCmd),
+
+    /// Inspect and maintain the journal of replicated files.
+    Journal(JournalCmd),
+
+    /// Reverse operations recorded in a journal.
+    Undo(UndoCmd),
+
+    /// Sort all files once and report throughput and failures.
+    Bench(BenchCmd),
+
+    /// Sort removable media (SD cards, cameras) as they are mounted.
+    Import(ImportCmd),
+
+    /// Compare two destinations (e.g. NAS and offsite drive) and report files
+    /// that drifted, without modifying them.
+    MirrorCheck(MirrorCheckCmd),
 }
 
 #[derive(Args, Debug)]
@@ -29,16 +59,182 @@ pub struct CliArgs {
     #[arg(short, long, default_value = "false", group = "CliArgs")]
     pub overwrite: bool,
 
+    /// How files whose destination file already exists are handled,
+    /// overrides --overwrite.
+    #[arg(
+        long,
+        value_name = "POLICY",
+        conflicts_with = "overwrite",
+        group = "CliArgs"
+    )]
+    pub on_conflict: Option<ConflictPolicyKind>,
+
     /// Ignore source files that match this regular expression.
     #[arg(short, long, group = "CliArgs")]
     pub ignore_regex: Option<Regex>,
 
+    /// Skip files with the same content as a file of a previous source, sources
+    /// are listed by priority.
+    #[arg(long, group = "CliArgs")]
+    pub dedup: bool,
+
     /// How files are replicated in preference order.
     #[arg(short, long, default_values = ["hardlink", "softlink", "copy"], group = "CliArgs")]
     pub replicators: Vec<ReplicatorKind>,
 
+    /// Refuse to replicate files unless a filesystem is mounted on this directory.
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with = "mount_uuid",
+        group = "CliArgs"
+    )]
+    pub mount_point: Option<PathBuf>,
+
+    /// Refuse to replicate files unless the filesystem with this UUID is mounted.
+    #[arg(long, value_name = "UUID", group = "CliArgs")]
+    pub mount_uuid: Option<String>,
+
+    /// Render templates relative to this directory and refuse rendered paths
+    /// outside of it (e.g. `..` in metadata).
+    #[arg(long, value_name = "DIR", group = "CliArgs")]
+    pub dest_root: Option<PathBuf>,
+
+    /// Locale used to render month and weekday names (e.g. fr_FR).
+    #[arg(long, value_parser = value_parser::parse_locale, group = "CliArgs")]
+    pub locale: Option<String>,
+
+    /// Timezone date variables are rendered in: utc, local, an offset
+    /// (e.g. +02:00) or a timezone name with the gps-timezone feature.
+    #[arg(long, value_name = "TZ", group = "CliArgs")]
+    pub timezone: Option<DateTimezone>,
+
+    /// Duration added to date variables to correct a misconfigured camera
+    /// clock (e.g. +2h30m, -1d).
+    #[arg(
+        long,
+        value_name = "OFFSET",
+        allow_hyphen_values = true,
+        group = "CliArgs"
+    )]
+    pub date_offset: Option<DateOffset>,
+
+    /// Sources of date variables in preference order, repeat to list several
+    /// (e.g. --date-source exif --date-source filename).
+    #[arg(
+        long = "date-source",
+        value_name = "SOURCE",
+        value_enum,
+        group = "CliArgs"
+    )]
+    pub date_sources: Vec<DateSource>,
+
+    /// Infer timezone of exif dates without offset from GPS coordinates.
+    #[cfg(feature = "gps-timezone")]
+    #[arg(long, group = "CliArgs")]
+    pub infer_timezone: bool,
+
+    /// How files whose path isn't valid UTF-8 (e.g. legacy Latin-1 names)
+    /// are sorted.
+    #[arg(long, value_name = "POLICY", default_value = "keep", group = "CliArgs")]
+    pub non_utf8: NonUtf8Policy,
+
+    /// Define a constant template variable (repeatable), e.g. owner=alice
+    /// for the template ":owner:/:file.name:".
+    #[arg(
+        long = "var",
+        value_name = "KEY=VALUE",
+        value_parser = value_parser::parse_variable,
+        group = "CliArgs"
+    )]
+    pub variables: Vec<(String, String)>,
+
+    /// Use the template only for files matching this regular expression
+    /// (repeatable), see --mirror.
+    #[arg(long = "rule", value_name = "REGEX", group = "CliArgs")]
+    pub rules: Vec<Regex>,
+
+    /// Mirror files that don't match any rule with their path relative to their
+    /// source under the template root directory.
+    #[arg(long, group = "CliArgs")]
+    pub mirror: bool,
+
+    /// Mark replicated files (extended attribute) and skip files marked by a
+    /// previous run, for destinations that are also sources.
+    #[arg(long, group = "CliArgs")]
+    pub mark: bool,
+
+    /// Remove source files once replicated, e.g. to empty an import directory.
+    #[arg(long = "move", group = "CliArgs")]
+    pub move_source: bool,
+
+    /// Rename moved files within a filesystem instead of replicating them,
+    /// e.g. to re-organize a destination in place.
+    #[arg(long, requires = "move_source", group = "CliArgs")]
+    pub rename: bool,
+
+    /// Skip files whose destination file exists with the same content.
+    #[arg(long, group = "CliArgs")]
+    pub skip_duplicates: bool,
+
+    /// Extract exif thumbnails of sorted files to this directory, mirroring
+    /// the destination tree.
+    #[arg(long, value_name = "DIR", group = "CliArgs")]
+    pub extract_thumbs: Option<PathBuf>,
+
+    /// Shorten destination path components longer than this number of bytes,
+    /// keeping their extension and appending a hash of the whole component.
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = value_parser::parse_max_component_length,
+        group = "CliArgs"
+    )]
+    pub max_component_length: Option<usize>,
+
+    /// Shorten the longest components of destination paths longer than this
+    /// number of bytes until they fit.
+    #[arg(long, value_name = "BYTES", group = "CliArgs")]
+    pub max_path_length: Option<usize>,
+
+    /// Replace characters invalid on Windows and SMB shares (e.g. `:`, `?`)
+    /// in destination paths with this character, reserved names (e.g. CON)
+    /// are suffixed with it.
+    #[arg(long, value_name = "CHAR", group = "CliArgs")]
+    pub portable_names: Option<PortableNames>,
+
+    /// Normalize destination paths to this Unicode form so names from macOS
+    /// (nfd) and Linux (nfc) end up in the same directories.
+    #[arg(long, value_name = "FORM", value_enum, group = "CliArgs")]
+    pub normalize_unicode: Option<UnicodeForm>,
+
+    /// How extended attributes, resource forks and alternate data streams
+    /// lost by the replicator are handled.
+    #[arg(long, value_name = "POLICY", default_value = "warn", group = "CliArgs")]
+    pub streams: StreamsPolicy,
+
+    /// Replicate files with this extension (repeatable, e.g. xmp) next to the
+    /// file they belong to instead of sorting them on their own.
+    #[arg(long = "sidecar", value_name = "EXT", group = "CliArgs")]
+    pub sidecars: Vec<String>,
+
+    /// Sort RAW files paired with a JPEG file (same name) with the template
+    /// and metadata of the JPEG file.
+    #[arg(long, group = "CliArgs")]
+    pub raw_jpeg_pairs: bool,
+
+    /// Sort Live Photo videos with the template and metadata of their still
+    /// image.
+    #[arg(long, group = "CliArgs")]
+    pub live_photos: bool,
+
+    /// Maximum number of minutes between consecutive photos of an event, see
+    /// the date.event variable.
+    #[arg(long, value_name = "MINUTES", group = "CliArgs")]
+    pub event_gap: Option<u32>,
+
     /// Destination file template.
-    #[arg(value_parser = TemplateParser::default(), group = "CliArgs")]
+    #[arg(value_parser = TemplateParser::default(), group = "CliArgs", required = false)]
     pub template: Template,
 
     /// Sources files/directories to replicates.
@@ -48,24 +244,53 @@ pub struct CliArgs {
 
 #[derive(Args, Debug)]
 pub struct ConfigArgs {
-    /// Sets config file path.
+    /// Sets config file path (defaults to $XDG_CONFIG_HOME/photosort/config.toml).
     #[arg(
         short = 'c',
         long = "config",
         conflicts_with = "overwrite",
+        conflicts_with = "on_conflict",
         conflicts_with = "ignore_regex",
+        conflicts_with = "dedup",
         conflicts_with = "replicators",
+        conflicts_with = "mount_point",
+        conflicts_with = "mount_uuid",
+        conflicts_with = "dest_root",
+        conflicts_with = "locale",
+        conflicts_with = "variables",
+        conflicts_with = "non_utf8",
+        conflicts_with = "rules",
+        conflicts_with = "mirror",
+        conflicts_with = "mark",
+        conflicts_with = "move_source",
+        conflicts_with = "rename",
+        conflicts_with = "skip_duplicates",
+        conflicts_with = "extract_thumbs",
+        conflicts_with = "max_component_length",
+        conflicts_with = "max_path_length",
+        conflicts_with = "portable_names",
+        conflicts_with = "normalize_unicode",
+        conflicts_with = "streams",
+        conflicts_with = "sidecars",
+        conflicts_with = "raw_jpeg_pairs",
+        conflicts_with = "live_photos",
+        conflicts_with = "event_gap",
+        conflicts_with = "timezone",
+        conflicts_with = "date_offset",
+        conflicts_with = "date_sources",
         conflicts_with = "template",
         conflicts_with = "sources",
         required = false
     )]
+    #[cfg_attr(feature = "gps-timezone", arg(conflicts_with = "infer_timezone"))]
     pub path: PathBuf,
 }
 
-// User should specify either CliArgs or ConfigArgs
+// User should specify either CliArgs or ConfigArgs, default config file is used
+// if none of them are specified.
 #[derive(Debug)]
 pub enum CliOrConfigArgs {
-    Cli(CliArgs),
+    Cli(Box<CliArgs>),
     Config(ConfigArgs),
 }
 
@@ -73,8 +298,16 @@ impl FromArgMatches for CliOrConfigArgs {
     fn from_arg_matches(matches: &clap::ArgMatches) -> Result<Self, clap::Error> {
         if matches.get_one::<PathBuf>("path").is_some() {
             ConfigArgs::from_arg_matches(matches).map(CliOrConfigArgs::Config)
+        } else if matches.contains_id("template") {
+            CliArgs::from_arg_matches(matches).map(|cli| CliOrConfigArgs::Cli(Box::new(cli)))
         } else {
-            CliArgs::from_arg_matches(matches).map(CliOrConfigArgs::Cli)
+            match dirs::default_config_path() {
+                Some(path) => Ok(CliOrConfigArgs::Config(ConfigArgs { path })),
+                None => Err(clap::Error::raw(
+                    ErrorKind::MissingRequiredArgument,
+                    "no template nor config file specified and default config path can't be determined",
+                )),
+            }
         }
     }
 
@@ -86,6 +319,33 @@ impl FromArgMatches for CliOrConfigArgs {
     }
 }
 
+impl CliArgs {
+    pub fn options(&self) -> Options {
+        let mut options = Options::default();
+        if let Some(locale) = &self.locale {
+            options.locale = context::parse_locale(locale).unwrap();
+        }
+        #[cfg(feature = "gps-timezone")]
+        {
+            options.infer_timezone = self.infer_timezone;
+        }
+        options.timezone = self.timezone;
+        options.date_offset = self.date_offset;
+        options.date_sources = self.date_sources.clone();
+        options.variables = self.variables.iter().cloned().collect();
+        options.non_utf8 = self.non_utf8;
+
+        options
+    }
+
+    pub fn mount(&self) -> Option<MountPin> {
+        self.mount_point
+            .clone()
+            .map(MountPin::MountPoint)
+            .or_else(|| self.mount_uuid.clone().map(MountPin::Uuid))
+    }
+}
+
 impl Args for CliOrConfigArgs {
     fn augment_args(cmd: clap::Command) -> clap::Command {
         let cmd = CliArgs::augment_args(cmd);
@@ -98,12 +358,267 @@ impl Args for CliOrConfigArgs {
     }
 }
 
+#[derive(Args, Debug)]
+pub struct SortCmd {
+    #[command(flatten)]
+    pub common: CliOrConfigArgs,
+
+    #[command(flatten)]
+    pub lock: LockArgs,
+
+    /// Report what would be done without touching the filesystem.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Wait for the destination filesystem to be mounted instead of failing.
+    #[arg(long)]
+    pub wait_for_mount: bool,
+
+    /// Print every variable the given file resolves to instead of sorting.
+    #[arg(long, value_name = "FILE")]
+    pub debug_context: Option<PathBuf>,
+
+    /// Print --debug-context output as JSON.
+    #[arg(long, requires = "debug_context")]
+    pub json: bool,
+
+    /// Sort only files dated on or after this date (YYYY-MM-DD).
+    #[arg(long, value_name = "DATE")]
+    pub since: Option<NaiveDate>,
+
+    /// Sort only files dated on or before this date (YYYY-MM-DD).
+    #[arg(long, value_name = "DATE")]
+    pub until: Option<NaiveDate>,
+
+    /// Sort only files whose path matches this regular expression.
+    #[arg(long = "match", value_name = "REGEX")]
+    pub pattern: Option<Regex>,
+
+    /// Write the plan (files to sort, duplicates and filtered files) as JSON
+    /// lines to this file ("-" for stdout) instead of sorting.
+    #[arg(long, value_name = "FILE", conflicts_with = "dry_run")]
+    pub plan: Option<PathBuf>,
+
+    /// Spill the plan to a new file of the state directory while sources are
+    /// walked and write it to the plan file once planning is done, the plan
+    /// file is never partial and walking isn't held up by a slow reader of
+    /// stdout.
+    #[arg(long, requires = "plan")]
+    pub plan_spill: bool,
+
+    /// Number of files sorted in parallel (defaults to the number of CPUs).
+    #[arg(short, long, value_name = "N")]
+    pub jobs: Option<NonZeroUsize>,
+
+    /// Render destination paths of every file instead of reusing paths
+    /// cached by previous runs for unchanged files.
+    #[arg(long)]
+    pub no_render_cache: bool,
+
+    /// Write a JSON summary of the run (counts, duration, errors, run id) to
+    /// this file once it ends.
+    #[arg(long, value_name = "FILE", conflicts_with = "plan")]
+    pub summary_file: Option<PathBuf>,
+
+    /// Skip the run if no source or destination file was added, removed or
+    /// modified (path, size and modification time) since the last successful
+    /// run with the same configuration.
+    #[arg(long, conflicts_with = "plan")]
+    pub skip_unchanged: bool,
+
+    /// Count source files first, then show sorted files out of the total,
+    /// the rate and the estimated time left on stderr.
+    #[arg(long, conflicts_with = "plan")]
+    pub progress: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct BenchCmd {
+    #[command(flatten)]
+    pub common: CliOrConfigArgs,
+
+    #[command(flatten)]
+    pub lock: LockArgs,
+
+    /// Replicate files below DIR/dst instead of their destination and keep
+    /// them, a temporary directory is used and removed by default.
+    #[arg(long, value_name = "DIR")]
+    pub scratch: Option<PathBuf>,
+
+    /// Fail filesystem operations with this probability (between 0 and 1).
+    #[cfg(feature = "chaos")]
+    #[arg(long, value_name = "RATE")]
+    pub chaos: Option<f64>,
+
+    /// Delay filesystem operations by this number of milliseconds.
+    #[cfg(feature = "chaos")]
+    #[arg(long, value_name = "MS", requires = "chaos")]
+    pub chaos_latency: Option<u64>,
+
+    /// Seed of injected faults, so the same operations fail on every run.
+    #[cfg(feature = "chaos")]
+    #[arg(long, value_name = "SEED", requires = "chaos")]
+    pub chaos_seed: Option<u64>,
+}
+
 #[derive(Args, Debug)]
 pub struct WatchCmd {
     #[command(flatten)]
     pub common: CliOrConfigArgs,
 
+    #[command(flatten)]
+    pub lock: LockArgs,
+
+    /// Report what would be done without touching the filesystem.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Wait for the destination filesystem to be mounted instead of failing.
+    #[arg(long)]
+    pub wait_for_mount: bool,
+
+    /// Create the destination root directory if it doesn't exist instead of
+    /// queuing events until it is mounted.
+    #[arg(long)]
+    pub create_dest_root: bool,
+
     /// Fork a daemon process.
-    #[arg(short, long)]
+    #[arg(short, long, conflicts_with = "once")]
     pub daemon: bool,
+
+    /// Sort pending files and watch for new ones until sources are idle, then exit.
+    #[arg(long)]
+    pub once: bool,
+
+    /// Number of seconds without events after which --once exits.
+    #[arg(long, value_name = "SECONDS", default_value = "10", requires = "once")]
+    pub idle_timeout: u64,
+
+    /// Maximum number of seconds --once runs, even if sources aren't idle.
+    #[arg(long, value_name = "SECONDS", requires = "once")]
+    pub max_duration: Option<u64>,
+
+    /// Write a JSON summary of the run (counts, duration, errors, run id) to
+    /// this file once --once exits.
+    #[arg(long, value_name = "FILE", requires = "once")]
+    pub summary_file: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ImportCmd {
+    #[command(flatten)]
+    pub common: CliOrConfigArgs,
+
+    #[command(flatten)]
+    pub lock: LockArgs,
+
+    /// Directories of mounted media to sort, `*` and `?` match part of a path
+    /// component (e.g. "/media/*/*/DCIM").
+    #[arg(long, value_name = "PATTERN", required = true)]
+    pub media: Vec<PathBuf>,
+
+    /// Eject media once all of their files are sorted.
+    #[arg(long)]
+    pub eject: bool,
+
+    /// Delete files from media once their destination file is verified to
+    /// have the same content hash.
+    #[arg(long, conflicts_with = "dry_run")]
+    pub delete_imported: bool,
+
+    /// Number of seconds between checks for new media.
+    #[arg(long, value_name = "SECONDS", default_value = "5")]
+    pub interval: u64,
+
+    /// Report what would be done without touching the filesystem.
+    #[arg(long, conflicts_with = "eject")]
+    pub dry_run: bool,
+}
+
+/// Prevents concurrent runs (e.g. a cron-triggered sort and the watch daemon)
+/// from replicating files simultaneously.
+#[derive(Args, Debug)]
+pub struct LockArgs {
+    /// Wait for other photosort processes sorting files to the same
+    /// destination (or rewriting the journal) to exit instead of failing,
+    /// commands rewriting the journal never wait.
+    #[arg(long, conflicts_with = "no_lock")]
+    pub wait_lock: bool,
+
+    /// Run even if conflicting photosort processes are running.
+    #[arg(long)]
+    pub no_lock: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct InitCmd {
+    #[command(flatten)]
+    pub args: Box<CliArgs>,
+
+    /// Sets config file path (defaults to $XDG_CONFIG_HOME/photosort/config.toml).
+    #[arg(short = 'c', long = "config")]
+    pub path: Option<PathBuf>,
+
+    /// Overwrite config file if it already exists.
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct MirrorCheckCmd {
+    /// First destination directory.
+    pub a: PathBuf,
+
+    /// Second destination directory.
+    pub b: PathBuf,
+
+    /// Also compare content hashes of files with the same size.
+    #[arg(long)]
+    pub hash: bool,
+
+    /// Print drifted files as JSON lines.
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct JournalCmd {
+    #[command(subcommand)]
+    pub command: JournalCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum JournalCommand {
+    /// Print entries (JSON lines) of files replicated to a destination path or
+    /// directory.
+    Query(JournalQueryCmd),
+
+    /// Keep only the last entry of every existing destination file.
+    Compact(JournalCompactCmd),
+}
+
+#[derive(Args, Debug)]
+pub struct JournalQueryCmd {
+    /// Destination file or directory.
+    #[arg(long, value_name = "PATH")]
+    pub dst: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct JournalCompactCmd {
+    #[command(flatten)]
+    pub lock: LockArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct UndoCmd {
+    /// Journal file (defaults to $XDG_STATE_HOME/photosort/journal.jsonl).
+    pub journal: Option<PathBuf>,
+
+    /// Undo only operations of the last run.
+    #[arg(long)]
+    pub last_run: bool,
+
+    #[command(flatten)]
+    pub lock: LockArgs,
 }
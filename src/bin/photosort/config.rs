@@ -1,35 +1,360 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 
 use regex::Regex;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-use photosort::sort;
+use photosort::encoding::NonUtf8Policy;
+use photosort::mount::MountPin;
+use photosort::portable::UnicodeForm;
+use photosort::sort::{self, ConflictPolicyKind};
+use photosort::streams::StreamsPolicy;
+use photosort::template::context::DateSource;
 
 use crate::args::CliArgs;
 
+/// Version of the config file format, logged on startup so config issues
+/// can be matched with the daemon reading them.
+pub const SCHEMA_VERSION: u32 = 1;
+
 #[derive(Debug, Deserialize)]
-pub struct Watch {
+pub struct Config {
+    /// Oldest photosort release supporting every option of the config file
+    /// (e.g. `0.5.0`), older releases refuse to run it.
+    #[serde(default)]
+    pub min_version: Option<String>,
+
     pub sources: Vec<PathBuf>,
 
     #[serde(with = "serde_regex", default = "Option::default")]
     pub ignore_regex: Option<Regex>,
 
+    /// Skip files with the same content as a file of a previous source.
+    #[serde(default)]
+    pub dedup: bool,
+
     #[serde(flatten)]
     pub sorter: sort::Config,
 }
 
-impl From<CliArgs> for Watch {
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read config file {1:?}: {0}")]
+    Read(#[source] io::Error, PathBuf),
+
+    #[error("failed to deserialize config file {1:?}: {0}")]
+    Deserialize(#[source] toml::de::Error, PathBuf),
+
+    #[error("failed to serialize config: {0}")]
+    Serialize(#[from] toml::ser::Error),
+
+    #[error("failed to write config file {1:?}: {0}")]
+    Write(#[source] io::Error, PathBuf),
+
+    #[error("invalid min_version {0:?} in config file {1:?}, expected a version like \"1.2.0\"")]
+    InvalidMinVersion(String, PathBuf),
+
+    #[error("config file {2:?} requires photosort {0} or newer, this is photosort {1}")]
+    UnsupportedVersion(String, &'static str, PathBuf),
+}
+
+/// Parses the numeric components of a version (`1.2.0`, `v1.2`), pre-release
+/// suffixes (`-rc.1`) are ignored.
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    let version = version.trim().trim_start_matches('v');
+    let version = version.split_once('-').map_or(version, |(v, _)| v);
+    let mut components = version
+        .split('.')
+        .map(|n| n.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+
+    // 1.2 is 1.2.0.
+    while components.last() == Some(&0) {
+        components.pop();
+    }
+    Some(components)
+}
+
+impl Config {
+    /// Reads and deserializes the TOML config file at the given path.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let cfg_str =
+            fs::read_to_string(path).map_err(|err| ConfigError::Read(err, path.to_owned()))?;
+
+        // options of newer releases may not deserialize, the required
+        // version is checked first.
+        if let Ok(version) = toml::from_str::<MinVersion>(&cfg_str) {
+            version.check(path)?;
+        }
+
+        toml::from_str(&cfg_str).map_err(|err| ConfigError::Deserialize(err, path.to_owned()))
+    }
+}
+
+/// MinVersion is the `min_version` field of a config file, read before the
+/// other fields.
+#[derive(Debug, Deserialize)]
+struct MinVersion {
+    #[serde(default)]
+    min_version: Option<String>,
+}
+
+impl MinVersion {
+    /// Returns an error if the config requires a newer release.
+    fn check(&self, path: &Path) -> Result<(), ConfigError> {
+        let min_version = match &self.min_version {
+            Some(min_version) => min_version,
+            None => return Ok(()),
+        };
+
+        let current = env!("CARGO_PKG_VERSION");
+        let required = parse_version(min_version)
+            .ok_or_else(|| ConfigError::InvalidMinVersion(min_version.clone(), path.to_owned()))?;
+        if parse_version(current).is_some_and(|current| required > current) {
+            return Err(ConfigError::UnsupportedVersion(
+                min_version.clone(),
+                current,
+                path.to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl From<CliArgs> for Config {
     fn from(args: CliArgs) -> Self {
-        let sorter = sort::Config::new(
+        let mount = args.mount();
+        let options = args.options();
+        let rules = args
+            .rules
+            .into_iter()
+            .map(|pattern| sort::Rule::new(pattern, None))
+            .collect();
+        let mut sorter = sort::Config::new(
             args.template,
             Box::from_iter(args.replicators),
             args.overwrite,
-        );
+        )
+        .with_mount(mount)
+        .with_dest_root(args.dest_root)
+        .with_rules(rules)
+        .with_mirror(args.mirror)
+        .with_mark(args.mark)
+        .with_move(args.move_source)
+        .with_rename(args.rename)
+        .with_skip_duplicates(args.skip_duplicates)
+        .with_extract_thumbs(args.extract_thumbs)
+        .with_max_component_length(args.max_component_length)
+        .with_max_path_length(args.max_path_length)
+        .with_portable_names(args.portable_names)
+        .with_normalize_unicode(args.normalize_unicode)
+        .with_streams(args.streams)
+        .with_sidecars(args.sidecars)
+        .with_raw_jpeg_pairs(args.raw_jpeg_pairs)
+        .with_live_photos(args.live_photos)
+        .with_event_gap(args.event_gap)
+        .with_options(options);
+        if let Some(kind) = args.on_conflict {
+            sorter = sorter.with_on_conflict(Box::new(kind));
+        }
 
         Self {
+            min_version: None,
             sources: args.sources,
             ignore_regex: args.ignore_regex,
+            dedup: args.dedup,
             sorter,
         }
     }
 }
+
+/// Serializable counterpart of [`Config`] used to generate config files from
+/// command line arguments.
+#[derive(Debug, Serialize)]
+struct ConfigFile<'a> {
+    sources: &'a [PathBuf],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ignore_regex: Option<&'a str>,
+    dedup: bool,
+    replicator: Vec<String>,
+    template: String,
+    overwrite: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    on_conflict: Option<ConflictPolicyKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mount: Option<MountPin>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dest_root: Option<&'a Path>,
+    mirror: bool,
+    mark: bool,
+    #[serde(rename = "move")]
+    move_source: bool,
+    rename: bool,
+    skip_duplicates: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extract_thumbs: Option<&'a Path>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_component_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_path_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    portable_names: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    normalize_unicode: Option<UnicodeForm>,
+    streams: StreamsPolicy,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    sidecars: &'a [String],
+    raw_jpeg_pairs: bool,
+    live_photos: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event_gap: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    locale: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timezone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_offset: Option<String>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    date_sources: &'a [DateSource],
+    #[cfg(feature = "gps-timezone")]
+    infer_timezone: bool,
+    non_utf8: NonUtf8Policy,
+    // tables must be serialized last.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    variables: BTreeMap<&'a str, &'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    rules: Vec<RuleFile<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct RuleFile<'a> {
+    #[serde(rename = "match")]
+    pattern: &'a str,
+}
+
+/// Writes a config file equivalent to the given command line arguments.
+pub fn write_config_file(path: &Path, args: &CliArgs) -> Result<(), ConfigError> {
+    let cfg = ConfigFile {
+        sources: &args.sources,
+        ignore_regex: args.ignore_regex.as_ref().map(|r| r.as_str()),
+        dedup: args.dedup,
+        replicator: args.replicators.iter().map(|r| r.to_string()).collect(),
+        template: args.template.to_string(),
+        overwrite: args.overwrite,
+        on_conflict: args.on_conflict,
+        mount: args.mount(),
+        dest_root: args.dest_root.as_deref(),
+        mirror: args.mirror,
+        mark: args.mark,
+        move_source: args.move_source,
+        rename: args.rename,
+        skip_duplicates: args.skip_duplicates,
+        extract_thumbs: args.extract_thumbs.as_deref(),
+        max_component_length: args.max_component_length,
+        max_path_length: args.max_path_length,
+        portable_names: args.portable_names.map(|p| p.to_string()),
+        normalize_unicode: args.normalize_unicode,
+        streams: args.streams,
+        sidecars: &args.sidecars,
+        raw_jpeg_pairs: args.raw_jpeg_pairs,
+        live_photos: args.live_photos,
+        event_gap: args.event_gap,
+        locale: args.locale.as_deref(),
+        timezone: args.timezone.map(|tz| tz.to_string()),
+        date_offset: args.date_offset.map(|offset| offset.to_string()),
+        date_sources: &args.date_sources,
+        #[cfg(feature = "gps-timezone")]
+        infer_timezone: args.infer_timezone,
+        non_utf8: args.non_utf8,
+        variables: args
+            .variables
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect(),
+        rules: args
+            .rules
+            .iter()
+            .map(|r| RuleFile {
+                pattern: r.as_str(),
+            })
+            .collect(),
+    };
+    let cfg_str = toml::to_string(&cfg)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|err| ConfigError::Write(err, path.to_owned()))?;
+    }
+    fs::write(path, cfg_str).map_err(|err| ConfigError::Write(err, path.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    use super::{parse_version, Config, ConfigError};
+
+    fn read_config(min_version: &str) -> Result<Config, ConfigError> {
+        read_config_with(min_version, "replicator = \"copy\"")
+    }
+
+    fn read_config_with(min_version: &str, options: &str) -> Result<Config, ConfigError> {
+        let path = env::temp_dir().join(format!("{}.toml", Uuid::new_v4()));
+        let content = format!(
+            "min_version = {:?}\n\
+             sources = []\n\
+             template = \"/dst/:file.name:\"\n\
+             {}\n",
+            min_version, options
+        );
+        fs::write(&path, content).unwrap();
+        let cfg = Config::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        cfg
+    }
+
+    #[test]
+    fn min_version() {
+        for version in [env!("CARGO_PKG_VERSION"), "0.1", "v0.5.0", "0.1.0-rc.1"] {
+            assert!(read_config(version).is_ok(), "{}", version);
+        }
+
+        let err = read_config("999.0.0").unwrap_err();
+        assert!(matches!(&err, ConfigError::UnsupportedVersion(v, _, _) if v == "999.0.0"));
+        assert!(err
+            .to_string()
+            .contains("requires photosort 999.0.0 or newer"));
+        // options of newer releases don't hide the required version.
+        assert!(matches!(
+            read_config_with("999.0.0", "replicator = { kind = \"teleport\" }"),
+            Err(ConfigError::UnsupportedVersion(..))
+        ));
+        assert!(matches!(
+            read_config_with(
+                env!("CARGO_PKG_VERSION"),
+                "replicator = { kind = \"teleport\" }"
+            ),
+            Err(ConfigError::Deserialize(..))
+        ));
+
+        for version in ["latest", "1..2", ""] {
+            assert!(matches!(
+                read_config(version),
+                Err(ConfigError::InvalidMinVersion(v, _)) if v == version
+            ));
+        }
+    }
+
+    #[test]
+    fn version() {
+        assert_eq!(parse_version("1.2"), parse_version("1.2.0"));
+        assert_eq!(parse_version("v1.2.0-rc.1"), Some(vec![1, 2]));
+        assert!(parse_version("1.10.0") > parse_version("1.9.3"));
+        assert_eq!(parse_version("1.x"), None);
+    }
+}
@@ -0,0 +1,49 @@
+use std::env;
+use std::path::PathBuf;
+
+use photosort::state::StateDir;
+
+const APP_NAME: &str = "photosort";
+
+/// Returns the value of the given XDG base directory environment variable or
+/// `$HOME/<fallback>` if it isn't set. Relative paths are ignored as required
+/// by the XDG base directory specification.
+fn xdg_dir(env_var: &str, fallback: &str) -> Option<PathBuf> {
+    if let Some(dir) = env::var_os(env_var).map(PathBuf::from) {
+        if dir.is_absolute() {
+            return Some(dir);
+        }
+    }
+
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .filter(|home| home.is_absolute())
+        .map(|home| home.join(fallback))
+}
+
+/// Directory containing photosort configuration files
+/// (`$XDG_CONFIG_HOME/photosort`).
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join(APP_NAME))
+}
+
+/// Directory containing photosort state files such as the journal or pid file
+/// (`$XDG_STATE_HOME/photosort`).
+pub fn state_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state").map(|dir| dir.join(APP_NAME))
+}
+
+/// Path of the config file used when none is specified.
+pub fn default_config_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+/// Layout of the state directory.
+pub fn state() -> Option<StateDir> {
+    state_dir().map(StateDir::new)
+}
+
+/// Path of the journal recording replicated files.
+pub fn journal_path() -> Option<PathBuf> {
+    state().map(|state| state.journal_path())
+}
@@ -0,0 +1,191 @@
+use std::fs::{self, File, TryLockError};
+use std::io;
+use std::path::{self, Path, PathBuf};
+
+use photosort::state::StateDir;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum LockError {
+    #[error("failed to open lock file {1:?}: {0}")]
+    Open(#[source] io::Error, PathBuf),
+
+    #[error("failed to lock {1:?}: {0}")]
+    Lock(#[source] io::Error, PathBuf),
+
+    #[error("another photosort process holds the lock {0:?}, use --wait-lock to wait for it")]
+    WouldBlock(PathBuf),
+
+    #[error(
+        "another photosort process sorts files to {0:?}, to a directory inside it or to one \
+         containing it, use --wait-lock to wait for it"
+    )]
+    DestinationBusy(PathBuf),
+
+    #[error(
+        "photosort processes (e.g. the watch daemon) are using the state directory {0:?}, \
+         stop them before rewriting the journal"
+    )]
+    StateBusy(PathBuf),
+}
+
+/// Advisory lock on a file, it is released when dropped.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    /// Locks the file at the given path (created if needed), blocking until
+    /// it is released by other processes if `wait` is true.
+    pub fn acquire(path: &Path, wait: bool) -> Result<Self, LockError> {
+        Self::acquire_with(path, wait, false)
+    }
+
+    /// Same as [`Lock::acquire`] but other processes may hold a shared lock
+    /// on the file at the same time.
+    pub fn acquire_shared(path: &Path, wait: bool) -> Result<Self, LockError> {
+        Self::acquire_with(path, wait, true)
+    }
+
+    /// Locks the given destination root for a run: the state directory and
+    /// ancestors of the root are locked shared and the root exclusively, so
+    /// runs to the same root or to nested roots exclude each other. Locks
+    /// are always taken from the top so waiting runs can't deadlock.
+    pub fn acquire_destination(
+        state: &StateDir,
+        root: &Path,
+        wait: bool,
+    ) -> Result<Vec<Self>, LockError> {
+        let root = fs::canonicalize(root)
+            .or_else(|_| path::absolute(root))
+            .unwrap_or_else(|_| root.to_owned());
+        let busy = |err| match err {
+            LockError::WouldBlock(_) => LockError::DestinationBusy(root.clone()),
+            err => err,
+        };
+
+        let mut locks = vec![Self::acquire_shared(&state.lock_path(), wait)?];
+        let ancestors: Vec<&Path> = root.ancestors().skip(1).collect();
+        for ancestor in ancestors.into_iter().rev() {
+            let path = state.destination_lock_path(ancestor);
+            locks.push(Self::acquire_shared(&path, wait).map_err(busy)?);
+        }
+        let path = state.destination_lock_path(&root);
+        locks.push(Self::acquire(&path, wait).map_err(busy)?);
+
+        Ok(locks)
+    }
+
+    /// Locks the state directory exclusively to rewrite the journal. Fails
+    /// without waiting if runs are using it, the watch daemon holds it until
+    /// it exits.
+    pub fn acquire_state(state: &StateDir) -> Result<Self, LockError> {
+        Self::acquire(&state.lock_path(), false).map_err(|err| match err {
+            LockError::WouldBlock(_) => LockError::StateBusy(state.root().to_owned()),
+            err => err,
+        })
+    }
+
+    fn acquire_with(path: &Path, wait: bool, shared: bool) -> Result<Self, LockError> {
+        let open = || {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            File::options()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(path)
+        };
+        let file = open().map_err(|err| LockError::Open(err, path.to_owned()))?;
+
+        let locked = match shared {
+            true => file.try_lock_shared(),
+            false => file.try_lock(),
+        };
+        match locked {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) if wait => {
+                log::info!(
+                    "waiting for another photosort process to release {:?}",
+                    path
+                );
+                match shared {
+                    true => file.lock_shared(),
+                    false => file.lock(),
+                }
+                .map_err(|err| LockError::Lock(err, path.to_owned()))?;
+            }
+            Err(TryLockError::WouldBlock) => return Err(LockError::WouldBlock(path.to_owned())),
+            Err(TryLockError::Error(err)) => return Err(LockError::Lock(err, path.to_owned())),
+        }
+
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    use super::{Lock, LockError};
+
+    #[test]
+    fn contended() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let path = root.join("photosort.lock");
+
+        let lock = Lock::acquire(&path, false).unwrap();
+        assert!(matches!(
+            Lock::acquire(&path, false),
+            Err(LockError::WouldBlock(p)) if p == path
+        ));
+        assert!(Lock::acquire_shared(&path, false).is_err());
+        drop(lock);
+
+        // shared locks only exclude exclusive ones.
+        let shared = Lock::acquire_shared(&path, false).unwrap();
+        let other = Lock::acquire_shared(&path, false).unwrap();
+        assert!(Lock::acquire(&path, false).is_err());
+        drop((shared, other));
+        assert!(Lock::acquire(&path, false).is_ok());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn destinations() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(root.join("photos/2023")).unwrap();
+        fs::create_dir_all(root.join("videos")).unwrap();
+        let state = photosort::state::StateDir::new(root.join("state"));
+        let acquire = |dir: &str| Lock::acquire_destination(&state, &root.join(dir), false);
+
+        let photos = acquire("photos").unwrap();
+        let _videos = acquire("videos").unwrap();
+        assert!(matches!(
+            acquire("videos/../photos"),
+            Err(LockError::DestinationBusy(p)) if p == root.join("photos")
+        ));
+        // nested roots exclude each other.
+        assert!(matches!(
+            acquire("photos/2023"),
+            Err(LockError::DestinationBusy(_))
+        ));
+        assert!(matches!(acquire(""), Err(LockError::DestinationBusy(_))));
+        drop(photos);
+        let _photos_2023 = acquire("photos/2023").unwrap();
+        assert!(acquire("photos").is_err());
+
+        // commands rewriting the journal don't wait for runs.
+        assert!(matches!(
+            Lock::acquire_state(&state),
+            Err(LockError::StateBusy(p)) if p == root.join("state")
+        ));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
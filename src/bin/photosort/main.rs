@@ -1,192 +1,1245 @@
+use std::env;
 use std::fs;
-use std::io;
-use std::path::Path;
-use std::process::exit;
+use std::io::{self, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::{self, exit};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use args::CliArgs;
+use args::BenchCmd;
 use args::CliOrConfigArgs;
 use args::Command;
+use args::ImportCmd;
+use args::InitCmd;
+use args::JournalCmd;
+use args::JournalCommand;
+use args::LockArgs;
+use args::MirrorCheckCmd;
+use args::SortCmd;
+use args::UndoCmd;
 use args::WatchCmd;
 use clap::Parser;
 use daemonize::Daemonize;
 use env_logger::Env;
+use serde::Serialize;
 
-use photosort::replicator::{Replicator, ReplicatorKind};
+use photosort::backoff::Backoff;
+#[cfg(feature = "chaos")]
+use photosort::chaos::ChaosFs;
+#[cfg(feature = "chaos")]
+use photosort::fs::RealFs;
+use photosort::index::{HashIndex, RenderCache};
+use photosort::journal::{self, Journal};
+use photosort::marker;
+use photosort::media::{self, GlobSource, MediaSource};
+use photosort::mirror;
+use photosort::mount;
+use photosort::plan::{DateRange, Duplicate, PlanItem, PlannedFile, Planner};
+use photosort::progress::Progress;
+use photosort::queue::PriorityQueue;
+use photosort::replicator::ReplicatorKind;
+use photosort::snapshot::Snapshot;
 use photosort::sort;
 use photosort::sort::SortError;
 use photosort::sort::Sorter;
+use photosort::state;
+use photosort::summary::Report;
 use photosort::template::Template;
+use photosort::template::{context, variables};
+use photosort::watch::{
+    DrainOptions, EventHandlerError, EventHandlerResult, EventWatcher, FilterReason,
+};
 
 mod args;
 mod config;
+mod dirs;
+mod lock;
+mod progress_bar;
 mod value_parser;
-mod watch;
 
 use args::Cli;
+use lock::Lock;
+use progress_bar::ProgressBar;
 use value_parser::TemplateParser;
-use watch::EventHandlerError;
-use watch::EventHandlerResult;
-use watch::EventWatcher;
-use watch::FilterReason;
 
 type ExitCode = i32;
 
+/// Number of planned files waiting to be sorted.
+const PLAN_CHANNEL_CAPACITY: usize = 1024;
+
 pub fn main() {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
     let cli = Cli::parse();
 
+    if let Err(exit_code) = migrate_state() {
+        exit(exit_code);
+    }
+
     let exit_code = match cli.command {
         Command::Sort(args) => sort_cmd(args),
         Command::Watch(args) => watch_cmd(args),
+        Command::Init(args) => init_cmd(args),
+        Command::Journal(args) => journal_cmd(args),
+        Command::Undo(args) => undo_cmd(args),
+        Command::Bench(args) => bench_cmd(args),
+        Command::Import(args) => import_cmd(args),
+        Command::MirrorCheck(args) => mirror_check_cmd(args),
     };
 
     exit(exit_code);
 }
 
-fn sort_cmd(args: CliArgs) -> ExitCode {
-    let replicator = Box::<dyn Replicator>::from_iter(args.replicators);
-    let sorter = Sorter::new(sort::Config::new(args.template, replicator, args.overwrite));
+/// Upgrades the layout of the state directory written by previous releases.
+fn migrate_state() -> Result<(), ExitCode> {
+    let state = match dirs::state() {
+        Some(state) => state,
+        None => return Ok(()),
+    };
 
-    let mut exit_code = 0;
+    match state.migrate() {
+        Ok(_) => Ok(()),
+        Err(err) => {
+            log::error!("{}", err);
+            Err(1)
+        }
+    }
+}
 
-    for src_path in args.sources {
-        if src_path.is_dir() {
-            exit_code += sort_dir(&sorter, &src_path);
-        } else {
-            let result = sorter.sort_file(&src_path);
-            if result.is_err() {
-                exit_code += 1;
+/// Loads the config, errors are recorded in the given report.
+fn load_config(args: CliOrConfigArgs, report: Option<&Report>) -> Result<config::Config, ExitCode> {
+    match args {
+        CliOrConfigArgs::Cli(args) => {
+            log::debug!("setting up config...");
+            let cfg = config::Config::from(*args);
+            log::debug!("config successfully setted up");
+
+            Ok(cfg)
+        }
+        CliOrConfigArgs::Config(args) => {
+            log::debug!("reading config file {:?}...", args.path);
+            match config::Config::from_file(&args.path) {
+                Ok(cfg) => {
+                    log::debug!("config file successfully read");
+                    Ok(cfg)
+                }
+                Err(err) => {
+                    log::error!("{}", err);
+                    if let Some(report) = report {
+                        report.error(None, &err);
+                    }
+                    Err(1)
+                }
+            }
+        }
+    }
+}
+
+/// Acquires the locks preventing concurrent runs unless disabled or dry
+/// running, locks are held until the returned value is dropped. Runs of a
+/// sorter lock its destination and share the state directory with runs to
+/// other destinations, commands rewriting the journal (no sorter) fail if
+/// any is running. Errors are recorded in the given report.
+fn acquire_lock(
+    args: &LockArgs,
+    dry_run: bool,
+    sorter: Option<&Sorter>,
+    report: Option<&Report>,
+) -> Result<Vec<Lock>, ExitCode> {
+    if args.no_lock || dry_run {
+        return Ok(Vec::new());
+    }
+
+    let state = match dirs::state() {
+        Some(state) => state,
+        None => {
+            let err = "lock file path can't be determined, use --no-lock to run anyway";
+            log::error!("{}", err);
+            if let Some(report) = report {
+                report.error(None, &err);
+            }
+            return Err(1);
+        }
+    };
+
+    // templates without root render paths relative to the working directory.
+    let locks = match sorter.map(Sorter::destination_root) {
+        Some(root) => {
+            let root = root.unwrap_or_else(|| PathBuf::from("."));
+            Lock::acquire_destination(&state, &root, args.wait_lock)
+        }
+        None => Lock::acquire_state(&state).map(|lock| vec![lock]),
+    };
+    locks.map_err(|err| {
+        log::error!("{}", err);
+        if let Some(report) = report {
+            report.error(None, &err);
+        }
+        1
+    })
+}
+
+/// Opens the journal in the state directory, files aren't journaled if it
+/// can't be opened.
+fn open_journal() -> Option<Journal> {
+    let path = dirs::journal_path()?;
+    match Journal::open(&path) {
+        Ok(journal) => Some(journal),
+        Err(err) => {
+            log::warn!("{}, replicated files won't be journaled", err);
+            None
+        }
+    }
+}
+
+fn journal_cmd(journal_args: JournalCmd) -> ExitCode {
+    let path = match dirs::journal_path() {
+        Some(path) => path,
+        None => {
+            log::error!("journal path can't be determined");
+            return 1;
+        }
+    };
+
+    match journal_args.command {
+        JournalCommand::Query(args) => match journal::query(&path, &args.dst) {
+            Ok(entries) => {
+                for entry in entries {
+                    match serde_json::to_string(&entry) {
+                        Ok(line) => println!("{}", line),
+                        Err(err) => log::error!("failed to serialize journal entry: {}", err),
+                    }
+                }
+                0
+            }
+            Err(err) => {
+                log::error!("{}", err);
+                1
+            }
+        },
+        JournalCommand::Compact(args) => {
+            let _lock = match acquire_lock(&args.lock, false, None, None) {
+                Ok(lock) => lock,
+                Err(exit_code) => return exit_code,
+            };
+
+            match journal::compact(&path) {
+                Ok(compaction) => {
+                    log::info!(
+                        "journal compacted from {} to {} entries",
+                        compaction.before,
+                        compaction.after
+                    );
+                    0
+                }
+                Err(err) => {
+                    log::error!("{}", err);
+                    1
+                }
             }
-            log_sort_result(&result, &src_path);
+        }
+    }
+}
+
+fn undo_cmd(undo_args: UndoCmd) -> ExitCode {
+    let path = match undo_args.journal.or_else(dirs::journal_path) {
+        Some(path) => path,
+        None => {
+            log::error!("journal path can't be determined, please specify one");
+            return 1;
+        }
+    };
+
+    let _lock = match acquire_lock(&undo_args.lock, false, None, None) {
+        Ok(lock) => lock,
+        Err(exit_code) => return exit_code,
+    };
+
+    let run_id = if undo_args.last_run {
+        match journal::last_run_id(&path) {
+            Ok(Some(run_id)) => Some(run_id),
+            Ok(None) => {
+                log::info!("nothing to undo");
+                return 0;
+            }
+            Err(err) => {
+                log::error!("{}", err);
+                return 1;
+            }
+        }
+    } else {
+        None
+    };
+
+    let undo = match journal::undo(&path, |entry| run_id.is_none() || entry.run_id == run_id) {
+        Ok(undo) => undo,
+        Err(err) => {
+            log::error!("{}", err);
+            return 1;
+        }
+    };
+
+    for entry in &undo.undone {
+        log::info!("undone: {:?} --> {:?}", entry.src, entry.dst);
+    }
+    for (entry, err) in &undo.errors {
+        log::error!(
+            "failed to undo {:?} --> {:?}: {}",
+            entry.src,
+            entry.dst,
+            err
+        );
+    }
+
+    undo.errors.len() as ExitCode
+}
+
+fn init_cmd(init_args: InitCmd) -> ExitCode {
+    let path = match init_args.path.or_else(dirs::default_config_path) {
+        Some(path) => path,
+        None => {
+            log::error!("failed to determine default config path, please specify one");
+            return 1;
+        }
+    };
+
+    if path.exists() && !init_args.force {
+        log::error!(
+            "config file {:?} already exists, use --force to overwrite it",
+            path
+        );
+        return 1;
+    }
+
+    if let Err(err) = config::write_config_file(&path, &init_args.args) {
+        log::error!("{}", err);
+        return 1;
+    }
+    log::info!("config file written to {:?}", path);
+
+    0
+}
+
+/// Returns the key of the source snapshot of a sort run, identifying its
+/// configuration file (or arguments), and the salt hashed with the snapshot,
+/// the content of its configuration.
+fn snapshot_salt(sort_args: &SortCmd) -> (Vec<u8>, Vec<u8>) {
+    let (mut key, mut salt) = match &sort_args.common {
+        CliOrConfigArgs::Cli(args) => {
+            let args = format!("{:?}", args).into_bytes();
+            (args.clone(), args)
+        }
+        CliOrConfigArgs::Config(args) => {
+            let path = fs::canonicalize(&args.path).unwrap_or_else(|_| args.path.clone());
+            (
+                path.into_os_string().into_encoded_bytes(),
+                fs::read(&args.path).unwrap_or_default(),
+            )
+        }
+    };
+    let filters = format!(
+        "{:?} {:?} {:?}",
+        sort_args.pattern.as_ref().map(|pattern| pattern.as_str()),
+        sort_args.since,
+        sort_args.until
+    )
+    .into_bytes();
+    key.extend(&filters);
+    salt.extend(filters);
+    (key, salt)
+}
+
+/// Returns the snapshot of sources and destination and whether it's the one
+/// of the last successful run, the snapshot is none if it can't be saved.
+fn snapshot_sources(
+    sources: &[PathBuf],
+    destination: Option<&Path>,
+    path: Option<&Path>,
+    salt: &[u8],
+) -> (Option<Snapshot>, bool) {
+    let Some(path) = path else {
+        log::warn!("state directory can't be determined, sources will be sorted");
+        return (None, false);
+    };
+
+    let snapshot = Snapshot::new(sources, salt).and_then(|snapshot| match destination {
+        Some(destination) => snapshot.with_destination(destination),
+        None => Ok(snapshot),
+    });
+    let snapshot = match snapshot {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            log::warn!(
+                "failed to snapshot sources: {}, sources will be sorted",
+                err
+            );
+            return (None, false);
+        }
+    };
+    let unchanged = match Snapshot::load(path) {
+        Ok(previous) => previous.as_ref() == Some(&snapshot),
+        Err(err) => {
+            log::warn!("failed to load source snapshot {:?}: {}", path, err);
+            false
+        }
+    };
+
+    (Some(snapshot), unchanged)
+}
+
+fn sort_cmd(sort_args: SortCmd) -> ExitCode {
+    let salt = sort_args.skip_unchanged.then(|| snapshot_salt(&sort_args));
+    let run_id = marker::run_id();
+    let report = sort_args
+        .summary_file
+        .as_ref()
+        .map(|_| Report::new(run_id.clone(), sort_args.dry_run, Progress::new()));
+    // runs ending early are summarized too.
+    let finish = |exit_code| {
+        if let (Some(report), Some(path)) = (&report, &sort_args.summary_file) {
+            write_summary(report, path, exit_code);
+        }
+        exit_code
+    };
+    let cfg = match load_config(sort_args.common, report.as_ref()) {
+        Ok(cfg) => cfg,
+        Err(exit_code) => return finish(exit_code),
+    };
+    let mut sorter = Sorter::new(cfg.sorter)
+        .with_dry_run(sort_args.dry_run)
+        .with_run_id(run_id);
+
+    if let Some(path) = sort_args.debug_context {
+        let source = cfg.sources.iter().find(|source| path.starts_with(source));
+        return debug_context(&sorter, &path, source.map(PathBuf::as_path), sort_args.json);
+    }
+
+    let _lock = match acquire_lock(
+        &sort_args.lock,
+        sort_args.dry_run,
+        Some(&sorter),
+        report.as_ref(),
+    ) {
+        Ok(lock) => lock,
+        Err(exit_code) => return finish(exit_code),
+    };
+
+    if !sort_args.dry_run {
+        if let Some(journal) = open_journal() {
+            sorter = sorter.with_journal(journal);
+        }
+    }
+
+    if let Err(err) = wait_for_mount(&sorter, sort_args.wait_for_mount) {
+        log::error!("{}", err);
+        if let Some(report) = &report {
+            report.error(None, &err);
+        }
+        return finish(1);
+    }
+
+    let destination = sorter.destination_root();
+    let snapshot_path = salt
+        .as_ref()
+        .and_then(|(key, _)| Some(dirs::state()?.snapshot_path(key)));
+    let (snapshot, unchanged) = match &salt {
+        Some((_, salt)) => snapshot_sources(
+            &cfg.sources,
+            destination.as_deref(),
+            snapshot_path.as_deref(),
+            salt,
+        ),
+        None => (None, false),
+    };
+    if unchanged {
+        log::info!("sources unchanged since the last successful run, nothing to sort");
+        return finish(0);
+    }
+
+    let mut planner = Planner::new(cfg.sources);
+    // event labels depend on dates of every planned file.
+    let events = sorter
+        .events()
+        .filter(|_| sort_args.plan.is_none())
+        .cloned();
+    if let Some(events) = &events {
+        planner = planner.with_events(events.clone(), sorter.options().clone());
+    }
+    let progress = match &report {
+        Some(report) => Some(report.progress().clone()),
+        None => sort_args.progress.then(Progress::new),
+    };
+    if let Some(progress) = &progress {
+        sorter = sorter.with_progress(progress.clone());
+        planner = planner.with_progress(progress.clone());
+    }
+    if let Some(pattern) = sort_args.pattern {
+        planner = planner.with_match(pattern);
+    }
+    if sort_args.since.is_some() || sort_args.until.is_some() {
+        planner = planner.with_date_range(DateRange {
+            since: sort_args.since,
+            until: sort_args.until,
+            options: sorter.options().clone(),
+        });
+    }
+    // resumed plannings wouldn't index dates of files before the checkpoint.
+    let checkpoint = !sort_args.dry_run && sort_args.plan.is_none() && events.is_none();
+    if let (Some(state), true) = (dirs::state(), checkpoint) {
+        planner = planner.with_checkpoint(state.checkpoint_path(), 10_000);
+    }
+    let index_path = dirs::state().map(|state| state.index_path());
+    if cfg.dedup {
+        let index = match &index_path {
+            Some(path) => match HashIndex::load(path) {
+                Ok(index) => index,
+                Err(err) => {
+                    log::warn!("{}, files will be hashed again", err);
+                    HashIndex::default()
+                }
+            },
+            None => HashIndex::default(),
+        };
+        planner = planner.with_dedup(index);
+    }
+
+    if let Some(path) = sort_args.plan {
+        let spill = match sort_args.plan_spill.then(dirs::state) {
+            Some(Some(state)) => Some(state.plan_spill_path(process::id())),
+            Some(None) => {
+                log::error!("failed to spill plan: state directory can't be determined");
+                return 1;
+            }
+            None => None,
+        };
+        return write_plan(planner, &path, spill);
+    }
+
+    let render_cache_path = dirs::state()
+        .filter(|_| !sort_args.no_render_cache)
+        .map(|state| state.render_cache_path());
+    let render_cache = render_cache_path
+        .as_ref()
+        .map(|path| match RenderCache::load(path) {
+            Ok(cache) => Arc::new(cache),
+            Err(err) => {
+                log::warn!("{}, destination paths will be rendered again", err);
+                Arc::default()
+            }
+        });
+    if let Some(cache) = &render_cache {
+        sorter = sorter.with_render_cache(cache.clone());
+    }
+
+    if let Some(path) = planner.resume_point() {
+        log::info!("planning resumed from checkpoint after {:?}", path);
+    }
+    let progress_bar = progress.filter(|_| sort_args.progress).map(|progress| {
+        log::info!("counting source files");
+        ProgressBar::spawn(progress, planner.count_files())
+    });
+    let jobs = sort_args
+        .jobs
+        .or_else(|| thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+    // files are sorted by a pool of workers while sources are walked, planned
+    // files wait in a priority queue so workers pick high priority ones first.
+    // Items are acknowledged once sorted so checkpoints never skip low
+    // priority files still waiting in the queue.
+    let (items, planning) = planner.stream(PLAN_CHANNEL_CAPACITY);
+    let queue = PriorityQueue::new(PLAN_CHANNEL_CAPACITY);
+    let exit_code: ExitCode = thread::scope(|scope| {
+        scope.spawn(|| {
+            // files are sorted once every date is indexed when using events.
+            let planned: Vec<_> = match &events {
+                Some(_) => items.iter().collect(),
+                None => Vec::new(),
+            };
+            for (item, ack) in planned.into_iter().chain(items) {
+                let priority = match &item {
+                    PlanItem::File(file) => {
+                        sorter.reserve_counter(&file.path, Some(&file.source));
+                        sorter.priority(&file.path)
+                    }
+                    _ => 0,
+                };
+                queue.push((item, ack), priority);
+            }
+            queue.close();
+        });
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut exit_code = 0;
+                    while let Some((item, ack)) = queue.pop() {
+                        exit_code += handle_plan_item(&sorter, item, report.as_ref());
+                        ack.ack();
+                    }
+                    exit_code
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("sort worker panicked"))
+            .sum()
+    });
+    let mut planner = planning.join().expect("planning thread panicked");
+    if let Some(progress_bar) = progress_bar {
+        progress_bar.finish();
+    }
+    let exit_code = finish(exit_code);
+
+    if sort_args.dry_run {
+        return exit_code;
+    }
+    if let (Some(index), Some(path)) = (planner.index_mut(), index_path) {
+        index.prune();
+        if let Err(err) = index.save(&path) {
+            log::warn!("{}", err);
+        }
+    }
+    if let (Some(cache), Some(path)) = (render_cache, render_cache_path) {
+        cache.prune();
+        if let Err(err) = cache.save(&path) {
+            log::warn!("{}", err);
+        }
+    }
+    if let (Some(snapshot), Some(path), 0) = (snapshot, snapshot_path, exit_code) {
+        // sources are snapshotted before sorting so files added meanwhile
+        // are sorted next time, the destination once files are sorted.
+        let snapshot = match destination {
+            Some(destination) => snapshot.with_destination(&destination),
+            None => Ok(snapshot),
+        };
+        if let Err(err) = snapshot.and_then(|snapshot| snapshot.save(&path)) {
+            log::warn!("failed to save source snapshot {:?}: {}", path, err);
         }
     }
 
     exit_code
 }
 
-fn sort_dir(sorter: &Sorter, src_path: &Path) -> ExitCode {
-    // create iterator
-    let dir_iter: Vec<io::Result<fs::DirEntry>> = match fs::read_dir(src_path) {
-        Ok(read_dir) => read_dir.collect(),
+/// Sorts files one after the other like the sort command into a scratch
+/// directory and prints the number of sorted files per second. Injected
+/// faults are expected to fail files, so failures only change the exit code
+/// without chaos.
+fn bench_cmd(bench_args: BenchCmd) -> ExitCode {
+    let cfg = match load_config(bench_args.common, None) {
+        Ok(cfg) => cfg,
+        Err(exit_code) => return exit_code,
+    };
+    if cfg.sorter.is_move() {
+        log::error!("bench can't move files, sources would be removed");
+        return 1;
+    }
+
+    // the library and the journal are left untouched.
+    let (scratch, created) = match bench_args.scratch {
+        Some(dir) => {
+            let created = fs::create_dir_all(&dir).map(|_| false);
+            (dir, created)
+        }
+        None => {
+            let dir = env::temp_dir().join(format!("photosort-bench-{}", process::id()));
+            let created = fs::create_dir(&dir).map(|_| true);
+            (dir, created)
+        }
+    };
+    let remove_scratch = match created {
+        Ok(remove_scratch) => remove_scratch,
         Err(err) => {
-            log::error!("failed to walk directory {:?}: {}", src_path, err);
+            log::error!("failed to create scratch directory {:?}: {}", scratch, err);
             return 1;
         }
     };
+    let cfg = config::Config {
+        sorter: cfg.sorter.with_scratch_root(&scratch.join("dst")),
+        ..cfg
+    };
 
-    let mut exit_code = 0;
+    #[cfg(feature = "chaos")]
+    let chaos = bench_args.chaos.map(|rate| {
+        let mut chaos = ChaosFs::new(std::sync::Arc::new(RealFs))
+            .with_error_rate(rate)
+            .with_latency(Duration::from_millis(
+                bench_args.chaos_latency.unwrap_or_default(),
+            ));
+        if let Some(seed) = bench_args.chaos_seed {
+            chaos = chaos.with_seed(seed);
+        }
+        std::sync::Arc::new(chaos)
+    });
+    #[cfg(feature = "chaos")]
+    let cfg = match &chaos {
+        Some(chaos) => config::Config {
+            sorter: cfg.sorter.with_fs(chaos.clone()),
+            ..cfg
+        },
+        None => cfg,
+    };
+    let mut sorter = Sorter::new(cfg.sorter);
 
-    // iterate over files in src_path
-    for dir_entry in dir_iter.into_iter().rev() {
-        match dir_entry {
-            Ok(entry) => {
-                let path = entry.path();
+    let _lock = match acquire_lock(&bench_args.lock, false, Some(&sorter), None) {
+        Ok(lock) => lock,
+        Err(exit_code) => {
+            if remove_scratch {
+                let _ = fs::remove_dir(&scratch);
+            }
+            return exit_code;
+        }
+    };
+    match Journal::open(&scratch.join("journal.jsonl")) {
+        Ok(journal) => sorter = sorter.with_journal(journal),
+        Err(err) => log::warn!("{}, replicated files won't be journaled", err),
+    }
+
+    let (mut replicated, mut skipped, mut failed) = (0, 0, 0);
+    let start = Instant::now();
+    Planner::new(cfg.sources).plan_each(|item| match item {
+        PlanItem::File(file) => match sorter.sort_source_file(&file.path, Some(&file.source)) {
+            Ok(sort::SortResult::Replicated { .. }) => replicated += 1,
+            Ok(sort::SortResult::Skipped { .. }) => skipped += 1,
+            Err(err) => {
+                log::warn!("failed to sort {:?}: {}", file.path, err);
+                failed += 1;
+            }
+        },
+        PlanItem::Duplicate(_) | PlanItem::Filtered(_) => skipped += 1,
+        PlanItem::Error(err) => {
+            log::warn!("{}", err);
+            failed += 1;
+        }
+    });
+    let elapsed = start.elapsed();
+
+    let files = replicated + skipped + failed;
+    println!(
+        "{} files in {:.2?} ({:.1} files/s): {} replicated, {} skipped, {} failed",
+        files,
+        elapsed,
+        files as f64 / elapsed.as_secs_f64(),
+        replicated,
+        skipped,
+        failed
+    );
+    if remove_scratch {
+        if let Err(err) = fs::remove_dir_all(&scratch) {
+            log::warn!("failed to remove scratch directory {:?}: {}", scratch, err);
+        }
+    }
 
-                if path.is_dir() {
-                    exit_code += sort_dir(sorter, &path);
+    #[cfg(feature = "chaos")]
+    if let Some(chaos) = chaos {
+        println!("{} faults injected", chaos.injected());
+        return 0;
+    }
+
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// Sorts media matching the patterns as they are mounted and ejects them if
+/// requested and all of their files were sorted. Runs until interrupted.
+fn import_cmd(import_args: ImportCmd) -> ExitCode {
+    let cfg = match load_config(import_args.common, None) {
+        Ok(cfg) => cfg,
+        Err(exit_code) => return exit_code,
+    };
+    let mut sorter = Sorter::new(cfg.sorter).with_dry_run(import_args.dry_run);
+    if !import_args.dry_run {
+        if let Some(journal) = open_journal() {
+            sorter = sorter.with_journal(journal);
+        }
+    }
+
+    let mut sources: Vec<GlobSource> = import_args
+        .media
+        .iter()
+        .map(|pattern| GlobSource::new(pattern))
+        .collect();
+    let interval = Duration::from_secs(import_args.interval);
+    log::info!("waiting for media matching {:?}", import_args.media);
+    loop {
+        for source in sources.iter_mut() {
+            let media = match source.poll() {
+                Ok(media) => media,
+                Err(err) => {
+                    log::warn!("failed to look for new media: {}", err);
+                    continue;
+                }
+            };
+
+            for dir in media {
+                log::info!("importing media {:?}", dir);
+                let exit_code =
+                    match acquire_lock(&import_args.lock, import_args.dry_run, Some(&sorter), None)
+                    {
+                        Ok(_lock) => {
+                            let mut exit_code = 0;
+                            let mut imported = Vec::new();
+                            let mut planner = Planner::new(vec![dir.clone()]);
+                            let mut handle = |item: PlanItem| match item {
+                                PlanItem::File(file) => {
+                                    let result =
+                                        sorter.sort_source_file(&file.path, Some(&file.source));
+                                    log_sort_result(&result, &file.path, sorter.is_dry_run());
+                                    match result {
+                                        Ok(
+                                            sort::SortResult::Replicated { replicate_path, .. }
+                                            | sort::SortResult::Skipped { replicate_path, .. },
+                                        ) => imported.push((file.path, replicate_path)),
+                                        Err(_) => exit_code += 1,
+                                    }
+                                }
+                                item => exit_code += handle_plan_item(&sorter, item, None),
+                            };
+                            match sorter.events() {
+                                Some(events) => {
+                                    // files are sorted once every date is indexed.
+                                    let mut planned = Vec::new();
+                                    planner
+                                        .with_events(events.clone(), sorter.options().clone())
+                                        .plan_each(|item| planned.push(item));
+                                    planned.into_iter().for_each(&mut handle);
+                                }
+                                None => planner.plan_each(&mut handle),
+                            }
+                            if import_args.delete_imported {
+                                delete_imported(&dir, &imported);
+                            }
+                            exit_code
+                        }
+                        Err(exit_code) => exit_code,
+                    };
+
+                if exit_code != 0 {
+                    log::error!("media {:?} wasn't fully imported, it isn't ejected", dir);
+                } else if import_args.eject {
+                    match mount::eject(&dir) {
+                        Ok(()) => log::info!("media {:?} imported and ejected", dir),
+                        Err(err) => log::error!("{}", err),
+                    }
                 } else {
-                    exit_code += sort_file(sorter, &path);
+                    log::info!("media {:?} imported", dir);
                 }
             }
+        }
+
+        thread::sleep(interval);
+    }
+}
+
+/// Deletes imported files of the given media whose destination file has the
+/// same content, other files are kept and listed.
+fn delete_imported(dir: &Path, imported: &[(PathBuf, PathBuf)]) {
+    let mut deleted = 0;
+    let mut kept = 0;
+    for (src_path, replicate_path) in imported {
+        match media::remove_verified(src_path, replicate_path) {
+            Ok(true) => deleted += 1,
+            Ok(false) => {
+                log::warn!(
+                    "{:?} kept: destination file {:?} differs or isn't a regular file",
+                    src_path,
+                    replicate_path
+                );
+                kept += 1;
+            }
             Err(err) => {
+                log::warn!(
+                    "{:?} kept: failed to verify destination file: {}",
+                    src_path,
+                    err
+                );
+                kept += 1;
+            }
+        }
+    }
+
+    log::info!(
+        "media {:?}: {} files deleted after verification, {} kept",
+        dir,
+        deleted,
+        kept
+    );
+}
+
+/// Prints files that differ between two destinations, the exit code is
+/// non-zero if any did.
+fn mirror_check_cmd(args: MirrorCheckCmd) -> ExitCode {
+    for dir in [&args.a, &args.b] {
+        if !dir.is_dir() {
+            log::error!("{:?} isn't a directory", dir);
+            return 1;
+        }
+    }
+
+    let summary = mirror::check(&args.a, &args.b, args.hash, |result| match result {
+        Ok(drift) if args.json => match serde_json::to_string(&drift) {
+            Ok(line) => println!("{}", line),
+            Err(err) => log::error!("failed to serialize drift: {}", err),
+        },
+        Ok(drift) => println!("{}", drift),
+        Err(err) => log::error!("{}", err),
+    });
+    log::info!(
+        "{} files compared, {} drifted, {} errors",
+        summary.compared,
+        summary.drifted,
+        summary.errors
+    );
+
+    if summary.drifted > 0 || summary.errors > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// Writes the plan as JSON lines to the given file (`-` for stdout) instead
+/// of sorting files, lines are written as sources are walked or to a
+/// temporary file copied once planning is done if `spill` is set.
+fn write_plan(mut planner: Planner, path: &Path, spill: Option<PathBuf>) -> ExitCode {
+    #[derive(Serialize)]
+    #[serde(rename_all = "snake_case")]
+    enum PlanLine<'a> {
+        File(&'a PlannedFile),
+        Duplicate(&'a Duplicate),
+        Filtered(&'a Path),
+    }
+
+    let out_path = spill.as_deref().unwrap_or(path);
+    let out = match &spill {
+        Some(spill) => spill_output(spill),
+        None => plan_output(path),
+    };
+    let mut out = match out {
+        Ok(out) => out,
+        Err(err) => {
+            log::error!("failed to create plan file {:?}: {}", out_path, err);
+            return 1;
+        }
+    };
+
+    let mut exit_code = 0;
+    planner.plan_each(|item| {
+        let line = match &item {
+            PlanItem::File(file) => PlanLine::File(file),
+            PlanItem::Duplicate(duplicate) => PlanLine::Duplicate(duplicate),
+            PlanItem::Filtered(path) => PlanLine::Filtered(path),
+            PlanItem::Error(err) => {
+                log::error!("{}", err);
                 exit_code += 1;
-                log::error!("failed to walk directory {:?}: {}", src_path, err);
+                return;
             }
+        };
+        let written = serde_json::to_writer(&mut out, &line)
+            .map_err(io::Error::from)
+            .and_then(|_| out.write_all(b"\n"));
+        if let Err(err) = written {
+            log::error!("failed to write plan file {:?}: {}", out_path, err);
+            exit_code += 1;
+        }
+    });
+
+    if let Err(err) = out.flush() {
+        log::error!("failed to write plan file {:?}: {}", out_path, err);
+        exit_code += 1;
+    }
+    drop(out);
+
+    if let Some(spill) = spill {
+        let copied = fs::File::open(&spill).and_then(|mut spilled| {
+            let mut out = plan_output(path)?;
+            io::copy(&mut spilled, &mut out)?;
+            out.flush()
+        });
+        if let Err(err) = copied {
+            log::error!("failed to write plan file {:?}: {}", path, err);
+            exit_code += 1;
+        }
+        if let Err(err) = fs::remove_file(&spill) {
+            log::warn!("failed to remove spilled plan {:?}: {}", spill, err);
         }
     }
 
     exit_code
 }
 
-fn sort_file(sorter: &Sorter, src_path: &Path) -> ExitCode {
-    let abs_path = match fs::canonicalize(src_path) {
-        Ok(path) => path,
+/// Opens the given plan file, `-` for stdout.
+fn plan_output(path: &Path) -> io::Result<Box<dyn Write>> {
+    if path == Path::new("-") {
+        return Ok(Box::new(io::stdout().lock()));
+    }
+
+    Ok(Box::new(io::BufWriter::new(fs::File::create(path)?)))
+}
+
+/// Creates the file the plan is spilled to, failing if it already exists so
+/// the plan of another run is never overwritten.
+fn spill_output(path: &Path) -> io::Result<Box<dyn Write>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    Ok(Box::new(io::BufWriter::new(file)))
+}
+
+/// Prints every built-in variable of the given file of the given source along
+/// with its rendered value or error.
+fn debug_context(sorter: &Sorter, path: &Path, source: Option<&Path>, json: bool) -> ExitCode {
+    let ctx = match sorter.source_context(path, source) {
+        Ok(ctx) => ctx,
         Err(err) => {
-            log::error!("failed to canonicalize source path {:?}: {}", src_path, err);
+            log::error!("{}", err);
             return 1;
         }
     };
+    let resolved = variables::resolve_all(&ctx);
 
-    let result = sorter.sort_file(&abs_path);
-    log_sort_result(&result, &abs_path);
-    if result.is_err() {
-        1
+    if json {
+        let variables: serde_json::Map<String, serde_json::Value> = resolved
+            .into_iter()
+            .map(|r| {
+                let mut value = json_result(&r.value);
+                if !r.sources.is_empty() {
+                    let sources = r
+                        .sources
+                        .iter()
+                        .map(|source| {
+                            let mut value = json_result(&source.value);
+                            value["name"] = source.name.clone().into();
+                            value
+                        })
+                        .collect();
+                    value["sources"] = serde_json::Value::Array(sources);
+                }
+                (r.name.to_owned(), value)
+            })
+            .collect();
+        println!("{}", serde_json::Value::Object(variables));
     } else {
-        0
+        let width = resolved.iter().map(|r| r.name.len()).max().unwrap_or(0);
+        for r in resolved {
+            match r.value {
+                Ok(value) => println!("{:width$}  {}", r.name, value),
+                Err(err) => println!("{:width$}  error: {}", r.name, err),
+            }
+            // fallback chain of composed variables.
+            for source in r.sources {
+                match source.value {
+                    Ok(value) => println!("{:width$}    <- {}: {}", "", source.name, value),
+                    Err(err) => println!("{:width$}    <- {}: error: {}", "", source.name, err),
+                }
+            }
+        }
+    }
+
+    0
+}
+
+fn json_result(result: &context::Result) -> serde_json::Value {
+    match result {
+        Ok(value) => serde_json::json!({ "value": value.to_string() }),
+        Err(err) => serde_json::json!({ "error": err.to_string() }),
+    }
+}
+
+/// Sorts a planned file, errors are recorded in the report if any.
+fn handle_plan_item(sorter: &Sorter, item: PlanItem, report: Option<&Report>) -> ExitCode {
+    match item {
+        PlanItem::File(file) => sort_file(sorter, &file.path, &file.source, report),
+        PlanItem::Duplicate(duplicate) => {
+            log::info!(
+                "duplicate skipped: {:?} has the same content as {:?}",
+                duplicate.path,
+                duplicate.original
+            );
+            0
+        }
+        PlanItem::Filtered(path) => {
+            log::debug!("file filtered: {:?}", path);
+            0
+        }
+        PlanItem::Error(err) => {
+            log::error!("{}", err);
+            if let Some(report) = report {
+                report.error(None, &err);
+            }
+            1
+        }
+    }
+}
+
+fn sort_file(sorter: &Sorter, src_path: &Path, source: &Path, report: Option<&Report>) -> ExitCode {
+    let result = sorter.sort_source_file(src_path, Some(source));
+    log_sort_result(&result, src_path, sorter.is_dry_run());
+    match (&result, report) {
+        (Err(err), Some(report)) => report.error(Some(src_path), err),
+        (Err(_), None) => {}
+        (Ok(_), _) => return 0,
+    }
+    1
+}
+
+/// Writes the summary of a finished run, failures are only logged as the
+/// run is over.
+fn write_summary(report: &Report, path: &Path, exit_code: ExitCode) {
+    if let Err(err) = report.summary(exit_code).write(path) {
+        log::error!("failed to write summary file {:?}: {}", path, err);
     }
 }
 
 fn watch_cmd(watch_args: WatchCmd) -> ExitCode {
+    let run_id = marker::run_id();
+    let report = watch_args
+        .summary_file
+        .as_ref()
+        .map(|_| Report::new(run_id.clone(), watch_args.dry_run, Progress::new()));
+    // runs ending early are summarized too.
+    let finish = |exit_code| {
+        if let (Some(report), Some(path)) = (&report, &watch_args.summary_file) {
+            write_summary(report, path, exit_code);
+        }
+        exit_code
+    };
     if watch_args.daemon {
         log::debug!("starting daemon process");
-        match Daemonize::new()
-            .exit_action(|| log::info!("daemon process successfully started"))
-            .start()
-        {
+        let mut daemonize =
+            Daemonize::new().exit_action(|| log::info!("daemon process successfully started"));
+        if let Some(state) = dirs::state() {
+            daemonize = daemonize.pid_file(state.pid_path());
+        }
+        match daemonize.start() {
             Ok(_) => {}
             Err(err) => {
                 log::error!("an error occurred while daemonzing the process: {}", err);
-                return 1;
+                if let Some(report) = &report {
+                    report.error(None, &err);
+                }
+                return finish(1);
             }
         }
         log::info!("daemon process started");
     }
-    let cfg = match watch_args.common {
-        CliOrConfigArgs::Cli(args) => {
-            log::debug!("setting up config...");
-            let cfg = config::Watch::from(args);
-            log::debug!("config successfully setted up");
+    let cfg = match load_config(watch_args.common, report.as_ref()) {
+        Ok(cfg) => cfg,
+        Err(exit_code) => return finish(exit_code),
+    };
+    log::info!(
+        "photosort {} (config schema {}, state layout {}, config min_version {})",
+        env!("CARGO_PKG_VERSION"),
+        config::SCHEMA_VERSION,
+        state::VERSION,
+        cfg.min_version.as_deref().unwrap_or("unset")
+    );
 
-            cfg
-        }
-        CliOrConfigArgs::Config(args) => {
-            log::debug!("reading config file...");
-            let cfg_str = match fs::read_to_string(&args.path) {
-                Ok(cfg_str) => cfg_str,
-                Err(err) => {
-                    log::error!("failed to read config file {:?}: {}", args.path, err);
-                    return 1;
-                }
-            };
-            log::debug!("config file successfully read");
-            log::debug!("deserializing config file...");
-            let cfg = match toml::from_str(&cfg_str) {
-                Ok(cfg) => cfg,
-                Err(err) => {
-                    log::error!("failed to deserialize config file: {}", err);
-                    return 1;
-                }
-            };
-            log::debug!("config file successfully deserialized");
+    let drain = if watch_args.once {
+        Some(DrainOptions {
+            idle_timeout: Duration::from_secs(watch_args.idle_timeout),
+            max_duration: watch_args.max_duration.map(Duration::from_secs),
+        })
+    } else {
+        None
+    };
 
-            cfg
-        }
+    let mut sorter = Sorter::new(cfg.sorter)
+        .with_dry_run(watch_args.dry_run)
+        .with_run_id(run_id);
+    let _lock = match acquire_lock(
+        &watch_args.lock,
+        watch_args.dry_run,
+        Some(&sorter),
+        report.as_ref(),
+    ) {
+        Ok(lock) => lock,
+        Err(exit_code) => return finish(exit_code),
     };
+    if !watch_args.dry_run {
+        if let Some(journal) = open_journal() {
+            sorter = sorter.with_journal(journal);
+        }
+    }
+    if let Some(report) = &report {
+        sorter = sorter.with_progress(report.progress().clone());
+    }
 
-    let result = EventWatcher::start(cfg, log_result);
+    sorter.index_events(&cfg.sources);
+    let dry_run = watch_args.dry_run;
+    let result = EventWatcher::new(sorter, cfg.sources)
+        .with_ignore_regex(cfg.ignore_regex)
+        .with_drain(drain)
+        .with_wait_for_mount(watch_args.wait_for_mount)
+        .with_create_root(watch_args.create_dest_root)
+        .start(|result| log_result(result, dry_run, report.as_ref()));
 
-    match result {
-        Ok(_) => {}
+    let exit_code = match result {
+        Ok(_) => 0,
         Err(err) => {
             log::error!("failed to start event watcher: {}", err);
-            return 1;
+            if let Some(report) = &report {
+                report.error(None, &err);
+            }
+            1
         }
-    }
+    };
+    finish(exit_code)
+}
 
-    0
+/// Checks that destination filesystem is mounted, waiting for it if `wait`
+/// is true.
+fn wait_for_mount(sorter: &Sorter, wait: bool) -> Result<(), SortError> {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60));
+    loop {
+        match sorter.check_mount() {
+            Err(SortError::NotMounted(mount)) if wait => {
+                let delay = backoff.next_delay();
+                log::info!(
+                    "waiting for {} to be mounted (next check in {:?})",
+                    mount,
+                    delay
+                );
+                thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
 }
 
-fn log_result(result: Result<EventHandlerResult, EventHandlerError>) {
+/// Logs the result of handling an event, errors are recorded in the report
+/// if any.
+fn log_result(
+    result: Result<EventHandlerResult, EventHandlerError>,
+    dry_run: bool,
+    report: Option<&Report>,
+) {
     match result {
         Ok(res) => match res {
             EventHandlerResult::Filtered(reason) => log_filtered(reason),
-            EventHandlerResult::Sort(src_path, result) => log_sort_result(&result, &src_path),
+            EventHandlerResult::Sort(src_path, result) => {
+                log_sort_result(&result, &src_path, dry_run);
+                if let (Err(err), Some(report)) = (&result, report) {
+                    report.error(Some(&src_path), err);
+                }
+            }
             EventHandlerResult::Ignored(event) => log::debug!("ignored event: {:?}", event),
         },
         Err(err) => match err {
             EventHandlerError::RetrieveEvent(err) => {
-                log::error!("failed to retrieve fs event: {}", err)
+                log::error!("failed to retrieve fs event: {}", err);
+                if let Some(report) = report {
+                    report.error(None, &err);
+                }
             }
         },
     }
@@ -201,41 +1254,54 @@ fn log_filtered(reason: FilterReason) {
     }
 }
 
-fn log_sort_result(result: &sort::Result, src_path: &Path) {
+/// Logs a sorted file.
+fn log_sorted(sort_result: &sort::SortResult, src_path: &Path, prefix: &str) {
+    match sort_result {
+        sort::SortResult::Skipped {
+            replicate_path,
+            reason,
+        } => {
+            let level = match reason {
+                sort::SkippedReason::Overwrite => log::Level::Warn,
+                sort::SkippedReason::SameFile
+                | sort::SkippedReason::AlreadySorted
+                | sort::SkippedReason::Duplicate
+                | sort::SkippedReason::Filtered
+                | sort::SkippedReason::Sidecar
+                | sort::SkippedReason::Paired => log::Level::Info,
+                sort::SkippedReason::NonUtf8 => log::Level::Warn,
+            };
+            log::log!(
+                level,
+                "{}{:?} -x- {:?}, skipped because: {}",
+                prefix,
+                src_path,
+                replicate_path,
+                reason
+            )
+        }
+        sort::SortResult::Replicated {
+            replicate_path,
+            overwrite,
+        } => {
+            log::info!(
+                "{}file sorted: {:?} --> {:?} (overwrite: {:?})",
+                prefix,
+                src_path,
+                replicate_path,
+                overwrite
+            )
+        }
+    }
+}
+
+/// Logs the result of sorting a file, results of a dry run are prefixed.
+fn log_sort_result(result: &sort::Result, src_path: &Path, dry_run: bool) {
     log::debug!("{:?}: {:?}", src_path, result);
+    let prefix = if dry_run { "[dry run] " } else { "" };
 
     match result {
-        Ok(sort_result) => {
-            match sort_result {
-                sort::SortResult::Skipped {
-                    replicate_path,
-                    reason,
-                } => {
-                    let level = match reason {
-                        sort::SkippedReason::Overwrite => log::Level::Warn,
-                        sort::SkippedReason::SameFile => log::Level::Info,
-                    };
-                    log::log!(
-                        level,
-                        "{:?} -x- {:?}, skipped because: {}",
-                        src_path,
-                        replicate_path,
-                        reason
-                    )
-                }
-                sort::SortResult::Replicated {
-                    replicate_path,
-                    overwrite,
-                } => {
-                    log::info!(
-                        "file sorted: {:?} --> {:?} (overwrite: {:?})",
-                        src_path,
-                        replicate_path,
-                        overwrite
-                    )
-                }
-            };
-        }
+        Ok(sort_result) => log_sorted(sort_result, src_path, prefix),
         Err(err) => {
             match err {
                 SortError::TemplateError(err) => {
@@ -244,11 +1310,114 @@ fn log_sort_result(result: &sort::Result, src_path: &Path) {
                 SortError::TemplateContextError(err) => {
                     log::error!("{:?} -x- ???: {}", src_path, err);
                 }
+                SortError::NotMounted(_)
+                | SortError::MountCheckError(_, _)
+                | SortError::MirrorWithoutRoot
+                | SortError::NonUtf8Path(_)
+                | SortError::OutsideRoot(_, _)
+                | SortError::RootTooLong(_, _) => {
+                    log::error!("{:?} -x- ???: {}", src_path, err);
+                }
+                SortError::FilterError(_, _) | SortError::PlanError(_, _) => {
+                    log::error!("{:?} -x- ???: {}", src_path, err);
+                }
+                SortError::HookError(_, _, sort_result) => {
+                    log_sorted(sort_result, src_path, prefix);
+                    log::error!("{:?}: {}", src_path, err);
+                }
+                SortError::JournalError(_)
+                | SortError::RemoveSourceError(_, _)
+                | SortError::StreamsError(_, _)
+                | SortError::ThumbnailError(_, _) => {
+                    log::error!("{:?}: {}", src_path, err);
+                }
                 SortError::ReplicateError(err, replicate_path)
-                | SortError::OverwriteError(err, replicate_path) => {
+                | SortError::OverwriteError(err, replicate_path)
+                | SortError::MarkError(err, replicate_path)
+                | SortError::PermissionsError(err, replicate_path)
+                | SortError::CompareError(err, replicate_path) => {
+                    log::error!("{:?} -x- {:?}: {}", src_path, replicate_path, err);
+                }
+                SortError::ConflictError(err, replicate_path) => {
+                    log::error!("{:?} -x- {:?}: {}", src_path, replicate_path, err);
+                }
+                SortError::UnverifiedReplicate(replicate_path) => {
                     log::error!("{:?} -x- {:?}: {}", src_path, replicate_path, err);
                 }
             };
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use clap::Parser;
+    use photosort::plan::Planner;
+    use uuid::Uuid;
+
+    use super::write_plan;
+    use crate::args::{Cli, Command};
+
+    #[test]
+    fn plan_spill() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/a.jpg"), "a").unwrap();
+        let planner = || Planner::new(vec![root.join("src")]);
+
+        let spill = root.join("queues/plan.jsonl");
+        assert_eq!(write_plan(planner(), &root.join("plan.jsonl"), None), 0);
+        assert_eq!(
+            write_plan(planner(), &root.join("spilled.jsonl"), Some(spill.clone())),
+            0
+        );
+        let plan = fs::read_to_string(root.join("plan.jsonl")).unwrap();
+        assert_eq!(plan.lines().count(), 1);
+        assert_eq!(
+            fs::read_to_string(root.join("spilled.jsonl")).unwrap(),
+            plan
+        );
+        // spilled plan is removed once written.
+        assert!(!spill.exists());
+
+        // an existing spill file is never overwritten.
+        fs::write(&spill, "other").unwrap();
+        assert_eq!(
+            write_plan(planner(), &root.join("other.jsonl"), Some(spill.clone())),
+            1
+        );
+        assert_eq!(fs::read_to_string(&spill).unwrap(), "other");
+        assert!(!root.join("other.jsonl").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn summary_of_failed_config() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        let summary = root.join("summary.json");
+        let config = root.join("missing.toml");
+        let args = [
+            "photosort".as_ref(),
+            "sort".as_ref(),
+            "--config".as_ref(),
+            config.as_os_str(),
+            "--summary-file".as_ref(),
+            summary.as_os_str(),
+        ];
+        let Command::Sort(sort_args) = Cli::parse_from(args).command else {
+            unreachable!()
+        };
+
+        assert_eq!(super::sort_cmd(sort_args), 1);
+        let summary: serde_json::Value =
+            serde_json::from_slice(&fs::read(&summary).unwrap()).unwrap();
+        assert_eq!(summary["exit_code"], 1);
+        assert_eq!(summary["errors"].as_array().unwrap().len(), 1);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use photosort::progress::{Progress, ProgressEvent};
+
+/// Delay between two redraws of the progress line.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// ProgressBar draws the progress of a sort on stderr until it's finished:
+/// handled files out of the total, the rate and the estimated time left.
+pub struct ProgressBar {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl ProgressBar {
+    /// Spawns a thread drawing the given progress, `total` is the number of
+    /// files expected to be discovered, filtered files included.
+    pub fn spawn(progress: Progress, total: usize) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            // the line is redrawn in place on terminals, written every
+            // redraw otherwise would flood logs.
+            let terminal = io::stderr().is_terminal();
+            loop {
+                let finished = !matches!(
+                    stopped.recv_timeout(REDRAW_INTERVAL),
+                    Err(RecvTimeoutError::Timeout)
+                );
+                if terminal || finished {
+                    let line = progress_line(&progress.snapshot(), total, start.elapsed());
+                    let mut stderr = io::stderr().lock();
+                    if terminal {
+                        let _ = write!(stderr, "\r\x1b[2K");
+                    }
+                    let _ = write!(stderr, "{}", line);
+                    let _ = stderr.flush();
+                }
+                if finished {
+                    let _ = writeln!(io::stderr());
+                    return;
+                }
+            }
+        });
+
+        Self { stop, handle }
+    }
+
+    /// Draws the final progress line and stops the drawing thread.
+    pub fn finish(self) {
+        let _ = self.stop.send(());
+        self.handle.join().expect("progress bar thread panicked");
+    }
+}
+
+fn progress_line(event: &ProgressEvent, total: usize, elapsed: Duration) -> String {
+    // filtered files are left out of the total as they're planned.
+    let handled = event.replicated + event.skipped + event.failed - event.filtered;
+    // files added since sources were counted.
+    let total = total
+        .saturating_sub(event.filtered)
+        .max(event.discovered - event.filtered)
+        .max(handled);
+    let percent = match total {
+        0 => 100.0,
+        total => handled as f64 * 100.0 / total as f64,
+    };
+    let rate = handled as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+    let eta = match (total - handled, rate > 0.0) {
+        (0, _) => "0s".to_owned(),
+        (left, true) => format_duration(Duration::from_secs_f64(left as f64 / rate)),
+        (_, false) => "-".to_owned(),
+    };
+
+    format!(
+        "{}/{} files ({:.1}%), {:.1} files/s, {} failed, ETA {}",
+        handled, total, percent, rate, event.failed, eta
+    )
+}
+
+/// Formats the given duration as `1h02m03s`, `2m03s` or `3s`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, mins, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    match (hours, mins) {
+        (0, 0) => format!("{}s", secs),
+        (0, mins) => format!("{}m{:02}s", mins, secs),
+        (hours, mins) => format!("{}h{:02}m{:02}s", hours, mins, secs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use photosort::progress::ProgressEvent;
+
+    use super::{format_duration, progress_line};
+
+    #[test]
+    fn line() {
+        let event = ProgressEvent::default();
+        assert_eq!(
+            progress_line(&event, 0, Duration::ZERO),
+            "0/0 files (100.0%), 0.0 files/s, 0 failed, ETA 0s"
+        );
+        // nothing handled yet, the rate is unknown.
+        assert_eq!(
+            progress_line(&event, 10, Duration::from_secs(2)),
+            "0/10 files (0.0%), 0.0 files/s, 0 failed, ETA -"
+        );
+
+        let event = ProgressEvent {
+            discovered: 12,
+            planned: 10,
+            replicated: 4,
+            skipped: 3,
+            failed: 1,
+            filtered: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            progress_line(&event, 22, Duration::from_secs(2)),
+            "6/20 files (30.0%), 3.0 files/s, 1 failed, ETA 4s"
+        );
+        // files added since sources were counted.
+        assert_eq!(
+            progress_line(&event, 5, Duration::from_secs(2)),
+            "6/10 files (60.0%), 3.0 files/s, 1 failed, ETA 1s"
+        );
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(format_duration(Duration::from_millis(3500)), "3s");
+        assert_eq!(format_duration(Duration::from_secs(123)), "2m03s");
+        assert_eq!(format_duration(Duration::from_secs(3600)), "1h00m00s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h02m03s");
+    }
+}
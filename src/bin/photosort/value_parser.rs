@@ -3,6 +3,8 @@ use std::str::FromStr;
 use clap::builder::TypedValueParser;
 use clap::error::ErrorKind;
 
+use photosort::sort;
+use photosort::template::context;
 use photosort::template::Template;
 
 #[derive(Clone, Default)]
@@ -32,3 +34,30 @@ impl TypedValueParser for TemplateParser {
         }
     }
 }
+
+/// Validates a locale name, the name is kept as is so it can be written back
+/// to a config file.
+pub fn parse_locale(value: &str) -> Result<String, String> {
+    match context::parse_locale(value) {
+        Ok(_) => Ok(value.to_owned()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Parses a max component length, lengths too short for the hash of
+/// shortened names are rejected.
+pub fn parse_max_component_length(value: &str) -> Result<usize, String> {
+    let max_len = value.parse::<usize>().map_err(|err| err.to_string())?;
+    sort::check_max_component_length(max_len)
+}
+
+/// Parses a `KEY=VALUE` user variable.
+pub fn parse_variable(value: &str) -> Result<(String, String), String> {
+    let (name, value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("invalid variable {:?}, expected KEY=VALUE", value))?;
+    context::parse_variables([(name.to_owned(), value.to_owned())])
+        .map_err(|err| err.to_string())?;
+
+    Ok((name.to_owned(), value.to_owned()))
+}
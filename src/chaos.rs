@@ -0,0 +1,248 @@
+//! Fault injection, enabled with the `chaos` feature.
+//!
+//! [`ChaosFs`] wraps a filesystem and randomly fails or delays its
+//! operations, to check that sorting survives IO errors: failed files are
+//! reported and can be sorted again, and neither source nor destination
+//! files are lost.
+
+use std::collections::hash_map::RandomState;
+use std::fmt::Debug;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::fs::{Fs, Metadata};
+
+/// Kinds of injected errors.
+const FAULTS: &[io::ErrorKind] = &[
+    io::ErrorKind::PermissionDenied,
+    io::ErrorKind::StorageFull,
+    io::ErrorKind::TimedOut,
+    io::ErrorKind::Interrupted,
+    io::ErrorKind::CrossesDevices,
+];
+
+/// ChaosFs fails operations of the wrapped filesystem with the given
+/// probability and delays them by the given latency.
+///
+/// Metadata reads are delayed but never fail: a failed existence check would
+/// let the sorter overwrite destination files, which real filesystems don't
+/// do either.
+#[derive(Debug)]
+pub struct ChaosFs {
+    inner: Arc<dyn Fs>,
+    error_rate: f64,
+    latency: Duration,
+    /// State of the splitmix64 generator deciding which operations fail.
+    state: Mutex<u64>,
+    injected: AtomicUsize,
+}
+
+impl ChaosFs {
+    /// Wraps the given filesystem without injecting faults, the generator
+    /// is randomly seeded.
+    pub fn new(inner: Arc<dyn Fs>) -> Self {
+        Self {
+            inner,
+            error_rate: 0.0,
+            latency: Duration::ZERO,
+            state: Mutex::new(RandomState::new().build_hasher().finish()),
+            injected: AtomicUsize::new(0),
+        }
+    }
+
+    /// Fails operations with the given probability, between 0 and 1.
+    pub fn with_error_rate(mut self, error_rate: f64) -> Self {
+        self.error_rate = error_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Delays every operation by the given duration.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Seeds the generator so the same operations fail on every run.
+    pub fn with_seed(self, seed: u64) -> Self {
+        *self.state.lock().unwrap() = seed;
+        self
+    }
+
+    /// Returns the number of injected errors.
+    pub fn injected(&self) -> usize {
+        self.injected.load(Ordering::Relaxed)
+    }
+
+    fn next(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn delay(&self) {
+        if !self.latency.is_zero() {
+            thread::sleep(self.latency);
+        }
+    }
+
+    /// Delays the given operation and returns an error if it must fail.
+    fn chaos(&self, op: &str, path: &Path) -> io::Result<()> {
+        self.delay();
+
+        let n = self.next();
+        // 53 bits uniform float in [0, 1).
+        if ((n >> 11) as f64 / (1u64 << 53) as f64) < self.error_rate {
+            self.injected.fetch_add(1, Ordering::Relaxed);
+            let kind = FAULTS[(n % FAULTS.len() as u64) as usize];
+            return Err(io::Error::new(
+                kind,
+                format!("injected {:?} fault on {:?}", op, path),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Fs for ChaosFs {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.delay();
+        self.inner.metadata(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.delay();
+        self.inner.symlink_metadata(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        self.chaos("open", path)?;
+        self.inner.open(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        self.chaos("read_dir", path)?;
+        self.inner.read_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.chaos("create_dir_all", path)?;
+        self.inner.create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.chaos("remove_file", path)?;
+        self.inner.remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.chaos("remove_dir", path)?;
+        self.inner.remove_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.chaos("remove_dir_all", path)?;
+        self.inner.remove_dir_all(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.chaos("rename", to)?;
+        self.inner.rename(from, to)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        self.chaos("copy", to)?;
+        self.inner.copy(from, to)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        self.chaos("hard_link", link)?;
+        self.inner.hard_link(original, link)
+    }
+
+    fn symlink_file(&self, original: &Path, link: &Path) -> io::Result<()> {
+        self.chaos("symlink_file", link)?;
+        self.inner.symlink_file(original, link)
+    }
+
+    fn same_file(&self, a: &Path, b: &Path) -> bool {
+        self.delay();
+        self.inner.same_file(a, b)
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        self.chaos("set_mode", path)?;
+        self.inner.set_mode(path, mode)
+    }
+
+    fn chown(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        self.chaos("chown", path)?;
+        self.inner.chown(path, uid, gid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use crate::fs::{Fs, MemFs};
+    use crate::replicator::CopyReplicator;
+    use crate::sort::{Config, Sorter};
+    use crate::template::Template;
+
+    use super::ChaosFs;
+
+    #[test]
+    fn sort_survives_faults() {
+        let mut mem = MemFs::new();
+        for i in 0..100 {
+            mem = mem.with_file(format!("/src/{}.jpg", i), format!("{}", i));
+        }
+        let mem = Arc::new(mem.with_file("/dst/0.jpg", "0"));
+        let chaos = Arc::new(ChaosFs::new(mem.clone()).with_error_rate(0.3).with_seed(42));
+        let sorter = Sorter::new(
+            Config::new(
+                Template::from_str("/dst/:file.name:").unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_move(true)
+            .with_fs(chaos.clone()),
+        );
+
+        // failed files are sorted again, no file is ever lost.
+        for _ in 0..10 {
+            for i in 0..100 {
+                let src = format!("/src/{}.jpg", i);
+                let dst = format!("/dst/{}.jpg", i);
+                if mem.exists(src.as_ref()) {
+                    let _ = sorter.replicate_file(src.as_ref(), dst.into(), Some("/src".as_ref()));
+                }
+            }
+        }
+        assert!(chaos.injected() > 0);
+
+        let mut moved = 0;
+        for i in 0..100 {
+            let src = format!("/src/{}.jpg", i);
+            let dst = format!("/dst/{}.jpg", i);
+            let content = i.to_string().into_bytes();
+            if mem.exists(dst.as_ref()) {
+                assert_eq!(mem.read(dst.as_ref()).unwrap(), content);
+                moved += 1;
+            } else {
+                assert_eq!(mem.read(src.as_ref()).unwrap(), content);
+            }
+        }
+        assert!(moved > 50);
+    }
+}
@@ -0,0 +1,101 @@
+//! Handling of file paths that aren't valid UTF-8, e.g. names written in a
+//! legacy encoding (Latin-1) by old cameras and operating systems.
+//!
+//! Regular expressions [match](is_match) the bytes of such names and names
+//! are rendered as is, unless they're [transliterated](transliterate). Dates
+//! and other metadata are parsed from transliterated names.
+
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// NonUtf8Policy define how files whose path isn't valid UTF-8 are sorted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum NonUtf8Policy {
+    /// Sort the file, names are rendered as is.
+    #[default]
+    Keep,
+    /// Log a warning and sort the file, invalid bytes of names are decoded
+    /// as Latin-1 so `caf\xe9` and `café` are rendered alike.
+    Transliterate,
+    /// Skip the file.
+    Skip,
+    /// Fail to sort the file.
+    Error,
+}
+
+/// Returns the given string as UTF-8, invalid bytes are decoded as Latin-1
+/// (`caf\xe9` becomes `café`).
+pub fn transliterate(s: &OsStr) -> Cow<'_, str> {
+    if let Some(s) = s.to_str() {
+        return Cow::Borrowed(s);
+    }
+
+    let mut transliterated = String::new();
+    for chunk in s.as_encoded_bytes().utf8_chunks() {
+        transliterated.push_str(chunk.valid());
+        transliterated.extend(chunk.invalid().iter().map(|&b| char::from(b)));
+    }
+    Cow::Owned(transliterated)
+}
+
+/// Returns whether the regular expression matches the given string, the
+/// bytes of strings that aren't valid UTF-8 are matched.
+pub fn is_match(regex: &Regex, s: &OsStr) -> bool {
+    match s.to_str() {
+        Some(s) => regex.is_match(s),
+        None => regex::bytes::Regex::new(regex.as_str())
+            .is_ok_and(|regex| regex.is_match(s.as_encoded_bytes())),
+    }
+}
+
+/// Returns the string of bytes matched in a string that isn't valid UTF-8,
+/// they're decoded lossily on platforms whose strings aren't bytes.
+pub fn os_string(bytes: &[u8]) -> OsString {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        OsStr::from_bytes(bytes).to_owned()
+    }
+    #[cfg(not(unix))]
+    {
+        String::from_utf8_lossy(bytes).into_owned().into()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    use regex::Regex;
+
+    use super::{is_match, transliterate};
+
+    #[test]
+    fn transliterate_latin1() {
+        assert_eq!(transliterate(OsStr::new("été.jpg")), "été.jpg");
+        assert_eq!(
+            transliterate(OsStr::from_bytes(b"caf\xe9-\xc3\xa9t\xe9.jpg")),
+            "café-été.jpg"
+        );
+    }
+
+    #[test]
+    fn match_bytes() {
+        let name = OsStr::from_bytes(b"caf\xe9.jpg");
+        assert!(is_match(&Regex::new(r"^caf").unwrap(), name));
+        assert!(is_match(&Regex::new(r"\.jpg$").unwrap(), name));
+        assert!(!is_match(&Regex::new(r"^caf.\.jpg$").unwrap(), name));
+        assert!(!is_match(&Regex::new(r"^caf[^a]\.jpg$").unwrap(), name));
+        assert!(!is_match(&Regex::new("café").unwrap(), name));
+        assert!(is_match(
+            &Regex::new("café").unwrap(),
+            OsStr::new("café.jpg")
+        ));
+    }
+}
@@ -0,0 +1,135 @@
+//! Clustering of files into events (holidays, shoots) by capture time: files
+//! whose dates are less than a gap apart belong to the same event.
+//!
+//! Labels depend on other files, so the planner indexes dates of every
+//! planned file before files are sorted. Files added later (watch mode) are
+//! indexed as they're sorted, they may join or merge events but files already
+//! sorted keep their label.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::RwLock;
+
+use chrono::{Duration, NaiveDateTime};
+
+/// Events indexes the dates of files and labels events.
+#[derive(Debug)]
+pub struct Events {
+    gap: Duration,
+    index: RwLock<Index>,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    dates: BTreeSet<NaiveDateTime>,
+    /// First date of every event with its number among events started the
+    /// same day, computed again once dates change.
+    starts: Option<BTreeMap<NaiveDateTime, usize>>,
+}
+
+impl Events {
+    pub fn new(gap: Duration) -> Self {
+        Self {
+            gap,
+            index: RwLock::default(),
+        }
+    }
+
+    pub fn insert(&self, date: NaiveDateTime) {
+        if self.index.read().unwrap().dates.contains(&date) {
+            return;
+        }
+
+        let mut index = self.index.write().unwrap();
+        if index.dates.insert(date) {
+            index.starts = None;
+        }
+    }
+
+    /// Returns the label of the event of the given date (e.g.
+    /// `2023-07-14_event-03`): the day the event started and the number of
+    /// the event among events started that day. The date must be indexed.
+    pub fn label(&self, date: NaiveDateTime) -> String {
+        if let Some(starts) = &self.index.read().unwrap().starts {
+            return Self::format(starts, date);
+        }
+
+        let mut index = self.index.write().unwrap();
+        let Index { dates, starts } = &mut *index;
+        let starts = starts.get_or_insert_with(|| self.starts(dates));
+        Self::format(starts, date)
+    }
+
+    /// Returns the first date of every event in a single pass over the
+    /// dates, with the number of the event among events started that day.
+    fn starts(&self, dates: &BTreeSet<NaiveDateTime>) -> BTreeMap<NaiveDateTime, usize> {
+        let mut starts = BTreeMap::new();
+        // last date with the start and number of its event.
+        let mut last: Option<(NaiveDateTime, NaiveDateTime, usize)> = None;
+        for &date in dates {
+            match last {
+                Some((previous, start, number)) if date - previous <= self.gap => {
+                    last = Some((date, start, number));
+                }
+                _ => {
+                    let number = match last {
+                        Some((_, start, number)) if start.date() == date.date() => number + 1,
+                        _ => 1,
+                    };
+                    starts.insert(date, number);
+                    last = Some((date, date, number));
+                }
+            }
+        }
+        starts
+    }
+
+    fn format(starts: &BTreeMap<NaiveDateTime, usize>, date: NaiveDateTime) -> String {
+        let (start, number) = starts
+            .range(..=date)
+            .next_back()
+            .map_or((date, 1), |(start, number)| (*start, *number));
+        format!("{}_event-{:02}", start.format("%Y-%m-%d"), number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+    use super::Events;
+
+    fn date(day: u32, hour: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 7, day)
+            .unwrap()
+            .and_hms_opt(hour, min, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn label() {
+        let events = Events::new(Duration::hours(2));
+        for date in [
+            date(14, 8, 0),
+            date(14, 9, 30),
+            date(14, 14, 0),
+            date(14, 23, 0),
+            date(15, 0, 30),
+            date(16, 10, 0),
+        ] {
+            events.insert(date);
+        }
+
+        assert_eq!(events.label(date(14, 8, 0)), "2023-07-14_event-01");
+        assert_eq!(events.label(date(14, 9, 30)), "2023-07-14_event-01");
+        assert_eq!(events.label(date(14, 14, 0)), "2023-07-14_event-02");
+        // events spanning midnight are labelled with their first day.
+        assert_eq!(events.label(date(15, 0, 30)), "2023-07-14_event-03");
+        assert_eq!(events.label(date(16, 10, 0)), "2023-07-16_event-01");
+
+        // dates inserted later merge events, labels are computed again.
+        events.insert(date(14, 11, 30));
+        events.insert(date(14, 13, 0));
+        assert_eq!(events.label(date(14, 14, 0)), "2023-07-14_event-01");
+        assert_eq!(events.label(date(15, 0, 30)), "2023-07-14_event-02");
+    }
+}
@@ -0,0 +1,623 @@
+//! Filesystem operations of the sorter and replicators.
+//!
+//! [`RealFs`] forwards to [`std::fs`], [`MemFs`] keeps files in memory and
+//! can fail operations on purpose, so overwrite, conflict and cleanup logic
+//! can be tested without touching the disk and with errors (e.g. cross-device
+//! links, full disks, denied permissions) that are hard to trigger on a real
+//! filesystem.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use symlink::symlink_file;
+
+/// Type of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// Metadata of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    file_type: FileType,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Metadata {
+    pub fn new(file_type: FileType, len: u64, modified: Option<SystemTime>) -> Self {
+        Self {
+            file_type,
+            len,
+            modified,
+        }
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type == FileType::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type == FileType::Dir
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type == FileType::Symlink
+    }
+
+    /// Size of the file in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Last modification time, if supported by the filesystem.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+}
+
+impl From<fs::Metadata> for Metadata {
+    fn from(metadata: fs::Metadata) -> Self {
+        let file_type = if metadata.file_type().is_symlink() {
+            FileType::Symlink
+        } else if metadata.is_dir() {
+            FileType::Dir
+        } else {
+            FileType::File
+        };
+
+        Self::new(file_type, metadata.len(), metadata.modified().ok())
+    }
+}
+
+/// Fs define filesystem operations, methods behave like their [`std::fs`]
+/// counterpart.
+pub trait Fs: Debug + Send + Sync {
+    /// Returns the metadata of the given path, symbolic links are followed.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+    /// Returns the metadata of the given path, symbolic links aren't
+    /// followed.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata>;
+    /// Opens the given file for reading.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>>;
+    /// Returns the paths of the entries of the given directory.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    /// Removes the given directory, fails if it isn't empty.
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64>;
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()>;
+    fn symlink_file(&self, original: &Path, link: &Path) -> io::Result<()>;
+    /// Returns whether both paths are the same file (e.g. hard links),
+    /// symbolic links are followed. False if either can't be read.
+    fn same_file(&self, a: &Path, b: &Path) -> bool;
+    /// Sets the permission bits of the given file, symbolic links are
+    /// followed (Unix only).
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()>;
+    /// Changes the owner and group of the given file, `None` leaves them
+    /// unchanged (Unix only).
+    fn chown(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()>;
+
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|m| m.is_file())
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|m| m.is_dir())
+    }
+}
+
+/// RealFs is the filesystem of the operating system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RealFs;
+
+impl Fs for RealFs {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::metadata(path).map(Metadata::from)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::symlink_metadata(path).map(Metadata::from)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        fs::copy(from, to)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        fs::hard_link(original, link)
+    }
+
+    fn symlink_file(&self, original: &Path, link: &Path) -> io::Result<()> {
+        symlink_file(original, link)
+    }
+
+    #[cfg(unix)]
+    fn same_file(&self, a: &Path, b: &Path) -> bool {
+        use std::os::unix::fs::MetadataExt;
+
+        match (fs::metadata(a), fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn same_file(&self, _a: &Path, _b: &Path) -> bool {
+        false
+    }
+
+    #[cfg(unix)]
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+
+    #[cfg(not(unix))]
+    fn set_mode(&self, _path: &Path, _mode: u32) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "modes are only supported on unix",
+        ))
+    }
+
+    #[cfg(unix)]
+    fn chown(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        std::os::unix::fs::chown(path, uid, gid)
+    }
+
+    #[cfg(not(unix))]
+    fn chown(&self, _path: &Path, _uid: Option<u32>, _gid: Option<u32>) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "owners are only supported on unix",
+        ))
+    }
+}
+
+/// Filesystem operations that can be failed on purpose, see
+/// [`MemFs::with_fault`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Op {
+    Open,
+    CreateDir,
+    Remove,
+    Rename,
+    Copy,
+    HardLink,
+    Symlink,
+    /// Changing the mode or the owner of a file.
+    SetPermissions,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    /// Content is shared by hard links, files are never modified in place.
+    File(Arc<Vec<u8>>),
+    Dir,
+    Symlink(PathBuf),
+}
+
+/// Mode and owner of a file, see [`MemFs::permissions`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemPermissions {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+/// MemFs is an in-memory filesystem. Its root directory always exists,
+/// other directories must be created before files are added to them.
+#[derive(Debug, Default)]
+pub struct MemFs {
+    nodes: Mutex<BTreeMap<PathBuf, Node>>,
+    faults: Mutex<HashMap<(Op, PathBuf), io::ErrorKind>>,
+    /// Permissions changed since files were added, by path.
+    permissions: Mutex<HashMap<PathBuf, MemPermissions>>,
+}
+
+impl MemFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file with the given content, parent directories are created.
+    pub fn with_file(self, path: impl AsRef<Path>, content: impl AsRef<[u8]>) -> Self {
+        self.write(path.as_ref(), content.as_ref())
+            .expect("failed to add file");
+        self
+    }
+
+    /// Adds a directory and its parents.
+    pub fn with_dir(self, path: impl AsRef<Path>) -> Self {
+        self.create_dir_all(path.as_ref())
+            .expect("failed to add directory");
+        self
+    }
+
+    /// Fails the given operation on the given path (the destination of
+    /// operations with two paths) with an error of the given kind, e.g.
+    /// [`io::ErrorKind::CrossesDevices`], [`io::ErrorKind::StorageFull`] or
+    /// [`io::ErrorKind::PermissionDenied`].
+    pub fn with_fault(self, op: Op, path: impl Into<PathBuf>, kind: io::ErrorKind) -> Self {
+        self.faults.lock().unwrap().insert((op, path.into()), kind);
+        self
+    }
+
+    /// Writes a file, its parent directories are created.
+    pub fn write(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)?;
+        }
+        self.insert(path, Node::File(Arc::new(content.to_vec())))
+    }
+
+    /// Returns the content of a file, symbolic links are followed.
+    pub fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.resolve(path)? {
+            Node::File(content) => Ok(content.to_vec()),
+            _ => Err(io::ErrorKind::IsADirectory.into()),
+        }
+    }
+
+    /// Returns the sorted paths of files and symbolic links.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes
+            .iter()
+            .filter(|(_, node)| !matches!(node, Node::Dir))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Returns the mode and owner set on the given path, nothing is set when
+    /// files are added.
+    pub fn permissions(&self, path: &Path) -> MemPermissions {
+        let permissions = self.permissions.lock().unwrap();
+        permissions.get(path).copied().unwrap_or_default()
+    }
+
+    /// Changes the permissions of an existing path.
+    fn set_permissions<F>(&self, path: &Path, f: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut MemPermissions),
+    {
+        self.fault(Op::SetPermissions, path)?;
+        self.resolve(path)?;
+        f(self
+            .permissions
+            .lock()
+            .unwrap()
+            .entry(path.to_owned())
+            .or_default());
+        Ok(())
+    }
+
+    fn fault(&self, op: Op, path: &Path) -> io::Result<()> {
+        match self.faults.lock().unwrap().get(&(op, path.to_owned())) {
+            Some(kind) => Err(io::Error::new(*kind, format!("{:?} fault", op))),
+            None => Ok(()),
+        }
+    }
+
+    fn node(&self, path: &Path) -> io::Result<Node> {
+        if path.parent().is_none() {
+            return Ok(Node::Dir);
+        }
+        let nodes = self.nodes.lock().unwrap();
+        nodes
+            .get(path)
+            .cloned()
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+
+    /// Returns the node of the given path, symbolic links are followed.
+    fn resolve(&self, path: &Path) -> io::Result<Node> {
+        let mut path = path.to_owned();
+        // bounded to detect symbolic link loops.
+        for _ in 0..40 {
+            match self.node(&path)? {
+                Node::Symlink(target) => path = path.parent().unwrap().join(target),
+                node => return Ok(node),
+            }
+        }
+        Err(io::Error::other("too many levels of symbolic links"))
+    }
+
+    /// Adds a node, its parent directory must exist and the path must be
+    /// free.
+    fn insert(&self, path: &Path, node: Node) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            if !matches!(self.resolve(parent)?, Node::Dir) {
+                return Err(io::ErrorKind::NotADirectory.into());
+            }
+        }
+        let mut nodes = self.nodes.lock().unwrap();
+        if nodes.contains_key(path) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        nodes.insert(path.to_owned(), node);
+        Ok(())
+    }
+}
+
+fn metadata_of(node: &Node) -> Metadata {
+    match node {
+        Node::File(content) => Metadata::new(FileType::File, content.len() as u64, None),
+        Node::Dir => Metadata::new(FileType::Dir, 0, None),
+        Node::Symlink(target) => {
+            Metadata::new(FileType::Symlink, target.as_os_str().len() as u64, None)
+        }
+    }
+}
+
+impl Fs for MemFs {
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.resolve(path).map(|node| metadata_of(&node))
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.node(path).map(|node| metadata_of(&node))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        self.fault(Op::Open, path)?;
+        Ok(Box::new(Cursor::new(self.read(path)?)))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.fault(Op::CreateDir, path)?;
+        match self.resolve(path) {
+            Ok(Node::Dir) => return Ok(()),
+            Ok(_) => return Err(io::ErrorKind::AlreadyExists.into()),
+            Err(_) => {}
+        }
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)?;
+        }
+        self.insert(path, Node::Dir)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        if !matches!(self.resolve(path)?, Node::Dir) {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+        let nodes = self.nodes.lock().unwrap();
+        Ok(nodes
+            .keys()
+            .filter(|p| p.parent() == Some(path))
+            .cloned()
+            .collect())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.fault(Op::Remove, path)?;
+        let mut nodes = self.nodes.lock().unwrap();
+        match nodes.get(path) {
+            Some(Node::Dir) => Err(io::ErrorKind::IsADirectory.into()),
+            Some(_) => {
+                nodes.remove(path);
+                Ok(())
+            }
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.fault(Op::Remove, path)?;
+        let mut nodes = self.nodes.lock().unwrap();
+        match nodes.get(path) {
+            Some(Node::Dir) => {}
+            Some(_) => return Err(io::ErrorKind::NotADirectory.into()),
+            None => return Err(io::ErrorKind::NotFound.into()),
+        }
+        if nodes.keys().any(|p| p != path && p.starts_with(path)) {
+            return Err(io::ErrorKind::DirectoryNotEmpty.into());
+        }
+        nodes.remove(path);
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.fault(Op::Remove, path)?;
+        let mut nodes = self.nodes.lock().unwrap();
+        if !matches!(nodes.get(path), Some(Node::Dir)) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        nodes.retain(|p, _| !p.starts_with(path));
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.fault(Op::Rename, to)?;
+        let node = self.node(from)?;
+        if matches!(node, Node::Dir) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "directories can't be renamed",
+            ));
+        }
+        if let Ok(Node::Dir) = self.node(to) {
+            return Err(io::ErrorKind::IsADirectory.into());
+        }
+        // the source is only removed once the destination can be written.
+        if let Some(parent) = to.parent() {
+            if !matches!(self.resolve(parent)?, Node::Dir) {
+                return Err(io::ErrorKind::NotADirectory.into());
+            }
+        }
+        let mut nodes = self.nodes.lock().unwrap();
+        nodes.remove(from);
+        nodes.insert(to.to_owned(), node);
+        Ok(())
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        self.fault(Op::Copy, to)?;
+        let content = self.read(from)?;
+        let _ = self.remove_file(to);
+        self.insert(to, Node::File(Arc::new(content.clone())))?;
+        Ok(content.len() as u64)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        self.fault(Op::HardLink, link)?;
+        match self.node(original)? {
+            Node::Dir => Err(io::ErrorKind::IsADirectory.into()),
+            node => self.insert(link, node),
+        }
+    }
+
+    fn symlink_file(&self, original: &Path, link: &Path) -> io::Result<()> {
+        self.fault(Op::Symlink, link)?;
+        self.insert(link, Node::Symlink(original.to_owned()))
+    }
+
+    fn same_file(&self, a: &Path, b: &Path) -> bool {
+        match (self.resolve(a), self.resolve(b)) {
+            (Ok(Node::File(a)), Ok(Node::File(b))) => Arc::ptr_eq(&a, &b),
+            (Ok(_), Ok(_)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        self.set_permissions(path, |permissions| permissions.mode = Some(mode))
+    }
+
+    fn chown(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        self.set_permissions(path, |permissions| {
+            permissions.uid = uid.or(permissions.uid);
+            permissions.gid = gid.or(permissions.gid);
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+    use std::path::{Path, PathBuf};
+
+    use super::{Fs, MemFs, Op};
+
+    #[test]
+    fn mem_fs() {
+        let fs = MemFs::new()
+            .with_file("/src/a.jpg", "a")
+            .with_dir("/dst")
+            .with_fault(Op::HardLink, "/dst/b.jpg", io::ErrorKind::CrossesDevices);
+        let path = Path::new;
+
+        // parent directories must exist.
+        assert_eq!(
+            fs.copy(path("/src/a.jpg"), path("/dst/2023/a.jpg"))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+        fs.create_dir_all(path("/dst/2023")).unwrap();
+        assert_eq!(
+            fs.copy(path("/src/a.jpg"), path("/dst/2023/a.jpg"))
+                .unwrap(),
+            1
+        );
+
+        assert_eq!(
+            fs.hard_link(path("/src/a.jpg"), path("/dst/b.jpg"))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::CrossesDevices
+        );
+        fs.symlink_file(path("/src/a.jpg"), path("/dst/c.jpg"))
+            .unwrap();
+        assert!(fs.same_file(path("/src/a.jpg"), path("/dst/c.jpg")));
+        assert!(!fs.same_file(path("/src/a.jpg"), path("/dst/2023/a.jpg")));
+        assert_eq!(
+            fs.read_dir(path("/dst")).unwrap(),
+            [PathBuf::from("/dst/2023"), PathBuf::from("/dst/c.jpg")]
+        );
+        assert!(fs
+            .symlink_metadata(path("/dst/c.jpg"))
+            .unwrap()
+            .is_symlink());
+        let mut content = String::new();
+        fs.open(path("/dst/c.jpg"))
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "a");
+
+        assert_eq!(
+            fs.remove_dir(path("/dst")).unwrap_err().kind(),
+            io::ErrorKind::DirectoryNotEmpty
+        );
+        // failed renames keep the source.
+        assert_eq!(
+            fs.rename(path("/src/a.jpg"), path("/dst/2024/a.jpg"))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(fs.read(path("/src/a.jpg")).unwrap(), b"a");
+        fs.rename(path("/src/a.jpg"), path("/dst/a.jpg")).unwrap();
+        fs.remove_dir(path("/src")).unwrap();
+        assert_eq!(
+            fs.files(),
+            ["/dst/2023/a.jpg", "/dst/a.jpg", "/dst/c.jpg"].map(std::path::PathBuf::from)
+        );
+    }
+}
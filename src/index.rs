@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Content hash of a file.
+pub type Hash = blake3::Hash;
+
+/// Computes the content hash of the file at the given path.
+pub fn hash_file(path: &Path) -> io::Result<Hash> {
+    hash_reader(fs::File::open(path)?)
+}
+
+/// Computes the content hash of the given reader.
+pub fn hash_reader(reader: impl Read) -> io::Result<Hash> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(reader)?;
+
+    Ok(hasher.finalize())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexEntry {
+    size: u64,
+    mtime: SystemTime,
+    hash: String,
+}
+
+#[derive(Error, Debug)]
+pub enum IndexError {
+    #[error("failed to read index file {1:?}: {0}")]
+    Read(#[source] io::Error, PathBuf),
+
+    #[error("failed to deserialize index file {1:?}: {0}")]
+    Deserialize(#[source] serde_json::Error, PathBuf),
+
+    #[error("failed to write index file {1:?}: {0}")]
+    Write(#[source] io::Error, PathBuf),
+}
+
+/// HashIndex caches content hashes of files so unchanged files aren't hashed
+/// again. Cached hashes are invalidated when file size or modification time
+/// changes.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HashIndex {
+    entries: HashMap<PathBuf, IndexEntry>,
+}
+
+/// Loads the index stored at the given path, an empty index is returned if
+/// the file doesn't exist.
+fn load<T: DeserializeOwned + Default>(path: &Path) -> Result<T, IndexError> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(err) => return Err(IndexError::Read(err, path.to_owned())),
+    };
+
+    serde_json::from_slice(&data).map_err(|err| IndexError::Deserialize(err, path.to_owned()))
+}
+
+/// Writes the index to the given path. Index is written to a temporary file
+/// first so an interrupted write doesn't corrupt it.
+fn save<T: Serialize>(index: &T, path: &Path) -> Result<(), IndexError> {
+    let write = || -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec(index)?)?;
+        fs::rename(tmp_path, path)
+    };
+
+    write().map_err(|err| IndexError::Write(err, path.to_owned()))
+}
+
+/// Returns the size and modification time of the file at the given path.
+pub(crate) fn signature(path: &Path) -> io::Result<(u64, SystemTime)> {
+    let md = fs::metadata(path)?;
+    Ok((md.len(), md.modified()?))
+}
+
+impl HashIndex {
+    /// Loads the index stored at the given path, an empty index is returned if
+    /// the file doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, IndexError> {
+        load(path)
+    }
+
+    /// Writes the index to the given path. Index is written to a temporary
+    /// file first so an interrupted write doesn't corrupt it.
+    pub fn save(&self, path: &Path) -> Result<(), IndexError> {
+        save(self, path)
+    }
+
+    /// Returns the content hash of the file at the given (absolute) path,
+    /// computing it only if it isn't cached or the file changed.
+    pub fn hash(&mut self, path: &Path) -> io::Result<Hash> {
+        let (size, mtime) = signature(path)?;
+
+        if let Some(entry) = self.entries.get(path) {
+            if entry.size == size && entry.mtime == mtime {
+                if let Ok(hash) = Hash::from_hex(&entry.hash) {
+                    return Ok(hash);
+                }
+            }
+        }
+
+        let hash = hash_file(path)?;
+        self.entries.insert(
+            path.to_owned(),
+            IndexEntry {
+                size,
+                mtime,
+                hash: hash.to_hex().to_string(),
+            },
+        );
+
+        Ok(hash)
+    }
+
+    /// Removes entries of files that no longer exist.
+    pub fn prune(&mut self) {
+        self.entries.retain(|path, _| path.exists());
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RenderEntry {
+    size: u64,
+    mtime: SystemTime,
+    /// Key of the template and options the path was rendered with.
+    template: String,
+    path: PathBuf,
+}
+
+/// RenderCache caches destination paths rendered for files, so sorting a
+/// mostly unchanged tree again doesn't read metadata of every file. Cached
+/// paths are invalidated when file size, modification time or the template
+/// key changes. It can be shared by threads sorting files concurrently.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RenderCache {
+    entries: Mutex<HashMap<PathBuf, RenderEntry>>,
+}
+
+impl RenderCache {
+    /// Loads the cache stored at the given path, an empty cache is returned if
+    /// the file doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, IndexError> {
+        load(path)
+    }
+
+    /// Writes the cache to the given path, see [`HashIndex::save`].
+    pub fn save(&self, path: &Path) -> Result<(), IndexError> {
+        save(self, path)
+    }
+
+    /// Returns the cached destination of the file at the given (absolute)
+    /// path if it was rendered with the same template key and didn't change
+    /// since, otherwise the destination is rendered and cached.
+    pub fn get_or_render<F, E>(&self, path: &Path, template: &str, render: F) -> Result<PathBuf, E>
+    where
+        F: FnOnce() -> Result<PathBuf, E>,
+    {
+        // signature is read before rendering so changes made meanwhile
+        // invalidate the entry.
+        let signature = signature(path).ok();
+        if let Some((size, mtime)) = signature {
+            if let Some(entry) = self.entries.lock().unwrap().get(path) {
+                if entry.size == size && entry.mtime == mtime && entry.template == template {
+                    return Ok(entry.path.clone());
+                }
+            }
+        }
+
+        let rendered = render()?;
+        if let Some((size, mtime)) = signature {
+            self.entries.lock().unwrap().insert(
+                path.to_owned(),
+                RenderEntry {
+                    size,
+                    mtime,
+                    template: template.to_owned(),
+                    path: rendered.clone(),
+                },
+            );
+        }
+
+        Ok(rendered)
+    }
+
+    /// Removes entries of files that no longer exist.
+    pub fn prune(&self) {
+        self.entries.lock().unwrap().retain(|path, _| path.exists());
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    use super::{hash_file, HashIndex, RenderCache};
+
+    #[test]
+    fn hash_cache() {
+        let path = env::temp_dir().join(format!("{}.txt", Uuid::new_v4()));
+        fs::write(&path, "hello").unwrap();
+
+        let mut index = HashIndex::default();
+        let hash = index.hash(&path).unwrap();
+        assert_eq!(hash, hash_file(&path).unwrap());
+        assert_eq!(index.len(), 1);
+
+        // size changed, hash is recomputed.
+        fs::write(&path, "hello world").unwrap();
+        assert_ne!(index.hash(&path).unwrap(), hash);
+        assert_eq!(index.len(), 1);
+
+        fs::remove_file(&path).unwrap();
+        index.prune();
+        assert!(index.is_empty());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let index_path = dir.join("index.json");
+        let path = dir.join("file.txt");
+        fs::create_dir(&dir).unwrap();
+        fs::write(&path, "hello").unwrap();
+
+        let missing = HashIndex::load(&index_path).unwrap();
+        assert!(missing.is_empty());
+
+        let mut index = HashIndex::default();
+        let hash = index.hash(&path).unwrap();
+        index.save(&index_path).unwrap();
+
+        let mut index = HashIndex::load(&index_path).unwrap();
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.hash(&path).unwrap(), hash);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn render_cache() {
+        let dir = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let cache_path = dir.join("render-cache.json");
+        let path = dir.join("a.jpg");
+        fs::create_dir(&dir).unwrap();
+        fs::write(&path, "a").unwrap();
+
+        let renders = std::cell::Cell::new(0);
+        let render = |dst: &str| {
+            renders.set(renders.get() + 1);
+            Ok::<_, ()>(dst.into())
+        };
+
+        let cache = RenderCache::default();
+        assert_eq!(
+            cache.get_or_render(&path, "t1", || render("/dst/a.jpg")),
+            Ok("/dst/a.jpg".into())
+        );
+        cache.save(&cache_path).unwrap();
+
+        let cache = RenderCache::load(&cache_path).unwrap();
+        assert_eq!(
+            cache.get_or_render(&path, "t1", || render("/other/a.jpg")),
+            Ok("/dst/a.jpg".into())
+        );
+        assert_eq!(renders.get(), 1);
+
+        // template or file changed, path is rendered again.
+        assert_eq!(
+            cache.get_or_render(&path, "t2", || render("/t2/a.jpg")),
+            Ok("/t2/a.jpg".into())
+        );
+        fs::write(&path, "changed").unwrap();
+        assert_eq!(
+            cache.get_or_render(&path, "t2", || render("/changed/a.jpg")),
+            Ok("/changed/a.jpg".into())
+        );
+        assert_eq!(renders.get(), 3);
+
+        fs::remove_file(&path).unwrap();
+        cache.prune();
+        assert!(cache.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,641 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Entry records a replicated file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    /// A file was overwritten to replicate this file.
+    pub overwrite: bool,
+    /// Source file was removed after replication.
+    #[serde(default)]
+    pub moved: bool,
+    /// Backup of the overwritten file, see [`Journal::backup`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<PathBuf>,
+    /// Identifier of the run that replicated the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+}
+
+impl Entry {
+    pub fn new(src: PathBuf, dst: PathBuf, overwrite: bool) -> Self {
+        Self {
+            time: Utc::now(),
+            src,
+            dst,
+            overwrite,
+            moved: false,
+            backup: None,
+            run_id: None,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error("failed to open journal {1:?}: {0}")]
+    Open(#[source] io::Error, PathBuf),
+
+    #[error("failed to read journal {1:?}: {0}")]
+    Read(#[source] io::Error, PathBuf),
+
+    #[error("failed to write journal {1:?}: {0}")]
+    Write(#[source] io::Error, PathBuf),
+
+    #[error("failed to serialize journal entry: {0}")]
+    Serialize(#[source] serde_json::Error),
+
+    #[error("corrupted journal {1:?} entry at offset {2}: {0}")]
+    Deserialize(#[source] serde_json::Error, PathBuf, u64),
+}
+
+/// Journal is an append only log of replicated files stored as JSON lines.
+///
+/// An index mapping destination paths to entries offset is stored next to the
+/// journal (`.idx` extension), it is updated lazily when the journal is
+/// queried.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl Journal {
+    /// Opens the journal at the given path for appending, it is created if
+    /// needed. A partially written last entry (e.g. after a crash) is removed
+    /// so appended entries start on their own line.
+    pub fn open(path: &Path) -> Result<Self, JournalError> {
+        let open = || {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .read(true)
+                .append(true)
+                .open(path)?;
+            trim_partial_entry(&mut file, path)?;
+            Ok(file)
+        };
+
+        Ok(Self {
+            path: path.to_owned(),
+            file: open().map_err(|err| JournalError::Open(err, path.to_owned()))?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves a file about to be overwritten to the backup directory of the
+    /// journal (`.backup` extension) so it can be restored on undo. Returns
+    /// the path of the backup.
+    pub fn backup(&self, path: &Path) -> io::Result<PathBuf> {
+        let dir = backup_dir(&self.path);
+        fs::create_dir_all(&dir)?;
+
+        let now = Utc::now();
+        let backup_path = dir.join(format!(
+            "{}-{}",
+            now.timestamp_nanos_opt().unwrap_or_else(|| now.timestamp()),
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        move_file(path, &backup_path)?;
+
+        Ok(backup_path)
+    }
+
+    /// Moves a backup of [`Journal::backup`] back to the given path.
+    pub fn restore(&self, backup: &Path, path: &Path) -> io::Result<()> {
+        move_file(backup, path)
+    }
+
+    /// Appends an entry to the journal. Entries are written with a single
+    /// write so concurrent appends don't interleave.
+    pub fn append(&self, entry: &Entry) -> Result<(), JournalError> {
+        let mut line = serde_json::to_vec(entry).map_err(JournalError::Serialize)?;
+        line.push(b'\n');
+
+        (&self.file)
+            .write_all(&line)
+            .map_err(|err| JournalError::Write(err, self.path.clone()))
+    }
+}
+
+/// Returns entries whose destination is `dst` or is under `dst` directory, in
+/// chronological order.
+pub fn query(path: &Path, dst: &Path) -> Result<Vec<Entry>, JournalError> {
+    let mut index = Index::load(path);
+    index.update(path)?;
+    // the index is a cache, it is rebuilt if it can't be saved.
+    let _ = index.save(path);
+
+    let mut offsets: Vec<u64> = index
+        .offsets
+        .iter()
+        .filter(|(entry_dst, _)| entry_dst.starts_with(dst))
+        .flat_map(|(_, offsets)| offsets.iter().copied())
+        .collect();
+    offsets.sort_unstable();
+
+    let file = fs::File::open(path).map_err(|err| JournalError::Read(err, path.to_owned()))?;
+    let mut reader = BufReader::new(file);
+    let mut entries = Vec::with_capacity(offsets.len());
+    for offset in offsets {
+        reader
+            .seek(SeekFrom::Start(offset))
+            .map_err(|err| JournalError::Read(err, path.to_owned()))?;
+        let mut line = String::new();
+        reader
+            .read_line(&mut line)
+            .map_err(|err| JournalError::Read(err, path.to_owned()))?;
+        entries.push(
+            serde_json::from_str(&line)
+                .map_err(|err| JournalError::Deserialize(err, path.to_owned(), offset))?,
+        );
+    }
+
+    Ok(entries)
+}
+
+/// Compaction statistics.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Compaction {
+    pub before: usize,
+    pub after: usize,
+}
+
+/// Rewrites the journal keeping only the last entry of every destination that
+/// still exists. Kept entries take the backup of the first entry of their
+/// destination, that is the file it contained before it was first sorted, so
+/// undoing them still restores it. Other backups are deleted.
+pub fn compact(path: &Path) -> Result<Compaction, JournalError> {
+    let mut entries = read_entries(path)?;
+    let before = entries.len();
+
+    let mut first: BTreeMap<PathBuf, usize> = BTreeMap::new();
+    let mut last: BTreeMap<PathBuf, usize> = BTreeMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+        first.entry(entry.dst.clone()).or_insert(i);
+        last.insert(entry.dst.clone(), i);
+    }
+
+    let backups: Vec<Option<PathBuf>> = entries.iter().map(|e| e.backup.clone()).collect();
+    let mut kept = vec![false; entries.len()];
+    for (dst, i) in last {
+        kept[i] = dst.exists();
+        entries[i].backup = backups[first[&dst]].clone();
+    }
+
+    let after = rewrite(path, &entries, &kept)?;
+    let retained: BTreeSet<&PathBuf> = entries
+        .iter()
+        .zip(&kept)
+        .filter_map(|(entry, kept)| entry.backup.as_ref().filter(|_| *kept))
+        .collect();
+    for backup in backups.iter().flatten() {
+        if !retained.contains(backup) {
+            let _ = remove_backup(backup);
+        }
+    }
+
+    Ok(Compaction { before, after })
+}
+
+/// Removes the given backup, a file or a directory.
+fn remove_backup(backup: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(backup)?.is_dir() {
+        fs::remove_dir_all(backup)
+    } else {
+        fs::remove_file(backup)
+    }
+}
+
+/// Undo lists undone journal entries.
+#[derive(Debug, Default)]
+pub struct Undo {
+    pub undone: Vec<Entry>,
+    /// Entries that couldn't be undone, they're kept in the journal.
+    pub errors: Vec<(Entry, io::Error)>,
+}
+
+/// Reverses operations of journal entries accepted by `filter`, from the most
+/// recent one: replicate files are removed (or moved back to their source if
+/// it was moved) and overwritten files are restored from their backup.
+/// Undone entries are removed from the journal.
+pub fn undo<F>(path: &Path, filter: F) -> Result<Undo, JournalError>
+where
+    F: Fn(&Entry) -> bool,
+{
+    let entries = read_entries(path)?;
+    let mut kept = vec![true; entries.len()];
+    let mut undo = Undo::default();
+
+    for (i, entry) in entries.iter().enumerate().rev() {
+        if !filter(entry) {
+            continue;
+        }
+
+        match undo_entry(entry) {
+            Ok(()) => {
+                kept[i] = false;
+                undo.undone.push(entry.clone());
+            }
+            Err(err) => undo.errors.push((entry.clone(), err)),
+        }
+    }
+
+    rewrite(path, &entries, &kept)?;
+    Ok(undo)
+}
+
+fn undo_entry(entry: &Entry) -> io::Result<()> {
+    if entry.moved {
+        if entry.src.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("source file {:?} exists", entry.src),
+            ));
+        }
+        if let Some(parent) = entry.src.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_file(&entry.dst, &entry.src)?;
+    } else {
+        match fs::remove_file(&entry.dst) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+
+    if let Some(backup) = &entry.backup {
+        move_file(backup, &entry.dst)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the run identifier of the last journal entry.
+pub fn last_run_id(path: &Path) -> Result<Option<String>, JournalError> {
+    Ok(read_entries(path)?.pop().and_then(|entry| entry.run_id))
+}
+
+/// Replaces the journal content with the kept entries and rebuilds its index.
+/// Returns the number of kept entries.
+fn rewrite(path: &Path, entries: &[Entry], kept: &[bool]) -> Result<usize, JournalError> {
+    let mut data = Vec::new();
+    let mut count = 0;
+    for (entry, _) in entries.iter().zip(kept).filter(|(_, kept)| **kept) {
+        serde_json::to_writer(&mut data, entry).map_err(JournalError::Serialize)?;
+        data.push(b'\n');
+        count += 1;
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data)
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .map_err(|err| JournalError::Write(err, path.to_owned()))?;
+
+    let mut index = Index::default();
+    index.update(path)?;
+    let _ = index.save(path);
+
+    Ok(count)
+}
+
+/// Directory containing backups of overwritten files.
+fn backup_dir(journal_path: &Path) -> PathBuf {
+    journal_path.with_extension("backup")
+}
+
+/// Renames a file, it is copied then removed if it can't be renamed (e.g.
+/// different filesystems).
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+fn read_entries(path: &Path) -> Result<Vec<Entry>, JournalError> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(JournalError::Read(err, path.to_owned())),
+    };
+
+    let mut reader = BufReader::new(file);
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let n = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|err| JournalError::Read(err, path.to_owned()))?;
+        if n == 0 {
+            break;
+        }
+        // entries are written with their newline, the last one was
+        // interrupted.
+        if line.last() != Some(&b'\n') {
+            log::warn!(
+                "ignoring partially written entry at offset {} of journal {:?}",
+                offset,
+                path
+            );
+            break;
+        }
+
+        entries.push(
+            serde_json::from_slice(&line)
+                .map_err(|err| JournalError::Deserialize(err, path.to_owned(), offset))?,
+        );
+        offset += n as u64;
+    }
+
+    Ok(entries)
+}
+
+/// Truncates the given journal file after its last newline.
+fn trim_partial_entry(file: &mut fs::File, path: &Path) -> io::Result<()> {
+    let len = file.metadata()?.len();
+    let mut buf = [0; 4096];
+    let mut end = len;
+    while end > 0 {
+        let start = end.saturating_sub(buf.len() as u64);
+        let chunk = &mut buf[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(i) = chunk.iter().rposition(|b| *b == b'\n') {
+            end = start + i as u64 + 1;
+            break;
+        }
+        end = start;
+    }
+
+    if end < len {
+        log::warn!(
+            "removing partially written entry at offset {} of journal {:?}",
+            end,
+            path
+        );
+        file.set_len(end)?;
+    }
+    Ok(())
+}
+
+/// Index maps destination paths to offset of their entries in the journal.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    /// Length of the indexed part of the journal.
+    len: u64,
+    offsets: BTreeMap<PathBuf, Vec<u64>>,
+}
+
+impl Index {
+    fn path(journal_path: &Path) -> PathBuf {
+        journal_path.with_extension("idx")
+    }
+
+    /// Loads the index of the given journal, an empty index is returned if it
+    /// doesn't exist or is corrupted.
+    fn load(journal_path: &Path) -> Self {
+        fs::read(Self::path(journal_path))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, journal_path: &Path) -> io::Result<()> {
+        let data = serde_json::to_vec(self)?;
+        fs::write(Self::path(journal_path), data)
+    }
+
+    /// Indexes entries appended since the last update. Index is rebuilt if the
+    /// journal was truncated.
+    fn update(&mut self, journal_path: &Path) -> Result<(), JournalError> {
+        let read_err = |err| JournalError::Read(err, journal_path.to_owned());
+
+        let file = match fs::File::open(journal_path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                *self = Self::default();
+                return Ok(());
+            }
+            Err(err) => return Err(read_err(err)),
+        };
+        if file.metadata().map_err(read_err)?.len() < self.len {
+            *self = Self::default();
+        }
+
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(self.len)).map_err(read_err)?;
+
+        #[derive(Deserialize)]
+        struct EntryDst {
+            dst: PathBuf,
+        }
+
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let n = reader.read_until(b'\n', &mut line).map_err(read_err)?;
+            // stop at EOF or on a partially written entry.
+            if n == 0 || line.last() != Some(&b'\n') {
+                break;
+            }
+
+            let entry: EntryDst = serde_json::from_slice(&line)
+                .map_err(|err| JournalError::Deserialize(err, journal_path.to_owned(), self.len))?;
+            self.offsets.entry(entry.dst).or_default().push(self.len);
+            self.len += n as u64;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    use super::{Entry, Journal};
+
+    fn entry(src: &str, dst: &Path) -> Entry {
+        Entry::new(PathBuf::from(src), dst.to_owned(), false)
+    }
+
+    #[test]
+    fn append_and_query() {
+        let dir = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let path = dir.join("journal.jsonl");
+        let journal = Journal::open(&path).unwrap();
+
+        let a = entry("/src/a.jpg", Path::new("/dst/2022/a.jpg"));
+        let b = entry("/src/b.jpg", Path::new("/dst/2023/b.jpg"));
+        journal.append(&a).unwrap();
+        journal.append(&b).unwrap();
+
+        assert_eq!(
+            super::query(&path, Path::new("/dst/2022")).unwrap(),
+            vec![a.clone()]
+        );
+        assert!(dir.join("journal.idx").exists());
+
+        // entries appended after the index was built are queried too.
+        let a2 = entry("/src/a2.jpg", Path::new("/dst/2022/a2.jpg"));
+        journal.append(&a2).unwrap();
+        assert_eq!(
+            super::query(&path, Path::new("/dst/2022")).unwrap(),
+            vec![a, a2]
+        );
+        assert_eq!(super::query(&path, Path::new("/dst")).unwrap().len(), 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn partial_entry() {
+        let dir = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let path = dir.join("journal.jsonl");
+        let a = entry("/src/a.jpg", Path::new("/dst/a.jpg"));
+        let b = entry("/src/b.jpg", Path::new("/dst/b.jpg"));
+        Journal::open(&path).unwrap().append(&a).unwrap();
+
+        // interrupted while appending b.
+        let line = serde_json::to_vec(&b).unwrap();
+        let mut content = fs::read(&path).unwrap();
+        content.extend_from_slice(&line[..line.len() / 2]);
+        fs::write(&path, &content).unwrap();
+        assert_eq!(super::read_entries(&path).unwrap(), vec![a.clone()]);
+
+        // appended entries don't follow the partial one.
+        Journal::open(&path).unwrap().append(&b).unwrap();
+        assert_eq!(super::read_entries(&path).unwrap(), vec![a, b]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn compact() {
+        let dir = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let path = dir.join("journal.jsonl");
+        let journal = Journal::open(&path).unwrap();
+
+        let dst = dir.join("a.jpg");
+        fs::write(&dst, "a").unwrap();
+        journal.append(&entry("/src/old-a.jpg", &dst)).unwrap();
+        journal
+            .append(&entry("/src/b.jpg", &dir.join("b.jpg")))
+            .unwrap();
+        let last = entry("/src/a.jpg", &dst);
+        journal.append(&last).unwrap();
+
+        let compaction = super::compact(&path).unwrap();
+        assert_eq!(compaction.before, 3);
+        assert_eq!(compaction.after, 1);
+        assert_eq!(super::query(&path, &dir).unwrap(), vec![last]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn compact_keeps_original_backup() {
+        let dir = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let path = dir.join("journal.jsonl");
+        let journal = Journal::open(&path).unwrap();
+
+        // user file overwritten twice, replicas of the first sort are backed
+        // up by the second one.
+        let dst = dir.join("a.jpg");
+        fs::write(&dst, "original").unwrap();
+        let mut first = entry("/src/a.jpg", &dst);
+        first.overwrite = true;
+        first.backup = Some(journal.backup(&dst).unwrap());
+        fs::write(&dst, "first").unwrap();
+        journal.append(&first).unwrap();
+        let mut second = entry("/src/a.jpg", &dst);
+        second.overwrite = true;
+        second.backup = Some(journal.backup(&dst).unwrap());
+        fs::write(&dst, "second").unwrap();
+        journal.append(&second).unwrap();
+
+        // overwritten directory of a removed destination.
+        let removed = dir.join("b");
+        fs::create_dir_all(removed.join("c")).unwrap();
+        let mut dir_entry = entry("/src/b", &removed);
+        dir_entry.overwrite = true;
+        dir_entry.backup = Some(journal.backup(&removed).unwrap());
+        journal.append(&dir_entry).unwrap();
+
+        let compaction = super::compact(&path).unwrap();
+        assert_eq!(compaction.after, 1);
+        let kept = super::query(&path, &dir).unwrap();
+        assert_eq!(kept[0].backup, first.backup);
+        assert!(first.backup.as_ref().unwrap().exists());
+        assert!(!second.backup.as_ref().unwrap().exists());
+        assert!(!dir_entry.backup.as_ref().unwrap().exists());
+
+        let undo = super::undo(&path, |_| true).unwrap();
+        assert!(undo.errors.is_empty());
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "original");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn undo() {
+        let dir = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let path = dir.join("journal.jsonl");
+        let journal = Journal::open(&path).unwrap();
+
+        // copied file.
+        let copy = dir.join("copy.jpg");
+        fs::write(&copy, "copy").unwrap();
+        journal.append(&entry("/src/copy.jpg", &copy)).unwrap();
+
+        // moved file.
+        let moved_src = dir.join("src/moved.jpg");
+        let moved_dst = dir.join("moved.jpg");
+        fs::write(&moved_dst, "moved").unwrap();
+        let mut moved = Entry::new(moved_src.clone(), moved_dst.clone(), false);
+        moved.moved = true;
+        journal.append(&moved).unwrap();
+
+        // overwritten file.
+        let overwritten = dir.join("overwritten.jpg");
+        fs::write(&overwritten, "old").unwrap();
+        let mut entry = entry("/src/overwritten.jpg", &overwritten);
+        entry.overwrite = true;
+        entry.backup = Some(journal.backup(&overwritten).unwrap());
+        fs::write(&overwritten, "new").unwrap();
+        journal.append(&entry).unwrap();
+
+        let undo = super::undo(&path, |_| true).unwrap();
+        assert!(undo.errors.is_empty());
+        assert_eq!(undo.undone.len(), 3);
+        assert!(!copy.exists());
+        assert!(!moved_dst.exists());
+        assert_eq!(fs::read_to_string(moved_src).unwrap(), "moved");
+        assert_eq!(fs::read_to_string(overwritten).unwrap(), "old");
+        assert!(super::query(&path, &dir).unwrap().is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,3 +1,31 @@
+pub mod backoff;
+#[cfg(any(test, feature = "chaos"))]
+pub mod chaos;
+pub mod encoding;
+pub mod event;
+pub mod fs;
+pub mod index;
+pub mod journal;
+pub mod marker;
+pub mod media;
+pub mod mirror;
+pub mod mount;
+pub mod pair;
+pub mod permissions;
+pub mod pipeline;
+pub mod plan;
+pub mod portable;
+pub mod progress;
+pub mod queue;
 pub mod replicator;
+pub mod snapshot;
 pub mod sort;
+pub mod state;
+pub mod streams;
+pub mod summary;
+pub mod tags;
 pub mod template;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+pub mod thumbnail;
+pub mod watch;
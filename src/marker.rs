@@ -0,0 +1,130 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chrono::Utc;
+
+use crate::index::{self, Hash};
+
+/// Extended attribute storing the marker of replicated files.
+pub const XATTR_NAME: &str = "user.photosort";
+
+/// Marker is stored in an extended attribute of replicated files so sorting a
+/// destination that is also a source recognizes files it produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Marker {
+    /// Hash of the source file content.
+    pub hash: Hash,
+    /// Identifier of the run that replicated the file.
+    pub run_id: String,
+}
+
+impl Marker {
+    pub fn new(hash: Hash, run_id: String) -> Self {
+        Self { hash, run_id }
+    }
+
+    /// Reads marker of the given file, none is returned if it has no valid
+    /// marker or extended attributes aren't supported.
+    pub fn read(path: &Path) -> io::Result<Option<Self>> {
+        let value = match xattr::get(path, XATTR_NAME) {
+            Ok(value) => value,
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        Ok(value
+            .and_then(|value| String::from_utf8(value).ok())
+            .and_then(|value| {
+                let (hash, run_id) = value.split_once(' ')?;
+                Some(Self::new(Hash::from_hex(hash).ok()?, run_id.to_owned()))
+            }))
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let value = format!("{} {}", self.hash.to_hex(), self.run_id);
+        xattr::set(path, XATTR_NAME, value.as_bytes())
+    }
+
+    /// Returns true if the given file is marked and its content is unchanged
+    /// since it was replicated. Hard linked files are never marked, a marker
+    /// on them is shared with their source.
+    pub fn is_marked(path: &Path) -> io::Result<bool> {
+        if is_hard_linked(&fs::metadata(path)?) {
+            return Ok(false);
+        }
+
+        match Self::read(path)? {
+            Some(marker) => Ok(index::hash_file(path)? == marker.hash),
+            None => Ok(false),
+        }
+    }
+}
+
+/// Returns true if the file has other hard links, extended attributes are
+/// stored on the inode shared by all of them.
+#[cfg(unix)]
+pub fn is_hard_linked(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink() > 1
+}
+
+#[cfg(not(unix))]
+pub fn is_hard_linked(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// Returns a new run identifier, it is unique per process and time.
+pub fn run_id() -> String {
+    format!(
+        "{}-{}",
+        Utc::now().format("%Y%m%dT%H%M%S"),
+        std::process::id()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    use crate::index;
+
+    use super::Marker;
+
+    #[test]
+    fn write_and_read() {
+        let path = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::write(&path, "content").unwrap();
+
+        if !xattr::SUPPORTED_PLATFORM {
+            return;
+        }
+        let marker = Marker::new(index::hash_file(&path).unwrap(), super::run_id());
+        if marker.write(&path).is_err() {
+            // filesystem doesn't support user extended attributes.
+            fs::remove_file(path).unwrap();
+            return;
+        }
+
+        assert_eq!(Marker::read(&path).unwrap(), Some(marker));
+        assert!(Marker::is_marked(&path).unwrap());
+
+        fs::write(&path, "modified").unwrap();
+        assert!(!Marker::is_marked(&path).unwrap());
+
+        // marker is shared with the hard link.
+        let marker = Marker::new(index::hash_file(&path).unwrap(), super::run_id());
+        marker.write(&path).unwrap();
+        assert!(Marker::is_marked(&path).unwrap());
+        let link = path.with_extension("link");
+        fs::hard_link(&path, &link).unwrap();
+        #[cfg(unix)]
+        assert!(!Marker::is_marked(&path).unwrap());
+
+        fs::remove_file(link).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+}
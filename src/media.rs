@@ -0,0 +1,202 @@
+//! Detection of removable media (SD cards, cameras) as they are mounted, for
+//! the photo importer workflow: sort a card as soon as it is plugged in, then
+//! eject it.
+
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use regex::Regex;
+
+use crate::encoding;
+use crate::index;
+
+/// MediaSource reports directories of media as they are mounted.
+///
+/// [`GlobSource`] polls the filesystem, event based backends (udev,
+/// DiskArbitration) can implement it to react without delay.
+pub trait MediaSource {
+    /// Returns the directories of media mounted since the last call, media
+    /// mounted before the first call are reported by it.
+    fn poll(&mut self) -> io::Result<Vec<PathBuf>>;
+}
+
+/// Part of a glob pattern.
+#[derive(Debug)]
+enum Segment {
+    Literal(OsString),
+    /// A path component with `*` or `?` wildcards.
+    Wildcard(Regex),
+}
+
+/// GlobSource reports directories matching a glob pattern (e.g.
+/// `/media/*/*/DCIM`) that didn't exist on the previous poll. Wildcards
+/// (`*` and `?`) match part of a single path component.
+#[derive(Debug)]
+pub struct GlobSource {
+    root: PathBuf,
+    segments: Vec<Segment>,
+    present: HashSet<PathBuf>,
+}
+
+impl GlobSource {
+    pub fn new(pattern: &Path) -> Self {
+        let mut root = PathBuf::new();
+        let mut segments = Vec::new();
+        for component in pattern.components() {
+            match component {
+                Component::Prefix(_) | Component::RootDir if segments.is_empty() => {
+                    root.push(component)
+                }
+                Component::Normal(name) => {
+                    let name = name.to_string_lossy();
+                    if name.contains(['*', '?']) {
+                        let regex = regex::escape(&name)
+                            .replace(r"\*", ".*")
+                            .replace(r"\?", ".");
+                        segments.push(Segment::Wildcard(
+                            Regex::new(&format!("^{}$", regex)).unwrap(),
+                        ));
+                    } else {
+                        segments.push(Segment::Literal(name.into_owned().into()));
+                    }
+                }
+                component => segments.push(Segment::Literal(component.as_os_str().to_owned())),
+            }
+        }
+        if root.as_os_str().is_empty() {
+            root.push(".");
+        }
+
+        Self {
+            root,
+            segments,
+            present: HashSet::new(),
+        }
+    }
+
+    /// Returns the directories matching the pattern.
+    fn expand(&self) -> io::Result<Vec<PathBuf>> {
+        let mut paths = vec![self.root.clone()];
+        for segment in &self.segments {
+            let mut matches = Vec::new();
+            for path in paths {
+                match segment {
+                    Segment::Literal(name) => matches.push(path.join(name)),
+                    Segment::Wildcard(regex) => {
+                        let entries = match fs::read_dir(&path) {
+                            Ok(entries) => entries,
+                            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                            Err(err) => return Err(err),
+                        };
+                        for entry in entries {
+                            let entry = entry?;
+                            if encoding::is_match(regex, &entry.file_name()) {
+                                matches.push(entry.path());
+                            }
+                        }
+                    }
+                }
+            }
+            paths = matches;
+        }
+
+        paths.retain(|path| path.is_dir());
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+impl MediaSource for GlobSource {
+    fn poll(&mut self) -> io::Result<Vec<PathBuf>> {
+        let paths = self.expand()?;
+        let mounted = paths
+            .iter()
+            .filter(|path| !self.present.contains(*path))
+            .cloned()
+            .collect();
+
+        // unmounted media are reported again once mounted back.
+        self.present = paths.into_iter().collect();
+        Ok(mounted)
+    }
+}
+
+/// Removes the imported file `src` once its replicate file is verified to be
+/// a regular file (not a symbolic link) with the same content hash, returns
+/// whether it was removed.
+pub fn remove_verified(src: &Path, replicate: &Path) -> io::Result<bool> {
+    let metadata = match fs::symlink_metadata(replicate) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    if !metadata.is_file() || fs::canonicalize(src)? == fs::canonicalize(replicate)? {
+        return Ok(false);
+    }
+    if metadata.len() != fs::metadata(src)?.len()
+        || index::hash_file(src)? != index::hash_file(replicate)?
+    {
+        return Ok(false);
+    }
+
+    fs::remove_file(src)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    use super::{GlobSource, MediaSource};
+
+    #[test]
+    fn remove_verified() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir(&root).unwrap();
+        let path = |name: &str| root.join(name);
+        fs::write(path("a.jpg"), "a").unwrap();
+        fs::write(path("b.jpg"), "b").unwrap();
+        fs::write(path("a-copy.jpg"), "a").unwrap();
+        fs::write(path("b-copy.jpg"), "corrupted").unwrap();
+
+        assert!(super::remove_verified(&path("a.jpg"), &path("a-copy.jpg")).unwrap());
+        assert!(!path("a.jpg").exists());
+        assert!(!super::remove_verified(&path("b.jpg"), &path("b-copy.jpg")).unwrap());
+        assert!(!super::remove_verified(&path("b.jpg"), &path("missing.jpg")).unwrap());
+        // a file is never its own copy.
+        assert!(!super::remove_verified(&path("b.jpg"), &path("b.jpg")).unwrap());
+        assert!(path("b.jpg").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn glob_source() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(root.join("card-a/DCIM")).unwrap();
+        fs::create_dir_all(root.join("usb")).unwrap();
+        let dcim = |name: &str| root.join(name).join("DCIM");
+
+        let mut source = GlobSource::new(&root.join("card-?/DCIM"));
+        assert_eq!(source.poll().unwrap(), [dcim("card-a")]);
+        assert_eq!(source.poll().unwrap(), Vec::<PathBuf>::new());
+
+        fs::create_dir_all(dcim("card-b")).unwrap();
+        fs::create_dir_all(dcim("usb")).unwrap();
+        assert_eq!(source.poll().unwrap(), [dcim("card-b")]);
+
+        // media mounted again are reported again.
+        fs::remove_dir_all(root.join("card-a")).unwrap();
+        assert_eq!(source.poll().unwrap(), Vec::<PathBuf>::new());
+        fs::create_dir_all(dcim("card-a")).unwrap();
+        assert_eq!(source.poll().unwrap(), [dcim("card-a")]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+//! Read-only comparison of two destinations replicated from the same sources
+//! (e.g. a NAS and an offsite drive), reporting files that drifted.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::index;
+use crate::plan::walk_files;
+
+/// Drift is a difference between the two trees, paths are relative to the
+/// tree roots.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "drift", rename_all = "snake_case")]
+pub enum Drift {
+    /// File only exists in the first tree.
+    OnlyInA { path: PathBuf },
+    /// File only exists in the second tree.
+    OnlyInB { path: PathBuf },
+    /// Files have different sizes.
+    Size { path: PathBuf, a: u64, b: u64 },
+    /// Files have the same size but different content hashes.
+    Content { path: PathBuf },
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::OnlyInA { path } => write!(f, "only in a: {}", path.display()),
+            Drift::OnlyInB { path } => write!(f, "only in b: {}", path.display()),
+            Drift::Size { path, a, b } => {
+                write!(f, "size differs: {} ({} != {} bytes)", path.display(), a, b)
+            }
+            Drift::Content { path } => write!(f, "content differs: {}", path.display()),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MirrorError {
+    #[error("failed to walk {1:?}: {0}")]
+    Walk(#[source] io::Error, PathBuf),
+
+    #[error("failed to read metadata of {1:?}: {0}")]
+    Metadata(#[source] io::Error, PathBuf),
+
+    #[error("failed to hash file {1:?}: {0}")]
+    Hash(#[source] io::Error, PathBuf),
+}
+
+/// Summary of a comparison.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    /// Files present in both trees.
+    pub compared: usize,
+    pub drifted: usize,
+    pub errors: usize,
+}
+
+/// Returns paths of the files under `root` relative to it, in walk order.
+fn relative_files<F>(root: &Path, f: &mut F) -> Vec<PathBuf>
+where
+    F: FnMut(Result<Drift, MirrorError>),
+{
+    let mut paths = Vec::new();
+    walk_files(root, &mut |result| match result {
+        Ok(path) => paths.push(path.strip_prefix(root).unwrap_or(&path).to_owned()),
+        Err(err) => f(Err(MirrorError::Walk(err, root.to_owned()))),
+    });
+    paths
+}
+
+/// Compares files of both trees (names and sizes, content hashes too if
+/// `hash` is set) and calls `f` on every drift or error. Trees are only
+/// read.
+pub fn check<F>(a: &Path, b: &Path, hash: bool, mut f: F) -> Summary
+where
+    F: FnMut(Result<Drift, MirrorError>),
+{
+    let mut summary = Summary::default();
+    let mut report = |result: Result<Drift, MirrorError>| {
+        match &result {
+            Ok(_) => summary.drifted += 1,
+            Err(_) => summary.errors += 1,
+        }
+        f(result)
+    };
+
+    // both walks are sorted, they're merged like sorted lists.
+    let a_paths = relative_files(a, &mut report);
+    let b_paths = relative_files(b, &mut report);
+    let (mut a_paths, mut b_paths) = (
+        a_paths.into_iter().peekable(),
+        b_paths.into_iter().peekable(),
+    );
+    loop {
+        let order = match (a_paths.peek(), b_paths.peek()) {
+            (Some(a_path), Some(b_path)) => a_path.cmp(b_path),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => break,
+        };
+
+        match order {
+            Ordering::Less => report(Ok(Drift::OnlyInA {
+                path: a_paths.next().unwrap(),
+            })),
+            Ordering::Greater => report(Ok(Drift::OnlyInB {
+                path: b_paths.next().unwrap(),
+            })),
+            Ordering::Equal => {
+                let path = a_paths.next().unwrap();
+                b_paths.next();
+                summary.compared += 1;
+                if let Some(result) = compare(&a.join(&path), &b.join(&path), hash, path) {
+                    report(result);
+                }
+            }
+        }
+    }
+
+    summary
+}
+
+/// Compares files present in both trees, returns none if they match.
+fn compare(a: &Path, b: &Path, hash: bool, path: PathBuf) -> Option<Result<Drift, MirrorError>> {
+    let len = |path: &Path| {
+        fs::metadata(path)
+            .map(|md| md.len())
+            .map_err(|err| MirrorError::Metadata(err, path.to_owned()))
+    };
+    let hash_file =
+        |path: &Path| index::hash_file(path).map_err(|err| MirrorError::Hash(err, path.to_owned()));
+
+    let result = (|| {
+        let (a_len, b_len) = (len(a)?, len(b)?);
+        if a_len != b_len {
+            return Ok(Some(Drift::Size {
+                path,
+                a: a_len,
+                b: b_len,
+            }));
+        }
+        if hash && hash_file(a)? != hash_file(b)? {
+            return Ok(Some(Drift::Content { path }));
+        }
+        Ok(None)
+    })();
+
+    result.transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::test_utils::TempTree;
+
+    use super::{Drift, Summary};
+
+    #[test]
+    fn check() {
+        let tree = TempTree::new()
+            .with_file("a/2023/same.jpg", "same")
+            .with_file("a/2023/size.jpg", "a")
+            .with_file("a/2023/content.jpg", "aaa")
+            .with_file("a/2023/only-a.jpg", "a")
+            .with_file("b/2023/same.jpg", "same")
+            .with_file("b/2023/size.jpg", "bb")
+            .with_file("b/2023/content.jpg", "bbb")
+            .with_file("b/2024/only-b.jpg", "b");
+        let path = |p: &str| PathBuf::from(p);
+
+        let mut drifts = Vec::new();
+        let summary = super::check(&tree.join("a"), &tree.join("b"), false, |result| {
+            drifts.push(result.unwrap())
+        });
+        assert_eq!(
+            drifts,
+            [
+                Drift::OnlyInA {
+                    path: path("2023/only-a.jpg")
+                },
+                Drift::Size {
+                    path: path("2023/size.jpg"),
+                    a: 1,
+                    b: 2
+                },
+                Drift::OnlyInB {
+                    path: path("2024/only-b.jpg")
+                },
+            ]
+        );
+        assert_eq!(
+            summary,
+            Summary {
+                compared: 3,
+                drifted: 3,
+                errors: 0
+            }
+        );
+
+        // content is only compared with hashes.
+        let mut drifts = Vec::new();
+        super::check(&tree.join("a"), &tree.join("b"), true, |result| {
+            drifts.push(result.unwrap())
+        });
+        assert_eq!(
+            drifts[0],
+            Drift::Content {
+                path: path("2023/content.jpg")
+            }
+        );
+        assert_eq!(drifts.len(), 4);
+    }
+}
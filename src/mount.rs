@@ -0,0 +1,202 @@
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+/// MountPin define the filesystem the destination must be stored on.
+///
+/// Replicating files while the destination filesystem isn't mounted would fill
+/// the (empty) mount point directory of the parent filesystem instead.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MountPin {
+    /// Path of the directory the filesystem must be mounted on.
+    MountPoint(PathBuf),
+    /// UUID of the filesystem, it can be mounted anywhere.
+    Uuid(String),
+}
+
+impl MountPin {
+    /// Returns whether the pinned filesystem is currently mounted.
+    pub fn is_mounted(&self) -> io::Result<bool> {
+        match self {
+            MountPin::MountPoint(path) => is_mount_point(path),
+            MountPin::Uuid(uuid) => is_uuid_mounted(uuid),
+        }
+    }
+}
+
+impl Display for MountPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MountPin::MountPoint(path) => write!(f, "mount point {:?}", path),
+            MountPin::Uuid(uuid) => write!(f, "filesystem UUID {}", uuid),
+        }
+    }
+}
+
+/// Unmounts and ejects the removable media the given path is stored on,
+/// with `diskutil` on macOS and `eject` on other platforms.
+pub fn eject(path: &Path) -> io::Result<()> {
+    let root = mount_point(path)?;
+
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("diskutil");
+        command.arg("eject");
+        command
+    } else {
+        Command::new("eject")
+    };
+    let status = command.arg(&root).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "failed to eject {:?}: {:?} exited with {}",
+            root,
+            command.get_program(),
+            status
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns the mount point of the filesystem the given path is stored on, the
+/// root filesystem isn't removable and is returned as error.
+fn mount_point(path: &Path) -> io::Result<PathBuf> {
+    let mut root = fs::canonicalize(path)?;
+    loop {
+        if root.parent().is_none() {
+            return Err(io::Error::other(format!(
+                "no mount point found for {:?}, it is stored on the root filesystem",
+                path
+            )));
+        }
+        if is_mount_point(&root)? {
+            return Ok(root);
+        }
+        root.pop();
+    }
+}
+
+/// A directory is a mount point if it isn't on the same device as its parent.
+#[cfg(unix)]
+fn is_mount_point(path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let path = match fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    let parent = match path.parent() {
+        Some(parent) => parent,
+        // root directory is always mounted.
+        None => return Ok(true),
+    };
+
+    Ok(fs::metadata(&path)?.dev() != fs::metadata(parent)?.dev())
+}
+
+#[cfg(not(unix))]
+fn is_mount_point(_path: &Path) -> io::Result<bool> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "mount point detection is not supported on this platform",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn is_uuid_mounted(uuid: &str) -> io::Result<bool> {
+    let device = match fs::canonicalize(Path::new("/dev/disk/by-uuid").join(uuid)) {
+        Ok(device) => device,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    Ok(mountinfo
+        .lines()
+        .filter_map(mount_source)
+        .any(|source| fs::canonicalize(source).is_ok_and(|s| s == device)))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_uuid_mounted(_uuid: &str) -> io::Result<bool> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "filesystem UUID pinning is not supported on this platform",
+    ))
+}
+
+/// Returns mount source of the given /proc/self/mountinfo line.
+#[cfg(target_os = "linux")]
+fn mount_source(line: &str) -> Option<&str> {
+    // Optional fields are terminated by a single hyphen, it is followed by
+    // filesystem type and mount source.
+    let (_, fields) = line.split_once(" - ")?;
+    fields.split(' ').nth(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::{Path, PathBuf};
+
+    use super::MountPin;
+
+    #[cfg(unix)]
+    #[test]
+    fn root_is_mount_point() {
+        let pin = MountPin::MountPoint(PathBuf::from("/"));
+        assert!(pin.is_mounted().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn root_isnt_ejected() {
+        assert!(super::mount_point(Path::new("/")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn regular_directory_is_not_mount_point() {
+        let dir = env::temp_dir().join(format!("{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+
+        let pin = MountPin::MountPoint(dir.clone());
+        let result = pin.is_mounted();
+        std::fs::remove_dir(&dir).unwrap();
+
+        assert!(!result.unwrap());
+    }
+
+    #[test]
+    fn missing_mount_point() {
+        let pin = MountPin::MountPoint(PathBuf::from("/this/path/does/not/exist"));
+        assert!(!pin.is_mounted().unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn mount_source() {
+        let line = "36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue";
+        assert_eq!(super::mount_source(line), Some("/dev/root"));
+    }
+
+    #[test]
+    fn deserialize() {
+        #[derive(serde::Deserialize)]
+        struct Cfg {
+            mount: MountPin,
+        }
+
+        let cfg: Cfg = toml::from_str("mount = { uuid = \"1234-ABCD\" }").unwrap();
+        assert_eq!(cfg.mount, MountPin::Uuid("1234-ABCD".to_owned()));
+
+        let cfg: Cfg = toml::from_str("mount = { mount_point = \"/mnt/nas\" }").unwrap();
+        assert_eq!(cfg.mount, MountPin::MountPoint(PathBuf::from("/mnt/nas")));
+    }
+}
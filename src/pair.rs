@@ -0,0 +1,588 @@
+//! Files sorted as a unit with the file leading their pair.
+//!
+//! RAW+JPEG pairs: cameras shooting in RAW+JPEG mode write both files with
+//! the same stem (`DSC0001.NEF` and `DSC0001.JPG`) in the same directory.
+//! The JPEG file leads its pair: its metadata is always readable, so the
+//! sorter renders the destination of the RAW file from it to keep both files
+//! together.
+//!
+//! Live Photos: Apple devices record a still image (HEIC or JPEG) and a short
+//! QuickTime video sharing a content identifier, stored in the Apple maker
+//! note of the image and in the metadata of the video. Exported files usually
+//! share their stem too (`IMG_0001.HEIC` and `IMG_0001.MOV`). The image leads
+//! its pair.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use exif::{In, Reader, Tag};
+
+use crate::template::variables::mp4::{self, Mp4Box};
+
+/// Extensions of camera RAW files, lowercase.
+pub const RAW_EXTENSIONS: &[&str] = &[
+    "3fr", "arw", "cr2", "cr3", "crw", "dng", "erf", "kdc", "mrw", "nef", "nrw", "orf", "pef",
+    "raf", "raw", "rw2", "rwl", "sr2", "srf", "srw", "x3f",
+];
+
+/// Extensions of JPEG files, lowercase.
+pub const JPEG_EXTENSIONS: &[&str] = &["jpg", "jpeg"];
+
+/// Extensions of Live Photo still images, lowercase.
+pub const LIVE_PHOTO_EXTENSIONS: &[&str] = &["heic", "heif", "jpg", "jpeg"];
+
+/// Extensions of Live Photo videos, lowercase.
+pub const LIVE_VIDEO_EXTENSIONS: &[&str] = &["mov"];
+
+/// Key of the content identifier in the metadata of Live Photo videos.
+const CONTENT_IDENTIFIER_KEY: &[u8] = b"com.apple.quicktime.content.identifier";
+
+/// Tag of the content identifier in Apple maker notes.
+const CONTENT_IDENTIFIER_TAG: u16 = 0x0011;
+
+/// Maximum size of the metadata boxes of a video read.
+const MAX_METADATA_LEN: u64 = 1 << 20;
+
+/// Role of a file in its pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Raw,
+    Jpeg,
+}
+
+impl Role {
+    /// Returns the role of the given file based on its extension, none if
+    /// it can't be paired.
+    pub fn of(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        if RAW_EXTENSIONS.contains(&extension.as_str()) {
+            Some(Self::Raw)
+        } else if JPEG_EXTENSIONS.contains(&extension.as_str()) {
+            Some(Self::Jpeg)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Raw => "raw",
+            Self::Jpeg => "jpeg",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Returns whether the extension of the given path is one of the given
+/// lowercase extensions.
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Returns the paths with the same stem as the given path and one of the
+/// given extensions, lowercase and uppercase.
+fn same_stem(path: &Path, extensions: &[&str]) -> Vec<PathBuf> {
+    extensions
+        .iter()
+        .flat_map(|extension| [extension.to_string(), extension.to_uppercase()])
+        .map(|extension| path.with_extension(extension))
+        .collect()
+}
+
+/// Returns the first existing file with the same stem as the given path and
+/// one of the given extensions.
+fn sibling(path: &Path, extensions: &[&str]) -> Option<PathBuf> {
+    same_stem(path, extensions)
+        .into_iter()
+        .find(|sibling| sibling.is_file())
+}
+
+/// Returns the paths the file leading the pair of the given file would have:
+/// JPEG files of RAW files (if `raw_jpeg` is set) and still images of Live
+/// Photo videos (if `live_photos` is set) with the same stem.
+pub fn leader_paths(path: &Path, raw_jpeg: bool, live_photos: bool) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if raw_jpeg && Role::of(path) == Some(Role::Raw) {
+        paths.extend(same_stem(path, JPEG_EXTENSIONS));
+    }
+    if live_photos && has_extension(path, LIVE_VIDEO_EXTENSIONS) {
+        paths.extend(same_stem(path, LIVE_PHOTO_EXTENSIONS));
+    }
+    paths
+}
+
+/// Returns whether the given file may lead a pair.
+pub fn may_lead(path: &Path) -> bool {
+    Role::of(path) == Some(Role::Jpeg) || has_extension(path, LIVE_PHOTO_EXTENSIONS)
+}
+
+/// Returns the other file of the RAW+JPEG pair the given file belongs to,
+/// none if it isn't paired.
+pub fn partner(path: &Path) -> Option<PathBuf> {
+    match Role::of(path)? {
+        Role::Raw => sibling(path, JPEG_EXTENSIONS),
+        Role::Jpeg => sibling(path, RAW_EXTENSIONS),
+    }
+}
+
+/// Returns the JPEG file leading the pair of the given RAW file, none if it
+/// isn't a paired RAW file.
+pub fn leader(path: &Path) -> Option<PathBuf> {
+    match Role::of(path)? {
+        Role::Raw => partner(path),
+        Role::Jpeg => None,
+    }
+}
+
+/// Returns the still image of the given Live Photo video, none if it isn't
+/// a Live Photo video. Images with the same stem are preferred, images of
+/// the same directory are searched by content identifier otherwise.
+pub fn live_photo(path: &Path, identifiers: &Identifiers) -> Option<PathBuf> {
+    if !has_extension(path, LIVE_VIDEO_EXTENSIONS) {
+        return None;
+    }
+    if let Some(photo) = sibling(path, LIVE_PHOTO_EXTENSIONS) {
+        return Some(photo);
+    }
+
+    identifiers.find(path, |dir| {
+        let identifier = dir.identifiers.get(path)?.as_ref()?;
+        dir.photos.get(identifier).cloned()
+    })
+}
+
+/// Returns the Live Photo videos of the given still image: the video with the
+/// same stem or videos of the same directory without such image sharing its
+/// content identifier.
+pub fn live_videos(path: &Path, identifiers: &Identifiers) -> Vec<PathBuf> {
+    if !has_extension(path, LIVE_PHOTO_EXTENSIONS) {
+        return Vec::new();
+    }
+    if let Some(video) = sibling(path, LIVE_VIDEO_EXTENSIONS) {
+        return vec![video];
+    }
+
+    identifiers
+        .find(path, |dir| {
+            let identifier = dir.identifiers.get(path)?.as_ref()?;
+            let videos = dir.videos.get(identifier)?;
+            Some(
+                videos
+                    .iter()
+                    .filter(|video| sibling(video, LIVE_PHOTO_EXTENSIONS).is_none())
+                    .cloned()
+                    .collect(),
+            )
+        })
+        .unwrap_or_default()
+}
+
+/// Content identifiers of the Live Photo images and videos of a directory.
+#[derive(Debug)]
+struct DirIdentifiers {
+    modified: Option<SystemTime>,
+    /// Identifiers of every image and video read, none if they have none.
+    identifiers: HashMap<PathBuf, Option<String>>,
+    /// First image (in path order) of every identifier.
+    photos: HashMap<String, PathBuf>,
+    videos: HashMap<String, Vec<PathBuf>>,
+}
+
+impl DirIdentifiers {
+    fn read(dir: &Path, modified: Option<SystemTime>) -> io::Result<Self> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .collect();
+        paths.sort();
+
+        let mut identifiers = HashMap::new();
+        let mut photos = HashMap::new();
+        let mut videos: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for path in paths {
+            let identifier = if has_extension(&path, LIVE_VIDEO_EXTENSIONS) {
+                let identifier = video_content_identifier(&path).ok().flatten();
+                if let Some(identifier) = &identifier {
+                    videos
+                        .entry(identifier.clone())
+                        .or_default()
+                        .push(path.clone());
+                }
+                identifier
+            } else if has_extension(&path, LIVE_PHOTO_EXTENSIONS) {
+                let identifier = photo_content_identifier(&path);
+                if let Some(identifier) = &identifier {
+                    photos
+                        .entry(identifier.clone())
+                        .or_insert_with(|| path.clone());
+                }
+                identifier
+            } else {
+                continue;
+            };
+            identifiers.insert(path, identifier);
+        }
+
+        Ok(Self {
+            modified,
+            identifiers,
+            photos,
+            videos,
+        })
+    }
+}
+
+/// Identifiers caches content identifiers of Live Photo images and videos by
+/// directory, so matching the files of a directory parses each of them once.
+/// A directory is read again when a file it didn't contain is looked up and
+/// it was modified since.
+#[derive(Debug, Default)]
+pub struct Identifiers {
+    dirs: Mutex<HashMap<PathBuf, Arc<DirIdentifiers>>>,
+}
+
+impl Identifiers {
+    /// Looks up the identifiers of the directory of the given file.
+    fn find<T, F>(&self, path: &Path, lookup: F) -> Option<T>
+    where
+        F: Fn(&DirIdentifiers) -> Option<T>,
+    {
+        let dir = path.parent()?;
+        let cached = self.dirs.lock().unwrap().get(dir).cloned();
+        if let Some(cached) = &cached {
+            if cached.identifiers.contains_key(path) {
+                return lookup(cached);
+            }
+        }
+
+        let modified = fs::metadata(dir).and_then(|m| m.modified()).ok();
+        if let Some(cached) = &cached {
+            if cached.modified == modified {
+                return lookup(cached);
+            }
+        }
+
+        // directories are read without holding the lock.
+        let identifiers = Arc::new(DirIdentifiers::read(dir, modified).ok()?);
+        self.dirs
+            .lock()
+            .unwrap()
+            .insert(dir.to_owned(), identifiers.clone());
+        lookup(&identifiers)
+    }
+}
+
+/// Returns the content identifier stored in the Apple maker note of the
+/// given image.
+pub fn photo_content_identifier(path: &Path) -> Option<String> {
+    let file = fs::File::open(path).ok()?;
+    let exif = Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+    match &exif.get_field(Tag::MakerNote, In::PRIMARY)?.value {
+        exif::Value::Undefined(maker_note, _) => maker_note_content_identifier(maker_note),
+        _ => None,
+    }
+}
+
+/// Returns the content identifier of an Apple maker note: a `Apple iOS`
+/// header followed by a big endian IFD whose offsets are relative to the
+/// maker note.
+fn maker_note_content_identifier(maker_note: &[u8]) -> Option<String> {
+    if !maker_note.starts_with(b"Apple iOS\0") || maker_note.get(12..14)? != b"MM" {
+        return None;
+    }
+    let u16_at = |offset: usize| {
+        Some(u16::from_be_bytes(
+            maker_note.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    let u32_at = |offset: usize| {
+        Some(u32::from_be_bytes(maker_note.get(offset..offset + 4)?.try_into().ok()?) as usize)
+    };
+
+    let count = u16_at(14)? as usize;
+    (0..count).find_map(|i| {
+        let entry = 16 + i * 12;
+        // ASCII values longer than 4 bytes are stored at an offset.
+        if u16_at(entry)? != CONTENT_IDENTIFIER_TAG || u16_at(entry + 2)? != 2 {
+            return None;
+        }
+        let len = u32_at(entry + 4)?;
+        let value = match len {
+            0..=4 => maker_note.get(entry + 8..entry + 8 + len)?,
+            _ => {
+                let offset = u32_at(entry + 8)?;
+                maker_note.get(offset..offset + len)?
+            }
+        };
+        let value = value.split(|b| *b == 0).next()?;
+        String::from_utf8(value.to_vec()).ok()
+    })
+}
+
+/// Returns the content identifier stored in the metadata (`moov/meta` keys
+/// and items) of the given QuickTime video.
+pub fn video_content_identifier(path: &Path) -> io::Result<Option<String>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let meta = match mp4::read_movie(&mut reader)?.and_then(|movie| mp4::find(&movie.moov, b"meta"))
+    {
+        Some(meta) => meta,
+        None => return Ok(None),
+    };
+    let children = meta_children(&mut reader, &meta)?;
+    let (keys, items) = match (mp4::find(&children, b"keys"), mp4::find(&children, b"ilst")) {
+        (Some(keys), Some(items)) => (keys, items),
+        _ => return Ok(None),
+    };
+
+    // keys are numbered from 1 in the order they're listed.
+    let keys = mp4::read_payload(&mut reader, &keys, MAX_METADATA_LEN)?;
+    let mut index = None;
+    let (mut offset, mut number) = (8, 1);
+    while let Some(size) = keys.get(offset..offset + 4) {
+        let size = u32::from_be_bytes(size.try_into().unwrap()) as usize;
+        if size < 8 || offset + size > keys.len() {
+            break;
+        }
+        if &keys[offset + 8..offset + size] == CONTENT_IDENTIFIER_KEY {
+            index = Some(number);
+            break;
+        }
+        offset += size;
+        number += 1;
+    }
+    let index: u32 = match index {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+
+    let item = match mp4::find(&mp4::children(&mut reader, &items)?, &index.to_be_bytes()) {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+    let data = match mp4::find(&mp4::children(&mut reader, &item)?, b"data") {
+        Some(data) => data,
+        None => return Ok(None),
+    };
+    // data payload starts with its type and locale.
+    let data = mp4::read_payload(&mut reader, &data, MAX_METADATA_LEN)?;
+    Ok(data
+        .get(8..)
+        .and_then(|value| String::from_utf8(value.to_vec()).ok()))
+}
+
+/// Returns the children of a `meta` box, it's a full box (version and flags
+/// before its children) in MP4 files but not in QuickTime files.
+fn meta_children<R: Read + Seek>(reader: &mut R, meta: &Mp4Box) -> io::Result<Vec<Mp4Box>> {
+    let header = mp4::read_payload(reader, meta, 8)?;
+    if header.get(4..8) == Some(b"hdlr") {
+        return mp4::children(reader, meta);
+    }
+
+    mp4::read_boxes(reader, meta.offset + 4, meta.offset + meta.len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::str::FromStr;
+    use std::{env, fs};
+
+    use exif::{Field, In, Tag, Value};
+    use uuid::Uuid;
+
+    use crate::replicator::CopyReplicator;
+    use crate::sort::{Config, Sorter};
+    use crate::template::Template;
+    use crate::test_utils;
+
+    use super::{Role, LIVE_PHOTO_EXTENSIONS};
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut b = (payload.len() as u32 + 8).to_be_bytes().to_vec();
+        b.extend_from_slice(kind);
+        b.extend_from_slice(payload);
+        b
+    }
+
+    /// Returns a QuickTime video whose metadata only contains the given
+    /// content identifier.
+    fn live_video(identifier: &str) -> Vec<u8> {
+        let key = [b"mdta".as_slice(), super::CONTENT_IDENTIFIER_KEY].concat();
+        let keys = [
+            [0; 4].as_slice(),
+            &1u32.to_be_bytes(),
+            &(key.len() as u32 + 4).to_be_bytes(),
+            &key,
+        ]
+        .concat();
+        let data = [[0, 0, 0, 1, 0, 0, 0, 0].as_slice(), identifier.as_bytes()].concat();
+        let items = mp4_box(&1u32.to_be_bytes(), &mp4_box(b"data", &data));
+        let meta = [
+            mp4_box(b"hdlr", &[0; 24]),
+            mp4_box(b"keys", &keys),
+            mp4_box(b"ilst", &items),
+        ]
+        .concat();
+
+        [
+            mp4_box(b"ftyp", b"qt  "),
+            mp4_box(b"moov", &mp4_box(b"meta", &meta)),
+        ]
+        .concat()
+    }
+
+    /// Returns a JPEG file whose Apple maker note contains the given content
+    /// identifier.
+    fn live_photo(identifier: &str) -> Vec<u8> {
+        let mut maker_note = b"Apple iOS\0\0\x01MM".to_vec();
+        maker_note.extend_from_slice(&1u16.to_be_bytes());
+        maker_note.extend_from_slice(&super::CONTENT_IDENTIFIER_TAG.to_be_bytes());
+        maker_note.extend_from_slice(&2u16.to_be_bytes());
+        maker_note.extend_from_slice(&(identifier.len() as u32 + 1).to_be_bytes());
+        maker_note.extend_from_slice(&32u32.to_be_bytes());
+        maker_note.extend_from_slice(&[0; 4]);
+        maker_note.extend_from_slice(identifier.as_bytes());
+        maker_note.push(0);
+
+        test_utils::jpeg(
+            &[Field {
+                tag: Tag::MakerNote,
+                ifd_num: In::PRIMARY,
+                value: Value::Undefined(maker_note, 0),
+            }],
+            None,
+        )
+    }
+
+    #[test]
+    fn pairs() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir(&root).unwrap();
+        for name in ["DSC0001.NEF", "DSC0001.JPG", "DSC0002.NEF", "IMG_0003.jpeg"] {
+            fs::write(root.join(name), name).unwrap();
+        }
+
+        assert_eq!(Role::of(Path::new("a.Cr3")), Some(Role::Raw));
+        assert_eq!(Role::of(Path::new("a.mov")), None);
+        assert_eq!(
+            super::partner(&root.join("DSC0001.NEF")),
+            Some(root.join("DSC0001.JPG"))
+        );
+        assert_eq!(
+            super::partner(&root.join("DSC0001.JPG")),
+            Some(root.join("DSC0001.NEF"))
+        );
+        assert_eq!(
+            super::leader(&root.join("DSC0001.NEF")),
+            Some(root.join("DSC0001.JPG"))
+        );
+        assert_eq!(super::leader(&root.join("DSC0001.JPG")), None);
+        assert_eq!(super::partner(&root.join("DSC0002.NEF")), None);
+        assert_eq!(super::partner(&root.join("IMG_0003.jpeg")), None);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn live_photos() {
+        let identifier = "0A2A9F7C-2D4B-4F4B-9F3E-6A3C4D5E6F70";
+        let tree = test_utils::TempTree::new()
+            .with_file("IMG_0001.HEIC", "photo")
+            .with_file("IMG_0001.MOV", "video")
+            .with_file("IMG_0002.JPG", live_photo(identifier))
+            .with_file("IMG_0002_1.MOV", live_video(identifier))
+            .with_file("IMG_0003.MOV", live_video("other"));
+
+        assert_eq!(
+            super::photo_content_identifier(&tree.join("IMG_0002.JPG")).as_deref(),
+            Some(identifier)
+        );
+        assert_eq!(
+            super::video_content_identifier(&tree.join("IMG_0002_1.MOV"))
+                .unwrap()
+                .as_deref(),
+            Some(identifier)
+        );
+        let identifiers = super::Identifiers::default();
+        assert_eq!(
+            super::live_photo(&tree.join("IMG_0001.MOV"), &identifiers),
+            Some(tree.join("IMG_0001.HEIC"))
+        );
+        assert_eq!(
+            super::live_photo(&tree.join("IMG_0002_1.MOV"), &identifiers),
+            Some(tree.join("IMG_0002.JPG"))
+        );
+        assert_eq!(
+            super::live_photo(&tree.join("IMG_0003.MOV"), &identifiers),
+            None
+        );
+        assert_eq!(
+            super::live_photo(&tree.join("IMG_0001.HEIC"), &identifiers),
+            None
+        );
+
+        assert_eq!(
+            super::live_videos(&tree.join("IMG_0001.HEIC"), &identifiers),
+            [tree.join("IMG_0001.MOV")]
+        );
+        assert_eq!(
+            super::live_videos(&tree.join("IMG_0002.JPG"), &identifiers),
+            [tree.join("IMG_0002_1.MOV")]
+        );
+        assert_eq!(
+            super::leader_paths(&tree.join("IMG_0003.MOV"), false, true).len(),
+            LIVE_PHOTO_EXTENSIONS.len() * 2
+        );
+    }
+
+    #[test]
+    fn moved_live_photos() {
+        let tree = test_utils::TempTree::new()
+            .with_file("src/IMG_0001.JPG", live_photo("1"))
+            .with_file("src/IMG_0001_1.MOV", live_video("1"))
+            .with_file("src/IMG_0002.JPG", live_photo("2"))
+            .with_file("src/IMG_0002_1.MOV", live_video("2"));
+        let sorter = Sorter::new(
+            Config::new(
+                Template::from_str(&format!("{}/:file.name:", tree.join("dst").display())).unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_move(true)
+            .with_live_photos(true),
+        );
+
+        // video sorted first.
+        for name in [
+            "IMG_0001_1.MOV",
+            "IMG_0001.JPG",
+            "IMG_0002.JPG",
+            "IMG_0002_1.MOV",
+        ] {
+            sorter.sort_file(&tree.join("src").join(name)).unwrap();
+        }
+        tree.assert_files(
+            "dst",
+            &[
+                "dst/IMG_0001.JPG",
+                "dst/IMG_0001_1.MOV",
+                "dst/IMG_0002.JPG",
+                "dst/IMG_0002_1.MOV",
+            ],
+        );
+        tree.assert_files("src", &[]);
+    }
+}
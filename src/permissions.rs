@@ -0,0 +1,442 @@
+//! Modes and ownership of directories and files created at the destination,
+//! e.g. for NAS shares consumed by other services.
+//!
+//! They are applied after replication to directories created by the sorter
+//! and to replicate files. Replicate files sharing their inode with the
+//! source (hard links) and symbolic links are left untouched so sources are
+//! never modified.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::fs::Fs;
+
+#[derive(Error, Debug)]
+pub enum PermissionsError {
+    #[error("invalid mode {0:?}, expected an octal mode such as \"0755\"")]
+    InvalidMode(String),
+
+    #[error("unknown user {0:?}")]
+    UnknownUser(String),
+
+    #[error("unknown group {0:?}")]
+    UnknownGroup(String),
+
+    #[error("changing the owner of files to uid {0} requires root")]
+    ChownUserDenied(u32),
+
+    #[error("changing the group of files to gid {0} requires root or being a member of the group")]
+    ChownGroupDenied(u32),
+}
+
+/// Unix permission bits, written in octal in config files (`"0755"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Mode(u32);
+
+impl Mode {
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+}
+
+impl FromStr for Mode {
+    type Err = PermissionsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match u32::from_str_radix(s, 8) {
+            Ok(bits) if bits <= 0o7777 => Ok(Self(bits)),
+            _ => Err(PermissionsError::InvalidMode(s.to_owned())),
+        }
+    }
+}
+
+impl TryFrom<String> for Mode {
+    type Error = PermissionsError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04o}", self.0)
+    }
+}
+
+/// Owner and group of files, written `user:group`, `user` or `:group` in
+/// config files. Names are resolved through the name service (local files,
+/// LDAP, SSSD...), numeric ids are used as is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl FromStr for Owner {
+    type Err = PermissionsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = s.split_once(':').unwrap_or((s, ""));
+        let uid = match user {
+            "" => None,
+            user => Some(resolve_id(
+                user,
+                nss::user_id,
+                PermissionsError::UnknownUser,
+            )?),
+        };
+        let gid = match group {
+            "" => None,
+            group => Some(resolve_id(
+                group,
+                nss::group_id,
+                PermissionsError::UnknownGroup,
+            )?),
+        };
+
+        Ok(Self { uid, gid })
+    }
+}
+
+impl TryFrom<String> for Owner {
+    type Error = PermissionsError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Owner {
+    /// Returns an error if the current process can't give files this owner:
+    /// only root changes the owner of files, other users may only change
+    /// their group to one they belong to.
+    #[cfg(unix)]
+    pub fn check_permitted(&self) -> Result<(), PermissionsError> {
+        // SAFETY: geteuid and getegid always succeed.
+        let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        if euid == 0 {
+            return Ok(());
+        }
+        if let Some(uid) = self.uid.filter(|uid| *uid != euid) {
+            return Err(PermissionsError::ChownUserDenied(uid));
+        }
+        if let Some(gid) = self
+            .gid
+            .filter(|gid| *gid != egid && !supplementary_groups().contains(gid))
+        {
+            return Err(PermissionsError::ChownGroupDenied(gid));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn check_permitted(&self) -> Result<(), PermissionsError> {
+        Ok(())
+    }
+}
+
+/// Returns the supplementary groups of the current process.
+#[cfg(unix)]
+fn supplementary_groups() -> Vec<u32> {
+    // SAFETY: a zero size only returns the number of groups, the buffer has
+    // room for the returned number of groups.
+    unsafe {
+        let len = libc::getgroups(0, std::ptr::null_mut());
+        let mut groups = vec![0; len.max(0) as usize];
+        let len = libc::getgroups(len, groups.as_mut_ptr());
+        groups.truncate(len.max(0) as usize);
+        groups
+    }
+}
+
+/// Returns the id of the given user or group name, looked up with the given
+/// name service function unless it's numeric.
+fn resolve_id(
+    name: &str,
+    lookup: fn(&str) -> Option<u32>,
+    unknown: fn(String) -> PermissionsError,
+) -> Result<u32, PermissionsError> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+
+    lookup(name).ok_or_else(|| unknown(name.to_owned()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum IdKind {
+    User,
+    Group,
+}
+
+lazy_static! {
+    /// Names of user and group ids looked up during the run, name service
+    /// lookups may read files or query a directory (LDAP, SSSD) each time.
+    static ref NAMES: Mutex<HashMap<(IdKind, u32), Option<String>>> = Mutex::default();
+}
+
+/// Returns the name of the given user id, none if the name service doesn't
+/// know it.
+pub fn user_name(uid: u32) -> Option<String> {
+    cached_name(IdKind::User, uid)
+}
+
+/// Returns the name of the given group id, none if the name service doesn't
+/// know it.
+pub fn group_name(gid: u32) -> Option<String> {
+    cached_name(IdKind::Group, gid)
+}
+
+fn cached_name(kind: IdKind, id: u32) -> Option<String> {
+    let mut names = NAMES.lock().unwrap_or_else(|err| err.into_inner());
+    names
+        .entry((kind, id))
+        .or_insert_with(|| match kind {
+            IdKind::User => nss::user_name(id),
+            IdKind::Group => nss::group_name(id),
+        })
+        .clone()
+}
+
+/// Name service switch lookups (`getpwuid_r(3)`, `getgrgid_r(3)`...), users
+/// and groups come from every configured source and not only from
+/// `/etc/passwd` and `/etc/group`.
+#[cfg(unix)]
+mod nss {
+    use std::ffi::{CStr, CString};
+    use std::mem::MaybeUninit;
+    use std::ptr;
+
+    use libc::{c_char, c_int};
+
+    /// Largest buffer used for a single entry, lookups are retried with a
+    /// larger buffer until then.
+    const MAX_BUFFER_LEN: usize = 1 << 20;
+
+    pub fn user_name(uid: u32) -> Option<String> {
+        lookup_name(
+            // SAFETY: pointers are valid for the duration of the call, the
+            // buffer length is the length of the given buffer.
+            |pwd, buf, len, result| unsafe { libc::getpwuid_r(uid, pwd, buf, len, result) },
+            |pwd: &libc::passwd| pwd.pw_name,
+        )
+    }
+
+    pub fn group_name(gid: u32) -> Option<String> {
+        lookup_name(
+            // SAFETY: see user_name.
+            |grp, buf, len, result| unsafe { libc::getgrgid_r(gid, grp, buf, len, result) },
+            |grp: &libc::group| grp.gr_name,
+        )
+    }
+
+    pub fn user_id(name: &str) -> Option<u32> {
+        let name = CString::new(name).ok()?;
+        lookup(
+            // SAFETY: see user_name, name is a nul-terminated string.
+            |pwd, buf, len, result| unsafe {
+                libc::getpwnam_r(name.as_ptr(), pwd, buf, len, result)
+            },
+            |pwd: &libc::passwd| pwd.pw_uid,
+        )
+    }
+
+    pub fn group_id(name: &str) -> Option<u32> {
+        let name = CString::new(name).ok()?;
+        lookup(
+            // SAFETY: see user_id.
+            |grp, buf, len, result| unsafe {
+                libc::getgrnam_r(name.as_ptr(), grp, buf, len, result)
+            },
+            |grp: &libc::group| grp.gr_gid,
+        )
+    }
+
+    /// Returns the name of the entry found by the given reentrant lookup
+    /// function.
+    fn lookup_name<T>(
+        call: impl Fn(*mut T, *mut c_char, usize, *mut *mut T) -> c_int,
+        name: impl Fn(&T) -> *const c_char,
+    ) -> Option<String> {
+        lookup(call, |entry| {
+            // SAFETY: strings of entries point into the lookup buffer, which
+            // outlives this call.
+            let name = unsafe { CStr::from_ptr(name(entry)) };
+            name.to_string_lossy().into_owned()
+        })
+    }
+
+    /// Calls a reentrant lookup function and returns the value of the found
+    /// entry, none if there is none or the lookup failed.
+    fn lookup<T, V>(
+        call: impl Fn(*mut T, *mut c_char, usize, *mut *mut T) -> c_int,
+        value: impl FnOnce(&T) -> V,
+    ) -> Option<V> {
+        let mut buf: Vec<c_char> = vec![0; 1024];
+        loop {
+            let mut entry = MaybeUninit::<T>::uninit();
+            let mut result = ptr::null_mut();
+            let ret = call(entry.as_mut_ptr(), buf.as_mut_ptr(), buf.len(), &mut result);
+            if ret == libc::ERANGE && buf.len() < MAX_BUFFER_LEN {
+                buf.resize(buf.len() * 2, 0);
+                continue;
+            }
+            if ret != 0 || result.is_null() {
+                return None;
+            }
+
+            // SAFETY: the entry is initialized when the lookup found it.
+            let entry = unsafe { entry.assume_init_ref() };
+            return Some(value(entry));
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod nss {
+    pub fn user_name(_uid: u32) -> Option<String> {
+        None
+    }
+
+    pub fn group_name(_gid: u32) -> Option<String> {
+        None
+    }
+
+    pub fn user_id(_name: &str) -> Option<u32> {
+        None
+    }
+
+    pub fn group_id(_name: &str) -> Option<u32> {
+        None
+    }
+}
+
+/// Permissions applied to directories and files created at the destination,
+/// nothing is changed by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Permissions {
+    pub dir_mode: Option<Mode>,
+    pub file_mode: Option<Mode>,
+    /// Changing the owner of files usually requires running as root.
+    pub chown: Option<Owner>,
+}
+
+impl Permissions {
+    /// Returns whether directories are changed.
+    pub fn changes_dirs(&self) -> bool {
+        self.dir_mode.is_some() || self.chown.is_some()
+    }
+
+    /// Applies the directory mode and owner to the given directory.
+    pub fn apply_dir(&self, fs: &dyn Fs, path: &Path) -> io::Result<()> {
+        apply(fs, path, self.dir_mode, self.chown)
+    }
+
+    /// Applies the file mode and owner to the replicate file of `src_path`,
+    /// unless it's a symbolic link or the same file as the source.
+    pub fn apply_file(
+        &self,
+        fs: &dyn Fs,
+        src_path: &Path,
+        replicate_path: &Path,
+    ) -> io::Result<()> {
+        if self.file_mode.is_none() && self.chown.is_none() {
+            return Ok(());
+        }
+
+        let metadata = fs.symlink_metadata(replicate_path)?;
+        if metadata.is_symlink() || fs.same_file(src_path, replicate_path) {
+            log::debug!(
+                "permissions of {:?} unchanged, it is linked to its source",
+                replicate_path
+            );
+            return Ok(());
+        }
+
+        apply(fs, replicate_path, self.file_mode, self.chown)
+    }
+}
+
+fn apply(fs: &dyn Fs, path: &Path, mode: Option<Mode>, owner: Option<Owner>) -> io::Result<()> {
+    if let Some(owner) = owner {
+        fs.chown(path, owner.uid, owner.gid)?;
+    }
+    // mode is set last as changing the owner clears setuid and setgid bits.
+    if let Some(mode) = mode {
+        fs.set_mode(path, mode.bits())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mode, Owner};
+
+    #[test]
+    fn parse() {
+        assert_eq!("0755".parse::<Mode>().unwrap(), Mode(0o755));
+        assert_eq!("2775".parse::<Mode>().unwrap().to_string(), "2775");
+        assert!("0855".parse::<Mode>().is_err());
+        assert!("17777".parse::<Mode>().is_err());
+
+        assert_eq!(
+            "1000:100".parse::<Owner>().unwrap(),
+            Owner {
+                uid: Some(1000),
+                gid: Some(100)
+            }
+        );
+        assert_eq!(
+            ":100".parse::<Owner>().unwrap(),
+            Owner {
+                uid: None,
+                gid: Some(100)
+            }
+        );
+        assert_eq!("root".parse::<Owner>().unwrap().uid, Some(0));
+        assert!("photosort-unknown-user".parse::<Owner>().is_err());
+        assert!(":photosort-unknown-group".parse::<Owner>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_permitted() {
+        // SAFETY: geteuid and getegid always succeed.
+        let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        let owner = Owner {
+            uid: Some(euid),
+            gid: Some(egid),
+        };
+        assert!(owner.check_permitted().is_ok());
+
+        let owner = Owner {
+            uid: Some(euid.wrapping_add(1)),
+            gid: None,
+        };
+        assert_eq!(owner.check_permitted().is_ok(), euid == 0);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(super::user_name(0).as_deref(), Some("root"));
+        assert_eq!(super::group_name(0).as_deref(), Some("root"));
+        assert_eq!(super::user_name(u32::MAX - 7), None);
+        // cached lookups
+        assert_eq!(super::user_name(u32::MAX - 7), None);
+        assert_eq!(super::user_name(0).as_deref(), Some("root"));
+    }
+}
@@ -0,0 +1,38 @@
+//! Stages library users can plug into a [`Sorter`](crate::sort::Sorter).
+//!
+//! A file goes through filters, then the template is rendered and passed
+//! through planners, the [`ConflictPolicy`](crate::sort::ConflictPolicy)
+//! decides what to do if the destination exists, the
+//! [`Replicator`](crate::replicator::Replicator) replicates the file and
+//! hooks are called with the result.
+
+use std::error::Error;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+
+use crate::sort::SortResult;
+
+/// Error returned by a pipeline stage.
+pub type StageError = Box<dyn Error + Send + Sync>;
+
+/// Filter selects files to sort, files rejected by any filter are skipped.
+pub trait Filter: Debug + Send + Sync {
+    /// Returns whether the given source file is sorted.
+    fn accept(&self, src_path: &Path) -> Result<bool, StageError>;
+}
+
+/// PathPlanner rewrites the destination path of a file, planners are applied in
+/// order to the rendered template.
+pub trait PathPlanner: Debug + Send + Sync {
+    /// Returns the destination path of the given source file, `planned` is
+    /// the path returned by the previous planner.
+    fn plan(&self, src_path: &Path, planned: PathBuf) -> Result<PathBuf, StageError>;
+}
+
+/// Hook is called once a file is sorted, hooks aren't called in dry run.
+pub trait Hook: Debug + Send + Sync {
+    /// Called with the result of sorting the given source file, files that
+    /// failed to sort aren't passed to hooks. Following hooks are skipped if
+    /// it fails, the error carries the result.
+    fn after_sort(&self, src_path: &Path, result: &SortResult) -> Result<(), StageError>;
+}
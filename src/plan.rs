@@ -0,0 +1,990 @@
+use std::cell::OnceCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::encoding;
+use crate::event::Events;
+use crate::index::{Hash, HashIndex};
+use crate::progress::Progress;
+use crate::template::context::{self, Context, Options};
+
+/// Recursively calls `f` on every file under `path` (or `path` itself if it
+/// isn't a directory). Directory entries are visited in name order.
+pub fn walk_files<F>(path: &Path, f: &mut F)
+where
+    F: FnMut(io::Result<PathBuf>),
+{
+    walk_files_after(path, None, f)
+}
+
+/// Same as [`walk_files`] but files up to `after` (included) in walk order are
+/// skipped, directories containing only skipped files aren't read.
+pub fn walk_files_after<F>(path: &Path, after: Option<&Path>, f: &mut F)
+where
+    F: FnMut(io::Result<PathBuf>),
+{
+    if !path.is_dir() {
+        if after.is_none_or(|after| path > after) {
+            f(Ok(path.to_owned()));
+        }
+        return;
+    }
+    if after.is_some_and(|after| path < after && !after.starts_with(path)) {
+        return;
+    }
+
+    let read_dir = match fs::read_dir(path) {
+        Ok(read_dir) => read_dir,
+        Err(err) => return f(Err(err)),
+    };
+
+    let mut paths = Vec::new();
+    for entry in read_dir {
+        match entry {
+            Ok(entry) => paths.push(entry.path()),
+            Err(err) => f(Err(err)),
+        }
+    }
+    paths.sort();
+
+    for path in paths {
+        walk_files_after(&path, after, f);
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum PlanError {
+    #[error("failed to walk source {1:?}: {0}")]
+    Walk(#[source] io::Error, PathBuf),
+
+    #[error("failed to canonicalize source path {1:?}: {0}")]
+    Canonicalize(#[source] io::Error, PathBuf),
+
+    #[error("failed to hash file {1:?}: {0}")]
+    Hash(#[source] io::Error, PathBuf),
+
+    #[error("failed to save planner checkpoint {1:?}: {0}")]
+    Checkpoint(#[source] io::Error, PathBuf),
+}
+
+/// Duplicate is a source file skipped because a file with the same content
+/// was planned before.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Duplicate {
+    pub path: PathBuf,
+    pub original: PathBuf,
+}
+
+/// PlannedFile is a source file to sort.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedFile {
+    /// Absolute path of the file.
+    pub path: PathBuf,
+    /// Absolute path of the source directory containing the file.
+    pub source: PathBuf,
+}
+
+/// PlanItem is a walked source file and what to do with it.
+#[derive(Debug)]
+pub enum PlanItem {
+    File(PlannedFile),
+    Duplicate(Duplicate),
+    /// File excluded by the planner filters.
+    Filtered(PathBuf),
+    Error(PlanError),
+}
+
+/// Plan is the list of source files to sort.
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub files: Vec<PlannedFile>,
+    pub duplicates: Vec<Duplicate>,
+    /// Files excluded by the planner filters.
+    pub filtered: Vec<PathBuf>,
+    pub errors: Vec<PlanError>,
+    /// Last walked file of the checkpoint planning was resumed from.
+    pub resumed_from: Option<PathBuf>,
+}
+
+/// DateRange restricts planned files to the ones whose resolved `date`
+/// variable is within the range (inclusive).
+#[derive(Debug)]
+pub struct DateRange {
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+    /// Options used to resolve dates.
+    pub options: Arc<Options>,
+}
+
+impl DateRange {
+    /// Returns whether the date of the given file is within the range, the
+    /// date is resolved once in `date`.
+    fn contains(&self, path: &Path, date: &OnceCell<Option<NaiveDateTime>>) -> bool {
+        let date = match date.get_or_init(|| resolve_date(path, &self.options)) {
+            Some(date) => date.date(),
+            None => return false,
+        };
+
+        self.since.is_none_or(|since| date >= since) && self.until.is_none_or(|until| date <= until)
+    }
+}
+
+/// Returns the date a file is sorted with, that is its `date` variable.
+pub(crate) fn resolve_date(path: &Path, options: &Arc<Options>) -> Option<NaiveDateTime> {
+    let mut ctx = Context::new(options.clone());
+    context::prepare_template_context(&mut ctx, path).ok()?;
+
+    ctx.get("date")?.render("date", &ctx).ok()?.as_date()
+}
+
+/// Planner walks sources and decides which files are sorted.
+///
+/// Sources are walked in order, when deduplication is enabled, files with the
+/// same content as a previously planned file are skipped. Hence, sources
+/// order define their priority.
+#[derive(Debug)]
+pub struct Planner {
+    sources: Vec<PathBuf>,
+    index: Option<HashIndex>,
+    filter: Filter,
+    checkpoint: Option<CheckpointOptions>,
+    progress: Option<Progress>,
+    /// Events dates of planned files are indexed in, with the options
+    /// resolving dates.
+    events: Option<(Arc<Events>, Arc<Options>)>,
+}
+
+#[derive(Debug)]
+struct CheckpointOptions {
+    path: PathBuf,
+    /// Number of walked files between checkpoints.
+    interval: usize,
+}
+
+/// Checkpoint is the progress of an interrupted planning, files walked up to
+/// the checkpoint were handled and aren't planned again.
+///
+/// It's stored as JSON lines: a header with the key, then a record per
+/// checkpoint with the hashes planned since the previous one, so saving a
+/// checkpoint doesn't rewrite the hashes of every planned file.
+#[derive(Debug, Default)]
+struct Checkpoint {
+    /// Identifies sources and planner options, a checkpoint is only resumed
+    /// by an identical planner.
+    key: String,
+    /// Index of the source being walked.
+    source: usize,
+    /// Last handled file of the source.
+    last: Option<PathBuf>,
+    /// Content hashes of planned files.
+    hashes: Vec<(String, PathBuf)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CheckpointHeader {
+    key: String,
+}
+
+/// Record of a checkpoint, see [`Checkpoint`].
+#[derive(Serialize)]
+struct CheckpointRecord<'a> {
+    source: usize,
+    last: &'a Path,
+    /// Content hashes planned since the previous record.
+    hashes: &'a [(String, PathBuf)],
+}
+
+#[derive(Deserialize)]
+struct OwnedCheckpointRecord {
+    source: usize,
+    last: PathBuf,
+    hashes: Vec<(String, PathBuf)>,
+}
+
+impl Checkpoint {
+    /// Loads the checkpoint of the given key, records are merged in order
+    /// and a partially written last record is ignored.
+    fn load(path: &Path, key: &str) -> Option<Self> {
+        let file = fs::File::open(path).ok()?;
+        let mut lines = BufReader::new(file).split(b'\n').map_while(Result::ok);
+        let header: CheckpointHeader = serde_json::from_slice(&lines.next()?).ok()?;
+        if header.key != key {
+            return None;
+        }
+
+        let mut checkpoint = Self {
+            key: key.to_owned(),
+            ..Default::default()
+        };
+        for line in lines {
+            let Ok(record) = serde_json::from_slice::<OwnedCheckpointRecord>(&line) else {
+                break;
+            };
+            checkpoint.source = record.source;
+            checkpoint.last = Some(record.last);
+            checkpoint.hashes.extend(record.hashes);
+        }
+        Some(checkpoint)
+    }
+
+    /// Replaces the checkpoint file with a header and a single record and
+    /// opens it for appending records.
+    fn save(&self, path: &Path) -> io::Result<CheckpointWriter> {
+        let mut data = Vec::new();
+        let header = CheckpointHeader {
+            key: self.key.clone(),
+        };
+        serde_json::to_writer(&mut data, &header)?;
+        data.push(b'\n');
+        if let Some(last) = &self.last {
+            let record = CheckpointRecord {
+                source: self.source,
+                last,
+                hashes: &self.hashes,
+            };
+            serde_json::to_writer(&mut data, &record)?;
+            data.push(b'\n');
+        }
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, path)?;
+        let file = fs::OpenOptions::new().append(true).open(path)?;
+        Ok(CheckpointWriter { file })
+    }
+}
+
+/// CheckpointWriter appends records to a checkpoint file.
+#[derive(Debug)]
+struct CheckpointWriter {
+    file: fs::File,
+}
+
+impl CheckpointWriter {
+    /// Appends a record with a single write.
+    fn append(&mut self, record: &CheckpointRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.write_all(&line)
+    }
+}
+
+/// Walked files of a planning in walk order, so checkpoints only move past
+/// files handled along with every file walked before them, whatever order
+/// files are handled in.
+#[derive(Debug, Default)]
+struct Walked {
+    /// Files from the first unhandled one, with their source index (none for
+    /// walk errors) and whether they're handled.
+    pending: VecDeque<(Option<(usize, PathBuf)>, bool)>,
+    /// Sequence number of the first pending file.
+    first: u64,
+    /// Sequence number of the next walked file.
+    next: u64,
+    /// Sequence number of the first file that won't be acknowledged, or that
+    /// isn't tracked because too many files were pending, checkpoints never
+    /// move past it and later files aren't tracked.
+    end: Option<u64>,
+    /// Last file handled along with every file walked before it.
+    last: Option<(usize, PathBuf)>,
+}
+
+impl Walked {
+    fn push(&mut self, file: Option<(usize, PathBuf)>) -> u64 {
+        let seq = self.next;
+        self.next += 1;
+        if self.end.is_none() && self.pending.len() >= MAX_PENDING_FILES {
+            log::warn!(
+                "{} walked files are waiting for an older one to be handled, planning progress won't be saved anymore",
+                self.pending.len()
+            );
+            self.end = Some(seq);
+        }
+        if self.end.is_none() {
+            self.pending.push_back((file, false));
+        }
+        seq
+    }
+
+    fn handled(&mut self, seq: u64) {
+        if seq < self.first || self.end.is_some_and(|end| seq >= end) {
+            return;
+        }
+        if let Some(entry) = self.pending.get_mut((seq - self.first) as usize) {
+            entry.1 = true;
+        }
+        while let Some((file, true)) = self.pending.front() {
+            if file.is_some() {
+                self.last = file.clone();
+            }
+            self.pending.pop_front();
+            self.first += 1;
+        }
+    }
+
+    /// Stops tracking files from the given one, which will never be handled.
+    fn dropped(&mut self, seq: u64) {
+        if seq < self.first {
+            return;
+        }
+        let end = self.end.map_or(seq, |end| end.min(seq));
+        self.pending.truncate((end - self.first) as usize);
+        self.end = Some(end);
+    }
+}
+
+/// Maximum number of walked files kept waiting for an older file to be
+/// handled before planning progress stops being saved.
+const MAX_PENDING_FILES: usize = 1 << 16;
+
+/// Ack acknowledges a planned item once it is handled, see
+/// [`Planner::stream`]. Items that are never acknowledged are planned again
+/// when an interrupted planning resumes, dropping an acknowledgement stops
+/// checkpoints before its item.
+#[derive(Debug)]
+pub struct Ack(Option<(Arc<Mutex<Walked>>, u64)>);
+
+impl Ack {
+    fn new(walked: &Arc<Mutex<Walked>>, file: Option<(usize, PathBuf)>) -> Self {
+        let seq = walked.lock().unwrap().push(file);
+        Self(Some((walked.clone(), seq)))
+    }
+
+    /// Marks the item as handled.
+    pub fn ack(mut self) {
+        if let Some((walked, seq)) = self.0.take() {
+            walked.lock().unwrap().handled(seq);
+        }
+    }
+}
+
+impl Drop for Ack {
+    fn drop(&mut self) {
+        if let Some((walked, seq)) = self.0.take() {
+            if let Ok(mut walked) = walked.lock() {
+                walked.dropped(seq);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Filter {
+    pattern: Option<Regex>,
+    date_range: Option<DateRange>,
+}
+
+impl Filter {
+    fn excludes(&self, path: &Path, date: &OnceCell<Option<NaiveDateTime>>) -> bool {
+        if let Some(pattern) = &self.pattern {
+            if !encoding::is_match(pattern, path.as_os_str()) {
+                return true;
+            }
+        }
+
+        match &self.date_range {
+            Some(range) => !range.contains(path, date),
+            None => false,
+        }
+    }
+}
+
+impl Planner {
+    pub fn new(sources: Vec<PathBuf>) -> Self {
+        Self {
+            sources,
+            index: None,
+            filter: Filter::default(),
+            checkpoint: None,
+            progress: None,
+            events: None,
+        }
+    }
+
+    /// Saves planning progress to the given file every `interval` walked
+    /// files, planning resumes from it if it was interrupted. The file is
+    /// removed once planning is done.
+    pub fn with_checkpoint(mut self, path: PathBuf, interval: usize) -> Self {
+        self.checkpoint = Some(CheckpointOptions { path, interval });
+        self
+    }
+
+    fn checkpoint_key(&self) -> String {
+        format!(
+            "{:?} {:?} {}",
+            self.sources,
+            self.filter,
+            self.index.is_some()
+        )
+    }
+
+    /// Plan only files whose path matches the given regular expression.
+    pub fn with_match(mut self, pattern: Regex) -> Self {
+        self.filter.pattern = Some(pattern);
+        self
+    }
+
+    /// Plan only files whose date is within the given range.
+    pub fn with_date_range(mut self, date_range: DateRange) -> Self {
+        self.filter.date_range = Some(date_range);
+        self
+    }
+
+    /// Enables deduplication of files across sources using the given index.
+    pub fn with_dedup(mut self, index: HashIndex) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Indexes dates of planned files in the given events as sources are
+    /// walked, files resumed from a checkpoint aren't indexed.
+    pub fn with_events(mut self, events: Arc<Events>, options: Arc<Options>) -> Self {
+        self.events = Some((events, options));
+        self
+    }
+
+    /// Counts planned items in the given progress.
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Returns the hash index, if deduplication is enabled.
+    pub fn index(&self) -> Option<&HashIndex> {
+        self.index.as_ref()
+    }
+
+    pub fn index_mut(&mut self) -> Option<&mut HashIndex> {
+        self.index.as_mut()
+    }
+
+    /// Returns the last handled file of the checkpoint planning resumes from,
+    /// if any.
+    pub fn resume_point(&self) -> Option<PathBuf> {
+        let options = self.checkpoint.as_ref()?;
+        Checkpoint::load(&options.path, &self.checkpoint_key())?.last
+    }
+
+    /// Returns the number of files planning will walk, files up to the
+    /// checkpoint are left out as they are when planning resumes. Files
+    /// aren't read and filters aren't applied, filtered files are left out
+    /// of the total as they're planned, e.g. by a progress bar.
+    pub fn count_files(&self) -> usize {
+        let checkpoint = self
+            .checkpoint
+            .as_ref()
+            .and_then(|options| Checkpoint::load(&options.path, &self.checkpoint_key()))
+            .unwrap_or_default();
+
+        let mut count = 0;
+        for (i, source) in self.sources.iter().enumerate().skip(checkpoint.source) {
+            let after = checkpoint
+                .last
+                .as_deref()
+                .filter(|_| i == checkpoint.source);
+            walk_files_after(source, after, &mut |_| count += 1);
+        }
+        count
+    }
+
+    /// Plans every file at once, see [`Planner::plan_each`] to plan files as
+    /// they're walked.
+    pub fn plan(&mut self) -> Plan {
+        let mut plan = Plan {
+            resumed_from: self.resume_point(),
+            ..Default::default()
+        };
+        self.plan_each(|item| match item {
+            PlanItem::File(file) => plan.files.push(file),
+            PlanItem::Duplicate(duplicate) => plan.duplicates.push(duplicate),
+            PlanItem::Filtered(path) => plan.filtered.push(path),
+            PlanItem::Error(err) => plan.errors.push(err),
+        });
+
+        plan
+    }
+
+    /// Calls `f` on every planned item as sources are walked, so the plan
+    /// never lives in memory. An item is considered handled once `f` returns.
+    pub fn plan_each<F>(&mut self, mut f: F)
+    where
+        F: FnMut(PlanItem),
+    {
+        self.walk(|item, ack| {
+            f(item);
+            ack.ack();
+        })
+    }
+
+    /// Streams planned items through a bounded channel of the given capacity
+    /// from a planning thread, so files are handled while sources are walked.
+    /// Consumers acknowledge items once handled, in any order, checkpoints
+    /// never move past an unacknowledged item. The planner is returned once
+    /// sources are walked.
+    pub fn stream(mut self, capacity: usize) -> (Receiver<(PlanItem, Ack)>, JoinHandle<Planner>) {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let handle = thread::spawn(move || {
+            self.walk(|item, ack| {
+                let _ = tx.send((item, ack));
+            });
+            self
+        });
+
+        (rx, handle)
+    }
+
+    /// Walks sources and calls `f` on every planned item with its
+    /// acknowledgement, checkpoints only move past acknowledged items.
+    fn walk<F>(&mut self, mut f: F)
+    where
+        F: FnMut(PlanItem, Ack),
+    {
+        let progress = self.progress.clone();
+        let mut f = |item: PlanItem, ack: Ack| {
+            if let Some(progress) = &progress {
+                progress.discovered(&item);
+            }
+            f(item, ack)
+        };
+
+        let key = self.checkpoint_key();
+        let checkpoint = self
+            .checkpoint
+            .as_ref()
+            .and_then(|options| Checkpoint::load(&options.path, &key))
+            .unwrap_or_else(|| Checkpoint {
+                key: key.clone(),
+                ..Default::default()
+            });
+        // the checkpoint is compacted to a single record so records are
+        // appended after complete lines.
+        let mut writer = match &self.checkpoint {
+            Some(options) => match checkpoint.save(&options.path) {
+                Ok(writer) => Some(writer),
+                Err(err) => {
+                    f(
+                        PlanItem::Error(PlanError::Checkpoint(err, options.path.clone())),
+                        Ack(None),
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+        let mut hashes: HashMap<Hash, PathBuf> = checkpoint
+            .hashes
+            .into_iter()
+            .filter_map(|(hash, path)| Some((Hash::from_hex(hash).ok()?, path)))
+            .collect();
+        // hashes planned since the last saved checkpoint.
+        let mut unsaved: Vec<(String, PathBuf)> = Vec::new();
+        let walked = Arc::new(Mutex::new(Walked::default()));
+
+        for (i, source) in self.sources.iter().enumerate().skip(checkpoint.source) {
+            let source_dir = match fs::canonicalize(source) {
+                Ok(path) if path.is_dir() => path,
+                Ok(path) => path.parent().map(Path::to_path_buf).unwrap_or(path),
+                Err(err) => {
+                    f(
+                        PlanItem::Error(PlanError::Canonicalize(err, source.to_owned())),
+                        Ack(None),
+                    );
+                    continue;
+                }
+            };
+
+            let after = checkpoint
+                .last
+                .as_deref()
+                .filter(|_| i == checkpoint.source);
+            let mut count = 0;
+            walk_files_after(source, after, &mut |result| {
+                if let (Some(options), Some(writer)) = (&self.checkpoint, &mut writer) {
+                    let last = walked.lock().unwrap().last.clone();
+                    if let (true, Some((last_source, last))) =
+                        (count > 0 && count % options.interval == 0, last)
+                    {
+                        let record = CheckpointRecord {
+                            source: last_source,
+                            last: &last,
+                            hashes: &unsaved,
+                        };
+                        match writer.append(&record) {
+                            Ok(()) => unsaved.clear(),
+                            Err(err) => f(
+                                PlanItem::Error(PlanError::Checkpoint(err, options.path.clone())),
+                                Ack(None),
+                            ),
+                        }
+                    }
+                }
+                count += 1;
+                let ack = Ack::new(&walked, result.as_ref().ok().map(|path| (i, path.clone())));
+
+                let path = match result
+                    .map_err(|err| PlanError::Walk(err, source.to_owned()))
+                    .and_then(|path| {
+                        fs::canonicalize(&path).map_err(|err| PlanError::Canonicalize(err, path))
+                    }) {
+                    Ok(path) => path,
+                    Err(err) => return f(PlanItem::Error(err), ack),
+                };
+
+                let date = OnceCell::new();
+                if self.filter.excludes(&path, &date) {
+                    return f(PlanItem::Filtered(path), ack);
+                }
+
+                if let Some(index) = &mut self.index {
+                    let hash = match index.hash(&path) {
+                        Ok(hash) => hash,
+                        Err(err) => return f(PlanItem::Error(PlanError::Hash(err, path)), ack),
+                    };
+
+                    // files walked again after a checkpoint are already hashed.
+                    match hashes.get(&hash) {
+                        Some(original) if *original != path => {
+                            return f(
+                                PlanItem::Duplicate(Duplicate {
+                                    path,
+                                    original: original.to_owned(),
+                                }),
+                                ack,
+                            );
+                        }
+                        Some(_) => {}
+                        None => {
+                            if writer.is_some() {
+                                unsaved.push((hash.to_hex().to_string(), path.clone()));
+                            }
+                            hashes.insert(hash, path.clone());
+                        }
+                    }
+                }
+
+                if let Some((events, options)) = &self.events {
+                    if let Some(date) = date.get_or_init(|| resolve_date(&path, options)) {
+                        events.insert(*date);
+                    }
+                }
+
+                f(
+                    PlanItem::File(PlannedFile {
+                        path,
+                        source: source_dir.clone(),
+                    }),
+                    ack,
+                );
+            });
+        }
+
+        if let Some(options) = &self.checkpoint {
+            let _ = fs::remove_file(&options.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::{env, fs};
+
+    use chrono::NaiveDate;
+    use regex::Regex;
+    use uuid::Uuid;
+
+    use crate::index::HashIndex;
+    use crate::queue::PriorityQueue;
+
+    use super::{
+        Checkpoint, CheckpointRecord, DateRange, Duplicate, PlanItem, Planner, Walked,
+        MAX_PENDING_FILES,
+    };
+
+    fn setup_sources() -> (PathBuf, PathBuf, PathBuf) {
+        let root = fs::canonicalize(env::temp_dir())
+            .unwrap()
+            .join(format!("{}", Uuid::new_v4()));
+        let phone = root.join("phone");
+        let cloud = root.join("cloud");
+        fs::create_dir_all(phone.join("DCIM")).unwrap();
+        fs::create_dir_all(&cloud).unwrap();
+
+        fs::write(phone.join("DCIM/a.jpg"), "a").unwrap();
+        fs::write(phone.join("b.jpg"), "b").unwrap();
+        fs::write(cloud.join("a-export.jpg"), "a").unwrap();
+        fs::write(cloud.join("c.jpg"), "c").unwrap();
+
+        (root, phone, cloud)
+    }
+
+    #[test]
+    fn plan_without_dedup() {
+        let (root, phone, cloud) = setup_sources();
+
+        let plan = Planner::new(vec![phone.clone(), cloud.clone()]).plan();
+        assert!(plan.errors.is_empty());
+        assert!(plan.duplicates.is_empty());
+        assert_eq!(plan.files[0].source, phone);
+        assert_eq!(
+            plan.files.into_iter().map(|f| f.path).collect::<Vec<_>>(),
+            vec![
+                phone.join("DCIM/a.jpg"),
+                phone.join("b.jpg"),
+                cloud.join("a-export.jpg"),
+                cloud.join("c.jpg"),
+            ]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn plan_with_dedup() {
+        let (root, phone, cloud) = setup_sources();
+
+        let mut planner =
+            Planner::new(vec![cloud.clone(), phone.clone()]).with_dedup(HashIndex::default());
+        let plan = planner.plan();
+        assert!(plan.errors.is_empty());
+        assert_eq!(
+            plan.files.into_iter().map(|f| f.path).collect::<Vec<_>>(),
+            vec![
+                cloud.join("a-export.jpg"),
+                cloud.join("c.jpg"),
+                phone.join("b.jpg"),
+            ]
+        );
+        assert_eq!(
+            plan.duplicates,
+            vec![Duplicate {
+                path: phone.join("DCIM/a.jpg"),
+                original: cloud.join("a-export.jpg"),
+            }]
+        );
+        assert_eq!(planner.index().unwrap().len(), 4);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn plan_with_filters() {
+        let (root, phone, cloud) = setup_sources();
+        fs::write(phone.join("2023-01-15.jpg"), "january").unwrap();
+        fs::write(phone.join("2023-07-01.jpg"), "july").unwrap();
+
+        let mut planner = Planner::new(vec![phone.clone(), cloud.clone()])
+            .with_match(Regex::new(r"/phone/").unwrap())
+            .with_date_range(DateRange {
+                since: NaiveDate::from_ymd_opt(2023, 1, 1),
+                until: NaiveDate::from_ymd_opt(2023, 6, 30),
+                options: Default::default(),
+            });
+        // filters aren't applied when counting.
+        assert_eq!(planner.count_files(), 6);
+        let plan = planner.plan();
+        assert!(plan.errors.is_empty());
+        assert_eq!(
+            plan.files.into_iter().map(|f| f.path).collect::<Vec<_>>(),
+            vec![phone.join("2023-01-15.jpg")]
+        );
+        assert_eq!(plan.filtered.len(), 5);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn plan_resumed_from_checkpoint() {
+        let (root, phone, cloud) = setup_sources();
+        let checkpoint_path = root.join("checkpoint.json");
+
+        let mut planner = Planner::new(vec![phone.clone(), cloud.clone()])
+            .with_checkpoint(checkpoint_path.clone(), 1);
+        // planning was interrupted after handling phone/DCIM/a.jpg.
+        Checkpoint {
+            key: planner.checkpoint_key(),
+            source: 0,
+            last: Some(phone.join("DCIM/a.jpg")),
+            hashes: Vec::new(),
+        }
+        .save(&checkpoint_path)
+        .unwrap();
+
+        assert_eq!(planner.count_files(), 3);
+        let plan = planner.plan();
+        assert!(plan.errors.is_empty());
+        assert_eq!(plan.resumed_from, Some(phone.join("DCIM/a.jpg")));
+        assert_eq!(
+            plan.files.into_iter().map(|f| f.path).collect::<Vec<_>>(),
+            vec![
+                phone.join("b.jpg"),
+                cloud.join("a-export.jpg"),
+                cloud.join("c.jpg"),
+            ]
+        );
+        assert!(!checkpoint_path.exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_records() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        let path = root.join("checkpoint.json");
+
+        let hash = |name: &str| (name.to_owned(), PathBuf::from("/src").join(name));
+        let mut writer = Checkpoint {
+            key: "key".to_owned(),
+            source: 0,
+            last: Some("/src/a.jpg".into()),
+            hashes: vec![hash("a.jpg")],
+        }
+        .save(&path)
+        .unwrap();
+        writer
+            .append(&CheckpointRecord {
+                source: 1,
+                last: "/src/b.jpg".as_ref(),
+                hashes: &[hash("b.jpg")],
+            })
+            .unwrap();
+        // interrupted while appending a record.
+        let mut content = fs::read(&path).unwrap();
+        content.extend_from_slice(br#"{"source":1,"last":"/src/c"#);
+        fs::write(&path, content).unwrap();
+
+        let checkpoint = Checkpoint::load(&path, "key").unwrap();
+        assert_eq!(checkpoint.source, 1);
+        assert_eq!(checkpoint.last, Some("/src/b.jpg".into()));
+        assert_eq!(checkpoint.hashes, vec![hash("a.jpg"), hash("b.jpg")]);
+        assert!(Checkpoint::load(&path, "other").is_none());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn plan_resumed_with_starved_item() {
+        let (root, _, _) = setup_sources();
+        let src = root.join("src");
+        fs::create_dir_all(&src).unwrap();
+        for name in ["a.jpg", "b.mp4", "c.jpg", "d.jpg", "e.jpg"] {
+            fs::write(src.join(name), name).unwrap();
+        }
+        let checkpoint_path = root.join("checkpoint.json");
+        let interrupted_path = root.join("interrupted.json");
+
+        // one file waits in the queue while the next one is walked, b.mp4
+        // has a low priority and stays there while newer files are handled
+        // until the run is interrupted.
+        let queue = PriorityQueue::new(8);
+        let mut handled = Vec::new();
+        Planner::new(vec![src.clone()])
+            .with_checkpoint(checkpoint_path.clone(), 1)
+            .walk(|item, ack| {
+                let PlanItem::File(file) = item else {
+                    panic!("unexpected item {:?}", item)
+                };
+                let priority = if file.path.ends_with("b.mp4") { -1 } else { 1 };
+                let first = file.path.ends_with("a.jpg");
+                queue.push((file.path, ack), priority);
+                if !first {
+                    let (path, ack) = queue.pop().unwrap();
+                    handled.push(path);
+                    ack.ack();
+                }
+                if checkpoint_path.exists() {
+                    fs::copy(&checkpoint_path, &interrupted_path).unwrap();
+                }
+            });
+        assert_eq!(
+            handled,
+            vec![
+                src.join("a.jpg"),
+                src.join("c.jpg"),
+                src.join("d.jpg"),
+                src.join("e.jpg"),
+            ]
+        );
+
+        fs::rename(&interrupted_path, &checkpoint_path).unwrap();
+        let plan = Planner::new(vec![src.clone()])
+            .with_checkpoint(checkpoint_path.clone(), 1)
+            .plan();
+        assert_eq!(plan.resumed_from, Some(src.join("a.jpg")));
+        assert_eq!(
+            plan.files.into_iter().map(|f| f.path).collect::<Vec<_>>(),
+            vec![
+                src.join("b.mp4"),
+                src.join("c.jpg"),
+                src.join("d.jpg"),
+                src.join("e.jpg"),
+            ]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn walked_pending_bounded() {
+        let file = |name: &str| Some((0, PathBuf::from(name)));
+
+        // a dropped item is never handled, files after it aren't tracked.
+        let mut walked = Walked::default();
+        let a = walked.push(file("a"));
+        let b = walked.push(file("b"));
+        let c = walked.push(file("c"));
+        walked.handled(c);
+        walked.dropped(b);
+        assert_eq!(walked.pending.len(), 1);
+        walked.push(file("d"));
+        walked.handled(a);
+        assert!(walked.pending.is_empty());
+        assert_eq!(walked.last, file("a"));
+
+        // files walked while the first one waits stop being tracked.
+        let mut walked = Walked::default();
+        let first = walked.push(file("first"));
+        for _ in 0..MAX_PENDING_FILES * 2 {
+            let seq = walked.push(None);
+            walked.handled(seq);
+        }
+        assert_eq!(walked.pending.len(), MAX_PENDING_FILES);
+        walked.handled(first);
+        assert!(walked.pending.is_empty());
+        assert_eq!(walked.last, file("first"));
+    }
+
+    #[test]
+    fn plan_streamed() {
+        let (root, phone, cloud) = setup_sources();
+
+        let planner =
+            Planner::new(vec![cloud.clone(), phone.clone()]).with_dedup(HashIndex::default());
+        let (rx, handle) = planner.stream(1);
+        let items: Vec<PlanItem> = rx
+            .iter()
+            .map(|(item, ack)| {
+                ack.ack();
+                item
+            })
+            .collect();
+        let planner = handle.join().unwrap();
+
+        assert_eq!(items.len(), 4);
+        assert!(matches!(&items[2], PlanItem::Duplicate(d) if d.path == phone.join("DCIM/a.jpg")));
+        assert_eq!(planner.index().unwrap().len(), 4);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+//! Portable destination names, for libraries stored on or copied to Windows
+//! filesystems and SMB shares.
+//!
+//! Rendered variables can contain characters invalid on these filesystems
+//! (`:` of times, `?` of titles) or render reserved device names (`CON`,
+//! `NUL`). Such characters are replaced, reserved names are suffixed and
+//! trailing dots and spaces, silently stripped by Windows, are replaced too.
+//!
+//! Accented names are also stored decomposed (NFD) by macOS and composed
+//! (NFC) by most Linux tools, libraries synced between them end up with
+//! directories differing only by their normalization form unless paths are
+//! normalized.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
+
+/// Characters invalid in Windows file names, control characters are invalid
+/// too.
+const INVALID_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*', '\\'];
+
+/// Device names reserved by Windows, with or without extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Error, Debug)]
+#[error("invalid replacement {0:?}, expected a single character valid in file names")]
+pub struct ReplacementError(String);
+
+/// PortableNames replaces characters and names invalid on Windows in
+/// destination paths, written as the replacement character in config files
+/// (`"_"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PortableNames {
+    replacement: char,
+}
+
+impl PortableNames {
+    /// Returns the given path with portable components, prefixes and root
+    /// are left untouched.
+    pub fn sanitize_path(&self, path: &Path) -> PathBuf {
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) => self.sanitize_name(&name.to_string_lossy()),
+                component => component.as_os_str().to_owned(),
+            })
+            .collect()
+    }
+
+    fn sanitize_name(&self, name: &str) -> OsString {
+        let mut sanitized: String = name
+            .chars()
+            .map(|c| if is_invalid(c) { self.replacement } else { c })
+            .collect();
+
+        let trimmed = sanitized.trim_end_matches(['.', ' ']).len();
+        let trailing = sanitized.len() - trimmed;
+        sanitized.truncate(trimmed);
+        sanitized.extend(std::iter::repeat_n(self.replacement, trailing));
+
+        let stem = sanitized.split('.').next().unwrap_or_default().trim_end();
+        if RESERVED_NAMES
+            .iter()
+            .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+        {
+            sanitized.insert(stem.len(), self.replacement);
+        }
+
+        sanitized.into()
+    }
+}
+
+fn is_invalid(c: char) -> bool {
+    c.is_control() || INVALID_CHARS.contains(&c)
+}
+
+impl FromStr for PortableNames {
+    type Err = ReplacementError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if !is_invalid(c) && !matches!(c, '/' | '.' | ' ') => {
+                Ok(Self { replacement: c })
+            }
+            _ => Err(ReplacementError(s.to_owned())),
+        }
+    }
+}
+
+impl TryFrom<String> for PortableNames {
+    type Error = ReplacementError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for PortableNames {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.replacement)
+    }
+}
+
+/// UnicodeForm is the Unicode normalization form of destination paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum UnicodeForm {
+    /// Composed characters, used by Linux and Windows.
+    Nfc,
+    /// Decomposed characters, used by macOS.
+    Nfd,
+}
+
+impl UnicodeForm {
+    /// Returns the given path normalized, prefixes, root and components that
+    /// aren't valid UTF-8 are left untouched.
+    pub fn normalize_path(&self, path: &Path) -> PathBuf {
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) => match name.to_str() {
+                    Some(name) => OsString::from(match self {
+                        Self::Nfc => name.nfc().collect::<String>(),
+                        Self::Nfd => name.nfd().collect::<String>(),
+                    }),
+                    None => name.to_owned(),
+                },
+                component => component.as_os_str().to_owned(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{PortableNames, UnicodeForm};
+
+    #[test]
+    fn sanitize_path() {
+        let portable: PortableNames = "_".parse().unwrap();
+        let sanitize = |path: &str| portable.sanitize_path(Path::new(path));
+
+        assert_eq!(
+            sanitize("/dst/2023/IMG_0001.jpg"),
+            PathBuf::from("/dst/2023/IMG_0001.jpg")
+        );
+        assert_eq!(
+            sanitize("/dst/18:42:07/What?*.jpg"),
+            PathBuf::from("/dst/18_42_07/What__.jpg")
+        );
+        assert_eq!(
+            sanitize("/dst/Holidays. /a\tb.jpg"),
+            PathBuf::from("/dst/Holidays__/a_b.jpg")
+        );
+        assert_eq!(
+            sanitize("/dst/con/NUL.txt/lpt1 .jpg"),
+            PathBuf::from("/dst/con_/NUL_.txt/lpt1_ .jpg")
+        );
+        assert_eq!(sanitize("/dst/CONTACTS"), PathBuf::from("/dst/CONTACTS"));
+
+        assert!("".parse::<PortableNames>().is_err());
+        assert!("__".parse::<PortableNames>().is_err());
+        assert!(":".parse::<PortableNames>().is_err());
+        assert_eq!("-".parse::<PortableNames>().unwrap().to_string(), "-");
+    }
+
+    #[test]
+    fn normalize_path() {
+        let composed = "/dst/\u{e9}t\u{e9}/caf\u{e9}.jpg";
+        let decomposed = "/dst/e\u{301}te\u{301}/cafe\u{301}.jpg";
+
+        assert_eq!(
+            UnicodeForm::Nfc.normalize_path(Path::new(decomposed)),
+            PathBuf::from(composed)
+        );
+        assert_eq!(
+            UnicodeForm::Nfd.normalize_path(Path::new(composed)),
+            PathBuf::from(decomposed)
+        );
+        assert_eq!(
+            UnicodeForm::Nfc.normalize_path(Path::new(composed)),
+            PathBuf::from(composed)
+        );
+    }
+}
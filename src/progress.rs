@@ -0,0 +1,179 @@
+//! Progress of a run as a stream of typed events, so GUI wrappers and
+//! terminal interfaces can follow a sort without parsing logs.
+//!
+//! A [`Progress`] is shared by the [`Planner`](crate::plan::Planner) that
+//! discovers files and the [`Sorter`](crate::sort::Sorter) that sorts them,
+//! subscribers receive a [`ProgressEvent`] every time a counter changes.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use crate::plan::PlanItem;
+use crate::sort::{self, SortResult};
+
+/// ProgressEvent is a snapshot of the counters of a run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ProgressEvent {
+    /// Files walked in sources, including duplicates, filtered files and
+    /// walk errors.
+    pub discovered: usize,
+    /// Files planned to be sorted.
+    pub planned: usize,
+    pub replicated: usize,
+    /// Sorted files that weren't replicated (e.g. existing destination).
+    pub skipped: usize,
+    /// Files that failed to be planned or sorted.
+    pub failed: usize,
+    /// Files excluded by planner filters, counted in `skipped` too.
+    pub filtered: usize,
+    /// Last discovered or sorted file.
+    pub current_path: Option<PathBuf>,
+    /// Size of replicated files.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    event: ProgressEvent,
+    subscribers: Vec<Sender<ProgressEvent>>,
+}
+
+/// Progress counts files of a run and sends an event to its subscribers on
+/// every change. Clones share the same counters and subscribers.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    state: Arc<Mutex<State>>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a channel receiving every following event, the subscription
+    /// ends when the receiver is dropped.
+    pub fn subscribe(&self) -> Receiver<ProgressEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.state.lock().unwrap().subscribers.push(tx);
+        rx
+    }
+
+    /// Returns the current counters.
+    pub fn snapshot(&self) -> ProgressEvent {
+        self.state.lock().unwrap().event.clone()
+    }
+
+    fn update<F>(&self, f: F)
+    where
+        F: FnOnce(&mut ProgressEvent),
+    {
+        let mut state = self.state.lock().unwrap();
+        f(&mut state.event);
+
+        let event = state.event.clone();
+        state
+            .subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// Counts an item of the planner.
+    pub fn discovered(&self, item: &PlanItem) {
+        self.update(|event| {
+            event.discovered += 1;
+            match item {
+                PlanItem::File(file) => {
+                    event.planned += 1;
+                    event.current_path = Some(file.path.clone());
+                }
+                PlanItem::Duplicate(duplicate) => {
+                    event.skipped += 1;
+                    event.current_path = Some(duplicate.path.clone());
+                }
+                PlanItem::Filtered(path) => {
+                    event.skipped += 1;
+                    event.filtered += 1;
+                    event.current_path = Some(path.clone());
+                }
+                PlanItem::Error(_) => event.failed += 1,
+            }
+        })
+    }
+
+    /// Counts the result of sorting a file, `bytes` is the size of the
+    /// replicated file.
+    pub fn sorted(&self, src_path: &Path, result: &sort::Result, bytes: u64) {
+        self.update(|event| {
+            match result {
+                Ok(SortResult::Replicated { .. }) => {
+                    event.replicated += 1;
+                    event.bytes += bytes;
+                }
+                Ok(SortResult::Skipped { .. }) => event.skipped += 1,
+                Err(_) => event.failed += 1,
+            }
+            event.current_path = Some(src_path.to_owned());
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    use crate::plan::{PlanItem, Planner};
+    use crate::replicator::CopyReplicator;
+    use crate::sort::{Config, Sorter};
+    use crate::template::Template;
+
+    use super::Progress;
+
+    #[test]
+    fn events() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let (src, dst) = (root.join("src"), root.join("dst"));
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&dst).unwrap();
+        fs::write(src.join("a.jpg"), "aaa").unwrap();
+        fs::write(src.join("b.jpg"), "bbbbb").unwrap();
+        fs::write(src.join("c.jpg"), "c").unwrap();
+        fs::write(dst.join("c.jpg"), "existing").unwrap();
+
+        let progress = Progress::new();
+        let events = progress.subscribe();
+        let template = format!("{}/:file.name:", dst.display());
+        let sorter = Sorter::new(Config::new(
+            Template::from_str(&template).unwrap(),
+            Box::new(CopyReplicator::default()),
+            false,
+        ))
+        .with_progress(progress.clone());
+        Planner::new(vec![src])
+            .with_progress(progress.clone())
+            .plan_each(|item| {
+                if let PlanItem::File(file) = item {
+                    let _ = sorter.sort_source_file(&file.path, Some(&file.source));
+                }
+            });
+
+        let snapshot = progress.snapshot();
+        assert_eq!((snapshot.discovered, snapshot.planned), (3, 3));
+        assert_eq!(
+            (snapshot.replicated, snapshot.skipped, snapshot.failed),
+            (2, 1, 0)
+        );
+        assert_eq!(snapshot.bytes, 8);
+
+        // one event per planned and sorted file.
+        let received: Vec<_> = events.try_iter().collect();
+        assert_eq!(received.len(), 6);
+        assert_eq!(received.last(), Some(&snapshot));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
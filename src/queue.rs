@@ -0,0 +1,143 @@
+//! Bounded queue handing out items by priority, so cheap high priority files
+//! (e.g. JPEGs) are sorted before big low priority ones (e.g. videos) found
+//! at the same time.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Condvar, Mutex};
+
+struct Entry<T> {
+    priority: i32,
+    /// Insertion order, items of the same priority are popped first in
+    /// first out.
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+struct State<T> {
+    heap: BinaryHeap<Entry<T>>,
+    seq: u64,
+    closed: bool,
+}
+
+/// PriorityQueue is a bounded multi-producer multi-consumer queue, items with
+/// the highest priority are popped first.
+pub struct PriorityQueue<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+impl<T> PriorityQueue<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                heap: BinaryHeap::new(),
+                seq: 0,
+                closed: false,
+            }),
+            capacity: capacity.max(1),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    /// Adds an item, waits while the queue is full.
+    pub fn push(&self, item: T, priority: i32) {
+        let mut state = self
+            .not_full
+            .wait_while(self.state.lock().unwrap(), |state| {
+                state.heap.len() >= self.capacity
+            })
+            .unwrap();
+
+        let seq = state.seq;
+        state.seq += 1;
+        state.heap.push(Entry {
+            priority,
+            seq,
+            item,
+        });
+        self.not_empty.notify_one();
+    }
+
+    /// Removes the item with the highest priority, waits for one if the queue
+    /// is empty. None is returned once the queue is closed and empty.
+    pub fn pop(&self) -> Option<T> {
+        let mut state = self
+            .not_empty
+            .wait_while(self.state.lock().unwrap(), |state| {
+                state.heap.is_empty() && !state.closed
+            })
+            .unwrap();
+
+        let entry = state.heap.pop()?;
+        self.not_full.notify_one();
+        Some(entry.item)
+    }
+
+    /// Closes the queue, consumers are done once remaining items are popped.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.not_empty.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::PriorityQueue;
+
+    #[test]
+    fn priority_order() {
+        let queue = PriorityQueue::new(8);
+        queue.push("a.mp4", -1);
+        queue.push("a.jpg", 1);
+        queue.push("a.txt", 0);
+        queue.push("b.jpg", 1);
+        queue.close();
+
+        let items: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(items, ["a.jpg", "b.jpg", "a.txt", "a.mp4"]);
+    }
+
+    #[test]
+    fn bounded() {
+        let queue = PriorityQueue::new(2);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..100 {
+                    queue.push(i, 0);
+                }
+                queue.close();
+            });
+
+            let items: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+            assert_eq!(items, (0..100).collect::<Vec<_>>());
+        });
+    }
+}
@@ -1,7 +1,6 @@
 use std::fmt;
 use std::fmt::Debug;
 use std::fmt::Display;
-use std::fs;
 use std::io;
 use std::path::Path;
 use std::str::FromStr;
@@ -10,9 +9,10 @@ use clap::builder::PossibleValue;
 use serde::de::Error;
 use serde::de::Visitor;
 use serde::{Deserialize, Serialize};
-use symlink::symlink_file;
 use thiserror::Error;
 
+use crate::fs::{Fs, RealFs};
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum ReplicatorKind {
@@ -70,8 +70,15 @@ impl FromStr for ReplicatorKind {
 
 /// Replicator represents a way to replicate a file.
 pub trait Replicator: Send + Sync {
-    fn replicate(&self, src: &Path, dst: &Path) -> io::Result<()>;
+    /// Replicates a file of the given filesystem, every operation goes
+    /// through it.
+    fn replicate_on(&self, fs: &dyn Fs, src: &Path, dst: &Path) -> io::Result<()>;
     fn kind(&self) -> ReplicatorKind;
+
+    /// Replicates a file of the real filesystem.
+    fn replicate(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        self.replicate_on(&RealFs, src, dst)
+    }
 }
 
 impl<'a> Display for dyn Replicator + 'a {
@@ -120,10 +127,7 @@ impl FromStr for Box<dyn Replicator> {
     type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let kind = match ReplicatorKind::from_str(s) {
-            Ok(kind) => kind,
-            Err(err) => return Err(err),
-        };
+        let kind = ReplicatorKind::from_str(s)?;
 
         Ok(Box::from(kind))
     }
@@ -185,15 +189,16 @@ impl ReplicatorWithFallback {
 }
 
 impl Replicator for ReplicatorWithFallback {
-    fn replicate(&self, src: &Path, dst: &Path) -> io::Result<()> {
-        match self.inner.replicate(src, dst) {
+    fn replicate_on(&self, fs: &dyn Fs, src: &Path, dst: &Path) -> io::Result<()> {
+        match self.inner.replicate_on(fs, src, dst) {
             Ok(_) => Ok(()),
             Err(err) => {
-                if let Err(fallback_err) = self.fallback.replicate(src, dst) {
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        ReplicatorFallbackError(self.kind().to_string(), err, fallback_err),
-                    ))
+                if let Err(fallback_err) = self.fallback.replicate_on(fs, src, dst) {
+                    Err(io::Error::other(ReplicatorFallbackError(
+                        self.kind().to_string(),
+                        err,
+                        fallback_err,
+                    )))
                 } else {
                     Ok(())
                 }
@@ -231,7 +236,7 @@ impl NoneReplicator {
 }
 
 impl Replicator for NoneReplicator {
-    fn replicate(&self, _src: &Path, _dst: &Path) -> io::Result<()> {
+    fn replicate_on(&self, _fs: &dyn Fs, _src: &Path, _dst: &Path) -> io::Result<()> {
         Err(Self::replicate_error())
     }
 
@@ -244,8 +249,8 @@ impl Replicator for NoneReplicator {
 pub struct SoftLinkReplicator {}
 
 impl Replicator for SoftLinkReplicator {
-    fn replicate(&self, src: &Path, dst: &Path) -> io::Result<()> {
-        symlink_file(&src, &dst)
+    fn replicate_on(&self, fs: &dyn Fs, src: &Path, dst: &Path) -> io::Result<()> {
+        fs.symlink_file(src, dst)
     }
 
     fn kind(&self) -> ReplicatorKind {
@@ -257,8 +262,8 @@ impl Replicator for SoftLinkReplicator {
 pub struct HardLinkReplicator {}
 
 impl Replicator for HardLinkReplicator {
-    fn replicate(&self, src: &Path, dst: &Path) -> io::Result<()> {
-        fs::hard_link(src, dst)
+    fn replicate_on(&self, fs: &dyn Fs, src: &Path, dst: &Path) -> io::Result<()> {
+        fs.hard_link(src, dst)
     }
 
     fn kind(&self) -> ReplicatorKind {
@@ -270,8 +275,8 @@ impl Replicator for HardLinkReplicator {
 pub struct CopyReplicator {}
 
 impl Replicator for CopyReplicator {
-    fn replicate(&self, src: &Path, dst: &Path) -> io::Result<()> {
-        match fs::copy(src, dst) {
+    fn replicate_on(&self, fs: &dyn Fs, src: &Path, dst: &Path) -> io::Result<()> {
+        match fs.copy(src, dst) {
             Ok(_) => Ok(()),
             Err(err) => Err(err),
         }
@@ -282,6 +287,7 @@ impl Replicator for CopyReplicator {
     }
 }
 
+#[cfg(test)]
 #[derive(Default)]
 struct MockReplicator<F>
 where
@@ -290,8 +296,10 @@ where
     pub replicate_fn: F,
 }
 
+#[cfg(test)]
 impl<F: Fn(&Path, &Path) -> io::Result<()> + Send + Sync> Replicator for MockReplicator<F> {
-    fn replicate(&self, src: &Path, dst: &Path) -> io::Result<()> {
+    /// Mocks replicate files of the real filesystem.
+    fn replicate_on(&self, _fs: &dyn Fs, src: &Path, dst: &Path) -> io::Result<()> {
         (self.replicate_fn)(src, dst)
     }
 
@@ -300,6 +308,7 @@ impl<F: Fn(&Path, &Path) -> io::Result<()> + Send + Sync> Replicator for MockRep
     }
 }
 
+#[cfg(test)]
 impl<F: Fn(&Path, &Path) -> io::Result<()>> Display for MockReplicator<F> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str("mock")
@@ -316,6 +325,7 @@ mod tests {
     #[cfg(unix)]
     use std::os::unix::fs::MetadataExt;
 
+    use crate::fs::{MemFs, Op};
     use crate::replicator::NONE_REPLICATE_ERR_MSG;
 
     use super::{
@@ -445,6 +455,24 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn replicate_on() {
+        let fs = MemFs::new()
+            .with_file("/src/a.jpg", "a")
+            .with_dir("/dst")
+            .with_fault(Op::HardLink, "/dst/a.jpg", io::ErrorKind::CrossesDevices);
+        let replicator = Box::<dyn Replicator>::from_iter([
+            Box::new(HardLinkReplicator::default()) as Box<dyn Replicator>,
+            Box::new(CopyReplicator::default()),
+        ]);
+
+        replicator
+            .replicate_on(&fs, Path::new("/src/a.jpg"), Path::new("/dst/a.jpg"))
+            .unwrap();
+        assert_eq!(fs.read(Path::new("/dst/a.jpg")).unwrap(), b"a");
+        assert!(!Path::new("/dst/a.jpg").exists());
+    }
+
     #[test]
     fn replicator_with_fallback() {
         let (src, dst) = setup();
@@ -495,7 +523,10 @@ mod tests {
         assert!(dst.exists());
         assert!(file_content_is(&dst, "bar"));
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string() == NONE_REPLICATE_ERR_MSG);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .ends_with(NONE_REPLICATE_ERR_MSG));
 
         teardown(&src, &dst);
     }
@@ -0,0 +1,169 @@
+//! Snapshots of source trees used to skip runs when nothing changed since
+//! the last successful one, e.g. for hourly scheduled sorts.
+//!
+//! A snapshot is a hash of the path, size and modification time of every
+//! source file, file contents aren't read. A salt (e.g. the configuration)
+//! is hashed with the listing so changing the configuration sorts files
+//! again. The destination tree is hashed the same way once files are
+//! sorted, so files removed from the destination are sorted again.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::plan;
+
+/// Snapshot of source files and of the destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    sources: blake3::Hash,
+    destination: Option<blake3::Hash>,
+}
+
+impl Snapshot {
+    /// Walks the given sources and returns their snapshot, fails if a file
+    /// can't be listed as the snapshot wouldn't reflect it.
+    pub fn new(sources: &[PathBuf], salt: &[u8]) -> io::Result<Self> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&(salt.len() as u64).to_le_bytes());
+        hasher.update(salt);
+        for source in sources {
+            hash_tree(&mut hasher, source)?;
+        }
+
+        Ok(Self {
+            sources: hasher.finalize(),
+            destination: None,
+        })
+    }
+
+    /// Walks the given destination directory and adds it to the snapshot,
+    /// replacing the destination snapshotted before if any. A missing
+    /// destination is snapshotted as empty.
+    pub fn with_destination(mut self, destination: &Path) -> io::Result<Self> {
+        let mut hasher = blake3::Hasher::new();
+        if destination.exists() {
+            hash_tree(&mut hasher, destination)?;
+        }
+        self.destination = Some(hasher.finalize());
+        Ok(self)
+    }
+
+    /// Loads the snapshot saved at the given path, none if there is none.
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        let hex = match fs::read_to_string(path) {
+            Ok(hex) => hex,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let parse = |hex: &str| {
+            blake3::Hash::from_hex(hex.trim())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        };
+        let mut lines = hex.lines();
+        Ok(Some(Self {
+            sources: parse(lines.next().unwrap_or_default())?,
+            destination: lines.next().map(parse).transpose()?,
+        }))
+    }
+
+    /// Saves the snapshot to the given path, the file is replaced atomically.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut hex = self.sources.to_hex().to_string();
+        if let Some(destination) = &self.destination {
+            hex.push('\n');
+            hex.push_str(&destination.to_hex());
+        }
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, hex.as_bytes())?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+/// Hashes the path, size and modification time of every file of the given
+/// tree, fails if a file can't be listed.
+fn hash_tree(hasher: &mut blake3::Hasher, root: &Path) -> io::Result<()> {
+    let mut result = Ok(());
+    plan::walk_files(root, &mut |path| {
+        if result.is_err() {
+            return;
+        }
+        result = path.and_then(|path| {
+            let metadata = fs::metadata(&path)?;
+            let mtime = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let path = path.as_os_str().as_encoded_bytes();
+            hasher.update(&(path.len() as u64).to_le_bytes());
+            hasher.update(path);
+            hasher.update(&metadata.len().to_le_bytes());
+            hasher.update(&mtime.as_nanos().to_le_bytes());
+            Ok(())
+        });
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    use super::Snapshot;
+
+    #[test]
+    fn snapshot() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(root.join("src/2022")).unwrap();
+        fs::write(root.join("src/a.jpg"), "a").unwrap();
+        fs::write(root.join("src/2022/b.jpg"), "b").unwrap();
+        let sources = [root.join("src")];
+        let path = root.join("state/source-snapshot");
+
+        assert_eq!(Snapshot::load(&path).unwrap(), None);
+        let snapshot = Snapshot::new(&sources, b"cfg").unwrap();
+        snapshot.save(&path).unwrap();
+        assert_eq!(Snapshot::load(&path).unwrap(), Some(snapshot.clone()));
+        assert_eq!(Snapshot::new(&sources, b"cfg").unwrap(), snapshot);
+        assert_ne!(Snapshot::new(&sources, b"other cfg").unwrap(), snapshot);
+
+        // same size, different modification time.
+        fs::File::options()
+            .write(true)
+            .open(root.join("src/a.jpg"))
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        let modified = Snapshot::new(&sources, b"cfg").unwrap();
+        assert_ne!(modified, snapshot);
+
+        fs::write(root.join("src/c.jpg"), "c").unwrap();
+        assert_ne!(Snapshot::new(&sources, b"cfg").unwrap(), modified);
+
+        // destination files are snapshotted too.
+        let with_destination = |snapshot: &Snapshot| {
+            snapshot
+                .clone()
+                .with_destination(&root.join("dst"))
+                .unwrap()
+        };
+        let empty = with_destination(&modified);
+        assert_ne!(empty, modified);
+        empty.save(&path).unwrap();
+        assert_eq!(Snapshot::load(&path).unwrap(), Some(empty.clone()));
+        fs::create_dir_all(root.join("dst")).unwrap();
+        assert_eq!(with_destination(&modified), empty);
+        fs::write(root.join("dst/a.jpg"), "a").unwrap();
+        assert_ne!(with_destination(&modified), empty);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
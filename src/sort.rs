@@ -1,111 +1,2143 @@
+use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::fs;
+use std::ffi::{OsStr, OsString};
+use std::fmt::Debug;
 use std::io;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::result;
+use std::sync::{Arc, Condvar, Mutex};
 
-use serde::Deserialize;
+use chrono::{Local, Months, NaiveDateTime};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::encoding::{self, NonUtf8Policy};
+use crate::event::Events;
+use crate::fs::{Fs, Metadata, RealFs};
+use crate::index::{self, RenderCache};
+use crate::journal::{Entry, Journal, JournalError};
+use crate::marker::{self, Marker};
+use crate::mount::MountPin;
+use crate::pair;
+use crate::permissions::Permissions;
+use crate::pipeline::{Filter, Hook, PathPlanner, StageError};
+use crate::plan;
+use crate::portable::{PortableNames, UnicodeForm};
+use crate::progress::Progress;
 use crate::replicator::Replicator;
+use crate::streams::{self, StreamsPolicy};
+use crate::tags;
 use crate::template;
-use crate::template::context::Context;
+use crate::template::context::{Context, Options, Value};
 use crate::template::Template;
+use crate::thumbnail;
 
 #[derive(Debug, Deserialize)]
+#[serde(try_from = "ConfigFile")]
 pub struct Config {
     template: Template,
     replicator: Box<dyn Replicator>,
 
+    #[serde(skip)]
+    on_conflict: Box<dyn ConflictPolicy>,
+
+    #[serde(default)]
+    mount: Option<MountPin>,
+
+    #[serde(default)]
+    dest_root: Option<PathBuf>,
+
+    #[serde(default)]
+    rules: Vec<Rule>,
+
+    #[serde(default)]
+    mirror: bool,
+
+    #[serde(default)]
+    mark: bool,
+
+    #[serde(default, rename = "move")]
+    move_source: bool,
+
+    #[serde(default)]
+    rename: bool,
+
+    #[serde(default)]
+    skip_duplicates: bool,
+
+    #[serde(default)]
+    extract_thumbs: Option<PathBuf>,
+
+    #[serde(default)]
+    max_component_length: Option<usize>,
+
+    #[serde(default)]
+    max_path_length: Option<usize>,
+
+    #[serde(default)]
+    portable_names: Option<PortableNames>,
+
+    #[serde(default)]
+    normalize_unicode: Option<UnicodeForm>,
+
+    #[serde(default)]
+    streams: StreamsPolicy,
+
+    #[serde(flatten)]
+    permissions: Permissions,
+
+    #[serde(default)]
+    sidecars: Vec<String>,
+
+    #[serde(default)]
+    raw_jpeg_pairs: bool,
+
+    #[serde(default)]
+    live_photos: bool,
+
+    #[serde(default)]
+    event_gap: Option<u32>,
+
+    #[serde(skip)]
+    filters: Vec<Box<dyn Filter>>,
+
+    #[serde(skip)]
+    planners: Vec<Box<dyn PathPlanner>>,
+
+    #[serde(skip)]
+    hooks: Vec<Box<dyn Hook>>,
+
+    #[serde(skip)]
+    fs: Arc<dyn Fs>,
+
+    #[serde(flatten)]
+    options: Arc<Options>,
+}
+
+/// Rule overrides the template used for files whose path matches a regular
+/// expression.
+#[derive(Debug, Deserialize)]
+pub struct Rule {
+    /// Matches every path if none.
+    #[serde(rename = "match", with = "serde_regex", default = "match_all")]
+    pattern: Regex,
+
+    /// Tag matching files must have (see [`tags`]), compared case
+    /// insensitively.
+    #[serde(default)]
+    tag: Option<String>,
+
+    /// Template of matching files, the default template is used if none.
+    #[serde(default)]
+    template: Option<Template>,
+
+    /// Matching files are sorted before files with a lower priority found
+    /// at the same time (see [`Sorter::priority`]), defaults to 0.
+    #[serde(default)]
+    priority: i32,
+
+    /// Only matches files whose resolved `date` variable is older than the
+    /// given number of years, files without date never match.
+    #[serde(default)]
+    older_than: Option<u32>,
+}
+
+fn match_all() -> Regex {
+    Regex::new("").unwrap()
+}
+
+impl Rule {
+    pub fn new(pattern: Regex, template: Option<Template>) -> Self {
+        Self {
+            pattern,
+            tag: None,
+            template,
+            priority: 0,
+            older_than: None,
+        }
+    }
+
+    /// Only matches files with the given tag.
+    pub fn with_tag(mut self, tag: Option<String>) -> Self {
+        self.tag = tag;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Only matches files older than the given number of years, e.g. to
+    /// route them to cold storage.
+    pub fn with_older_than(mut self, years: Option<u32>) -> Self {
+        self.older_than = years;
+        self
+    }
+
+    /// Returns whether the rule applies to the given file, `date` returns
+    /// its resolved date and is only called by rules with an age.
+    pub fn is_match(&self, path: &Path, date: impl FnOnce() -> Option<NaiveDateTime>) -> bool {
+        encoding::is_match(&self.pattern, path.as_os_str())
+            && self.tag.as_ref().is_none_or(|tag| tags::has_tag(path, tag))
+            && self
+                .older_than
+                .is_none_or(|years| is_older_than(date(), years))
+    }
+}
+
+/// Returns whether the date is older than the given number of years.
+fn is_older_than(date: Option<NaiveDateTime>, years: u32) -> bool {
+    let threshold = Local::now()
+        .naive_local()
+        .checked_sub_months(Months::new(years.saturating_mul(12)));
+    match (date, threshold) {
+        (Some(date), Some(threshold)) => date < threshold,
+        _ => false,
+    }
+}
+
+/// ConfigFile is the deserialized form of [`Config`], aliases are expanded
+/// when it is converted.
+#[derive(Deserialize)]
+struct ConfigFile {
+    template: Template,
+    replicator: Box<dyn Replicator>,
+
     #[serde(default)]
     overwrite: bool,
+
+    /// Overrides `overwrite`.
+    #[serde(default)]
+    on_conflict: Option<ConflictPolicyKind>,
+
+    #[serde(default)]
+    mount: Option<MountPin>,
+
+    #[serde(default)]
+    dest_root: Option<PathBuf>,
+
+    #[serde(default)]
+    rules: Vec<Rule>,
+
+    #[serde(default)]
+    mirror: bool,
+
+    #[serde(default)]
+    mark: bool,
+
+    #[serde(default, rename = "move")]
+    move_source: bool,
+
+    #[serde(default)]
+    rename: bool,
+
+    #[serde(default)]
+    skip_duplicates: bool,
+
+    #[serde(default)]
+    extract_thumbs: Option<PathBuf>,
+
+    #[serde(default, deserialize_with = "deserialize_max_component_length")]
+    max_component_length: Option<usize>,
+
+    #[serde(default)]
+    max_path_length: Option<usize>,
+
+    #[serde(default)]
+    portable_names: Option<PortableNames>,
+
+    #[serde(default)]
+    normalize_unicode: Option<UnicodeForm>,
+
+    #[serde(default)]
+    streams: StreamsPolicy,
+
+    #[serde(flatten)]
+    permissions: Permissions,
+
+    #[serde(default)]
+    sidecars: Vec<String>,
+
+    #[serde(default)]
+    raw_jpeg_pairs: bool,
+
+    #[serde(default)]
+    live_photos: bool,
+
+    /// Minutes.
+    #[serde(default)]
+    event_gap: Option<u32>,
+
+    #[serde(default)]
+    aliases: HashMap<String, String>,
+
+    #[serde(flatten)]
+    options: Options,
+}
+
+impl TryFrom<ConfigFile> for Config {
+    type Error = template::AliasError;
+
+    fn try_from(cfg: ConfigFile) -> result::Result<Self, Self::Error> {
+        let mut template = cfg.template;
+        template.expand_aliases(&cfg.aliases)?;
+
+        let mut rules = cfg.rules;
+        for rule in rules.iter_mut() {
+            if let Some(template) = &mut rule.template {
+                template.expand_aliases(&cfg.aliases)?;
+            }
+        }
+
+        let mut config = Config::new(template, cfg.replicator, cfg.overwrite);
+        if let Some(kind) = cfg.on_conflict {
+            config = config.with_on_conflict(Box::new(kind));
+        }
+
+        Ok(config
+            .with_mount(cfg.mount)
+            .with_dest_root(cfg.dest_root)
+            .with_rules(rules)
+            .with_mirror(cfg.mirror)
+            .with_mark(cfg.mark)
+            .with_move(cfg.move_source)
+            .with_rename(cfg.rename)
+            .with_skip_duplicates(cfg.skip_duplicates)
+            .with_extract_thumbs(cfg.extract_thumbs)
+            .with_max_component_length(cfg.max_component_length)
+            .with_max_path_length(cfg.max_path_length)
+            .with_portable_names(cfg.portable_names)
+            .with_normalize_unicode(cfg.normalize_unicode)
+            .with_streams(cfg.streams)
+            .with_permissions(cfg.permissions)
+            .with_sidecars(cfg.sidecars)
+            .with_raw_jpeg_pairs(cfg.raw_jpeg_pairs)
+            .with_live_photos(cfg.live_photos)
+            .with_event_gap(cfg.event_gap)
+            .with_options(cfg.options))
+    }
+}
+
+impl Config {
+    /// Creates a config replicating files with the given template and
+    /// replicator, existing replicate files are overwritten if `overwrite` is
+    /// true and skipped otherwise (see [`Config::with_on_conflict`]).
+    pub fn new(template: Template, replicator: Box<dyn Replicator>, overwrite: bool) -> Self {
+        Self {
+            template,
+            replicator,
+            on_conflict: Box::new(if overwrite {
+                ConflictPolicyKind::Overwrite
+            } else {
+                ConflictPolicyKind::Skip
+            }),
+            mount: None,
+            dest_root: None,
+            rules: Vec::new(),
+            mirror: false,
+            mark: false,
+            move_source: false,
+            rename: false,
+            skip_duplicates: false,
+            extract_thumbs: None,
+            max_component_length: None,
+            max_path_length: None,
+            portable_names: None,
+            normalize_unicode: None,
+            streams: StreamsPolicy::default(),
+            permissions: Permissions::default(),
+            sidecars: Vec::new(),
+            raw_jpeg_pairs: false,
+            live_photos: false,
+            event_gap: None,
+            filters: Vec::new(),
+            planners: Vec::new(),
+            hooks: Vec::new(),
+            fs: Arc::new(RealFs),
+            options: Arc::default(),
+        }
+    }
+
+    /// Sets rules overriding the template of matching files, the first
+    /// matching rule is used.
+    pub fn with_rules(mut self, rules: Vec<Rule>) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Mirror files that don't match any rule with their path relative to
+    /// their source under the destination root instead of rendering the
+    /// template.
+    pub fn with_mirror(mut self, mirror: bool) -> Self {
+        self.mirror = mirror;
+        self
+    }
+
+    /// Mark replicated files with the hash of their source (see [`Marker`])
+    /// and skip marked files, so sorting a destination that is also a source
+    /// doesn't replicate files again.
+    pub fn with_mark(mut self, mark: bool) -> Self {
+        self.mark = mark;
+        self
+    }
+
+    /// Remove source files once their replicate file is verified to have the
+    /// same content. Files replicated as symbolic links are never removed.
+    /// Directories emptied by moves are removed up to the source directory.
+    pub fn with_move(mut self, move_source: bool) -> Self {
+        self.move_source = move_source;
+        self
+    }
+
+    /// Rename moved files when the destination is on the same filesystem
+    /// instead of replicating them, the replicator is only used for other
+    /// filesystems. Files are only renamed if they're moved, see
+    /// [`Config::with_move`].
+    pub fn with_rename(mut self, rename: bool) -> Self {
+        self.rename = rename;
+        self
+    }
+
+    /// Sets how files whose replicate file already exists are handled, see
+    /// [`ConflictPolicyKind`] for built-in policies.
+    pub fn with_on_conflict(mut self, policy: Box<dyn ConflictPolicy>) -> Self {
+        self.on_conflict = policy;
+        self
+    }
+
+    /// Skip files whose replicate file already exists with the same content
+    /// instead of overwriting it or skipping it as an overwrite.
+    pub fn with_skip_duplicates(mut self, skip_duplicates: bool) -> Self {
+        self.skip_duplicates = skip_duplicates;
+        self
+    }
+
+    /// Extract exif thumbnails of replicated files to the given directory,
+    /// with the path of their replicate file relative to the destination
+    /// root. Files without thumbnail are ignored.
+    pub fn with_extract_thumbs(mut self, dir: Option<PathBuf>) -> Self {
+        self.extract_thumbs = dir;
+        self
+    }
+
+    /// Shortens rendered path components longer than the given number of
+    /// bytes (255 on most filesystems): the end of their name is replaced
+    /// with a hash of the whole component, extensions are kept. The length
+    /// must be at least [`MIN_COMPONENT_LENGTH_LIMIT`].
+    pub fn with_max_component_length(mut self, max_len: Option<usize>) -> Self {
+        self.max_component_length = max_len;
+        self
+    }
+
+    /// Shortens the longest components of rendered paths longer than the
+    /// given number of bytes (4096 on Linux) until they fit, components are
+    /// shortened like too long components.
+    pub fn with_max_path_length(mut self, max_len: Option<usize>) -> Self {
+        self.max_path_length = max_len;
+        self
+    }
+
+    /// Replaces characters invalid on Windows filesystems and SMB shares in
+    /// rendered paths, suffixes reserved names (`CON`, `NUL`) and trailing
+    /// dots and spaces are replaced too.
+    pub fn with_portable_names(mut self, portable_names: Option<PortableNames>) -> Self {
+        self.portable_names = portable_names;
+        self
+    }
+
+    /// Normalizes rendered paths to the given Unicode form so names typed or
+    /// synced on macOS (NFD) and Linux (NFC) end up in the same directories.
+    pub fn with_normalize_unicode(mut self, form: Option<UnicodeForm>) -> Self {
+        self.normalize_unicode = form;
+        self
+    }
+
+    /// Sets how named streams (extended attributes, resource forks and
+    /// alternate data streams) lost by the replicator are handled, they are
+    /// reported by default.
+    pub fn with_streams(mut self, streams: StreamsPolicy) -> Self {
+        self.streams = streams;
+        self
+    }
+
+    /// Sets modes and owner of directories created at the destination and
+    /// of replicate files, see [`Permissions`]. Owners the current process
+    /// can't give files are logged as they fail every sort.
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        if let Some(Err(err)) = permissions.chown.map(|owner| owner.check_permitted()) {
+            log::warn!("{}, replicate files will fail to be sorted", err);
+        }
+        self.permissions = permissions;
+        self
+    }
+
+    /// Sets extensions of sidecar files (e.g. `xmp`), sidecars of a file
+    /// (`IMG_0001.xmp` or `IMG_0001.CR2.xmp` for `IMG_0001.CR2`) are
+    /// replicated next to its replicate file instead of being sorted on
+    /// their own.
+    pub fn with_sidecars(mut self, extensions: Vec<String>) -> Self {
+        self.sidecars = extensions;
+        self
+    }
+
+    /// Sets whether RAW files paired with a JPEG file (`DSC0001.NEF` and
+    /// `DSC0001.JPG`) are sorted with the template and metadata of the JPEG
+    /// file, so both files end up next to each other.
+    pub fn with_raw_jpeg_pairs(mut self, raw_jpeg_pairs: bool) -> Self {
+        self.raw_jpeg_pairs = raw_jpeg_pairs;
+        self
+    }
+
+    /// Sets whether Live Photo videos (`IMG_0001.MOV`) are sorted with the
+    /// template and metadata of their still image (`IMG_0001.HEIC`), matched
+    /// by name or content identifier.
+    pub fn with_live_photos(mut self, live_photos: bool) -> Self {
+        self.live_photos = live_photos;
+        self
+    }
+
+    /// Sets the maximum number of minutes between consecutive files of an
+    /// event, see the `date.event` variable.
+    pub fn with_event_gap(mut self, minutes: Option<u32>) -> Self {
+        self.event_gap = minutes;
+        self
+    }
+
+    /// Sets filters selecting files to sort, files rejected by any filter are
+    /// skipped.
+    pub fn with_filters(mut self, filters: Vec<Box<dyn Filter>>) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    /// Sets planners rewriting destination paths in order, after the template
+    /// or mirror path is rendered and before conflicts are handled.
+    pub fn with_planners(mut self, planners: Vec<Box<dyn PathPlanner>>) -> Self {
+        self.planners = planners;
+        self
+    }
+
+    /// Sets hooks called with the result of every sorted file.
+    pub fn with_hooks(mut self, hooks: Vec<Box<dyn Hook>>) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Sets the filesystem files are replicated, overwritten and moved on
+    /// (see [`crate::fs::MemFs`] for tests). Template variables, markers,
+    /// journals and thumbnails always use the real filesystem.
+    pub fn with_fs(mut self, fs: Arc<dyn Fs>) -> Self {
+        self.fs = fs;
+        self
+    }
+
+    /// Sets options used to render template values.
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.options = Arc::new(options);
+        self
+    }
+
+    /// Refuse to replicate files while the given filesystem isn't mounted.
+    pub fn with_mount(mut self, mount: Option<MountPin>) -> Self {
+        self.mount = mount;
+        self
+    }
+
+    /// Renders templates relative to the given directory, rendered paths
+    /// outside of it (`..` components or absolute paths elsewhere) are
+    /// refused so malformed metadata can't write outside the library.
+    pub fn with_dest_root(mut self, root: Option<PathBuf>) -> Self {
+        self.dest_root = root;
+        self
+    }
+
+    /// Replicates files below the given directory instead of their
+    /// destination (e.g. to benchmark sorting without touching the library),
+    /// absolute destinations are kept below it. The filesystem pin doesn't
+    /// apply to the scratch directory.
+    pub fn with_scratch_root(mut self, scratch: &Path) -> Self {
+        let rebase = |path: &Path| {
+            let path = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+            let relative: PathBuf = path
+                .components()
+                .skip_while(|c| matches!(c, Component::Prefix(_) | Component::RootDir))
+                .collect();
+            scratch.join(relative)
+        };
+
+        self.template = self.template.rebase(scratch);
+        for rule in &mut self.rules {
+            rule.template = rule.template.as_ref().map(|t| t.rebase(scratch));
+        }
+        // rendered paths are confined to the scratch directory.
+        self.dest_root = Some(match &self.dest_root {
+            Some(root) => rebase(root),
+            None => scratch.to_owned(),
+        });
+        self.extract_thumbs = self.extract_thumbs.as_deref().map(rebase);
+        self.mount = None;
+        self
+    }
+
+    /// Returns whether source files are removed once replicated.
+    pub fn is_move(&self) -> bool {
+        self.move_source
+    }
+}
+
+/// ConflictPolicy define how files whose replicate file already exists are
+/// handled.
+///
+/// Applications embedding the sorter can implement it to apply their own
+/// rules, [`ConflictPolicyKind`] implements the built-in policies.
+pub trait ConflictPolicy: Debug + Send + Sync {
+    /// Decides what to do with source file `src` whose replicate file `dst`
+    /// already exists, `metadata` are the metadata of `dst`.
+    fn decide(&self, src: &Path, dst: &Path, metadata: &Metadata) -> Decision;
+}
+
+/// Decision of a [`ConflictPolicy`].
+#[derive(Debug)]
+pub enum Decision {
+    /// Skip the file.
+    Skip,
+    /// Replace the existing replicate file.
+    Overwrite,
+    /// Replicate the file to the first free path with a `-1`, `-2`, ...
+    /// suffix. Files with the same content as the existing replicate file or
+    /// one of the suffixed files are skipped as duplicates, so sorting again
+    /// doesn't create copies.
+    Rename,
+    /// Fail to sort the file with the given error.
+    Error(Box<dyn Error + Send + Sync>),
+}
+
+/// ConflictPolicyKind define built-in conflict policies, they always take the
+/// decision of the same name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicyKind {
+    /// Skip the file.
+    #[default]
+    Skip,
+    /// Replace the existing replicate file.
+    Overwrite,
+    /// Replicate the file to the first free path with a `-1`, `-2`, ...
+    /// suffix (see [`Decision::Rename`]).
+    Rename,
+}
+
+impl ConflictPolicy for ConflictPolicyKind {
+    fn decide(&self, _src: &Path, _dst: &Path, _metadata: &Metadata) -> Decision {
+        match self {
+            Self::Skip => Decision::Skip,
+            Self::Overwrite => Decision::Overwrite,
+            Self::Rename => Decision::Rename,
+        }
+    }
+}
+
+/// Sorter define a file sorter.
+///
+/// Sorter render a path template before replicating the file. It can be
+/// shared between threads to sort files in parallel.
+#[derive(Debug)]
+pub struct Sorter {
+    cfg: Config,
+    journal: Option<Journal>,
+    dry_run: bool,
+    run_id: String,
+    /// Replicate files produced under the source directory of the file they
+    /// were replicated from, they're skipped if they are sorted so sorting
+    /// the destination in place doesn't loop.
+    produced: Mutex<HashSet<PathBuf>>,
+    /// Sidecar files replicated with their primary file, they're skipped
+    /// when they are sorted.
+    replicated_sidecars: Mutex<HashSet<PathBuf>>,
+    /// Files sorted with the file leading their pair (or following it),
+    /// they're skipped when they are sorted.
+    paired: Mutex<HashSet<PathBuf>>,
+    /// Replicate files of moved files that may lead a pair, so files of the
+    /// pair sorted afterwards are still rendered with their leader.
+    moved_leaders: Mutex<HashMap<PathBuf, PathBuf>>,
+    /// Content identifiers of Live Photo images and videos by directory.
+    live_photo_identifiers: pair::Identifiers,
+    replicating: Destinations,
+    /// Values of the `counter` variable found or allocated so far.
+    counters: Mutex<Counters>,
+    progress: Option<Progress>,
+    render_cache: Option<Arc<RenderCache>>,
+    /// Dates of files clustered into events, if an event gap is set.
+    events: Option<Arc<Events>>,
+}
+
+/// Variables whose value may change while file size and modification time
+/// don't (e.g. `chown` only changes the change time), templates using them
+/// aren't cached.
+const UNCACHEABLE_VARIABLES: &[&str] = &[
+    "counter",
+    "tags",
+    "tags.first",
+    "date.event",
+    "file.md.owner",
+    "file.md.group",
+    "file.md.mode",
+];
+
+/// Values of the `counter` variable, paths rendered with a counter are keyed
+/// by the path rendered with a zero counter.
+#[derive(Debug, Default)]
+struct Counters {
+    /// Next free counter of each key, every lower counter was found or
+    /// allocated.
+    next: HashMap<PathBuf, i64>,
+    /// Counters found or allocated by key and file size, files are only
+    /// compared with paths of the same size.
+    sizes: HashMap<(PathBuf, u64), Vec<i64>>,
+    /// Paths reserved to source files until they are rendered again, none
+    /// while the reservation is rendered.
+    reserved: HashMap<PathBuf, Option<PathBuf>>,
+}
+
+/// Destinations being replicated, so files sorted concurrently to the same
+/// destination are replicated one after the other.
+#[derive(Debug, Default)]
+struct Destinations {
+    paths: Mutex<HashSet<PathBuf>>,
+    released: Condvar,
+}
+
+impl Destinations {
+    /// Reserves the given destination until the returned reservation is
+    /// dropped, waiting for other threads to release it first.
+    fn reserve(&self, path: &Path) -> Reservation<'_> {
+        let mut paths = self.paths.lock().unwrap();
+        while paths.contains(path) {
+            paths = self.released.wait(paths).unwrap();
+        }
+        paths.insert(path.to_owned());
+
+        Reservation {
+            destinations: self,
+            path: path.to_owned(),
+        }
+    }
+}
+
+struct Reservation<'a> {
+    destinations: &'a Destinations,
+    path: PathBuf,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.destinations.paths.lock().unwrap().remove(&self.path);
+        self.destinations.released.notify_all();
+    }
 }
 
-impl Config {
-    pub fn new(template: Template, replicator: Box<dyn Replicator>, overwrite: bool) -> Self {
-        Self {
-            template,
-            replicator,
-            overwrite,
+impl Sorter {
+    pub fn new(cfg: Config) -> Self {
+        let events = cfg
+            .event_gap
+            .map(|minutes| Arc::new(Events::new(chrono::Duration::minutes(minutes.into()))));
+        Self {
+            cfg,
+            journal: None,
+            dry_run: false,
+            run_id: marker::run_id(),
+            produced: Mutex::default(),
+            replicated_sidecars: Mutex::default(),
+            paired: Mutex::default(),
+            moved_leaders: Mutex::default(),
+            live_photo_identifiers: pair::Identifiers::default(),
+            replicating: Destinations::default(),
+            counters: Mutex::default(),
+            progress: None,
+            render_cache: None,
+            events,
+        }
+    }
+
+    /// Returns the events files are clustered into if templates use the
+    /// `date.event` variable, dates of every file must be indexed before
+    /// files are sorted (see [`plan::Planner::with_events`]).
+    pub fn events(&self) -> Option<&Arc<Events>> {
+        let events = self.events.as_ref()?;
+        std::iter::once(&self.cfg.template)
+            .chain(
+                self.cfg
+                    .rules
+                    .iter()
+                    .filter_map(|rule| rule.template.as_ref()),
+            )
+            .any(|template| template.uses("date.event"))
+            .then_some(events)
+    }
+
+    /// Indexes dates of files of the given sources so `date.event`
+    /// variables can be rendered, nothing is done unless templates use them.
+    /// Files planned with [`plan::Planner::with_events`] are already indexed.
+    pub fn index_events(&self, sources: &[PathBuf]) {
+        if let Some(events) = self.events() {
+            log::info!("indexing dates of source files to cluster events...");
+            plan::Planner::new(sources.to_vec())
+                .with_events(events.clone(), self.cfg.options.clone())
+                .plan_each(|_| {});
+        }
+    }
+
+    /// Report what would be done without touching the filesystem, sort
+    /// results are the ones that would be returned otherwise.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Returns the identifier of the run recorded in journal entries and
+    /// markers of replicated files.
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Sets the identifier of the run, e.g. the one of a summary started
+    /// before the sorter.
+    pub fn with_run_id(mut self, run_id: String) -> Self {
+        self.run_id = run_id;
+        self
+    }
+
+    /// Records replicated files in the given journal.
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Counts sorted files and replicated bytes in the given progress.
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Caches rendered destination paths in the given cache, files that
+    /// didn't change since they were cached aren't rendered again.
+    pub fn with_render_cache(mut self, cache: Arc<RenderCache>) -> Self {
+        self.render_cache = Some(cache);
+        self
+    }
+
+    /// Returns options used to render template values.
+    pub fn options(&self) -> &Arc<Options> {
+        &self.cfg.options
+    }
+
+    /// Returns the directory containing every replicated files, if any. See
+    /// [`Template::root`], the root of relative templates is joined to the
+    /// destination root.
+    pub fn destination_root(&self) -> Option<PathBuf> {
+        self.template_root(&self.cfg.template)
+    }
+
+    /// Returns the directory containing every file replicated with the given
+    /// template, if any.
+    fn template_root(&self, template: &Template) -> Option<PathBuf> {
+        match (&self.cfg.dest_root, template.root()) {
+            (Some(dest_root), Some(root)) => Some(dest_root.join(root)),
+            (dest_root, root) => root.or_else(|| dest_root.clone()),
+        }
+    }
+
+    /// Returns the longest root of the given path rendered with the given
+    /// template, those components aren't rendered from values so they're
+    /// kept as is.
+    fn fixed_root(&self, template: &Template, path: &Path) -> PathBuf {
+        [self.template_root(template), self.cfg.dest_root.clone()]
+            .into_iter()
+            .flatten()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())
+            .unwrap_or_default()
+    }
+
+    /// Returns an error if files can't be replicated to the destination
+    /// because its pinned filesystem isn't mounted.
+    pub fn check_mount(&self) -> result::Result<(), SortError> {
+        let mount = match &self.cfg.mount {
+            Some(mount) => mount,
+            None => return Ok(()),
+        };
+
+        match mount.is_mounted() {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(SortError::NotMounted(mount.clone())),
+            Err(err) => Err(SortError::MountCheckError(err, mount.clone())),
+        }
+    }
+
+    /// Returns the template rendering context of the given file.
+    pub fn context(&self, src_path: &Path) -> result::Result<Context, SortError> {
+        self.source_context(src_path, None)
+    }
+
+    /// Returns the template rendering context of a file of the given source
+    /// directory, `file.relpath` is its path relative to the source.
+    pub fn source_context(
+        &self,
+        src_path: &Path,
+        source: Option<&Path>,
+    ) -> result::Result<Context, SortError> {
+        self.paired_context(src_path, source, self.pair_leader(src_path).as_deref())
+    }
+
+    /// Returns the template rendering context of a file whose pair is led by
+    /// the given file.
+    fn paired_context(
+        &self,
+        src_path: &Path,
+        source: Option<&Path>,
+        leader: Option<&Path>,
+    ) -> result::Result<Context, SortError> {
+        let mut ctx = Context::new(self.cfg.options.clone());
+        template::context::prepare_template_context(&mut ctx, leader.unwrap_or(src_path))?;
+        if source.is_some() {
+            ctx.insert(
+                &["file.relpath"],
+                Box::new(Value::Path(relative_path(src_path, source).to_owned())),
+            );
+        }
+        if let Some(events) = &self.events {
+            if let Some(date) = context_date(&ctx) {
+                // files added since sources were indexed join events.
+                events.insert(date);
+                ctx.insert(&["date.event"], Box::new(Value::String(events.label(date))));
+            }
+        }
+        if leader.is_some() && pair::Role::of(src_path) == Some(pair::Role::Raw) {
+            // paired RAW files are rendered with metadata of their JPEG file.
+            ctx.insert(
+                &["pair.role"],
+                Box::new(Value::String(pair::Role::Raw.to_string())),
+            );
+        }
+
+        Ok(ctx)
+    }
+
+    /// Returns the file leading the pair of the given file: the JPEG file of
+    /// a RAW file or the still image of a Live Photo video, if these pairs
+    /// are sorted together. The replicate file of a leader moved before is
+    /// returned if it isn't found.
+    fn pair_leader(&self, src_path: &Path) -> Option<PathBuf> {
+        if self.cfg.raw_jpeg_pairs {
+            if let Some(leader) = pair::leader(src_path) {
+                return Some(leader);
+            }
+        }
+        let moved_leaders = self.moved_leaders.lock().unwrap();
+        if self.cfg.live_photos {
+            // images matched by content identifier may have been moved.
+            match pair::live_photo(src_path, &self.live_photo_identifiers) {
+                Some(photo) if self.cfg.fs.is_file(&photo) => return Some(photo),
+                Some(photo) => return moved_leaders.get(&photo).cloned(),
+                None => (),
+            }
+        }
+
+        pair::leader_paths(src_path, self.cfg.raw_jpeg_pairs, self.cfg.live_photos)
+            .iter()
+            .find_map(|leader| moved_leaders.get(leader).cloned())
+    }
+
+    /// Returns the files following the given file in its pairs: its RAW file
+    /// and its Live Photo video, if these pairs are sorted together.
+    fn pair_followers(&self, leader: &Path) -> Vec<PathBuf> {
+        let mut followers = Vec::new();
+        if self.cfg.raw_jpeg_pairs && pair::Role::of(leader) == Some(pair::Role::Jpeg) {
+            followers.extend(pair::partner(leader));
+        }
+        if self.cfg.live_photos {
+            followers.extend(pair::live_videos(leader, &self.live_photo_identifiers));
+        }
+        followers
+    }
+
+    /// Claims the other files of the pair of the given file so they're sorted
+    /// with it, along with the file leading their pair. None is returned if
+    /// the file was claimed by another file of its pair.
+    fn claim_pair(
+        &self,
+        src_path: &Path,
+        leader: Option<&Path>,
+    ) -> Option<Vec<(PathBuf, Option<PathBuf>)>> {
+        let mut members = Vec::new();
+        match leader {
+            // leaders moved before aren't next to the file anymore.
+            Some(leader) if leader.parent() == src_path.parent() => {
+                members.push((leader.to_owned(), None));
+                members.extend(
+                    self.pair_followers(leader)
+                        .into_iter()
+                        .filter(|follower| follower != src_path)
+                        .map(|follower| (follower, Some(leader.to_owned()))),
+                );
+            }
+            Some(_) => (),
+            None => members.extend(
+                self.pair_followers(src_path)
+                    .into_iter()
+                    .map(|follower| (follower, Some(src_path.to_owned()))),
+            ),
+        }
+
+        let mut paired = self.paired.lock().unwrap();
+        if paired.remove(src_path) {
+            return None;
+        }
+        members.retain(|(member, _)| paired.insert(member.clone()));
+        Some(members)
+    }
+
+    pub fn sort_file(&self, src_path: &Path) -> Result {
+        self.sort_source_file(src_path, None)
+    }
+
+    /// Sorts a file of the given source directory, mirrored files keep their
+    /// path relative to it. See [`crate::pipeline`] for the stages a file
+    /// goes through.
+    pub fn sort_source_file(&self, src_path: &Path, source: Option<&Path>) -> Result {
+        let result = self.sort_stages(src_path, source).and_then(|result| {
+            if !self.dry_run {
+                for hook in &self.cfg.hooks {
+                    if let Err(err) = hook.after_sort(src_path, &result) {
+                        return Err(SortError::HookError(err, src_path.to_owned(), result));
+                    }
+                }
+            }
+            Ok(result)
+        });
+
+        if let Some(progress) = &self.progress {
+            let bytes = match &result {
+                // source files are gone once moved.
+                Ok(SortResult::Replicated { replicate_path, .. }) => self
+                    .cfg
+                    .fs
+                    .metadata(src_path)
+                    .or_else(|_| self.cfg.fs.metadata(replicate_path))
+                    .map_or(0, |metadata| metadata.len()),
+                _ => 0,
+            };
+            progress.sorted(src_path, &result, bytes);
+        }
+
+        result
+    }
+
+    fn sort_stages(&self, src_path: &Path, source: Option<&Path>) -> Result {
+        if self.produced.lock().unwrap().remove(src_path) {
+            return Ok(SortResult::Skipped {
+                replicate_path: src_path.to_owned(),
+                reason: SkippedReason::AlreadySorted,
+            });
+        }
+
+        if src_path.to_str().is_none() {
+            match self.cfg.options.non_utf8 {
+                NonUtf8Policy::Keep => {}
+                NonUtf8Policy::Transliterate => {
+                    log::warn!("{:?} isn't valid UTF-8, names are transliterated", src_path)
+                }
+                NonUtf8Policy::Skip => {
+                    return Ok(SortResult::Skipped {
+                        replicate_path: src_path.to_owned(),
+                        reason: SkippedReason::NonUtf8,
+                    })
+                }
+                NonUtf8Policy::Error => return Err(SortError::NonUtf8Path(src_path.to_owned())),
+            }
+        }
+
+        for filter in &self.cfg.filters {
+            let accepted = filter
+                .accept(src_path)
+                .map_err(|err| SortError::FilterError(err, src_path.to_owned()))?;
+            if !accepted {
+                return Ok(SortResult::Skipped {
+                    replicate_path: src_path.to_owned(),
+                    reason: SkippedReason::Filtered,
+                });
+            }
+        }
+
+        // moved sidecars have no primary file left.
+        if self.replicated_sidecars.lock().unwrap().remove(src_path) {
+            return Ok(SortResult::Skipped {
+                replicate_path: src_path.to_owned(),
+                reason: SkippedReason::Sidecar,
+            });
+        }
+
+        if let Some(primary) = self.primary_of(src_path) {
+            // sidecar added after its primary file was sorted.
+            let leader = self.pair_leader(&primary);
+            let primary_replicate_path = self.destination(&primary, source, leader.as_deref())?;
+            let replicate_path = sidecar_path(&primary, &primary_replicate_path, src_path);
+            return self.replicate_file(src_path, replicate_path, source);
+        }
+
+        // files of a pair are sorted together, their destinations are
+        // rendered before any of them is moved.
+        let leader = self.pair_leader(src_path);
+        let members = match self.claim_pair(src_path, leader.as_deref()) {
+            Some(members) => members,
+            None => {
+                return Ok(SortResult::Skipped {
+                    replicate_path: src_path.to_owned(),
+                    reason: SkippedReason::Paired,
+                })
+            }
+        };
+        let replicate_path = self.destination(src_path, source, leader.as_deref());
+        let members: Vec<_> = members
+            .into_iter()
+            .map(|(member, leader)| {
+                let replicate_path = self.destination(&member, source, leader.as_deref());
+                (member, replicate_path)
+            })
+            .collect();
+
+        let result = replicate_path
+            .and_then(|replicate_path| self.replicate_member(src_path, replicate_path, source));
+        for (member, replicate_path) in members {
+            let result = replicate_path
+                .and_then(|replicate_path| self.replicate_member(&member, replicate_path, source));
+            match result {
+                Ok(result) => log::info!("paired file {:?} sorted: {:?}", member, result),
+                Err(err) => {
+                    log::warn!("failed to sort paired file {:?}: {}", member, err);
+                    // sorted again if it wasn't yet.
+                    self.paired.lock().unwrap().remove(&member);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Replicates a file and its sidecar files, the replicate files of moved
+    /// files leading a pair are recorded.
+    fn replicate_member(
+        &self,
+        src_path: &Path,
+        replicate_path: PathBuf,
+        source: Option<&Path>,
+    ) -> Result {
+        let result = self.replicate_file(src_path, replicate_path, source)?;
+        if let SortResult::Replicated { replicate_path, .. } = &result {
+            self.replicate_sidecars(src_path, replicate_path, source);
+            if self.cfg.move_source
+                && (self.cfg.raw_jpeg_pairs || self.cfg.live_photos)
+                && pair::may_lead(src_path)
+            {
+                self.moved_leaders
+                    .lock()
+                    .unwrap()
+                    .insert(src_path.to_owned(), replicate_path.clone());
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Returns the planned destination path of a file whose pair is led by
+    /// the given file.
+    fn destination(
+        &self,
+        src_path: &Path,
+        source: Option<&Path>,
+        leader: Option<&Path>,
+    ) -> result::Result<PathBuf, SortError> {
+        // rules with an age match the date of the rendering context, it's
+        // then prepared before the template is chosen.
+        let ctx = match self.cfg.rules.iter().any(|rule| rule.older_than.is_some()) {
+            true => self.paired_context(src_path, source, leader).ok(),
+            false => None,
+        };
+        let date = || ctx.as_ref().and_then(context_date);
+
+        // paired files are sorted with the template of their leader.
+        let replicate_path = match self.template_of(leader.unwrap_or(src_path), date) {
+            Some(template) => self.render_file(template, src_path, source, leader, ctx)?,
+            None => self.mirror_path(src_path, source)?,
+        };
+        self.plan(src_path, replicate_path)
+    }
+
+    fn is_sidecar_extension(&self, extension: &OsStr) -> bool {
+        self.cfg
+            .sidecars
+            .iter()
+            .any(|sidecar| extension.eq_ignore_ascii_case(sidecar))
+    }
+
+    /// Returns the primary file of the given sidecar file, none if it isn't
+    /// a sidecar or its primary file doesn't exist.
+    fn primary_of(&self, path: &Path) -> Option<PathBuf> {
+        if !self.is_sidecar_extension(path.extension()?) {
+            return None;
+        }
+
+        // IMG_0001.CR2.xmp
+        let primary = path.with_extension("");
+        if primary
+            .extension()
+            .is_some_and(|extension| !self.is_sidecar_extension(extension))
+            && self.cfg.fs.is_file(&primary)
+        {
+            return Some(primary);
+        }
+
+        // IMG_0001.xmp
+        let stem = path.file_stem()?;
+        let mut primaries: Vec<PathBuf> = self
+            .cfg
+            .fs
+            .read_dir(path.parent()?)
+            .ok()?
+            .into_iter()
+            .filter(|candidate| {
+                candidate.file_stem() == Some(stem)
+                    && candidate
+                        .extension()
+                        .is_some_and(|extension| !self.is_sidecar_extension(extension))
+                    && self.cfg.fs.is_file(candidate)
+            })
+            .collect();
+        primaries.sort();
+        primaries.into_iter().next()
+    }
+
+    /// Replicates sidecar files of the given file next to its replicate
+    /// file, failures are logged as the file itself is sorted.
+    fn replicate_sidecars(&self, src_path: &Path, replicate_path: &Path, source: Option<&Path>) {
+        for extension in &self.cfg.sidecars {
+            for extension in [extension.to_lowercase(), extension.to_uppercase()] {
+                let candidates = [
+                    src_path.with_extension(&extension),
+                    append_extension(src_path, &extension),
+                ];
+                let sidecars: Vec<_> = candidates
+                    .into_iter()
+                    .filter(|sidecar| sidecar != src_path && self.cfg.fs.is_file(sidecar))
+                    .collect();
+                if sidecars.is_empty() {
+                    continue;
+                }
+
+                for sidecar in sidecars {
+                    let sidecar_replicate_path = sidecar_path(src_path, replicate_path, &sidecar);
+                    match self.replicate_file(&sidecar, sidecar_replicate_path, source) {
+                        Ok(result) => log::info!("sidecar {:?} sorted: {:?}", sidecar, result),
+                        Err(err) => log::warn!("failed to sort sidecar {:?}: {}", sidecar, err),
+                    }
+                    self.replicated_sidecars.lock().unwrap().insert(sidecar);
+                }
+                // sidecar was found with the lowercase extension, uppercase
+                // candidates are the same files on case insensitive
+                // filesystems.
+                break;
+            }
+        }
+    }
+
+    /// Renders the destination path of a file, using the render cache if the
+    /// template can be cached.
+    fn render_file(
+        &self,
+        template: &Template,
+        src_path: &Path,
+        source: Option<&Path>,
+        leader: Option<&Path>,
+        ctx: Option<Context>,
+    ) -> result::Result<PathBuf, SortError> {
+        let render = || {
+            // prepare template rendering context
+            let mut ctx = match ctx {
+                Some(ctx) => ctx,
+                None => self.paired_context(src_path, source, leader)?,
+            };
+            // paired files are named after the file leading their pair.
+            let extension = leader.and(src_path.extension());
+
+            // render destination path template
+            if template.uses("counter") {
+                self.render_counted(template, &mut ctx, src_path, extension)
+            } else {
+                self.render(template, &ctx, extension)
+            }
+        };
+
+        match &self.render_cache {
+            Some(cache) if !UNCACHEABLE_VARIABLES.iter().any(|name| template.uses(name)) => {
+                let key = self.template_key(template, source);
+                match leader {
+                    // paired files are rendered with metadata of their leader.
+                    Some(leader) => match index::signature(leader) {
+                        Ok(signature) => {
+                            let key = format!("{}\n{:?}\n{:?}", key, leader, signature);
+                            cache.get_or_render(src_path, &key, render)
+                        }
+                        Err(_) => render(),
+                    },
+                    None => cache.get_or_render(src_path, &key, render),
+                }
+            }
+            _ => render(),
+        }
+    }
+
+    /// Returns the render cache key of a template, it changes with the
+    /// template, the source directory of the file (`file.relpath`) and the
+    /// options affecting rendered paths.
+    fn template_key(&self, template: &Template, source: Option<&Path>) -> String {
+        let key = format!(
+            "{}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}",
+            template,
+            source,
+            self.cfg.dest_root,
+            self.cfg.options,
+            self.cfg.max_component_length,
+            self.cfg.max_path_length,
+            self.cfg.portable_names,
+            self.cfg.normalize_unicode
+        );
+        blake3::hash(key.as_bytes()).to_hex().to_string()
+    }
+
+    /// Passes the rendered destination path through planners.
+    fn plan(&self, src_path: &Path, rendered: PathBuf) -> result::Result<PathBuf, SortError> {
+        self.cfg
+            .planners
+            .iter()
+            .try_fold(rendered, |planned, planner| {
+                planner
+                    .plan(src_path, planned)
+                    .map_err(|err| SortError::PlanError(err, src_path.to_owned()))
+            })
+    }
+
+    /// Renders a template, replaces the extension of the rendered path if
+    /// any is given, normalizes it, makes names portable and shortens
+    /// components longer than the max component length and paths longer
+    /// than the max path length.
+    fn render(
+        &self,
+        template: &Template,
+        ctx: &Context,
+        extension: Option<&OsStr>,
+    ) -> result::Result<PathBuf, SortError> {
+        let mut path = template.render(ctx).map_err(SortError::TemplateError)?;
+        if let Some(root) = &self.cfg.dest_root {
+            path = root.join(path);
+        }
+        if let Some(extension) = extension {
+            path.set_extension(extension);
+        }
+        let root = self.fixed_root(template, &path);
+        if let Some(form) = &self.cfg.normalize_unicode {
+            // existing roots are found by their exact name.
+            path = map_below(&root, &path, |path| form.normalize_path(path));
+        }
+        if let Some(portable_names) = &self.cfg.portable_names {
+            // roots (e.g. GVFS mounts of SMB shares) may not be portable.
+            path = map_below(&root, &path, |path| portable_names.sanitize_path(path));
+        }
+
+        if let Some(max_len) = self.cfg.max_component_length {
+            path = map_below(&root, &path, |path| truncate_components(path, max_len));
+        }
+
+        if let Some(max_len) = self.cfg.max_path_length {
+            path = truncate_path(&root, &path, max_len)
+                .ok_or_else(|| SortError::RootTooLong(root.clone(), max_len))?;
+        }
+
+        // paths are confined once every component is final.
+        match &self.cfg.dest_root {
+            Some(root) => {
+                confine(root, &path).ok_or_else(|| SortError::OutsideRoot(path, root.clone()))
+            }
+            None => Ok(path),
+        }
+    }
+
+    /// Renders a template using the `counter` variable with the counter of
+    /// an existing path with the same content as the given file (so sorting
+    /// again doesn't create copies) or the next free counter. Existing paths
+    /// are probed once, counters are then allocated in the order files are
+    /// rendered (see [`Sorter::reserve_counter`]).
+    fn render_counted(
+        &self,
+        template: &Template,
+        ctx: &mut Context,
+        src_path: &Path,
+        extension: Option<&OsStr>,
+    ) -> result::Result<PathBuf, SortError> {
+        let render = |ctx: &mut Context, counter| {
+            ctx.set_counter(counter);
+            self.render(template, ctx, extension)
+        };
+
+        let reserving = match self.counters.lock().unwrap().reserved.remove(src_path) {
+            Some(Some(path)) => return Ok(path),
+            Some(None) => true,
+            None => false,
+        };
+        let key = render(ctx, 0)?;
+        let size = self
+            .cfg
+            .fs
+            .metadata(src_path)
+            .map_err(|err| SortError::CompareError(err, src_path.to_owned()))?
+            .len();
+
+        let mut counters = self.counters.lock().unwrap();
+        let counters = &mut *counters;
+        let next = counters.next.entry(key.clone()).or_insert(1);
+        let free = loop {
+            let path = render(ctx, *next)?;
+            match self.cfg.fs.metadata(&path) {
+                Ok(metadata) => {
+                    if metadata.is_file() {
+                        let key = (key.clone(), metadata.len());
+                        counters.sizes.entry(key).or_default().push(*next);
+                    }
+                    *next += 1;
+                }
+                Err(_) => break path,
+            }
+        };
+
+        let same_size = counters.sizes.entry((key, size)).or_default();
+        let mut path = None;
+        for &counter in same_size.iter() {
+            let candidate = render(ctx, counter)?;
+            // paths allocated to files still being sorted don't exist yet.
+            if self.cfg.fs.is_file(&candidate)
+                && same_content(&*self.cfg.fs, src_path, &candidate)
+                    .map_err(|err| SortError::CompareError(err, candidate.clone()))?
+            {
+                path = Some(candidate);
+                break;
+            }
+        }
+        let path = match path {
+            Some(path) => path,
+            None => {
+                same_size.push(*next);
+                *next += 1;
+                free
+            }
+        };
+
+        if reserving {
+            counters
+                .reserved
+                .insert(src_path.to_owned(), Some(path.clone()));
+        }
+        Ok(path)
+    }
+
+    /// Allocates the `counter` variable value of a file ahead of sorting it.
+    /// Counters are allocated in the order files are reserved, reserving
+    /// files as they are planned keeps counters independent of the order
+    /// concurrent workers sort them in. Nothing is done unless templates use
+    /// the `counter` variable.
+    pub fn reserve_counter(&self, src_path: &Path, source: Option<&Path>) {
+        let uses_counter = std::iter::once(&self.cfg.template)
+            .chain(
+                self.cfg
+                    .rules
+                    .iter()
+                    .filter_map(|rule| rule.template.as_ref()),
+            )
+            .any(|template| template.uses("counter"));
+        // sidecars and filtered files don't get a counter.
+        if !uses_counter
+            || self.primary_of(src_path).is_some()
+            || !self
+                .cfg
+                .filters
+                .iter()
+                .all(|filter| filter.accept(src_path).unwrap_or(true))
+        {
+            return;
+        }
+
+        let leader = self.pair_leader(src_path);
+        self.counters
+            .lock()
+            .unwrap()
+            .reserved
+            .insert(src_path.to_owned(), None);
+        // errors are reported when the file is sorted.
+        if let Err(err) = self.destination(src_path, source, leader.as_deref()) {
+            log::debug!("failed to reserve counter of {:?}: {}", src_path, err);
+            self.counters.lock().unwrap().reserved.remove(src_path);
+        }
+    }
+
+    /// Returns the sorting priority of the given file, the priority of the
+    /// first matching rule or 0. Files with a higher priority are sorted
+    /// first when several are waiting.
+    pub fn priority(&self, src_path: &Path) -> i32 {
+        let date = OnceCell::new();
+        let date = || *date.get_or_init(|| plan::resolve_date(src_path, &self.cfg.options));
+        self.cfg
+            .rules
+            .iter()
+            .find(|rule| rule.is_match(src_path, date))
+            .map_or(0, |rule| rule.priority)
+    }
+
+    /// Returns the template of the given file or none if it must be mirrored,
+    /// `date` returns its resolved date.
+    fn template_of(
+        &self,
+        src_path: &Path,
+        date: impl Fn() -> Option<NaiveDateTime>,
+    ) -> Option<&Template> {
+        match self
+            .cfg
+            .rules
+            .iter()
+            .find(|rule| rule.is_match(src_path, &date))
+        {
+            Some(rule) => Some(rule.template.as_ref().unwrap_or(&self.cfg.template)),
+            None if self.cfg.mirror => None,
+            None => Some(&self.cfg.template),
+        }
+    }
+
+    fn mirror_path(
+        &self,
+        src_path: &Path,
+        source: Option<&Path>,
+    ) -> result::Result<PathBuf, SortError> {
+        let root = self
+            .destination_root()
+            .ok_or(SortError::MirrorWithoutRoot)?;
+
+        Ok(root.join(relative_path(src_path, source)))
+    }
+
+    /// Returns the path of the thumbnail of the given replicate file in the
+    /// thumbnails directory.
+    fn thumbnail_path(&self, dir: &Path, replicate_path: &Path) -> PathBuf {
+        let root = self.destination_root();
+        let relative_path = match root.and_then(|root| replicate_path.strip_prefix(root).ok()) {
+            Some(path) if !path.as_os_str().is_empty() => path.to_owned(),
+            _ => PathBuf::from(replicate_path.file_name().unwrap_or_default()),
+        };
+
+        dir.join(relative_path).with_extension("jpg")
+    }
+
+    /// Marks the replicate file, symbolic links, hard links (the marker would
+    /// be shared with their source) and filesystems without extended
+    /// attributes support aren't marked.
+    fn mark(&self, replicate_path: &Path) -> result::Result<(), SortError> {
+        let mark_err = |err| SortError::MarkError(err, replicate_path.to_owned());
+
+        let metadata = std::fs::symlink_metadata(replicate_path).map_err(mark_err)?;
+        if metadata.file_type().is_symlink() || marker::is_hard_linked(&metadata) {
+            return Ok(());
+        }
+
+        let hash = index::hash_file(replicate_path).map_err(mark_err)?;
+        match Marker::new(hash, self.run_id.clone()).write(replicate_path) {
+            Err(err) if err.kind() != io::ErrorKind::Unsupported => Err(mark_err(err)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Warns about or copies named streams of the source file that the
+    /// replicate file doesn't have. Files whose streams can't be listed (e.g.
+    /// not on the real filesystem) are ignored.
+    fn check_streams(
+        &self,
+        src_path: &Path,
+        replicate_path: &Path,
+    ) -> result::Result<(), SortError> {
+        if self.cfg.streams == StreamsPolicy::Ignore {
+            return Ok(());
+        }
+
+        // extended attributes of symbolic links are those of the link, not
+        // of the linked file.
+        let resolve = |path: &Path| match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_symlink() => std::fs::canonicalize(path),
+            _ => Ok(path.to_owned()),
+        };
+        let (src_path, replicate_path) = match (resolve(src_path), resolve(replicate_path)) {
+            (Ok(src_path), Ok(replicate_path)) => (src_path, replicate_path),
+            (Err(err), _) | (_, Err(err)) => {
+                log::debug!("failed to resolve links of {:?}: {}", replicate_path, err);
+                return Ok(());
+            }
+        };
+        let (src_path, replicate_path) = (src_path.as_path(), replicate_path.as_path());
+        if src_path == replicate_path {
+            return Ok(());
+        }
+
+        let missing = match streams::missing(src_path, replicate_path) {
+            Ok(missing) if !missing.is_empty() => missing,
+            Ok(_) => return Ok(()),
+            Err(err) => {
+                log::debug!("failed to list named streams of {:?}: {}", src_path, err);
+                return Ok(());
+            }
+        };
+
+        match self.cfg.streams {
+            StreamsPolicy::Copy => streams::copy(src_path, replicate_path, &missing)
+                .map_err(|err| SortError::StreamsError(err, replicate_path.to_owned())),
+            _ => {
+                log::warn!(
+                    "named streams {:?} of {:?} weren't replicated to {:?}",
+                    missing,
+                    src_path,
+                    replicate_path
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Removes the source file once the replicate file is verified to be a
+    /// regular file with the same content.
+    fn remove_source(
+        &self,
+        src_path: &Path,
+        replicate_path: &Path,
+    ) -> result::Result<(), SortError> {
+        let fs = &*self.cfg.fs;
+        let verify = || -> io::Result<bool> {
+            if fs.symlink_metadata(replicate_path)?.is_symlink() {
+                return Ok(false);
+            }
+
+            same_content(fs, src_path, replicate_path)
+        };
+
+        match verify() {
+            Ok(true) => fs.remove_file(src_path),
+            Ok(false) => return Err(SortError::UnverifiedReplicate(replicate_path.to_owned())),
+            Err(err) => Err(err),
+        }
+        .map_err(|err| SortError::RemoveSourceError(err, src_path.to_owned()))
+    }
+
+    /// Removes directories emptied by moving the given file, up to its source
+    /// directory (excluded).
+    fn remove_empty_dirs(&self, src_path: &Path, source: &Path) {
+        let mut dir = src_path.parent();
+        while let Some(path) = dir.filter(|dir| *dir != source && dir.starts_with(source)) {
+            // fails if directory isn't empty.
+            if self.cfg.fs.remove_dir(path).is_err() {
+                break;
+            }
+            dir = path.parent();
+        }
+    }
+
+    pub(crate) fn replicate_file(
+        &self,
+        src_path: &Path,
+        mut replicate_path: PathBuf,
+        source: Option<&Path>,
+    ) -> Result {
+        if replicate_path == src_path {
+            return Ok(SortResult::Skipped {
+                replicate_path,
+                reason: SkippedReason::SameFile,
+            });
+        }
+
+        // files sorted concurrently may have the same destination.
+        let _reservation = self.replicating.reserve(&replicate_path);
+
+        if self.cfg.mark
+            && Marker::is_marked(src_path)
+                .map_err(|err| SortError::MarkError(err, src_path.to_owned()))?
+        {
+            return Ok(SortResult::Skipped {
+                replicate_path,
+                reason: SkippedReason::AlreadySorted,
+            });
+        }
+
+        self.check_mount()?;
+
+        let fs = &*self.cfg.fs;
+        let mut overwrite = false;
+        let mut backup = None;
+        if let Ok(metadata) = fs.metadata(&replicate_path) {
+            let duplicate = || {
+                same_content(fs, src_path, &replicate_path)
+                    .map_err(|err| SortError::CompareError(err, replicate_path.clone()))
+            };
+            if self.cfg.skip_duplicates && duplicate()? {
+                return Ok(SortResult::Skipped {
+                    replicate_path,
+                    reason: SkippedReason::Duplicate,
+                });
+            }
+
+            match self
+                .cfg
+                .on_conflict
+                .decide(src_path, &replicate_path, &metadata)
+            {
+                Decision::Skip => {
+                    return Ok(SortResult::Skipped {
+                        replicate_path,
+                        reason: SkippedReason::Overwrite,
+                    });
+                }
+                Decision::Overwrite => {
+                    overwrite = true;
+                    if !self.dry_run {
+                        let removed = match &self.journal {
+                            // overwritten file is restored on undo.
+                            Some(journal) => journal.backup(&replicate_path).map(|path| {
+                                backup = Some(path);
+                            }),
+                            None if metadata.is_dir() => fs.remove_dir_all(&replicate_path),
+                            None => fs.remove_file(&replicate_path),
+                        };
+                        if let Err(err) = removed {
+                            return Err(SortError::OverwriteError(err, replicate_path));
+                        }
+                    }
+                }
+                Decision::Rename => {
+                    if !self.cfg.skip_duplicates && duplicate()? {
+                        return Ok(SortResult::Skipped {
+                            replicate_path,
+                            reason: SkippedReason::Duplicate,
+                        });
+                    }
+
+                    let rendered_path = replicate_path.clone();
+                    for n in 1.. {
+                        replicate_path = suffixed_path(&rendered_path, n);
+                        if !fs.exists(&replicate_path) {
+                            break;
+                        }
+                        if same_content(fs, src_path, &replicate_path)
+                            .map_err(|err| SortError::CompareError(err, replicate_path.clone()))?
+                        {
+                            return Ok(SortResult::Skipped {
+                                replicate_path,
+                                reason: SkippedReason::Duplicate,
+                            });
+                        }
+                    }
+                }
+                Decision::Error(err) => return Err(SortError::ConflictError(err, replicate_path)),
+            }
+        }
+
+        if self.dry_run {
+            return Ok(SortResult::Replicated {
+                replicate_path,
+                overwrite,
+            });
+        }
+
+        // Ensure parent directory exist
+        if let Some(parent) = replicate_path.parent() {
+            self.create_dirs(parent, &replicate_path)?;
+        }
+
+        // moved files are renamed within a filesystem if enabled.
+        let renamed =
+            self.cfg.move_source && self.cfg.rename && fs.rename(src_path, &replicate_path).is_ok();
+        if !renamed {
+            if let Err(err) = self
+                .cfg
+                .replicator
+                .replicate_on(fs, src_path, &replicate_path)
+            {
+                let err = SortError::ReplicateError(err, replicate_path.clone());
+                // partial replicate is only removed if a file was backed up in
+                // its place.
+                return Err(match backup.as_deref() {
+                    Some(backup) => {
+                        self.rollback(err, src_path, &replicate_path, false, Some(backup))
+                    }
+                    None => err,
+                });
+            }
+        }
+
+        // replicate isn't journaled yet, it is rolled back on error.
+        if let Err(err) = self.finish_replicate(src_path, &replicate_path, renamed) {
+            let backup = backup.as_deref();
+            return Err(self.rollback(err, src_path, &replicate_path, renamed, backup));
+        }
+
+        if let Some(journal) = &self.journal {
+            let mut entry = Entry::new(src_path.to_owned(), replicate_path.clone(), overwrite);
+            entry.moved = self.cfg.move_source;
+            entry.backup = backup.clone();
+            entry.run_id = Some(self.run_id.clone());
+            if let Err(err) = journal.append(&entry) {
+                // moved files are renamed back to their source.
+                let moved = self.cfg.move_source;
+                let backup = backup.as_deref();
+                let err = SortError::JournalError(err);
+                return Err(self.rollback(err, src_path, &replicate_path, moved, backup));
+            }
+        }
+
+        if source.is_some_and(|source| replicate_path.starts_with(source)) {
+            self.produced.lock().unwrap().insert(replicate_path.clone());
+        }
+
+        if self.cfg.move_source {
+            if let Some(source) = source {
+                self.remove_empty_dirs(src_path, source);
+            }
+        }
+
+        if let Some(dir) = &self.cfg.extract_thumbs {
+            let thumbnail_path = self.thumbnail_path(dir, &replicate_path);
+            thumbnail::extract(&replicate_path, &thumbnail_path)
+                .map_err(|err| SortError::ThumbnailError(err, thumbnail_path))?;
+        }
+
+        Ok(SortResult::Replicated {
+            replicate_path,
+            overwrite,
+        })
+    }
+
+    /// Copies named streams, applies permissions, marks the replicate file and
+    /// removes the moved source file.
+    fn finish_replicate(
+        &self,
+        src_path: &Path,
+        replicate_path: &Path,
+        renamed: bool,
+    ) -> result::Result<(), SortError> {
+        // renamed files keep their named streams.
+        if !renamed {
+            self.check_streams(src_path, replicate_path)?;
+        }
+
+        self.cfg
+            .permissions
+            .apply_file(&*self.cfg.fs, src_path, replicate_path)
+            .map_err(|err| SortError::PermissionsError(err, replicate_path.to_owned()))?;
+
+        if self.cfg.mark {
+            self.mark(replicate_path)?;
+        }
+
+        if self.cfg.move_source && !renamed {
+            self.remove_source(src_path, replicate_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the replicate file of a failed sort (renamed files are renamed
+    /// back to their source) and restores the file it overwrote from the
+    /// given backup so nothing unjournaled is left behind. Returns the error
+    /// of the sort.
+    fn rollback(
+        &self,
+        err: SortError,
+        src_path: &Path,
+        replicate_path: &Path,
+        renamed: bool,
+        backup: Option<&Path>,
+    ) -> SortError {
+        let fs = &*self.cfg.fs;
+        let removed = match renamed {
+            true => fs.rename(replicate_path, src_path),
+            false => match fs.remove_file(replicate_path) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            },
+        };
+        let restored = removed.and_then(|_| match (&self.journal, backup) {
+            (Some(journal), Some(backup)) => journal.restore(backup, replicate_path),
+            _ => Ok(()),
+        });
+        if let Err(rollback_err) = restored {
+            log::error!(
+                "failed to roll back replicate {:?} of {:?}: {}",
+                replicate_path,
+                src_path,
+                rollback_err
+            );
+        }
+
+        err
+    }
+
+    /// Creates the given directory and its missing parents, permissions are
+    /// applied to created directories.
+    fn create_dirs(&self, dir: &Path, replicate_path: &Path) -> result::Result<(), SortError> {
+        let fs = &*self.cfg.fs;
+        let created: Vec<&Path> = match self.cfg.permissions.changes_dirs() {
+            true => dir.ancestors().take_while(|dir| !fs.exists(dir)).collect(),
+            false => Vec::new(),
+        };
+
+        fs.create_dir_all(dir)
+            .map_err(|err| SortError::ReplicateError(err, replicate_path.to_owned()))?;
+        for dir in created.into_iter().rev() {
+            self.cfg
+                .permissions
+                .apply_dir(fs, dir)
+                .map_err(|err| SortError::PermissionsError(err, dir.to_owned()))?;
         }
+
+        Ok(())
     }
 }
 
-/// Sorter define a file sorter.
-///
-/// Sorter render a path template before replicating the file.
-#[derive(Debug)]
-pub struct Sorter {
-    cfg: Config,
+/// Returns the given path with an extension appended to its file name.
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".");
+    file_name.push(extension);
+    path.with_file_name(file_name)
 }
 
-impl Sorter {
-    pub fn new(cfg: Config) -> Self {
-        Self { cfg }
+/// Returns the destination of a sidecar file of `primary` replicated to
+/// `primary_replicate_path`, named after the replicate file.
+fn sidecar_path(primary: &Path, primary_replicate_path: &Path, sidecar: &Path) -> PathBuf {
+    let extension = sidecar.extension().unwrap_or_default();
+    if sidecar.with_extension("") == primary {
+        let mut file_name = primary_replicate_path
+            .file_name()
+            .unwrap_or_default()
+            .to_owned();
+        file_name.push(".");
+        file_name.push(extension);
+        primary_replicate_path.with_file_name(file_name)
+    } else {
+        primary_replicate_path.with_extension(extension)
     }
+}
 
-    pub fn sort_file(&self, src_path: &Path) -> Result {
-        // prepare template rendering context
-        let mut ctx = Context::default();
-        template::context::prepare_template_context(&mut ctx, src_path)?;
+/// Returns the given path with a `-n` suffix appended to its file stem.
+fn suffixed_path(path: &Path, n: usize) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_owned();
+    file_name.push(format!("-{}", n));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
 
-        // render destination path template
-        let replicate_path = match self.cfg.template.render(&ctx) {
-            Ok(path) => path,
-            Err(err) => return Err(SortError::TemplateError(err)),
-        };
+    path.with_file_name(file_name)
+}
+
+/// Shortens components of the given path longer than `max_len` bytes, the
+/// end of their stem is replaced with the first 8 hex digits of the hash of
+/// the component so shortened names stay unique.
+fn truncate_components(path: &Path, max_len: usize) -> PathBuf {
+    path.components()
+        .map(|component| match component {
+            Component::Normal(name) if name.len() > max_len => truncate_name(name, max_len),
+            component => component.as_os_str().to_owned(),
+        })
+        .collect()
+}
 
-        self.replicate_file(src_path, replicate_path)
+/// Returns the given path with its components below `root` transformed.
+fn map_below(root: &Path, path: &Path, transform: impl FnOnce(&Path) -> PathBuf) -> PathBuf {
+    match path.strip_prefix(root) {
+        Ok(relative) if relative.as_os_str().is_empty() => path.to_owned(),
+        Ok(relative) => root.join(transform(relative)),
+        Err(_) => transform(path),
     }
+}
 
-    fn replicate_file(&self, src_path: &Path, replicate_path: PathBuf) -> Result {
-        if replicate_path == src_path {
-           return Ok(SortResult::Skipped {
-                replicate_path,
-                reason: SkippedReason::SameFile,
-            });
-        }
+/// Returns the `date` variable of a rendering context.
+fn context_date(ctx: &Context) -> Option<NaiveDateTime> {
+    ctx.get("date")?.render("date", ctx).ok()?.as_date()
+}
 
-        let mut overwrite = false;
-        if replicate_path.exists() {
-            if self.cfg.overwrite {
-                overwrite = true;
-                if replicate_path.is_dir() {
-                    if let Err(err) = fs::remove_dir_all(&replicate_path) {
-                        return Err(SortError::OverwriteError(err, replicate_path));
-                    };
-                } else if let Err(err) = fs::remove_file(&replicate_path) {
-                    return Err(SortError::OverwriteError(err, replicate_path));
+/// Returns the path of a file relative to its source directory, its file name
+/// if the source is unknown or isn't a parent of the file.
+fn relative_path<'a>(src_path: &'a Path, source: Option<&Path>) -> &'a Path {
+    match source.and_then(|source| src_path.strip_prefix(source).ok()) {
+        Some(path) if !path.as_os_str().is_empty() => path,
+        _ => Path::new(src_path.file_name().unwrap_or_default()),
+    }
+}
+
+/// Resolves `.` and `..` components of the given path, none if the resolved
+/// path isn't inside the given root. Relative paths (and roots) are relative
+/// to the current directory. Symbolic links aren't resolved.
+fn confine(root: &Path, path: &Path) -> Option<PathBuf> {
+    // relative paths are made absolute first so `..` components can't pop
+    // past their start.
+    let resolve = |path: &Path| -> Option<PathBuf> {
+        let mut resolved = PathBuf::new();
+        for component in std::path::absolute(path).ok()?.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    resolved.pop();
                 }
-            } else {
-                return Ok(SortResult::Skipped {
-                    replicate_path,
-                    reason: SkippedReason::Overwrite,
-                });
+                component => resolved.push(component),
             }
         }
+        Some(resolved)
+    };
 
-        // Ensure parent directory exist
-        if let Some(parent) = replicate_path.parent() {
-            if let Err(err) = fs::create_dir_all(parent) {
-                return Err(SortError::ReplicateError(err, replicate_path));
-            };
+    let root = resolve(root)?;
+    let path = resolve(path)?;
+    if path.starts_with(&root) && path != root {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Smallest component length limit, shortened names end with a dash and 8 hex
+/// digits of their hash.
+pub const MIN_COMPONENT_LENGTH_LIMIT: usize = 9;
+
+/// Checks a component length limit, see [`MIN_COMPONENT_LENGTH_LIMIT`].
+pub fn check_max_component_length(max_len: usize) -> result::Result<usize, String> {
+    match max_len >= MIN_COMPONENT_LENGTH_LIMIT {
+        true => Ok(max_len),
+        false => Err(format!(
+            "max component length must be at least {} bytes, got {}",
+            MIN_COMPONENT_LENGTH_LIMIT, max_len
+        )),
+    }
+}
+
+/// Deserializes a component length limit, limits too short for the hash of
+/// shortened names are rejected.
+fn deserialize_max_component_length<'de, D>(
+    deserializer: D,
+) -> result::Result<Option<usize>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<usize>::deserialize(deserializer)?
+        .map(check_max_component_length)
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Components shortened to fit a path length limit keep at least this number
+/// of bytes, enough for the hash and a short extension.
+const MIN_COMPONENT_LENGTH: usize = 16;
+
+/// Shortens the longest component below `root` of a path longer than
+/// `max_len` bytes until it fits or every component is at the minimum length,
+/// none if the root alone is longer than `max_len`.
+fn truncate_path(root: &Path, path: &Path, max_len: usize) -> Option<PathBuf> {
+    if root.as_os_str().len() > max_len {
+        return None;
+    }
+
+    let below = match path.starts_with(root) {
+        true => root.components().count(),
+        false => 0,
+    };
+    let mut path = path.to_owned();
+    loop {
+        let excess = path.as_os_str().len().saturating_sub(max_len);
+        if excess == 0 {
+            return Some(path);
         }
 
-        if let Err(err) = self.cfg.replicator.replicate(src_path, &replicate_path) {
-            return Err(SortError::ReplicateError(err, replicate_path));
+        let longest = path
+            .components()
+            .enumerate()
+            .skip(below)
+            .filter_map(|(i, component)| match component {
+                Component::Normal(name) if name.len() > MIN_COMPONENT_LENGTH => {
+                    Some((i, name.len()))
+                }
+                _ => None,
+            })
+            .max_by_key(|(_, len)| *len);
+        let Some((index, len)) = longest else {
+            return Some(path);
+        };
+
+        let target = len.saturating_sub(excess).max(MIN_COMPONENT_LENGTH);
+        path = path
+            .components()
+            .enumerate()
+            .map(|(i, component)| {
+                if i == index {
+                    truncate_name(component.as_os_str(), target)
+                } else {
+                    component.as_os_str().to_owned()
+                }
+            })
+            .collect();
+    }
+}
+
+fn truncate_name(name: &OsStr, max_len: usize) -> OsString {
+    let hash = blake3::hash(name.as_encoded_bytes()).to_hex();
+    let suffix = format!("-{}", &hash[..8]);
+
+    let name = name.to_string_lossy();
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension))
+            if !stem.is_empty() && extension.len() + 1 + suffix.len() < max_len =>
+        {
+            (stem, format!(".{}", extension))
         }
+        _ => (&*name, String::new()),
+    };
 
-        Ok(SortResult::Replicated {
-            replicate_path,
-            overwrite,
-        })
+    let mut end = max_len
+        .saturating_sub(suffix.len() + extension.len())
+        .min(stem.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    format!("{}{}{}", &stem[..end], suffix, extension).into()
+}
+
+/// Returns true if both files of the given filesystem have the same content.
+fn same_content(fs: &dyn Fs, a: &Path, b: &Path) -> io::Result<bool> {
+    if fs.metadata(a)?.len() != fs.metadata(b)?.len() {
+        return Ok(false);
     }
+
+    Ok(index::hash_reader(fs.open(a)?)? == index::hash_reader(fs.open(b)?)?)
 }
 
 pub type Result = result::Result<SortResult, SortError>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SortResult {
     /// File wasn't replicated because overwrite is disabled or source path
     /// is same as replicate path.
@@ -135,15 +2167,91 @@ pub enum SortError {
 
     #[error("failed to overwrite destination file {1:?}: {0}")]
     OverwriteError(#[source] io::Error, PathBuf),
+
+    #[error("destination file {1:?} already exists: {0}")]
+    ConflictError(#[source] Box<dyn Error + Send + Sync>, PathBuf),
+
+    #[error("failed to filter file {1:?}: {0}")]
+    FilterError(#[source] StageError, PathBuf),
+
+    #[error("failed to plan destination of {1:?}: {0}")]
+    PlanError(#[source] StageError, PathBuf),
+
+    /// The file was sorted, the result is kept so callers can still record
+    /// it.
+    #[error("file {1:?} sorted but hook failed: {0}")]
+    HookError(#[source] StageError, PathBuf, SortResult),
+
+    #[error("destination {0} is not mounted")]
+    NotMounted(MountPin),
+
+    #[error("failed to check if destination {1} is mounted: {0}")]
+    MountCheckError(#[source] io::Error, MountPin),
+
+    #[error("can't mirror file, template has no root directory")]
+    MirrorWithoutRoot,
+
+    #[error("path {0:?} isn't valid UTF-8")]
+    NonUtf8Path(PathBuf),
+
+    #[error("rendered path {0:?} is outside of destination root {1:?}")]
+    OutsideRoot(PathBuf, PathBuf),
+
+    #[error("destination root {0:?} is longer than the max path length ({1} bytes)")]
+    RootTooLong(PathBuf, usize),
+
+    #[error("failed to compare existing replicate file {1:?} with source: {0}")]
+    CompareError(#[source] io::Error, PathBuf),
+
+    #[error("file replicated but named streams weren't copied to {1:?}: {0}")]
+    StreamsError(#[source] io::Error, PathBuf),
+
+    #[error("failed to set mode or owner of {1:?}: {0}")]
+    PermissionsError(#[source] io::Error, PathBuf),
+
+    #[error("failed to read or write marker of {1:?}: {0}")]
+    MarkError(#[source] io::Error, PathBuf),
+
+    #[error(
+        "replicate file {0:?} differs from source or is a symbolic link, source wasn't removed"
+    )]
+    UnverifiedReplicate(PathBuf),
+
+    #[error("file replicated but thumbnail {1:?} wasn't extracted: {0}")]
+    ThumbnailError(#[source] io::Error, PathBuf),
+
+    #[error("file replicated but source {1:?} wasn't removed: {0}")]
+    RemoveSourceError(#[source] io::Error, PathBuf),
+
+    #[error("file replicated but not journaled: {0}")]
+    JournalError(#[source] JournalError),
 }
 
-#[derive(Error, Debug, PartialEq, Eq)]
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SkippedReason {
     #[error("can't overwrite replicate file")]
     Overwrite,
 
     #[error("source and replicate paths are the same")]
     SameFile,
+
+    #[error("file was replicated by photosort and is unchanged")]
+    AlreadySorted,
+
+    #[error("replicate file exists with the same content")]
+    Duplicate,
+
+    #[error("file rejected by a filter")]
+    Filtered,
+
+    #[error("path isn't valid UTF-8")]
+    NonUtf8,
+
+    #[error("sidecar file was replicated with its primary file")]
+    Sidecar,
+
+    #[error("file was sorted with its pair")]
+    Paired,
 }
 
 #[cfg(test)]
@@ -151,26 +2259,817 @@ mod tests {
     use std::io::{Read, Write};
     use std::path::{Path, PathBuf};
     use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
     use std::{env, fs, io};
 
     use uuid::Uuid;
 
+    use crate::fs::{Fs, MemFs, Metadata, Op};
+    use crate::index::RenderCache;
+    use crate::journal::{self, Journal};
+    use crate::mount::MountPin;
+    use crate::portable::UnicodeForm;
     use crate::replicator::CopyReplicator;
     use crate::sort::{SkippedReason, SortResult};
+    use crate::test_utils;
     use crate::{
         replicator::{NoneReplicator, SoftLinkReplicator},
         template::{self, Template},
     };
 
-    use super::{SortError, Sorter};
+    use crate::pipeline::{Filter, Hook, PathPlanner, StageError};
+
+    use super::{ConflictPolicy, ConflictPolicyKind, Decision, Rule, SortError, Sorter};
+
+    #[test]
+    fn rules_and_mirror() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let src = root.join("src");
+        let dst = root.join("dst");
+        fs::create_dir_all(src.join("docs")).unwrap();
+        fs::write(src.join("docs/notes.txt"), "notes").unwrap();
+        fs::write(src.join("2022-08-19.jpg"), "picture").unwrap();
+
+        let template = |s: &str| Template::from_str(s).unwrap();
+        let sorter = Sorter::new(
+            super::Config::new(
+                template(&format!(
+                    "{}/:file.name.date.year:/:file.name:",
+                    dst.display()
+                )),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_rules(vec![Rule::new(regex::Regex::new(r"\.jpg$").unwrap(), None)])
+            .with_mirror(true),
+        );
+
+        let result = sorter.sort_source_file(&src.join("2022-08-19.jpg"), Some(&src));
+        assert_eq!(
+            result.unwrap(),
+            SortResult::Replicated {
+                replicate_path: dst.join("2022/2022-08-19.jpg"),
+                overwrite: false
+            }
+        );
+
+        let result = sorter.sort_source_file(&src.join("docs/notes.txt"), Some(&src));
+        assert_eq!(
+            result.unwrap(),
+            SortResult::Replicated {
+                replicate_path: dst.join("docs/notes.txt"),
+                overwrite: false
+            }
+        );
+        assert!(dst.join("docs/notes.txt").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn relpath() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let src = root.join("src");
+        let dst = root.join("dst");
+        fs::create_dir_all(src.join("2022/trip")).unwrap();
+        fs::write(src.join("2022/trip/a.jpg"), "a").unwrap();
+        fs::write(src.join("b.jpg"), "b").unwrap();
+
+        let sorter = Sorter::new(super::Config::new(
+            Template::from_str(&format!("{}/sorted/:file.relpath:", dst.display())).unwrap(),
+            Box::new(CopyReplicator::default()),
+            false,
+        ));
+
+        let result = sorter.sort_source_file(&src.join("2022/trip/a.jpg"), Some(&src));
+        assert_eq!(
+            result.unwrap(),
+            SortResult::Replicated {
+                replicate_path: dst.join("sorted/2022/trip/a.jpg"),
+                overwrite: false
+            }
+        );
+        // files of unknown sources have no relative path.
+        let result = sorter.sort_file(&src.join("b.jpg"));
+        assert!(matches!(result, Err(SortError::TemplateError(_))));
+        assert!(!dst.join("sorted/b.jpg").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn scratch_root() {
+        let tree = test_utils::TempTree::new().with_file("src/a.jpg", "a");
+        let scratch = tree.join("scratch");
+        let template = format!("{}/:file.name:", tree.join("dst").display());
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&template).unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_rules(vec![Rule::new(
+                regex::Regex::new("a").unwrap(),
+                Some(Template::from_str("/rule/:file.name:").unwrap()),
+            )
+            .with_priority(1)])
+            .with_scratch_root(&scratch),
+        );
+
+        let relative: PathBuf = tree.join("dst").components().skip(1).collect();
+        assert_eq!(
+            sorter.destination_root(),
+            Some(scratch.join(&relative).join(""))
+        );
+        assert_eq!(
+            sorter.sort_file(&tree.join("src/a.jpg")).unwrap(),
+            SortResult::Replicated {
+                replicate_path: scratch.join("rule/a.jpg"),
+                overwrite: false
+            }
+        );
+        tree.assert_files("scratch", &["scratch/rule/a.jpg"]);
+    }
+
+    #[test]
+    fn root_kept_as_is() {
+        let tree = test_utils::TempTree::new()
+            .with_file("src/What?.jpg", "a")
+            .with_file("src/e\u{301}te\u{301}.jpg", "b");
+        let root = tree.join("smb-share:server=nas");
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(":file.name:").unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_dest_root(Some(root.clone()))
+            .with_portable_names(Some("_".parse().unwrap())),
+        )
+        .with_dry_run(true);
+
+        assert_eq!(
+            sorter.sort_file(&tree.join("src/What?.jpg")).unwrap(),
+            SortResult::Replicated {
+                replicate_path: root.join("What_.jpg"),
+                overwrite: false
+            }
+        );
+
+        // roots aren't normalized.
+        let root = tree.join("Cafe\u{301}");
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(":file.name:").unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_dest_root(Some(root.clone()))
+            .with_normalize_unicode(Some(UnicodeForm::Nfc)),
+        )
+        .with_dry_run(true);
+        assert_eq!(
+            sorter
+                .sort_file(&tree.join("src/e\u{301}te\u{301}.jpg"))
+                .unwrap(),
+            SortResult::Replicated {
+                replicate_path: root.join("\u{e9}t\u{e9}.jpg"),
+                overwrite: false
+            }
+        );
+
+        // only rendered components are shortened.
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(":file.name:").unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_dest_root(Some(root.clone()))
+            .with_max_component_length(Some(20)),
+        )
+        .with_dry_run(true);
+        assert_eq!(
+            sorter.sort_file(&tree.join("src/What?.jpg")).unwrap(),
+            SortResult::Replicated {
+                replicate_path: root.join("What?.jpg"),
+                overwrite: false
+            }
+        );
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(":file.name:").unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_dest_root(Some(root.clone()))
+            .with_max_path_length(Some(10)),
+        );
+        assert!(matches!(
+            sorter.sort_file(&tree.join("src/What?.jpg")),
+            Err(SortError::RootTooLong(_, 10))
+        ));
+    }
+
+    #[test]
+    fn render_cache() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/a.jpg"), "a").unwrap();
+        fs::write(root.join("src/b.jpg"), "b").unwrap();
+
+        let sorter = |template: String, cache: &Arc<RenderCache>| {
+            Sorter::new(super::Config::new(
+                Template::from_str(&template).unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            ))
+            .with_render_cache(cache.clone())
+        };
+
+        let cache = Arc::new(RenderCache::default());
+        let dst = root.join("dst");
+        let named = sorter(format!("{}/:file.name:", dst.display()), &cache);
+        named.sort_file(&root.join("src/a.jpg")).unwrap();
+        assert_eq!(cache.len(), 1);
+
+        // paths depending on other files aren't cached.
+        let counted = sorter(format!("{}/:counter:.jpg", dst.display()), &cache);
+        counted.sort_file(&root.join("src/b.jpg")).unwrap();
+        assert_eq!(cache.len(), 1);
+
+        // permissions change without changing the modification time.
+        #[cfg(unix)]
+        {
+            let owned = sorter(format!("{}/:file.md.mode:.jpg", dst.display()), &cache);
+            owned.sort_file(&root.join("src/b.jpg")).unwrap();
+            assert_eq!(cache.len(), 1);
+        }
+
+        // relative paths depend on the source directory.
+        let relative = sorter(
+            format!("{}/:file.relpath:", root.join("rel").display()),
+            &cache,
+        );
+        for (source, expected) in [("src", "rel/a.jpg"), ("", "rel/src/a.jpg")] {
+            assert_eq!(
+                relative
+                    .sort_source_file(&root.join("src/a.jpg"), Some(&root.join(source)))
+                    .unwrap(),
+                SortResult::Replicated {
+                    replicate_path: root.join(expected),
+                    overwrite: false
+                }
+            );
+        }
+
+        // other templates don't use cached paths.
+        let renamed = sorter(format!("{}/renamed-:file.name:", dst.display()), &cache);
+        assert_eq!(
+            renamed.sort_file(&root.join("src/a.jpg")).unwrap(),
+            SortResult::Replicated {
+                replicate_path: dst.join("renamed-a.jpg"),
+                overwrite: false
+            }
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use crate::permissions::Permissions;
+        use crate::replicator::{HardLinkReplicator, Replicator};
+
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/a.jpg"), "a").unwrap();
+        fs::set_permissions(root.join("src/a.jpg"), fs::Permissions::from_mode(0o644)).unwrap();
+        let mode =
+            |path: &str| fs::metadata(root.join(path)).unwrap().permissions().mode() & 0o7777;
+
+        let permissions = Permissions {
+            dir_mode: Some("0750".parse().unwrap()),
+            file_mode: Some("0640".parse().unwrap()),
+            chown: None,
+        };
+        let sorter = |replicator: Box<dyn Replicator>, dir: &str| {
+            Sorter::new(
+                super::Config::new(
+                    Template::from_str(&format!("{}/{}/2023/:file.name:", root.display(), dir))
+                        .unwrap(),
+                    replicator,
+                    false,
+                )
+                .with_permissions(permissions),
+            )
+        };
+
+        sorter(Box::<CopyReplicator>::default(), "copy")
+            .sort_file(&root.join("src/a.jpg"))
+            .unwrap();
+        assert_eq!(mode("copy"), 0o750);
+        assert_eq!(mode("copy/2023"), 0o750);
+        assert_eq!(mode("copy/2023/a.jpg"), 0o640);
+        // existing directories are left as is.
+        assert_eq!(mode("src"), mode("."));
+
+        // sources aren't changed through hard links.
+        sorter(Box::<HardLinkReplicator>::default(), "link")
+            .sort_file(&root.join("src/a.jpg"))
+            .unwrap();
+        assert_eq!(mode("link/2023"), 0o750);
+        assert_eq!(mode("src/a.jpg"), 0o644);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn non_utf8_paths() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        use crate::encoding::NonUtf8Policy;
+        use crate::template::context::Options;
+
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let src = root.join("src").join(OsStr::from_bytes(b"caf\xe9.jpg"));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(&src, "a").unwrap();
+
+        let sorter_of = |template: &str, non_utf8| {
+            Sorter::new(
+                super::Config::new(
+                    Template::from_str(&format!("{}/dst/{}", root.display(), template)).unwrap(),
+                    Box::new(CopyReplicator::default()),
+                    false,
+                )
+                .with_options(Options {
+                    non_utf8,
+                    ..Default::default()
+                }),
+            )
+        };
+        let sorter = |non_utf8| sorter_of(":file.stem|upper:.jpg", non_utf8);
+
+        // names are kept as is by default, they don't collide with their
+        // UTF-8 counterpart.
+        fs::write(root.join("src/café.jpg"), "b").unwrap();
+        for name in [OsStr::from_bytes(b"caf\xe9.jpg"), OsStr::new("café.jpg")] {
+            assert_eq!(
+                sorter_of(":file.name:", NonUtf8Policy::default())
+                    .sort_file(&root.join("src").join(name))
+                    .unwrap(),
+                SortResult::Replicated {
+                    replicate_path: root.join("dst").join(name),
+                    overwrite: false
+                }
+            );
+        }
+
+        assert!(matches!(
+            sorter(NonUtf8Policy::Skip).sort_file(&src),
+            Ok(SortResult::Skipped {
+                reason: SkippedReason::NonUtf8,
+                ..
+            })
+        ));
+        assert!(matches!(
+            sorter(NonUtf8Policy::Error).sort_file(&src),
+            Err(SortError::NonUtf8Path(_))
+        ));
+        assert_eq!(
+            sorter(NonUtf8Policy::Transliterate)
+                .sort_file(&src)
+                .unwrap(),
+            SortResult::Replicated {
+                replicate_path: root.join("dst/CAFÉ.jpg"),
+                overwrite: false
+            }
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn moved_sidecars() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let src = root.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("IMG_0001.CR2"), "raw").unwrap();
+        fs::write(src.join("IMG_0001.xmp"), "xmp").unwrap();
+
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&format!("{}/dst/:file.name:", root.display())).unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_move(true)
+            .with_sidecars(vec!["xmp".to_owned()]),
+        );
+
+        sorter.sort_file(&src.join("IMG_0001.CR2")).unwrap();
+        assert!(!src.join("IMG_0001.xmp").exists());
+        // sidecar planned with its primary file was moved along with it.
+        assert!(matches!(
+            sorter.sort_file(&src.join("IMG_0001.xmp")),
+            Ok(SortResult::Skipped {
+                reason: SkippedReason::Sidecar,
+                ..
+            })
+        ));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn sidecars() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let src = root.join("src");
+        fs::create_dir_all(&src).unwrap();
+        for name in [
+            "IMG_0001.CR2",
+            "IMG_0001.xmp",
+            "IMG_0001.CR2.pp3",
+            "notes.xmp",
+        ] {
+            fs::write(src.join(name), name).unwrap();
+        }
+
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&format!("{}/dst/photo.:file.extension:", root.display()))
+                    .unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_sidecars(vec!["xmp".to_owned(), "pp3".to_owned(), "aae".to_owned()]),
+        );
+
+        assert_eq!(
+            sorter.sort_file(&src.join("IMG_0001.CR2")).unwrap(),
+            SortResult::Replicated {
+                replicate_path: root.join("dst/photo.CR2"),
+                overwrite: false
+            }
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("dst/photo.xmp")).unwrap(),
+            "IMG_0001.xmp"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("dst/photo.CR2.pp3")).unwrap(),
+            "IMG_0001.CR2.pp3"
+        );
+        assert!(matches!(
+            sorter.sort_file(&src.join("IMG_0001.xmp")),
+            Ok(SortResult::Skipped {
+                reason: SkippedReason::Sidecar,
+                ..
+            })
+        ));
+
+        // sidecar added after its primary file was sorted.
+        fs::write(src.join("IMG_0001.aae"), "IMG_0001.aae").unwrap();
+        assert_eq!(
+            sorter.sort_file(&src.join("IMG_0001.aae")).unwrap(),
+            SortResult::Replicated {
+                replicate_path: root.join("dst/photo.aae"),
+                overwrite: false
+            }
+        );
+
+        // sidecar without primary file is sorted as a regular file.
+        assert_eq!(
+            sorter.sort_file(&src.join("notes.xmp")).unwrap(),
+            SortResult::Skipped {
+                replicate_path: root.join("dst/photo.xmp"),
+                reason: SkippedReason::Overwrite
+            }
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn raw_jpeg_pairs() {
+        let date = chrono::NaiveDate::from_ymd_opt(2021, 7, 14)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let tree = test_utils::TempTree::new()
+            .with_jpeg("src/DSC0001.JPG", date)
+            .with_file("src/DSC0001.NEF", "raw")
+            .with_file("src/DSC0002.NEF", "raw");
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&format!(
+                    "{}/:date.year:/:?pair.role:/:file.name:",
+                    tree.join("dst").display()
+                ))
+                .unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_raw_jpeg_pairs(true),
+        );
+
+        for name in ["DSC0001.JPG", "DSC0001.NEF", "DSC0002.NEF"] {
+            sorter.sort_file(&tree.join("src").join(name)).unwrap();
+        }
+        let year = chrono::Local::now().format("%Y").to_string();
+        tree.assert_files(
+            "dst",
+            &[
+                "dst/2021/jpeg/DSC0001.JPG",
+                "dst/2021/raw/DSC0001.NEF",
+                &format!("dst/{}/DSC0002.NEF", year),
+            ],
+        );
+    }
+
+    #[test]
+    fn moved_raw_jpeg_pairs() {
+        let date = chrono::NaiveDate::from_ymd_opt(2021, 7, 14)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let tree = test_utils::TempTree::new()
+            .with_jpeg("src/DSC0001.JPG", date)
+            .with_file("src/DSC0001.NEF", "raw")
+            .with_jpeg("src/DSC0002.JPG", date)
+            .with_file("src/DSC0002.NEF", "raw")
+            .with_jpeg("src/DSC0003.JPG", date);
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&format!(
+                    "{}/:date.year:/:?pair.role:/:file.name:",
+                    tree.join("dst").display()
+                ))
+                .unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_move(true)
+            .with_raw_jpeg_pairs(true),
+        );
+
+        // JPEG file sorted first.
+        sorter.sort_file(&tree.join("src/DSC0001.JPG")).unwrap();
+        assert!(matches!(
+            sorter.sort_file(&tree.join("src/DSC0001.NEF")).unwrap(),
+            SortResult::Skipped {
+                reason: SkippedReason::Paired,
+                ..
+            }
+        ));
+        // RAW file sorted first.
+        sorter.sort_file(&tree.join("src/DSC0002.NEF")).unwrap();
+        assert!(matches!(
+            sorter.sort_file(&tree.join("src/DSC0002.JPG")).unwrap(),
+            SortResult::Skipped {
+                reason: SkippedReason::Paired,
+                ..
+            }
+        ));
+        // RAW file added after its JPEG file was moved.
+        sorter.sort_file(&tree.join("src/DSC0003.JPG")).unwrap();
+        fs::write(tree.join("src/DSC0003.NEF"), "raw").unwrap();
+        sorter.sort_file(&tree.join("src/DSC0003.NEF")).unwrap();
+
+        tree.assert_files(
+            "dst",
+            &[
+                "dst/2021/jpeg/DSC0001.JPG",
+                "dst/2021/jpeg/DSC0002.JPG",
+                "dst/2021/DSC0003.JPG",
+                "dst/2021/raw/DSC0001.NEF",
+                "dst/2021/raw/DSC0002.NEF",
+                "dst/2021/raw/DSC0003.NEF",
+            ],
+        );
+        tree.assert_files("src", &[]);
+    }
+
+    #[test]
+    fn live_photos() {
+        let date = chrono::NaiveDate::from_ymd_opt(2021, 7, 14)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let tree = test_utils::TempTree::new()
+            .with_jpeg("src/IMG_0001.JPG", date)
+            .with_file("src/IMG_0001.MOV", "video");
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&format!(
+                    "{}/:date.year:/:date%Y%m%d:.:file.extension:",
+                    tree.join("dst").display()
+                ))
+                .unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_live_photos(true),
+        );
+
+        for name in ["IMG_0001.JPG", "IMG_0001.MOV"] {
+            sorter.sort_file(&tree.join("src").join(name)).unwrap();
+        }
+        tree.assert_files("dst", &["dst/2021/20210714.JPG", "dst/2021/20210714.MOV"]);
+    }
+
+    #[test]
+    fn events() {
+        let date = |hour| {
+            chrono::NaiveDate::from_ymd_opt(2023, 7, 14)
+                .unwrap()
+                .and_hms_opt(hour, 0, 0)
+                .unwrap()
+        };
+        let tree = test_utils::TempTree::new()
+            .with_jpeg("src/a.jpg", date(8))
+            .with_jpeg("src/b.jpg", date(9))
+            .with_jpeg("src/c.jpg", date(14));
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&format!(
+                    "{}/:date.event:/:file.name:",
+                    tree.join("dst").display()
+                ))
+                .unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_event_gap(Some(120)),
+        );
+
+        sorter.index_events(&[tree.join("src")]);
+        for name in ["c.jpg", "b.jpg", "a.jpg"] {
+            sorter.sort_file(&tree.join("src").join(name)).unwrap();
+        }
+        tree.assert_files(
+            "dst",
+            &[
+                "dst/2023-07-14_event-01/a.jpg",
+                "dst/2023-07-14_event-01/b.jpg",
+                "dst/2023-07-14_event-02/c.jpg",
+            ],
+        );
+    }
+
+    #[test]
+    fn deserialize_rules() {
+        let cfg: super::Config = toml::from_str(
+            r#"
+            replicator = "copy"
+            template = "/dst/:file.name:"
+            mirror = true
+            dir_mode = "0775"
+            chown = "0:0"
+
+            [[rules]]
+            match = "\\.jpg$"
+            template = "/dst/:date.year:/:file.name:"
+
+            [[rules]]
+            match = "\\.png$"
+            priority = 10
+            older_than = 5
+            "#,
+        )
+        .unwrap();
+        assert!(cfg.mirror);
+        assert_eq!(cfg.permissions.dir_mode.unwrap().bits(), 0o775);
+        assert_eq!(cfg.permissions.chown.unwrap().gid, Some(0));
+        assert_eq!(cfg.rules.len(), 2);
+        assert!(cfg.rules[0].is_match(Path::new("/src/a.jpg"), || None));
+        assert!(cfg.rules[1].template.is_none());
+        assert_eq!((cfg.rules[0].priority, cfg.rules[1].priority), (0, 10));
+        assert_eq!(
+            (cfg.rules[0].older_than, cfg.rules[1].older_than),
+            (None, Some(5))
+        );
+    }
+
+    #[test]
+    fn deserialize_max_component_length() {
+        let parse = |max_len: usize| {
+            toml::from_str::<super::Config>(&format!(
+                "replicator = \"copy\"\ntemplate = \"/dst/:file.name:\"\nmax_component_length = {}",
+                max_len
+            ))
+        };
+
+        assert_eq!(parse(9).unwrap().max_component_length, Some(9));
+        // shortened names wouldn't fit the hash.
+        assert!(parse(8).is_err());
+    }
+
+    #[test]
+    fn tag_rules() {
+        let src = setup();
+        let dst = PathBuf::from(format!("{}-Print", src.display()));
+        if xattr::set(&src, "user.xdg.tags", b"Print").is_err() {
+            // filesystem doesn't support user extended attributes.
+            return teardown(&src, &dst);
+        }
+
+        let cfg: super::Config = toml::from_str(
+            r#"
+            replicator = "copy"
+            template = ":file.path:-copy"
+
+            [[rules]]
+            tag = "print"
+            template = ":file.path:-:tags.first:"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Sorter::new(cfg).sort_file(&src).unwrap(),
+            SortResult::Replicated {
+                replicate_path: dst.clone(),
+                overwrite: false
+            }
+        );
+
+        teardown(&src, &dst);
+    }
+
+    #[test]
+    fn age_rules() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/2001-01-01.jpg"), "old").unwrap();
+        fs::write(root.join(format!("src/{}.jpg", today)), "new").unwrap();
+
+        let (hot, cold) = (root.join("hot"), root.join("cold"));
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&format!("{}/:file.name:", hot.display())).unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_rules(vec![Rule::new(
+                regex::Regex::new("").unwrap(),
+                Some(Template::from_str(&format!("{}/:file.name:", cold.display())).unwrap()),
+            )
+            .with_older_than(Some(5))]),
+        );
+
+        for name in ["2001-01-01.jpg", &format!("{}.jpg", today)] {
+            sorter.sort_file(&root.join("src").join(name)).unwrap();
+        }
+        assert!(cold.join("2001-01-01.jpg").exists());
+        assert!(hot.join(format!("{}.jpg", today)).exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn deserialize_aliases() {
+        let cfg: super::Config = toml::from_str(
+            r#"
+            replicator = "copy"
+            template = "/dst/:y:/:m:/:file.name:"
+
+            [aliases]
+            y = "date.year"
+            m = "date|month"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.template.to_string(),
+            "/dst/:date.year:/:date|month:/:file.name:"
+        );
+
+        let result: Result<super::Config, _> = toml::from_str(
+            r#"
+            replicator = "copy"
+            template = "/dst/:file.name:"
+
+            [aliases]
+            "file.name" = "file.stem"
+            "#,
+        );
+        assert!(result.is_err());
+    }
 
     #[test]
     fn template_error() {
-        let sorter = Sorter::new(super::Config {
-            template: Template::from_str(":inexistent.variable:").unwrap(),
-            replicator: Box::new(NoneReplicator::default()),
-            overwrite: false,
-        });
+        let sorter = Sorter::new(super::Config::new(
+            Template::from_str(":inexistent.variable:").unwrap(),
+            Box::new(NoneReplicator::default()),
+            false,
+        ));
 
         let result = sorter.sort_file(&PathBuf::from("/dev/null"));
 
@@ -191,11 +3090,11 @@ mod tests {
 
     #[test]
     fn replicate_error() {
-        let sorter = Sorter::new(super::Config {
-            template: Template::from_str(":file.path:2").unwrap(),
-            replicator: Box::new(NoneReplicator::default()),
-            overwrite: false,
-        });
+        let sorter = Sorter::new(super::Config::new(
+            Template::from_str(":file.path:2").unwrap(),
+            Box::new(NoneReplicator::default()),
+            false,
+        ));
 
         let result = sorter.sort_file(&PathBuf::from("/dev/null"));
 
@@ -215,11 +3114,11 @@ mod tests {
     fn overwrite_error() {
         let src_path = PathBuf::from("/proc/self/stat");
 
-        let sorter = Sorter::new(super::Config {
-            template: Template::from_str(":file.path:us").unwrap(),
-            replicator: Box::new(SoftLinkReplicator::default()),
-            overwrite: true,
-        });
+        let sorter = Sorter::new(super::Config::new(
+            Template::from_str(":file.path:us").unwrap(),
+            Box::new(SoftLinkReplicator::default()),
+            true,
+        ));
 
         let result = sorter.sort_file(&src_path);
 
@@ -235,14 +3134,42 @@ mod tests {
         assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
     }
 
+    #[test]
+    fn not_mounted_error() {
+        let src = setup();
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(":file.path:-copy").unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_mount(Some(MountPin::MountPoint(PathBuf::from("/does/not/exist")))),
+        );
+
+        let result = sorter.sort_file(&src);
+        let dst = PathBuf::from(format!("{}-copy", src.to_str().unwrap()));
+        assert!(!dst.exists());
+        teardown(&src, &dst);
+
+        match result {
+            Err(SortError::NotMounted(mount)) => {
+                assert_eq!(
+                    mount,
+                    MountPin::MountPoint(PathBuf::from("/does/not/exist"))
+                )
+            }
+            _ => panic!("expected error of type NotMounted, got \"{:?}\"", result),
+        }
+    }
+
     #[test]
     fn skipped_source_and_destination_are_same() {
         let src_path = PathBuf::from(env::args().next().unwrap());
-        let sorter = Sorter::new(super::Config {
-            template: Template::from_str(src_path.to_str().unwrap()).unwrap(),
-            replicator: Box::new(SoftLinkReplicator::default()),
-            overwrite: true,
-        });
+        let sorter = Sorter::new(super::Config::new(
+            Template::from_str(src_path.to_str().unwrap()).unwrap(),
+            Box::new(SoftLinkReplicator::default()),
+            true,
+        ));
 
         let result = sorter.sort_file(&src_path);
 
@@ -263,11 +3190,11 @@ mod tests {
     #[test]
     fn skipped_overwrite_disabled() {
         let src_path = PathBuf::from(env::args().next().unwrap());
-        let sorter = Sorter::new(super::Config {
-            template: Template::from_str(src_path.to_str().unwrap()).unwrap(),
-            replicator: Box::new(SoftLinkReplicator::default()),
-            overwrite: true,
-        });
+        let sorter = Sorter::new(super::Config::new(
+            Template::from_str(src_path.to_str().unwrap()).unwrap(),
+            Box::new(SoftLinkReplicator::default()),
+            true,
+        ));
 
         let result = sorter.sort_file(&src_path);
 
@@ -323,11 +3250,11 @@ mod tests {
         let mut expected_dst = src.to_str().unwrap().to_string();
         expected_dst.push_str("-copy");
 
-        let sorter = Sorter::new(super::Config {
-            template: Template::from_str(":file.path:-copy").unwrap(),
-            replicator: Box::new(CopyReplicator::default()),
-            overwrite: false,
-        });
+        let sorter = Sorter::new(super::Config::new(
+            Template::from_str(":file.path:-copy").unwrap(),
+            Box::new(CopyReplicator::default()),
+            false,
+        ));
 
         let result = sorter.sort_file(&src);
         assert!(result.is_ok());
@@ -359,11 +3286,11 @@ mod tests {
         expected_dst.push_str("-copy");
         let _ = fs::File::create(&expected_dst).unwrap();
 
-        let sorter = Sorter::new(super::Config {
-            template: Template::from_str(":file.path:-copy").unwrap(),
-            replicator: Box::new(CopyReplicator::default()),
-            overwrite: true,
-        });
+        let sorter = Sorter::new(super::Config::new(
+            Template::from_str(":file.path:-copy").unwrap(),
+            Box::new(CopyReplicator::default()),
+            true,
+        ));
 
         let result = sorter.sort_file(&src);
         assert!(result.is_ok());
@@ -386,4 +3313,948 @@ mod tests {
 
         teardown(&src, &replicate_path);
     }
+
+    #[test]
+    fn replicated_journaled() {
+        let src = setup();
+        let journal_path = env::temp_dir().join(format!("{}.jsonl", Uuid::new_v4()));
+
+        let sorter = Sorter::new(super::Config::new(
+            Template::from_str(":file.path:-copy").unwrap(),
+            Box::new(CopyReplicator::default()),
+            false,
+        ))
+        .with_journal(Journal::open(&journal_path).unwrap());
+
+        let replicate_path = match sorter.sort_file(&src).unwrap() {
+            SortResult::Replicated { replicate_path, .. } => replicate_path,
+            result => panic!(
+                "expected sort result of type Replicated, got \"{:?}\"",
+                result
+            ),
+        };
+
+        let entries = journal::query(&journal_path, &replicate_path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].src, src);
+        assert_eq!(entries[0].dst, replicate_path);
+        assert!(!entries[0].overwrite);
+
+        teardown(&src, &replicate_path);
+        let _ = fs::remove_file(journal_path.with_extension("idx"));
+        let _ = fs::remove_file(journal_path);
+    }
+
+    #[test]
+    fn dry_run() {
+        let src = setup();
+        let mut expected_dst = src.to_str().unwrap().to_string();
+        expected_dst.push_str("-copy");
+
+        let sorter = Sorter::new(super::Config::new(
+            Template::from_str(":file.path:-copy").unwrap(),
+            Box::new(CopyReplicator::default()),
+            true,
+        ))
+        .with_dry_run(true);
+
+        assert_eq!(
+            sorter.sort_file(&src).unwrap(),
+            SortResult::Replicated {
+                replicate_path: PathBuf::from(&expected_dst),
+                overwrite: false
+            }
+        );
+        assert!(!Path::new(&expected_dst).exists());
+
+        // existing replicate file is reported as overwritten but left untouched.
+        fs::write(&expected_dst, "").unwrap();
+        assert_eq!(
+            sorter.sort_file(&src).unwrap(),
+            SortResult::Replicated {
+                replicate_path: PathBuf::from(&expected_dst),
+                overwrite: true
+            }
+        );
+        assert!(!file_content_eq(&src, Path::new(&expected_dst)));
+
+        teardown(&src, Path::new(&expected_dst));
+    }
+
+    #[test]
+    fn copy_named_streams() {
+        let src = setup();
+        let dst = PathBuf::from(format!("{}-copy", src.display()));
+        if xattr::set(&src, "user.xdg.tags", b"holidays").is_err() {
+            // filesystem doesn't support user extended attributes.
+            return teardown(&src, &dst);
+        }
+
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(":file.path:-copy").unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_streams(crate::streams::StreamsPolicy::Copy),
+        );
+        sorter.sort_file(&src).unwrap();
+        assert_eq!(
+            xattr::get(&dst, "user.xdg.tags").unwrap().unwrap(),
+            b"holidays"
+        );
+
+        teardown(&src, &dst);
+    }
+
+    #[test]
+    fn skipped_already_sorted() {
+        let src = setup();
+        let dst = PathBuf::from(format!("{}-copy", src.display()));
+
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(":file.path:-copy").unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_mark(true),
+        );
+        sorter.sort_file(&src).unwrap();
+        if xattr::get(&dst, crate::marker::XATTR_NAME)
+            .ok()
+            .flatten()
+            .is_none()
+        {
+            // filesystem doesn't support user extended attributes.
+            return teardown(&src, &dst);
+        }
+
+        assert_eq!(
+            sorter.sort_file(&dst).unwrap(),
+            SortResult::Skipped {
+                replicate_path: PathBuf::from(format!("{}-copy", dst.display())),
+                reason: SkippedReason::AlreadySorted
+            }
+        );
+
+        teardown(&src, &dst);
+    }
+
+    #[test]
+    fn hard_link_not_marked() {
+        use crate::replicator::HardLinkReplicator;
+
+        let src = setup();
+        let dst = PathBuf::from(format!("{}-link", src.display()));
+
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(":file.path:-link").unwrap(),
+                Box::new(HardLinkReplicator::default()),
+                false,
+            )
+            .with_mark(true),
+        );
+        sorter.sort_file(&src).unwrap();
+        assert!(xattr::get(&src, crate::marker::XATTR_NAME)
+            .ok()
+            .flatten()
+            .is_none());
+
+        // source isn't recognized as a replicate on the next run.
+        fs::remove_file(&dst).unwrap();
+        assert!(matches!(
+            sorter.sort_file(&src).unwrap(),
+            SortResult::Replicated { .. }
+        ));
+
+        teardown(&src, &dst);
+    }
+
+    #[test]
+    fn moved() {
+        let src = setup();
+        let content = fs::read(&src).unwrap();
+        let dst = PathBuf::from(format!("{}-copy", src.display()));
+
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(":file.path:-copy").unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_move(true),
+        );
+
+        assert!(sorter.sort_file(&src).is_ok());
+        assert!(!src.exists());
+        assert_eq!(fs::read(&dst).unwrap(), content);
+
+        teardown(&src, &dst);
+    }
+
+    #[test]
+    fn moved_symlink_unverified() {
+        let src = setup();
+        let dst = PathBuf::from(format!("{}-link", src.display()));
+
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(":file.path:-link").unwrap(),
+                Box::new(SoftLinkReplicator::default()),
+                false,
+            )
+            .with_move(true),
+        );
+
+        match sorter.sort_file(&src) {
+            Err(SortError::UnverifiedReplicate(path)) => assert_eq!(path, dst),
+            result => panic!("expected UnverifiedReplicate error, got {:?}", result),
+        }
+        assert!(src.exists());
+
+        teardown(&src, &dst);
+    }
+
+    #[test]
+    fn failed_replicate_rolled_back() {
+        let src = setup();
+        let dst = PathBuf::from(format!("{}-link", src.display()));
+        fs::write(&dst, "old").unwrap();
+        let journal_path = env::temp_dir().join(format!("{}.jsonl", Uuid::new_v4()));
+
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(":file.path:-link").unwrap(),
+                Box::new(SoftLinkReplicator::default()),
+                true,
+            )
+            .with_move(true),
+        )
+        .with_journal(Journal::open(&journal_path).unwrap());
+
+        // link can't be verified, the overwritten file is restored.
+        assert!(matches!(
+            sorter.sort_file(&src),
+            Err(SortError::UnverifiedReplicate(_))
+        ));
+        assert!(!fs::symlink_metadata(&dst).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+        assert!(src.exists());
+        assert!(journal::query(&journal_path, &dst).unwrap().is_empty());
+
+        teardown(&src, &dst);
+        let _ = fs::remove_file(journal_path.with_extension("idx"));
+        let _ = fs::remove_file(journal_path);
+    }
+
+    #[test]
+    fn moved_in_place() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(root.join("old/2022")).unwrap();
+        fs::write(root.join("old/2022/2022-08-19.jpg"), "picture").unwrap();
+
+        // files are renamed, the symbolic link replicator isn't used.
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&format!(
+                    "{}/:file.name.date.year:/:file.name:",
+                    root.display()
+                ))
+                .unwrap(),
+                Box::new(SoftLinkReplicator::default()),
+                false,
+            )
+            .with_move(true)
+            .with_rename(true),
+        );
+
+        let dst = root.join("2022/2022-08-19.jpg");
+        let result = sorter.sort_source_file(&root.join("old/2022/2022-08-19.jpg"), Some(&root));
+        assert_eq!(
+            result.unwrap(),
+            SortResult::Replicated {
+                replicate_path: dst.clone(),
+                overwrite: false
+            }
+        );
+        assert!(!fs::symlink_metadata(&dst).unwrap().file_type().is_symlink());
+        assert!(!root.join("old").exists());
+
+        // moved file is skipped when its rename is handled.
+        assert_eq!(
+            sorter.sort_source_file(&dst, Some(&root)).unwrap(),
+            SortResult::Skipped {
+                replicate_path: dst.clone(),
+                reason: SkippedReason::AlreadySorted
+            }
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn renamed_move_journaled() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(root.join("old")).unwrap();
+        let src = root.join("old/a.jpg");
+        fs::write(&src, "a").unwrap();
+        let journal_path = root.join("journal.jsonl");
+
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&format!("{}/new/:file.name:", root.display())).unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_move(true)
+            .with_rename(true),
+        )
+        .with_journal(Journal::open(&journal_path).unwrap());
+
+        let dst = root.join("new/a.jpg");
+        sorter.sort_source_file(&src, Some(&root)).unwrap();
+        assert!(!root.join("old").exists());
+
+        let entries = journal::query(&journal_path, &dst).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].src, src);
+        assert!(entries[0].moved);
+
+        // renamed file is moved back to its source.
+        let undo = journal::undo(&journal_path, |_| true).unwrap();
+        assert!(undo.errors.is_empty());
+        assert_eq!(undo.undone.len(), 1);
+        assert_eq!(fs::read(&src).unwrap(), b"a");
+        assert!(!dst.exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn produced_not_sorted_again() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.jpg"), "a").unwrap();
+
+        // every sort of a file produces a new file under the source.
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&format!("{}/sorted-:file.name:", root.display())).unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_move(true)
+            .with_rename(true),
+        );
+
+        let dst = root.join("sorted-a.jpg");
+        assert_eq!(
+            sorter
+                .sort_source_file(&root.join("a.jpg"), Some(&root))
+                .unwrap(),
+            SortResult::Replicated {
+                replicate_path: dst.clone(),
+                overwrite: false
+            }
+        );
+        assert_eq!(
+            sorter.sort_source_file(&dst, Some(&root)).unwrap(),
+            SortResult::Skipped {
+                replicate_path: dst.clone(),
+                reason: SkippedReason::AlreadySorted
+            }
+        );
+        assert!(dst.exists());
+        assert!(!root.join("sorted-sorted-a.jpg").exists());
+
+        // produced files are skipped once, a later run sorts them.
+        assert!(matches!(
+            sorter.sort_source_file(&dst, Some(&root)),
+            Ok(SortResult::Replicated { .. })
+        ));
+        assert!(root.join("sorted-sorted-a.jpg").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn renamed_on_conflict() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(root.join("dst")).unwrap();
+        fs::write(root.join("dst/a.jpg"), "existing").unwrap();
+        fs::write(root.join("a.jpg"), "a").unwrap();
+
+        let template = format!("{}/dst/:file.name:", root.display());
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&template).unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_on_conflict(Box::new(ConflictPolicyKind::Rename)),
+        );
+
+        assert_eq!(
+            sorter.sort_file(&root.join("a.jpg")).unwrap(),
+            SortResult::Replicated {
+                replicate_path: root.join("dst/a-1.jpg"),
+                overwrite: false
+            }
+        );
+        assert_eq!(fs::read(root.join("dst/a.jpg")).unwrap(), b"existing");
+
+        // renamed file is a duplicate when sorted again.
+        assert_eq!(
+            sorter.sort_file(&root.join("a.jpg")).unwrap(),
+            SortResult::Skipped {
+                replicate_path: root.join("dst/a-1.jpg"),
+                reason: SkippedReason::Duplicate
+            }
+        );
+
+        fs::write(root.join("a.jpg"), "b").unwrap();
+        assert_eq!(
+            sorter.sort_file(&root.join("a.jpg")).unwrap(),
+            SortResult::Replicated {
+                replicate_path: root.join("dst/a-2.jpg"),
+                overwrite: false
+            }
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn custom_conflict_policy() {
+        /// Overwrites smaller replicate files and refuses to replace others.
+        #[derive(Debug)]
+        struct KeepLargest;
+
+        impl ConflictPolicy for KeepLargest {
+            fn decide(&self, src: &Path, _dst: &Path, metadata: &Metadata) -> Decision {
+                match fs::metadata(src) {
+                    Ok(src) if src.len() > metadata.len() => Decision::Overwrite,
+                    Ok(_) => Decision::Error("replicate file is larger".into()),
+                    Err(err) => Decision::Error(err.into()),
+                }
+            }
+        }
+
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(root.join("dst")).unwrap();
+        fs::write(root.join("dst/a.jpg"), "ab").unwrap();
+        fs::write(root.join("a.jpg"), "abc").unwrap();
+
+        let template = format!("{}/dst/:file.name:", root.display());
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&template).unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_on_conflict(Box::new(KeepLargest)),
+        );
+
+        assert_eq!(
+            sorter.sort_file(&root.join("a.jpg")).unwrap(),
+            SortResult::Replicated {
+                replicate_path: root.join("dst/a.jpg"),
+                overwrite: true
+            }
+        );
+        assert_eq!(fs::read(root.join("dst/a.jpg")).unwrap(), b"abc");
+
+        fs::write(root.join("a.jpg"), "a").unwrap();
+        assert!(matches!(
+            sorter.sort_file(&root.join("a.jpg")),
+            Err(SortError::ConflictError(_, path)) if path == root.join("dst/a.jpg")
+        ));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn filesystem_errors() {
+        let path = Path::new;
+        let mem = Arc::new(
+            MemFs::new()
+                .with_file("/src/2023/a.jpg", "a")
+                .with_file("/src/b.jpg", "b")
+                .with_file("/src/c.jpg", "c")
+                .with_file("/dst/c.jpg", "old")
+                .with_fault(Op::Rename, "/dst/a.jpg", io::ErrorKind::CrossesDevices)
+                .with_fault(Op::Rename, "/dst/b.jpg", io::ErrorKind::CrossesDevices)
+                .with_fault(Op::Copy, "/dst/b.jpg", io::ErrorKind::StorageFull)
+                .with_fault(Op::Remove, "/dst/c.jpg", io::ErrorKind::PermissionDenied),
+        );
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str("/dst/:file.name:").unwrap(),
+                Box::new(CopyReplicator::default()),
+                true,
+            )
+            .with_move(true)
+            .with_fs(mem.clone()),
+        );
+        let sort =
+            |src: &str, dst: &str| sorter.replicate_file(path(src), dst.into(), Some(path("/src")));
+
+        // moved across filesystems: copied, source and emptied directory
+        // removed.
+        assert_eq!(
+            sort("/src/2023/a.jpg", "/dst/a.jpg").unwrap(),
+            SortResult::Replicated {
+                replicate_path: "/dst/a.jpg".into(),
+                overwrite: false
+            }
+        );
+        assert!(!mem.exists(path("/src/2023")));
+
+        assert!(matches!(
+            sort("/src/b.jpg", "/dst/b.jpg"),
+            Err(SortError::ReplicateError(err, _)) if err.kind() == io::ErrorKind::StorageFull
+        ));
+        assert!(mem.exists(path("/src/b.jpg")));
+
+        assert!(matches!(
+            sort("/src/c.jpg", "/dst/c.jpg"),
+            Err(SortError::OverwriteError(err, _)) if err.kind() == io::ErrorKind::PermissionDenied
+        ));
+        assert_eq!(mem.read(path("/dst/c.jpg")).unwrap(), b"old");
+
+        assert_eq!(
+            mem.files(),
+            ["/dst/a.jpg", "/dst/c.jpg", "/src/b.jpg", "/src/c.jpg"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn permissions_on_fs() {
+        use crate::fs::MemPermissions;
+        use crate::permissions::{Owner, Permissions};
+        use crate::replicator::{HardLinkReplicator, Replicator};
+
+        let path = Path::new;
+        let mem = Arc::new(
+            MemFs::new()
+                .with_file("/src/a.jpg", "a")
+                .with_file("/src/b.jpg", "b")
+                .with_dir("/dst")
+                .with_fault(
+                    Op::SetPermissions,
+                    "/dst/copy/b.jpg",
+                    io::ErrorKind::PermissionDenied,
+                ),
+        );
+        let sorter = |replicator: Box<dyn Replicator>, dir: &str| {
+            Sorter::new(
+                super::Config::new(
+                    Template::from_str(&format!("/dst/{}/:file.name:", dir)).unwrap(),
+                    replicator,
+                    false,
+                )
+                .with_permissions(Permissions {
+                    dir_mode: Some("0750".parse().unwrap()),
+                    file_mode: Some("0640".parse().unwrap()),
+                    chown: Some(Owner {
+                        uid: None,
+                        gid: Some(0),
+                    }),
+                })
+                .with_fs(mem.clone()),
+            )
+        };
+        let permissions = |mode| MemPermissions {
+            mode: Some(mode),
+            uid: None,
+            gid: Some(0),
+        };
+
+        let copy = sorter(Box::<CopyReplicator>::default(), "copy");
+        copy.replicate_file(path("/src/a.jpg"), "/dst/copy/a.jpg".into(), None)
+            .unwrap();
+        assert_eq!(mem.permissions(path("/dst/copy")), permissions(0o750));
+        assert_eq!(mem.permissions(path("/dst/copy/a.jpg")), permissions(0o640));
+        // existing directories are left as is.
+        assert_eq!(mem.permissions(path("/dst")), MemPermissions::default());
+        assert!(matches!(
+            copy.replicate_file(path("/src/b.jpg"), "/dst/copy/b.jpg".into(), None),
+            Err(SortError::PermissionsError(err, _)) if err.kind() == io::ErrorKind::PermissionDenied
+        ));
+
+        // sources aren't changed through hard links.
+        sorter(Box::<HardLinkReplicator>::default(), "link")
+            .replicate_file(path("/src/a.jpg"), "/dst/link/a.jpg".into(), None)
+            .unwrap();
+        assert_eq!(mem.permissions(path("/dst/link")), permissions(0o750));
+        assert_eq!(
+            mem.permissions(path("/dst/link/a.jpg")),
+            MemPermissions::default()
+        );
+    }
+
+    #[test]
+    fn pipeline_stages() {
+        #[derive(Debug)]
+        struct NoText;
+
+        impl Filter for NoText {
+            fn accept(&self, src_path: &Path) -> Result<bool, StageError> {
+                Ok(src_path.extension() != Some("txt".as_ref()))
+            }
+        }
+
+        #[derive(Debug)]
+        struct Lowercase;
+
+        impl PathPlanner for Lowercase {
+            fn plan(&self, _src_path: &Path, planned: PathBuf) -> Result<PathBuf, StageError> {
+                Ok(PathBuf::from(planned.to_string_lossy().to_lowercase()))
+            }
+        }
+
+        #[derive(Debug, Default)]
+        struct Record(Arc<Mutex<Vec<SortResult>>>);
+
+        #[derive(Debug)]
+        struct Fail;
+
+        impl Hook for Fail {
+            fn after_sort(&self, _src_path: &Path, _result: &SortResult) -> Result<(), StageError> {
+                Err("hook failed".into())
+            }
+        }
+
+        impl Hook for Record {
+            fn after_sort(&self, _src_path: &Path, result: &SortResult) -> Result<(), StageError> {
+                self.0.lock().unwrap().push(result.clone());
+                Ok(())
+            }
+        }
+
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("A.JPG"), "a").unwrap();
+        fs::write(root.join("notes.txt"), "notes").unwrap();
+
+        let record = Record::default();
+        let results = record.0.clone();
+        let template = format!("{}/dst/:file.name:", root.display());
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&template).unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_filters(vec![Box::new(NoText)])
+            .with_planners(vec![Box::new(Lowercase)])
+            .with_hooks(vec![Box::new(record)]),
+        );
+
+        let replicated = SortResult::Replicated {
+            replicate_path: PathBuf::from(format!("{}/dst/a.jpg", root.display()).to_lowercase()),
+            overwrite: false,
+        };
+        assert_eq!(sorter.sort_file(&root.join("A.JPG")).unwrap(), replicated);
+
+        let filtered = SortResult::Skipped {
+            replicate_path: root.join("notes.txt"),
+            reason: SkippedReason::Filtered,
+        };
+        assert_eq!(sorter.sort_file(&root.join("notes.txt")).unwrap(), filtered);
+        assert_eq!(*results.lock().unwrap(), [replicated, filtered]);
+
+        // results of sorted files are kept when a hook fails.
+        let template = format!("{}/failed/:file.name:", root.display());
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&template).unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_hooks(vec![Box::new(Fail)]),
+        );
+        assert!(matches!(
+            sorter.sort_file(&root.join("A.JPG")),
+            Err(SortError::HookError(_, _, SortResult::Replicated { replicate_path, .. }))
+                if replicate_path == root.join("failed/A.JPG")
+        ));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn counter() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        for name in ["a", "b", "c"] {
+            fs::write(root.join(format!("{}.jpg", name)), name).unwrap();
+        }
+
+        let template = format!("{}/dst/:counter:.:file.extension:", root.display());
+        let sorter = || {
+            Sorter::new(
+                super::Config::new(
+                    Template::from_str(&template).unwrap(),
+                    Box::new(CopyReplicator::default()),
+                    false,
+                )
+                .with_skip_duplicates(true),
+            )
+        };
+        let replicate_path = |result: super::Result| match result.unwrap() {
+            SortResult::Replicated { replicate_path, .. } => replicate_path,
+            SortResult::Skipped { replicate_path, .. } => replicate_path,
+        };
+
+        let first = sorter();
+        assert_eq!(
+            replicate_path(first.sort_file(&root.join("a.jpg"))),
+            root.join("dst/0001.jpg")
+        );
+        assert_eq!(
+            replicate_path(first.sort_file(&root.join("b.jpg"))),
+            root.join("dst/0002.jpg")
+        );
+
+        // existing files with the same content keep their counter, whatever
+        // the order they are sorted in.
+        let second = sorter();
+        assert_eq!(
+            second.sort_file(&root.join("b.jpg")).unwrap(),
+            SortResult::Skipped {
+                replicate_path: root.join("dst/0002.jpg"),
+                reason: SkippedReason::Duplicate
+            }
+        );
+        assert_eq!(
+            replicate_path(second.sort_file(&root.join("c.jpg"))),
+            root.join("dst/0003.jpg")
+        );
+        assert_eq!(
+            replicate_path(second.sort_file(&root.join("a.jpg"))),
+            root.join("dst/0001.jpg")
+        );
+
+        // counters are allocated in the order files are reserved.
+        fs::remove_dir_all(root.join("dst")).unwrap();
+        let reserved = sorter();
+        for name in ["a", "b", "c"] {
+            reserved.reserve_counter(&root.join(format!("{}.jpg", name)), None);
+        }
+        for (name, counter) in [("c", 3), ("a", 1), ("b", 2)] {
+            assert_eq!(
+                replicate_path(reserved.sort_file(&root.join(format!("{}.jpg", name)))),
+                root.join(format!("dst/{:04}.jpg", counter))
+            );
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn skipped_duplicate() {
+        let src = setup();
+        let dst = PathBuf::from(format!("{}-copy", src.display()));
+        fs::copy(&src, &dst).unwrap();
+
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(":file.path:-copy").unwrap(),
+                Box::new(CopyReplicator::default()),
+                true,
+            )
+            .with_skip_duplicates(true),
+        );
+        assert_eq!(
+            sorter.sort_file(&src).unwrap(),
+            SortResult::Skipped {
+                replicate_path: dst.clone(),
+                reason: SkippedReason::Duplicate
+            }
+        );
+
+        // different content is overwritten.
+        fs::write(&dst, "").unwrap();
+        assert_eq!(
+            sorter.sort_file(&src).unwrap(),
+            SortResult::Replicated {
+                replicate_path: dst.clone(),
+                overwrite: true
+            }
+        );
+
+        teardown(&src, &dst);
+    }
+
+    #[test]
+    fn extracted_thumbnail() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let thumbs = root.join("thumbs");
+        fs::create_dir_all(root.join("src")).unwrap();
+        let thumbnail = b"\xff\xd8thumbnail\xff\xd9";
+        fs::write(
+            root.join("src/photo.jpeg"),
+            test_utils::jpeg_with_thumbnail(thumbnail),
+        )
+        .unwrap();
+        fs::write(root.join("src/notes.txt"), "notes").unwrap();
+
+        let template = format!("{}/dst/:file.extension:/:file.name:", root.display());
+        let sorter = Sorter::new(
+            super::Config::new(
+                Template::from_str(&template).unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_extract_thumbs(Some(thumbs.clone())),
+        );
+
+        assert!(sorter.sort_file(&root.join("src/photo.jpeg")).is_ok());
+        assert_eq!(fs::read(thumbs.join("jpeg/photo.jpg")).unwrap(), thumbnail);
+
+        // files without thumbnail are replicated only.
+        assert!(sorter.sort_file(&root.join("src/notes.txt")).is_ok());
+        assert!(root.join("dst/txt/notes.txt").exists());
+        assert!(!thumbs.join("txt/notes.jpg").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn sorted_in_parallel() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let sources: Vec<PathBuf> = (0..8).map(|i| root.join(format!("{}/a.jpg", i))).collect();
+        for (i, src) in sources.iter().enumerate() {
+            fs::create_dir_all(src.parent().unwrap()).unwrap();
+            fs::write(src, format!("{}", i)).unwrap();
+        }
+
+        let template = format!("{}/dst/:file.name:", root.display());
+        let sorter = Sorter::new(super::Config::new(
+            Template::from_str(&template).unwrap(),
+            Box::new(CopyReplicator::default()),
+            false,
+        ));
+
+        let results: Vec<SortResult> = std::thread::scope(|scope| {
+            let handles: Vec<_> = sources
+                .iter()
+                .map(|src| scope.spawn(|| sorter.sort_file(src).unwrap()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        // files with the same destination are replicated one at a time.
+        let replicated = results
+            .iter()
+            .filter(|r| matches!(r, SortResult::Replicated { .. }))
+            .count();
+        assert_eq!(replicated, 1);
+        assert!(results.iter().all(|r| matches!(
+            r,
+            SortResult::Replicated { .. }
+                | SortResult::Skipped {
+                    reason: SkippedReason::Overwrite,
+                    ..
+                }
+        )));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn truncate_components() {
+        let truncate = |path: &str| super::truncate_components(Path::new(path), 24);
+
+        assert_eq!(
+            truncate("/dst/2023/short.jpg"),
+            PathBuf::from("/dst/2023/short.jpg")
+        );
+
+        let long_name = format!("{}.jpg", "a".repeat(40));
+        let path = truncate(&format!("/dst/{}/{}", "é".repeat(20), long_name));
+        let components: Vec<&str> = path.iter().map(|c| c.to_str().unwrap()).collect();
+        assert_eq!(components.len(), 4);
+        // multi-byte characters aren't split.
+        assert!(components[2].starts_with(&"é".repeat(7)));
+        assert!(components[2].len() <= 24);
+        assert!(components[3].starts_with(&"a".repeat(11)));
+        assert!(components[3].ends_with(".jpg"));
+        assert_eq!(components[3].len(), 24);
+
+        // names differing after the truncation stay unique.
+        let other = truncate(&format!("/dst/{}b.jpg", "a".repeat(40)));
+        assert_ne!(other.file_name(), path.file_name());
+    }
+
+    #[test]
+    fn confine() {
+        let root = Path::new("/library");
+        let confine = |path: &str| super::confine(root, &root.join(path));
+
+        assert_eq!(
+            confine("2023/./07/a.jpg"),
+            Some(PathBuf::from("/library/2023/07/a.jpg"))
+        );
+        assert_eq!(
+            confine("/library/2023/a.jpg"),
+            Some(PathBuf::from("/library/2023/a.jpg"))
+        );
+        assert_eq!(
+            confine("2023/../a.jpg"),
+            Some(PathBuf::from("/library/a.jpg"))
+        );
+        assert_eq!(confine("2023/../../etc/passwd"), None);
+        assert_eq!(confine("/etc/passwd"), None);
+        assert_eq!(confine("/library-old/a.jpg"), None);
+        assert_eq!(confine("2023/.."), None);
+
+        // relative roots are resolved from the current directory.
+        let parent = env::current_dir().unwrap().parent().unwrap().to_owned();
+        let root = Path::new("../photos");
+        assert_eq!(
+            super::confine(root, &root.join("2023/a.jpg")),
+            Some(parent.join("photos/2023/a.jpg"))
+        );
+        assert_eq!(super::confine(root, Path::new("../a.jpg")), None);
+    }
+
+    #[test]
+    fn truncate_path() {
+        let path = format!(
+            "/dst/{}/{}/{}.jpg",
+            "a".repeat(40),
+            "b".repeat(30),
+            "c".repeat(20)
+        );
+        let root = Path::new("/dst");
+        assert_eq!(
+            super::truncate_path(root, Path::new(&path), 200),
+            Some(PathBuf::from(&path))
+        );
+
+        let truncated = super::truncate_path(root, Path::new(&path), 80).unwrap();
+        assert!(truncated.as_os_str().len() <= 80);
+        let components: Vec<&str> = truncated.iter().map(|c| c.to_str().unwrap()).collect();
+        // the longest components are shortened first.
+        assert!(components[2].len() < 40);
+        assert!(components[2].starts_with("aaaa"));
+        assert_eq!(components[4], format!("{}.jpg", "c".repeat(20)));
+
+        // components aren't shortened below the minimum length.
+        let truncated = super::truncate_path(root, Path::new(&path), 10).unwrap();
+        assert!(truncated.iter().skip(2).all(|c| c.len() == 16));
+        // paths whose root alone is too long are errors.
+        assert_eq!(super::truncate_path(root, Path::new(&path), 3), None);
+
+        // components of the root aren't shortened.
+        let root = format!("/{}", "r".repeat(40));
+        let path = format!("{}/{}.jpg", root, "a".repeat(40));
+        let truncated = super::truncate_path(Path::new(&root), Path::new(&path), 60).unwrap();
+        assert!(truncated.starts_with(&root));
+        assert!(truncated.as_os_str().len() <= 60);
+    }
 }
@@ -0,0 +1,263 @@
+//! Versioned layout of the state directory: undo journal, hash index and
+//! queues of interrupted runs.
+//!
+//! The layout version is stored in the `VERSION` file of the directory,
+//! directories without it use the unversioned layout of previous releases.
+//! [`StateDir::migrate`] upgrades older layouts one version at a time, the
+//! version is written after each migration so an interrupted upgrade resumes
+//! where it stopped. Migrations must be idempotent as a crash between a
+//! migration and the version update runs it again.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+/// Layout version written by this release.
+pub const VERSION: u32 = MIGRATIONS.len() as u32;
+
+const VERSION_FILE: &str = "VERSION";
+
+/// Migration from the layout version at its index to the next one.
+type Migration = fn(&Path) -> io::Result<()>;
+
+const MIGRATIONS: [Migration; 2] = [migrate_v0, migrate_v1];
+
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("failed to read state version file {1:?}: {0}")]
+    ReadVersion(io::Error, PathBuf),
+
+    #[error("invalid state version file {0:?}")]
+    InvalidVersion(PathBuf),
+
+    #[error(
+        "state directory {0:?} has version {1} but this release only supports up to version {VERSION}, upgrade photosort"
+    )]
+    UnsupportedVersion(PathBuf, u32),
+
+    #[error("failed to migrate state directory {1:?} to version {2}: {0}")]
+    Migration(io::Error, PathBuf, u32),
+}
+
+/// StateDir resolves paths of state files in a state directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateDir {
+    root: PathBuf,
+}
+
+impl StateDir {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Journal recording replicated files for undo.
+    pub fn journal_path(&self) -> PathBuf {
+        self.root.join("journal.jsonl")
+    }
+
+    /// Hash index of deduplicated files.
+    pub fn index_path(&self) -> PathBuf {
+        self.root.join("index.json")
+    }
+
+    /// Destination paths rendered for source files.
+    pub fn render_cache_path(&self) -> PathBuf {
+        self.root.join("render-cache.json")
+    }
+
+    /// Snapshot of sources of the last successful run of the configuration
+    /// identified by the given key.
+    pub fn snapshot_path(&self, key: &[u8]) -> PathBuf {
+        let hash = blake3::hash(key);
+        self.root.join("snapshots").join(&hash.to_hex()[..16])
+    }
+
+    /// Checkpoint of the planner, files left to sort by an interrupted run.
+    pub fn checkpoint_path(&self) -> PathBuf {
+        self.root.join("queues").join("plan-checkpoint.json")
+    }
+
+    /// Plan spilled by the given process while sources are walked, removed
+    /// once it is written to the plan file.
+    pub fn plan_spill_path(&self, pid: u32) -> PathBuf {
+        self.root.join("queues").join(format!("plan-{}.jsonl", pid))
+    }
+
+    /// Lock file of the state directory, held shared by runs and exclusively
+    /// by commands rewriting the journal.
+    pub fn lock_path(&self) -> PathBuf {
+        self.root.join("photosort.lock")
+    }
+
+    /// Lock file preventing concurrent runs sorting files to the given
+    /// destination root, identified by its canonical path if it exists.
+    pub fn destination_lock_path(&self, root: &Path) -> PathBuf {
+        let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_owned());
+        let hash = blake3::hash(root.as_os_str().as_encoded_bytes());
+        self.root
+            .join("locks")
+            .join(format!("{}.lock", &hash.to_hex()[..16]))
+    }
+
+    /// Pid file of the watch daemon.
+    pub fn pid_path(&self) -> PathBuf {
+        self.root.join("photosort.pid")
+    }
+
+    /// Returns the layout version of the directory, 0 if it isn't versioned.
+    pub fn version(&self) -> Result<u32, StateError> {
+        let path = self.root.join(VERSION_FILE);
+        match fs::read_to_string(&path) {
+            Ok(version) => version
+                .trim()
+                .parse()
+                .map_err(|_| StateError::InvalidVersion(path)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(StateError::ReadVersion(err, path)),
+        }
+    }
+
+    /// Creates the directory if needed and upgrades its layout to
+    /// [`VERSION`], returns the previous version. Directories written by a
+    /// newer release are left untouched.
+    pub fn migrate(&self) -> Result<u32, StateError> {
+        let migration_err = |err, version| StateError::Migration(err, self.root.clone(), version);
+
+        fs::create_dir_all(&self.root).map_err(|err| migration_err(err, VERSION))?;
+        let previous = self.version()?;
+        if previous > VERSION {
+            return Err(StateError::UnsupportedVersion(self.root.clone(), previous));
+        }
+
+        for (version, migration) in (previous..).zip(&MIGRATIONS[previous as usize..]) {
+            log::info!(
+                "migrating state directory {:?} to version {}",
+                self.root,
+                version + 1
+            );
+            migration(&self.root)
+                .and_then(|_| self.write_version(version + 1))
+                .map_err(|err| migration_err(err, version + 1))?;
+        }
+
+        Ok(previous)
+    }
+
+    fn write_version(&self, version: u32) -> io::Result<()> {
+        let path = self.root.join(VERSION_FILE);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, format!("{}\n", version))?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+/// Moves the planner checkpoint to the queues directory and removes temporary
+/// files of interrupted writes.
+fn migrate_v0(root: &Path) -> io::Result<()> {
+    fs::create_dir_all(root.join("queues"))?;
+    let checkpoint = root.join("plan-checkpoint.json");
+    if checkpoint.exists() {
+        fs::rename(checkpoint, root.join("queues").join("plan-checkpoint.json"))?;
+    }
+
+    for name in ["journal.tmp", "index.tmp", "plan-checkpoint.tmp"] {
+        match fs::remove_file(root.join(name)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the source snapshot shared by every configuration, snapshots are
+/// now saved per configuration.
+fn migrate_v1(root: &Path) -> io::Result<()> {
+    for name in ["source-snapshot", "source-snapshot.tmp"] {
+        match fs::remove_file(root.join(name)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    use super::{StateDir, StateError, VERSION};
+
+    #[test]
+    fn migrate() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("journal.jsonl"), "{}\n").unwrap();
+        fs::write(root.join("plan-checkpoint.json"), "[]").unwrap();
+        fs::write(root.join("index.tmp"), "").unwrap();
+        fs::write(root.join("source-snapshot"), "").unwrap();
+
+        let state = StateDir::new(root.clone());
+        assert_eq!(state.version().unwrap(), 0);
+        assert_eq!(state.migrate().unwrap(), 0);
+        assert_eq!(state.version().unwrap(), VERSION);
+        assert_eq!(fs::read_to_string(state.checkpoint_path()).unwrap(), "[]");
+        assert_eq!(fs::read_to_string(state.journal_path()).unwrap(), "{}\n");
+        assert!(!root.join("plan-checkpoint.json").exists());
+        assert!(!root.join("index.tmp").exists());
+        assert!(!root.join("source-snapshot").exists());
+
+        // up to date directories are left as is.
+        assert_eq!(state.migrate().unwrap(), VERSION);
+
+        // directories of newer releases aren't downgraded.
+        fs::write(root.join("VERSION"), format!("{}", VERSION + 1)).unwrap();
+        assert!(matches!(
+            state.migrate(),
+            Err(StateError::UnsupportedVersion(_, v)) if v == VERSION + 1
+        ));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn migrate_new_dir() {
+        let root = env::temp_dir()
+            .join(format!("{}", Uuid::new_v4()))
+            .join("photosort");
+        let state = StateDir::new(root.clone());
+
+        assert_eq!(state.migrate().unwrap(), 0);
+        assert_eq!(state.version().unwrap(), VERSION);
+        assert!(state.checkpoint_path().parent().unwrap().is_dir());
+
+        fs::remove_dir_all(root.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn destination_lock_path() {
+        let root = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir_all(root.join("photos")).unwrap();
+        fs::create_dir_all(root.join("videos")).unwrap();
+        let state = StateDir::new(root.join("state"));
+
+        let lock_path = state.destination_lock_path(&root.join("photos"));
+        assert_eq!(lock_path.parent(), Some(root.join("state/locks").as_path()));
+        assert_eq!(
+            state.destination_lock_path(&root.join("videos/../photos")),
+            lock_path
+        );
+        assert_ne!(state.destination_lock_path(&root.join("videos")), lock_path);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+//! Named streams of files: extended attributes on unix (which hold macOS
+//! resource forks and Finder info) and alternate data streams on Windows
+//! (e.g. zone identifiers and AFP resource forks written by macOS servers).
+//!
+//! Replicators copy file content only, named streams may be lost when files
+//! are replicated across filesystems or platforms.
+
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::marker;
+
+/// Alternate data streams probed on Windows, they can't be listed without
+/// the Win32 API.
+#[cfg(windows)]
+const KNOWN_STREAMS: &[&str] = &["Zone.Identifier", "AFP_AfpInfo", "AFP_Resource"];
+
+/// StreamsPolicy define how named streams missing on replicate files are
+/// handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamsPolicy {
+    /// Don't check named streams.
+    Ignore,
+    /// Log a warning listing lost named streams.
+    #[default]
+    Warn,
+    /// Copy lost named streams from the source file.
+    Copy,
+}
+
+/// Returns the names of the named streams of the given file, the photosort
+/// marker is excluded. Filesystems without named streams have none.
+pub fn names(path: &Path) -> io::Result<Vec<OsString>> {
+    let mut names: Vec<OsString> = match xattr::list(path) {
+        Ok(names) => names.filter(|name| name != marker::XATTR_NAME).collect(),
+        Err(err) if err.kind() == io::ErrorKind::Unsupported => Vec::new(),
+        Err(err) => return Err(err),
+    };
+
+    #[cfg(windows)]
+    for name in KNOWN_STREAMS {
+        if std::fs::metadata(stream_path(path, name.as_ref())).is_ok() {
+            names.push(name.into());
+        }
+    }
+
+    names.sort();
+    Ok(names)
+}
+
+/// Returns the named streams of `src` that `dst` doesn't have.
+pub fn missing(src: &Path, dst: &Path) -> io::Result<Vec<OsString>> {
+    let dst_names = names(dst)?;
+    Ok(names(src)?
+        .into_iter()
+        .filter(|name| !dst_names.contains(name))
+        .collect())
+}
+
+/// Copies the given named streams from `src` to `dst`.
+pub fn copy(src: &Path, dst: &Path, names: &[OsString]) -> io::Result<()> {
+    for name in names {
+        #[cfg(windows)]
+        if KNOWN_STREAMS.iter().any(|known| name == known) {
+            std::fs::copy(stream_path(src, name), stream_path(dst, name))?;
+            continue;
+        }
+
+        if let Some(value) = xattr::get(src, name)? {
+            xattr::set(dst, name, &value)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn stream_path(path: &Path, name: &std::ffi::OsStr) -> std::path::PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(":");
+    path.push(name);
+    path.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    #[test]
+    fn copy_missing() {
+        let src = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        let dst = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::write(&src, "content").unwrap();
+        fs::write(&dst, "content").unwrap();
+        let teardown = || {
+            fs::remove_file(&src).unwrap();
+            fs::remove_file(&dst).unwrap();
+        };
+
+        if xattr::set(&src, "user.xdg.origin.url", b"https://example.com").is_err()
+            || xattr::set(&src, crate::marker::XATTR_NAME, b"marker").is_err()
+        {
+            // filesystem doesn't support user extended attributes.
+            return teardown();
+        }
+
+        let missing = super::missing(&src, &dst).unwrap();
+        assert_eq!(missing, ["user.xdg.origin.url"]);
+
+        super::copy(&src, &dst, &missing).unwrap();
+        assert!(super::missing(&src, &dst).unwrap().is_empty());
+        assert_eq!(
+            xattr::get(&dst, "user.xdg.origin.url").unwrap().unwrap(),
+            b"https://example.com"
+        );
+
+        teardown();
+    }
+}
@@ -0,0 +1,177 @@
+//! Summary of a run written as JSON at its end, so wrapper scripts and
+//! backup orchestrators can branch on results without parsing logs.
+
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::progress::Progress;
+
+/// Maximum number of errors listed in a summary, following errors are only
+/// counted.
+const MAX_ERRORS: usize = 1000;
+
+/// Error of a run, path is none if the error isn't specific to a file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RunError {
+    pub path: Option<PathBuf>,
+    pub error: String,
+}
+
+/// Summary of a finished run.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    /// Identifier of the run recorded in journal entries.
+    pub run_id: String,
+    pub dry_run: bool,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration_secs: f64,
+    /// Files walked in sources, only counted by runs walking sources.
+    pub discovered: usize,
+    pub planned: usize,
+    pub replicated: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Size of replicated files.
+    pub bytes: u64,
+    /// First errors of the run, see `errors_truncated`.
+    pub errors: Vec<RunError>,
+    /// Number of errors that aren't listed.
+    pub errors_truncated: usize,
+    pub exit_code: i32,
+}
+
+impl Summary {
+    /// Writes the summary as JSON to the given path, the file is replaced
+    /// atomically so readers never see a partial summary.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Errors {
+    listed: Vec<RunError>,
+    truncated: usize,
+}
+
+/// Report collects counters (through its [`Progress`]) and errors of a run.
+/// Clones share the same report.
+#[derive(Debug, Clone)]
+pub struct Report {
+    run_id: String,
+    dry_run: bool,
+    started_at: DateTime<Utc>,
+    start: Instant,
+    progress: Progress,
+    errors: Arc<Mutex<Errors>>,
+}
+
+impl Report {
+    /// Starts a report, `progress` must be the one of the planner and sorter
+    /// of the run.
+    pub fn new(run_id: String, dry_run: bool, progress: Progress) -> Self {
+        Self {
+            run_id,
+            dry_run,
+            started_at: Utc::now(),
+            start: Instant::now(),
+            progress,
+            errors: Arc::default(),
+        }
+    }
+
+    /// Returns the progress counting files of the run.
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    /// Records an error of the run.
+    pub fn error(&self, path: Option<&Path>, err: &dyn Display) {
+        let mut errors = self.errors.lock().unwrap();
+        if errors.listed.len() < MAX_ERRORS {
+            errors.listed.push(RunError {
+                path: path.map(Path::to_owned),
+                error: err.to_string(),
+            });
+        } else {
+            errors.truncated += 1;
+        }
+    }
+
+    /// Returns the summary of the run, which ended now.
+    pub fn summary(&self, exit_code: i32) -> Summary {
+        let counters = self.progress.snapshot();
+        let errors = self.errors.lock().unwrap();
+
+        Summary {
+            run_id: self.run_id.clone(),
+            dry_run: self.dry_run,
+            started_at: self.started_at,
+            finished_at: Utc::now(),
+            duration_secs: self.start.elapsed().as_secs_f64(),
+            discovered: counters.discovered,
+            planned: counters.planned,
+            replicated: counters.replicated,
+            skipped: counters.skipped,
+            failed: counters.failed,
+            bytes: counters.bytes,
+            errors: errors.listed.clone(),
+            errors_truncated: errors.truncated,
+            exit_code,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    use crate::progress::Progress;
+
+    use super::{Report, RunError};
+
+    #[test]
+    fn write_summary() {
+        let dir = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("summary.json");
+
+        let report = Report::new("run".to_owned(), false, Progress::new());
+        report.error(Some(Path::new("/src/a.jpg")), &"permission denied");
+        for _ in 0..super::MAX_ERRORS {
+            report.error(None, &"failed");
+        }
+        let summary = report.summary(1);
+        assert_eq!(
+            summary.errors[0],
+            RunError {
+                path: Some(Path::new("/src/a.jpg").to_owned()),
+                error: "permission denied".to_owned()
+            }
+        );
+        assert_eq!(summary.errors.len(), super::MAX_ERRORS);
+        assert_eq!(summary.errors_truncated, 1);
+
+        summary.write(&path).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(json["run_id"], "run");
+        assert_eq!(json["exit_code"], 1);
+        assert_eq!(json["replicated"], 0);
+        assert!(!dir.join("summary.tmp").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
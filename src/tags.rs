@@ -0,0 +1,181 @@
+//! User tags of files: macOS Finder tags and Linux `user.xdg.tags`, both
+//! stored in extended attributes.
+
+use std::io;
+use std::path::Path;
+
+/// Extended attribute storing Finder tags, a binary property list of
+/// strings.
+const FINDER_XATTR: &str = "com.apple.metadata:_kMDItemUserTags";
+
+/// Extended attribute storing comma separated tags (freedesktop.org).
+const XDG_XATTR: &str = "user.xdg.tags";
+
+/// Returns the tags of the given file in order, Finder tags first. Files
+/// without tags and filesystems without extended attributes have none.
+pub fn read(path: &Path) -> io::Result<Vec<String>> {
+    let get = |name| match xattr::get(path, name) {
+        Err(err) if err.kind() == io::ErrorKind::Unsupported => Ok(None),
+        result => result,
+    };
+
+    let mut tags = Vec::new();
+    if let Some(value) = get(FINDER_XATTR)? {
+        // Finder tags are suffixed with their color label, e.g. "Keep\n6".
+        tags.extend(
+            bplist_strings(&value)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|tag| match tag.split_once('\n') {
+                    Some((name, _color)) => name.to_owned(),
+                    None => tag,
+                }),
+        );
+    }
+    if let Some(value) = get(XDG_XATTR)? {
+        tags.extend(
+            String::from_utf8_lossy(&value)
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_owned),
+        );
+    }
+
+    tags.dedup();
+    Ok(tags)
+}
+
+/// Returns true if the given file has the given tag, tags are compared case
+/// insensitively. Files whose tags can't be read don't have any.
+pub fn has_tag(path: &Path, tag: &str) -> bool {
+    read(path).is_ok_and(|tags| tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+}
+
+/// Decodes a binary property list whose top object is an array of strings,
+/// none is returned for other property lists.
+fn bplist_strings(data: &[u8]) -> Option<Vec<String>> {
+    let uint = |bytes: &[u8]| {
+        (bytes.len() <= 8).then(|| bytes.iter().fold(0u64, |n, b| n << 8 | u64::from(*b)))
+    };
+    let usize_at = |pos: usize, size: usize| -> Option<usize> {
+        uint(data.get(pos..pos.checked_add(size)?)?)?
+            .try_into()
+            .ok()
+    };
+
+    if !data.starts_with(b"bplist00") || data.len() < 40 {
+        return None;
+    }
+    let trailer = &data[data.len() - 32..];
+    let offset_size = usize::from(trailer[6]);
+    let ref_size = usize::from(trailer[7]);
+    let top = usize::try_from(uint(&trailer[16..24])?).ok()?;
+    let table = usize::try_from(uint(&trailer[24..32])?).ok()?;
+
+    let offset = |object: usize| {
+        usize_at(
+            table.checked_add(object.checked_mul(offset_size)?)?,
+            offset_size,
+        )
+    };
+    // returns the length of the object at the given offset and the offset of
+    // its content.
+    let length = |pos: usize| -> Option<(usize, usize)> {
+        let marker = *data.get(pos)?;
+        match marker & 0x0f {
+            0x0f => {
+                // length is stored in the next integer object.
+                let int_marker = *data.get(pos + 1)?;
+                if int_marker >> 4 != 0x1 {
+                    return None;
+                }
+                let size = 1usize.checked_shl(u32::from(int_marker & 0x0f))?;
+                Some((usize_at(pos + 2, size)?, pos + 2 + size))
+            }
+            len => Some((usize::from(len), pos + 1)),
+        }
+    };
+
+    let array = offset(top)?;
+    if data.get(array)? >> 4 != 0xa {
+        return None;
+    }
+    let (count, refs) = length(array)?;
+
+    let mut strings = Vec::with_capacity(count.min(64));
+    for i in 0..count {
+        let object = offset(usize_at(refs + i * ref_size, ref_size)?)?;
+        let (len, start) = length(object)?;
+        let string = match data.get(object)? >> 4 {
+            // ASCII string.
+            0x5 => {
+                let bytes = data.get(start..start.checked_add(len)?)?;
+                String::from_utf8_lossy(bytes).into_owned()
+            }
+            // UTF-16 string, its length is in code units.
+            0x6 => {
+                let units: Vec<u16> = data
+                    .get(start..start.checked_add(len.checked_mul(2)?)?)?
+                    .chunks_exact(2)
+                    .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            _ => return None,
+        };
+        strings.push(string);
+    }
+
+    Some(strings)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    #[test]
+    fn bplist_strings() {
+        let mut data = b"bplist00".to_vec();
+        // array of objects 1 and 2.
+        data.extend([0xa2, 1, 2]);
+        // ASCII string "Keep\n6".
+        data.extend([0x56]);
+        data.extend(b"Keep\n6");
+        // UTF-16 string "Été".
+        data.extend([0x63, 0x00, 0xc9, 0x00, 0x74, 0x00, 0xe9]);
+        // offset table.
+        data.extend([8, 11, 18]);
+        // trailer: offset and reference sizes, objects count, top object and
+        // offset table offset.
+        data.extend([0, 0, 0, 0, 0, 0, 1, 1]);
+        data.extend(3u64.to_be_bytes());
+        data.extend(0u64.to_be_bytes());
+        data.extend(25u64.to_be_bytes());
+
+        assert_eq!(
+            super::bplist_strings(&data).unwrap(),
+            ["Keep\n6".to_owned(), "Été".to_owned()]
+        );
+        assert_eq!(super::bplist_strings(&data[..30]), None);
+    }
+
+    #[test]
+    fn xdg_tags() {
+        let path = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::write(&path, "content").unwrap();
+        if xattr::set(&path, super::XDG_XATTR, b"Keep, print,").is_err() {
+            // filesystem doesn't support user extended attributes.
+            fs::remove_file(path).unwrap();
+            return;
+        }
+
+        assert_eq!(super::read(&path).unwrap(), ["Keep", "print"]);
+        assert!(super::has_tag(&path, "Print"));
+        assert!(!super::has_tag(&path, "archive"));
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,28 +1,663 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::ffi::OsString;
+use std::fmt::{self, Display};
 use std::path::{Path, PathBuf};
 use std::result::Result as StdResult;
 use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
 use std::{fs, io};
 
+use chrono::format::{Item, StrftimeItems};
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, Local, LocalResult, Locale, NaiveDate,
+    NaiveDateTime, NaiveTime, Offset, TimeZone,
+};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+
 use thiserror::Error;
 
-use super::variables;
+use super::variables::mp4::{self, Movie};
+use super::variables::{self, ExifTag, ExifTagError};
+use crate::encoding::NonUtf8Policy;
+
+/// Options define user settings used by template values.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    /// Locale used to render month and weekday names.
+    #[serde(deserialize_with = "deserialize_locale")]
+    pub locale: Locale,
+
+    /// Infer timezone of exif dates without offset from GPS coordinates.
+    #[cfg(feature = "gps-timezone")]
+    pub infer_timezone: bool,
+
+    /// How local times around DST transitions are resolved when exif dates
+    /// without offset are placed in the timezone of their GPS coordinates.
+    pub dst_policy: DstPolicy,
+
+    /// Timezone `date` variables are rendered in, dates are left in the
+    /// timezone of their source (camera or system local time) if none or
+    /// if their source has no known offset.
+    pub timezone: Option<DateTimezone>,
+
+    /// Duration added to `date` variables, corrects the clock of a
+    /// misconfigured camera.
+    pub date_offset: Option<DateOffset>,
+
+    /// Exif datetime tags preferred by `exif.date` variables.
+    pub exif_date: ExifDate,
+
+    /// Replace path separators of metadata strings (exif, tags, audio, ...)
+    /// and refuse to render ones containing a `..` component, so they can't
+    /// escape the directory of the template. Paths and dates are left as is.
+    pub safe_variables: bool,
+
+    /// Guess the year of scanned photos from file and directory names
+    /// (`scan.year` variable), disabled if none.
+    pub scan: Option<ScanOptions>,
+
+    /// Fallback to dates of parent directory names (`dir.date` variables)
+    /// before file metadata in `date` variables, `file.name.date` only
+    /// parses file names then.
+    pub dir_date_fallback: bool,
+
+    /// Sources of `date` variables in preference order, replaces the default
+    /// chain (and `dir_date_fallback`) if not empty.
+    pub date_sources: Vec<DateSource>,
+
+    /// Exif tags rendered as strings by user defined variables, keyed by
+    /// variable name. The `[exif_tags]` config table maps tag names to
+    /// variable names (`"Image.Artist" = "exif.artist"`). Sorted so options
+    /// format the same way in every process (see render cache keys).
+    #[serde(deserialize_with = "deserialize_exif_tags")]
+    pub exif_tags: BTreeMap<String, ExifTag>,
+
+    /// Constant user defined variables, keyed by variable name (`[variables]`
+    /// config table).
+    #[serde(deserialize_with = "deserialize_variables")]
+    pub variables: BTreeMap<String, String>,
+
+    /// Regular expression matched against file names, its named capture
+    /// groups are exposed as `file.re.<name>` variables.
+    #[serde(with = "serde_regex")]
+    pub file_regex: Option<Regex>,
+
+    /// File name date patterns tried before the built-in ones
+    /// (`[[name_dates]]` config tables).
+    pub name_dates: Vec<NameDate>,
+
+    /// Disable built-in file name date patterns, only `name_dates` are used.
+    pub name_dates_only: bool,
+
+    /// How files whose path isn't valid UTF-8 are sorted.
+    pub non_utf8: NonUtf8Policy,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            locale: Locale::POSIX,
+            dst_policy: DstPolicy::default(),
+            timezone: None,
+            date_offset: None,
+            exif_date: ExifDate::default(),
+            safe_variables: false,
+            scan: None,
+            dir_date_fallback: false,
+            date_sources: Vec::new(),
+            exif_tags: BTreeMap::new(),
+            variables: BTreeMap::new(),
+            file_regex: None,
+            name_dates: Vec::new(),
+            name_dates_only: false,
+            non_utf8: NonUtf8Policy::default(),
+            #[cfg(feature = "gps-timezone")]
+            infer_timezone: false,
+        }
+    }
+}
+
+impl Options {
+    /// Converts a local (wall clock) time to a datetime of the given timezone
+    /// according to the DST policy.
+    ///
+    /// Local times repeated when clocks are turned back are ambiguous, they're
+    /// resolved to the earliest or latest instant. Local times skipped when
+    /// clocks are turned forward don't exist, they're shifted forward by the
+    /// length of the gap (02:30 becomes 03:30). Both are errors with
+    /// [`DstPolicy::Error`].
+    pub fn localize<Tz: TimeZone>(
+        &self,
+        tz: &Tz,
+        date: &NaiveDateTime,
+    ) -> StdResult<DateTime<Tz>, DstError> {
+        match tz.from_local_datetime(date) {
+            LocalResult::Single(date) => Ok(date),
+            LocalResult::Ambiguous(earliest, latest) => match self.dst_policy {
+                DstPolicy::Earliest => Ok(earliest),
+                DstPolicy::Latest => Ok(latest),
+                DstPolicy::Error => Err(DstError::Ambiguous(*date)),
+            },
+            LocalResult::None => {
+                if self.dst_policy == DstPolicy::Error {
+                    return Err(DstError::Nonexistent(*date));
+                }
+
+                // Use the offset before the transition, a day earlier is
+                // before any DST gap.
+                let offset = tz
+                    .offset_from_utc_datetime(&(*date - Duration::days(1)))
+                    .fix();
+                Ok(tz.from_utc_datetime(&(*date - offset)))
+            }
+        }
+    }
+
+    /// Returns the local time of the given date in the `timezone` option,
+    /// or in its own offset if none.
+    pub fn to_timezone(&self, date: &DateTime<FixedOffset>) -> NaiveDateTime {
+        let Some(timezone) = self.timezone else {
+            return date.naive_local();
+        };
+
+        match timezone {
+            DateTimezone::Utc => date.naive_utc(),
+            DateTimezone::Local => date.with_timezone(&Local).naive_local(),
+            DateTimezone::Fixed(offset) => date.with_timezone(&offset).naive_local(),
+            #[cfg(feature = "gps-timezone")]
+            DateTimezone::Named(tz) => date.with_timezone(&tz).naive_local(),
+        }
+    }
+
+    /// Adds the `date_offset` option to a local time and converts it to the
+    /// `timezone` option if its offset is known. Dates without a known offset
+    /// (e.g. exif dates without `OffsetTime*` tags) are left in their own
+    /// timezone.
+    pub fn adjust(
+        &self,
+        date: &NaiveDateTime,
+        offset: Option<FixedOffset>,
+    ) -> StdResult<NaiveDateTime, AdjustError> {
+        let date = match self.date_offset {
+            Some(date_offset) => date
+                .checked_add_signed(date_offset.0)
+                .ok_or(AdjustError::OutOfRange(*date, date_offset))?,
+            None => *date,
+        };
+        let Some(offset) = offset else {
+            return Ok(date);
+        };
+
+        let zoned = offset
+            .from_local_datetime(&date)
+            .single()
+            .ok_or(AdjustError::OffsetOutOfRange(date, offset))?;
+        Ok(self.to_timezone(&zoned))
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum AdjustError {
+    #[error("date {0} offset by {1} is out of range")]
+    OutOfRange(NaiveDateTime, DateOffset),
+
+    #[error("date {0} at offset {1} is out of range")]
+    OffsetOutOfRange(NaiveDateTime, FixedOffset),
+}
+
+/// Date offsets are at most a century, clocks set to a wrong timezone or year
+/// are off by far less.
+const MAX_DATE_OFFSET_DAYS: i64 = 36525;
+
+/// DateOffset is a duration written as a signed sequence of days, hours,
+/// minutes and seconds (`+2h30m`, `-1d`, `45s`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct DateOffset(Duration);
+
+impl DateOffset {
+    pub fn duration(&self) -> Duration {
+        self.0
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("invalid date offset {0:?}, expected a duration such as \"+2h30m\" or \"-1d\"")]
+pub struct DateOffsetError(String);
+
+impl FromStr for DateOffset {
+    type Err = DateOffsetError;
+
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        let err = || DateOffsetError(s.to_owned());
+
+        let (negative, mut rest) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        if rest.is_empty() {
+            return Err(err());
+        }
+
+        let mut duration = Duration::zero();
+        while !rest.is_empty() {
+            let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or_else(err)?;
+            let n: i64 = rest[..digits].parse().map_err(|_| err())?;
+            let unit = match rest[digits..].chars().next() {
+                Some('d') => Duration::try_days(n),
+                Some('h') => Duration::try_hours(n),
+                Some('m') => Duration::try_minutes(n),
+                Some('s') => Duration::try_seconds(n),
+                _ => None,
+            };
+            duration = unit
+                .and_then(|unit| duration.checked_add(&unit))
+                .ok_or_else(err)?;
+            rest = &rest[digits + 1..];
+        }
+
+        if duration > Duration::days(MAX_DATE_OFFSET_DAYS) {
+            return Err(err());
+        }
+
+        Ok(Self(if negative { -duration } else { duration }))
+    }
+}
+
+impl TryFrom<String> for DateOffset {
+    type Error = DateOffsetError;
+
+    fn try_from(s: String) -> StdResult<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for DateOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.0.num_seconds();
+        f.write_str(if seconds < 0 { "-" } else { "+" })?;
+
+        let seconds = seconds.unsigned_abs();
+        let parts = [
+            (seconds / 86400, 'd'),
+            (seconds / 3600 % 24, 'h'),
+            (seconds / 60 % 60, 'm'),
+            (seconds % 60, 's'),
+        ];
+        if seconds == 0 {
+            return f.write_str("0s");
+        }
+        for (n, unit) in parts {
+            if n > 0 {
+                write!(f, "{}{}", n, unit)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// DateTimezone is the timezone `date` variables are rendered in, written
+/// `utc`, `local`, a fixed offset (`+02:00`) or, with the `gps-timezone`
+/// feature, a timezone name (`Europe/Paris`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum DateTimezone {
+    Utc,
+    Local,
+    Fixed(FixedOffset),
+    #[cfg(feature = "gps-timezone")]
+    Named(chrono_tz::Tz),
+}
+
+#[derive(Error, Debug)]
+#[error("invalid timezone {0:?}, expected utc, local or an offset such as \"+02:00\"")]
+pub struct TimezoneError(String);
+
+impl FromStr for DateTimezone {
+    type Err = TimezoneError;
+
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        match s {
+            "utc" | "UTC" | "Z" => return Ok(Self::Utc),
+            "local" => return Ok(Self::Local),
+            _ => {}
+        }
+        if let Ok(offset) = s.parse() {
+            return Ok(Self::Fixed(offset));
+        }
+        #[cfg(feature = "gps-timezone")]
+        if let Ok(tz) = s.parse() {
+            return Ok(Self::Named(tz));
+        }
+
+        Err(TimezoneError(s.to_owned()))
+    }
+}
+
+impl TryFrom<String> for DateTimezone {
+    type Error = TimezoneError;
+
+    fn try_from(s: String) -> StdResult<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for DateTimezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Utc => f.write_str("utc"),
+            Self::Local => f.write_str("local"),
+            Self::Fixed(offset) => write!(f, "{}", offset),
+            #[cfg(feature = "gps-timezone")]
+            Self::Named(tz) => f.write_str(tz.name()),
+        }
+    }
+}
+
+/// ExifDate define which exif datetime tag is preferred by `exif.date`
+/// variables, the other tags are used as fallback.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExifDate {
+    /// DateTimeOriginal (capture date), then DateTimeDigitized and DateTime.
+    #[default]
+    Original,
+    /// DateTime, the last modification date on many cameras, then
+    /// DateTimeOriginal and DateTimeDigitized.
+    Modified,
+}
+
+impl ExifDate {
+    /// Returns the exif datetime tags in preference order.
+    pub fn tags(&self) -> [exif::Tag; 3] {
+        use exif::Tag;
+
+        match self {
+            Self::Original => [Tag::DateTimeOriginal, Tag::DateTimeDigitized, Tag::DateTime],
+            Self::Modified => [Tag::DateTime, Tag::DateTimeOriginal, Tag::DateTimeDigitized],
+        }
+    }
+}
+
+/// DateSource is a source of `date` variables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DateSource {
+    /// Exif datetime tags (`exif.date`).
+    Exif,
+    /// DJI drone metadata (`dji.date`).
+    Dji,
+    /// GoPro metadata (`gopro.date`).
+    Gopro,
+    /// Video container creation time (`video.date`).
+    Video,
+    /// PNG, WebP and HEIF metadata (`image.date`).
+    Image,
+    /// Date in the file name (`file.name.date`).
+    Filename,
+    /// Date in parent directory names (`dir.date`).
+    Dir,
+    /// File creation time (`file.md.creation_date`).
+    Btime,
+    /// File modification time (`file.md.modification_date`).
+    Mtime,
+}
+
+impl DateSource {
+    /// Returns the variable of the source.
+    pub fn variable(&self) -> &'static str {
+        match self {
+            Self::Exif => "exif.date",
+            Self::Dji => "dji.date",
+            Self::Gopro => "gopro.date",
+            Self::Video => "video.date",
+            Self::Image => "image.date",
+            Self::Filename => "file.name.date",
+            Self::Dir => "dir.date",
+            Self::Btime => "file.md.creation_date",
+            Self::Mtime => "file.md.modification_date",
+        }
+    }
+}
+
+/// DstPolicy define how ambiguous and nonexistent local times are resolved,
+/// see [`Options::localize`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DstPolicy {
+    /// Use the first occurrence of a repeated local time.
+    #[default]
+    Earliest,
+    /// Use the last occurrence of a repeated local time.
+    Latest,
+    /// Fail to render ambiguous and nonexistent local times.
+    Error,
+}
+
+/// ScanOptions configure the heuristics guessing the year of scanned photos
+/// from their file and parent directory names.
+///
+/// Years are rated by confidence: 3 for a year next to a month or season
+/// name (`Summer 1992`), 2 for a lone year (`1987`) and 1 for a decade
+/// (`1980s`, `80s`). The most confident year is used, the file name wins over
+/// directories and nearest directories over farther ones.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ScanOptions {
+    /// Oldest accepted year.
+    pub min_year: i32,
+    /// Newest accepted year, defaults to the current year.
+    pub max_year: i32,
+    /// Number of parent directories searched.
+    pub depth: usize,
+    /// Years with a lower confidence are ignored.
+    pub min_confidence: u8,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            min_year: 1850,
+            max_year: Local::now().year(),
+            depth: 2,
+            min_confidence: 2,
+        }
+    }
+}
+
+/// NameDate is a user defined file name date pattern: the `date` capture
+/// group of the regex (or the whole match) is parsed with a strftime format,
+/// times are optional (`%d.%m.%Y` or `%d.%m.%Y %Hh%M`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct NameDate {
+    #[serde(with = "serde_regex")]
+    pub regex: Regex,
+    #[serde(deserialize_with = "deserialize_strftime")]
+    pub format: String,
+}
+
+impl NameDate {
+    /// Returns the date and time, if the format has one, of the given file
+    /// name.
+    pub fn parse(&self, filename: &str) -> Option<(NaiveDate, Option<NaiveTime>)> {
+        let captures = self.regex.captures(filename)?;
+        let date = captures.name("date").or_else(|| captures.get(0))?.as_str();
+
+        match NaiveDateTime::parse_from_str(date, &self.format) {
+            Ok(datetime) => Some((datetime.date(), Some(datetime.time()))),
+            Err(_) => match NaiveDate::parse_from_str(date, &self.format) {
+                Ok(date) => Some((date, None)),
+                Err(err) => {
+                    // the pattern or the format is likely wrong.
+                    log::warn!(
+                        "{:?} matches name date pattern {:?} but {:?} doesn't match format {:?}: {}",
+                        filename,
+                        self.regex.as_str(),
+                        date,
+                        self.format,
+                        err
+                    );
+                    None
+                }
+            },
+        }
+    }
+}
+
+/// Deserializes a strftime format, unknown specifiers are rejected.
+fn deserialize_strftime<'de, D>(deserializer: D) -> StdResult<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let format = String::deserialize(deserializer)?;
+    if StrftimeItems::new(&format).any(|item| item == Item::Error) {
+        return Err(serde::de::Error::custom(format!(
+            "invalid date format {:?}",
+            format
+        )));
+    }
+
+    Ok(format)
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum DstError {
+    #[error("ambiguous local time {0} (DST transition)")]
+    Ambiguous(NaiveDateTime),
+
+    #[error("nonexistent local time {0} (DST transition)")]
+    Nonexistent(NaiveDateTime),
+}
+
+#[derive(Error, Debug)]
+#[error("unknown locale {0:?}")]
+pub struct UnknownLocaleError(String);
+
+/// Parses a locale name such as `fr_FR`.
+pub fn parse_locale(name: &str) -> StdResult<Locale, UnknownLocaleError> {
+    Locale::try_from(name).map_err(|_| UnknownLocaleError(name.to_owned()))
+}
+
+fn deserialize_locale<'de, D>(deserializer: D) -> StdResult<Locale, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    parse_locale(&name).map_err(serde::de::Error::custom)
+}
+
+#[derive(Error, Debug)]
+pub enum ExifTagsError {
+    #[error(transparent)]
+    Tag(#[from] ExifTagError),
+    #[error("exif tag variable {0:?} collides with a built-in variable")]
+    Collision(String),
+    #[error("exif tag variable {0:?} is mapped to several tags")]
+    Duplicate(String),
+}
+
+/// Parses the `[exif_tags]` config table (tag name to variable name).
+pub fn parse_exif_tags(
+    tags: HashMap<String, String>,
+) -> StdResult<BTreeMap<String, ExifTag>, ExifTagsError> {
+    let mut variables = BTreeMap::new();
+    for (tag, variable) in tags {
+        if variables::is_builtin(&variable) {
+            return Err(ExifTagsError::Collision(variable));
+        }
+
+        let tag = tag.parse()?;
+        if variables.insert(variable.clone(), tag).is_some() {
+            return Err(ExifTagsError::Duplicate(variable));
+        }
+    }
+
+    Ok(variables)
+}
+
+fn deserialize_exif_tags<'de, D>(deserializer: D) -> StdResult<BTreeMap<String, ExifTag>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let tags = HashMap::<String, String>::deserialize(deserializer)?;
+    parse_exif_tags(tags).map_err(serde::de::Error::custom)
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum VariablesError {
+    #[error("user variable {0:?} collides with a built-in variable")]
+    Collision(String),
+    #[error("invalid user variable name {0:?}")]
+    InvalidName(String),
+}
+
+/// Parses constant user defined variables (variable name to value).
+pub fn parse_variables<I>(variables: I) -> StdResult<BTreeMap<String, String>, VariablesError>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    variables
+        .into_iter()
+        .map(|(name, value)| {
+            if variables::is_builtin(&name) {
+                return Err(VariablesError::Collision(name));
+            }
+            // characters of the template syntax.
+            if name.is_empty() || name.contains([':', '|', '%', '?', '/']) {
+                return Err(VariablesError::InvalidName(name));
+            }
+
+            Ok((name, value))
+        })
+        .collect()
+}
+
+fn deserialize_variables<'de, D>(deserializer: D) -> StdResult<BTreeMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let variables = HashMap::<String, String>::deserialize(deserializer)?;
+    parse_variables(variables).map_err(serde::de::Error::custom)
+}
 
 /// Context define the rendering context of a Template. It contains template value.
 #[derive(Default)]
 pub struct Context {
     variables: HashMap<String, usize>,
     template_values: Vec<Box<dyn TemplateValue>>,
+    options: Arc<Options>,
+    counter: i64,
+    movie: OnceLock<Option<Movie>>,
 }
 
 impl Context {
+    pub fn new(options: Arc<Options>) -> Self {
+        Self {
+            options,
+            counter: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Returns the value of the `counter` variable, it starts at 1.
+    pub fn counter(&self) -> i64 {
+        self.counter
+    }
+
+    pub fn set_counter(&mut self, counter: i64) {
+        self.counter = counter;
+    }
+
     pub fn get(&self, key: &str) -> Option<&dyn TemplateValue> {
-        let index = match self.variables.get(&key.to_string()) {
-            Some(index) => index,
-            None => return None,
-        };
+        let index = self.variables.get(key)?;
 
         self.template_values
             .get(index.to_owned())
@@ -34,6 +669,29 @@ impl Context {
             .ok_or_else(|| missing_variable(key.to_string()))
     }
 
+    /// Returns absolute path of the file being sorted.
+    pub fn file_path(&self) -> StdResult<PathBuf, Box<dyn Error>> {
+        let value = self.get_or_err(":file.path")?.render("", self)?;
+        Ok(PathBuf::from(value.into_os_string()))
+    }
+
+    /// Returns the boxes of the file being sorted if it's an MP4/MOV file,
+    /// the file is parsed on first use. Malformed files have no boxes.
+    pub fn movie(&self) -> StdResult<Option<&Movie>, Box<dyn Error>> {
+        if let Some(movie) = self.movie.get() {
+            return Ok(movie.as_ref());
+        }
+
+        let mut file = io::BufReader::new(fs::File::open(self.file_path()?)?);
+        let movie = match mp4::read_movie(&mut file) {
+            Ok(movie) => movie,
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => None,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(err) => return Err(Box::new(err)),
+        };
+        Ok(self.movie.get_or_init(|| movie).as_ref())
+    }
+
     pub fn insert(&mut self, keys: &[&str], value: Box<dyn TemplateValue>) {
         assert!(!keys.is_empty());
 
@@ -63,20 +721,138 @@ pub fn prepare_template_context(ctx: &mut Context, path: &Path) -> StdResult<(),
     // by other template value to fetch absolute filepath.
     ctx.insert(&[":file.path"], Box::new(abs_path));
 
+    let options = ctx.options.clone();
+    for (name, value) in &options.variables {
+        ctx.insert(&[name], Box::new(Value::String(value.clone())));
+    }
+
     variables::prepare_template_context(ctx)?;
 
     Ok(())
 }
 
+/// Error of variables without a value for the rendered file (e.g. files
+/// without exif data), default values and optional variables replace them.
+#[derive(Error, Debug)]
+#[error("{0}")]
+struct MissingValueError(String);
+
 pub fn missing_variable(name: String) -> Box<dyn Error> {
-    #[derive(Error, Debug)]
-    #[error("missing variable \"{0}\"")]
-    struct MissingVariableError(String);
+    missing_value(format!("missing variable \"{}\"", name))
+}
+
+/// Returns the error of a missing value described by the given message.
+pub fn missing_value(message: String) -> Box<dyn Error> {
+    Box::new(MissingValueError(message))
+}
+
+/// Returns true if the given rendering error is a missing value, see
+/// [`missing_value`].
+pub fn is_missing(err: &(dyn Error + 'static)) -> bool {
+    err.is::<MissingValueError>()
+}
+
+pub type Result = StdResult<Value, Box<dyn Error>>;
+
+/// Value is the typed result of a [`TemplateValue`] rendering. Values keep
+/// their type so they can be compared or transformed, they're converted to
+/// an [`OsString`] at final render time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    /// An integer zero-padded to `width` digits when rendered.
+    Int {
+        value: i64,
+        width: usize,
+    },
+    /// A date, rendered in RFC3339 format (without time).
+    Date(NaiveDateTime),
+    Path(PathBuf),
+}
+
+impl Value {
+    pub fn int(value: i64) -> Self {
+        Value::Int { value, width: 0 }
+    }
+
+    pub fn padded_int(value: i64, width: usize) -> Self {
+        Value::Int { value, width }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(str) => Some(str),
+            Value::Path(path) => path.to_str(),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int { value, .. } => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_date(&self) -> Option<NaiveDateTime> {
+        match self {
+            Value::Date(date) => Some(*date),
+            _ => None,
+        }
+    }
+
+    pub fn into_os_string(self) -> OsString {
+        match self {
+            Value::String(str) => OsString::from(str),
+            Value::Path(path) => path.into_os_string(),
+            v => OsString::from(v.to_string()),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(str) => f.write_str(str),
+            Value::Int { value, width } => write!(f, "{:0width$}", value, width = width),
+            Value::Date(date) => write!(f, "{}", date.format("%Y-%m-%d")),
+            Value::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl From<String> for Value {
+    fn from(str: String) -> Self {
+        Value::String(str)
+    }
+}
 
-    Box::new(MissingVariableError(name))
+impl From<&str> for Value {
+    fn from(str: &str) -> Self {
+        Value::String(str.to_owned())
+    }
 }
 
-pub type Result = StdResult<OsString, Box<dyn Error>>;
+impl From<PathBuf> for Value {
+    fn from(path: PathBuf) -> Self {
+        Value::Path(path)
+    }
+}
+
+impl From<OsString> for Value {
+    fn from(str: OsString) -> Self {
+        match str.into_string() {
+            Ok(str) => Value::String(str),
+            Err(str) => Value::Path(PathBuf::from(str)),
+        }
+    }
+}
+
+impl From<NaiveDateTime> for Value {
+    fn from(date: NaiveDateTime) -> Self {
+        Value::Date(date)
+    }
+}
 
 /// TemplateValue defines a value used in the rendering of a [`Template`].
 /// It should be stateless and reusable.
@@ -84,6 +860,13 @@ pub type Result = StdResult<OsString, Box<dyn Error>>;
 /// can be stored multiple times in a [`Context`] with different keys.
 pub trait TemplateValue {
     fn render(&self, name: &str, ctx: &Context) -> Result;
+
+    /// Returns the given date variable with its UTC offset, none if the
+    /// offset isn't known (e.g. camera time). Used to convert `date`
+    /// variables to the `timezone` option.
+    fn zoned_date(&self, _name: &str, _ctx: &Context) -> Option<DateTime<FixedOffset>> {
+        None
+    }
 }
 
 impl TemplateValue for dyn ToString {
@@ -93,25 +876,227 @@ impl TemplateValue for dyn ToString {
 }
 
 impl TemplateValue for &str {
-    fn render(&self, name: &str, ctx: &Context) -> Result {
-        self.to_owned().to_owned().render(name, ctx)
+    fn render(&self, _name: &str, _ctx: &Context) -> Result {
+        Ok(Value::from(*self))
     }
 }
 
 impl TemplateValue for String {
     fn render(&self, _name: &str, _ctx: &Context) -> Result {
-        Ok(OsString::from_str(self).unwrap())
+        Ok(Value::from(self.clone()))
     }
 }
 
 impl TemplateValue for PathBuf {
     fn render(&self, _name: &str, _ctx: &Context) -> Result {
-        Ok(self.clone().into_os_string())
+        Ok(Value::from(self.clone()))
     }
 }
 
 impl TemplateValue for OsString {
+    fn render(&self, _name: &str, _ctx: &Context) -> Result {
+        Ok(Value::from(self.clone()))
+    }
+}
+
+impl TemplateValue for Value {
     fn render(&self, _name: &str, _ctx: &Context) -> Result {
         Ok(self.clone())
     }
 }
+
+impl TemplateValue for DateTime<FixedOffset> {
+    fn render(&self, _name: &str, _ctx: &Context) -> Result {
+        Ok(Value::Date(self.naive_local()))
+    }
+
+    fn zoned_date(&self, _name: &str, _ctx: &Context) -> Option<DateTime<FixedOffset>> {
+        Some(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, FixedOffset, NaiveDate, NaiveDateTime, TimeZone};
+    use chrono_tz::Europe::Paris;
+
+    use std::sync::Arc;
+
+    use crate::template::Template;
+
+    use super::{
+        AdjustError, Context, DateOffset, DateTimezone, DstError, DstPolicy, Options,
+        VariablesError,
+    };
+
+    fn datetime(day: u32, hour: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 3, day)
+            .unwrap()
+            .and_hms_opt(hour, min, 0)
+            .unwrap()
+    }
+
+    fn localize(policy: DstPolicy, date: NaiveDateTime) -> Result<String, DstError> {
+        let options = Options {
+            dst_policy: policy,
+            ..Default::default()
+        };
+        options.localize(&Paris, &date).map(|d| d.to_rfc3339())
+    }
+
+    #[test]
+    fn localize_single() {
+        for policy in [DstPolicy::Earliest, DstPolicy::Latest, DstPolicy::Error] {
+            assert_eq!(
+                localize(policy, datetime(1, 12, 0)),
+                Ok("2023-03-01T12:00:00+01:00".to_owned())
+            );
+        }
+    }
+
+    #[test]
+    fn localize_nonexistent() {
+        // Clocks were turned forward from 02:00 to 03:00 on 2023-03-26.
+        let date = datetime(26, 2, 30);
+
+        assert_eq!(
+            localize(DstPolicy::Earliest, date),
+            Ok("2023-03-26T03:30:00+02:00".to_owned())
+        );
+        assert_eq!(
+            localize(DstPolicy::Latest, date),
+            Ok("2023-03-26T03:30:00+02:00".to_owned())
+        );
+        assert_eq!(
+            localize(DstPolicy::Error, date),
+            Err(DstError::Nonexistent(date))
+        );
+    }
+
+    #[test]
+    fn localize_ambiguous() {
+        // Clocks were turned back from 03:00 to 02:00 on 2023-10-29.
+        let date = NaiveDate::from_ymd_opt(2023, 10, 29)
+            .unwrap()
+            .and_hms_opt(2, 30, 0)
+            .unwrap();
+
+        assert_eq!(
+            localize(DstPolicy::Earliest, date),
+            Ok("2023-10-29T02:30:00+02:00".to_owned())
+        );
+        assert_eq!(
+            localize(DstPolicy::Latest, date),
+            Ok("2023-10-29T02:30:00+01:00".to_owned())
+        );
+        assert_eq!(
+            localize(DstPolicy::Error, date),
+            Err(DstError::Ambiguous(date))
+        );
+    }
+
+    #[test]
+    fn deserialize_dst_policy() {
+        let options: Options = toml::from_str("dst_policy = \"latest\"").unwrap();
+        assert_eq!(options.dst_policy, DstPolicy::Latest);
+
+        let options: Options = toml::from_str("").unwrap();
+        assert_eq!(options.dst_policy, DstPolicy::Earliest);
+    }
+
+    #[test]
+    fn timezone() {
+        let options: Options = toml::from_str("timezone = \"+09:00\"").unwrap();
+        let tz = options.timezone.unwrap();
+        assert_eq!(tz.to_string(), "+09:00");
+        assert_eq!("utc".parse::<DateTimezone>().unwrap(), DateTimezone::Utc);
+        assert!("Mars/Olympus".parse::<DateTimezone>().is_err());
+
+        // +02:00 to +09:00.
+        let date = datetime(14, 12, 0);
+        let paris = FixedOffset::east_opt(2 * 3600).unwrap();
+        let zoned = paris.from_local_datetime(&date).unwrap();
+        assert_eq!(options.to_timezone(&zoned), datetime(14, 19, 0));
+        assert_eq!(Options::default().to_timezone(&zoned), date);
+
+        // dates without offset are left in their own timezone.
+        assert_eq!(options.adjust(&date, None).unwrap(), date);
+        assert_eq!(
+            options.adjust(&date, Some(paris)).unwrap(),
+            datetime(14, 19, 0)
+        );
+    }
+
+    #[test]
+    fn date_offset() {
+        let offset: DateOffset = "+2h30m".parse().unwrap();
+        assert_eq!(offset.duration(), Duration::minutes(150));
+        assert_eq!(offset.to_string(), "+2h30m");
+        let offset: DateOffset = "-1d12h".parse().unwrap();
+        assert_eq!(offset.duration(), -Duration::hours(36));
+        assert_eq!(offset.to_string(), "-1d12h");
+        for invalid in ["", "+", "2", "2x", "h", "1h-2m", "100000000d", "-36526d"] {
+            assert!(invalid.parse::<DateOffset>().is_err(), "{}", invalid);
+        }
+
+        let options: Options = toml::from_str("date_offset = \"-1h\"").unwrap();
+        let date = datetime(14, 12, 0);
+        assert_eq!(options.adjust(&date, None).unwrap(), datetime(14, 11, 0));
+
+        // offsets past the supported dates are errors, not panics.
+        let options: Options = toml::from_str("date_offset = \"36525d\"").unwrap();
+        assert!(matches!(
+            options.adjust(&NaiveDateTime::MAX, None),
+            Err(AdjustError::OutOfRange(_, _))
+        ));
+    }
+
+    #[test]
+    fn user_variables() {
+        let variables = [("owner", "alice"), ("event", "Wedding")]
+            .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let options = Options {
+            variables: super::parse_variables(variables).unwrap(),
+            ..Default::default()
+        };
+        let mut ctx = Context::new(Arc::new(options));
+        super::prepare_template_context(&mut ctx, file!().as_ref()).unwrap();
+
+        let template: Template = ":owner:/:event|lower:/:file.name:".parse().unwrap();
+        assert_eq!(
+            template.render(&ctx).unwrap(),
+            std::path::PathBuf::from("alice/wedding/context.rs")
+        );
+
+        let parse = |name: &str| super::parse_variables([(name.to_owned(), "x".to_owned())]);
+        assert_eq!(
+            parse("file.name"),
+            Err(VariablesError::Collision("file.name".to_owned()))
+        );
+        assert_eq!(
+            parse("a:b"),
+            Err(VariablesError::InvalidName("a:b".to_owned()))
+        );
+    }
+
+    #[test]
+    fn movie_read_once() {
+        let mut mp4 = 16_u32.to_be_bytes().to_vec();
+        mp4.extend_from_slice(b"ftypisom\0\0\0\0");
+        mp4.extend_from_slice(&8_u32.to_be_bytes());
+        mp4.extend_from_slice(b"moov");
+        let tree = crate::test_utils::TempTree::new().with_file("a.mp4", &mp4);
+
+        let mut ctx = Context::default();
+        ctx.insert(&[":file.path"], Box::new(tree.join("a.mp4")));
+        assert!(ctx.movie().unwrap().unwrap().has_ftyp());
+
+        // the file isn't read again.
+        std::fs::write(tree.join("a.mp4"), "not a movie").unwrap();
+        assert!(ctx.movie().unwrap().is_some());
+
+        let mut ctx = Context::default();
+        ctx.insert(&[":file.path"], Box::new(tree.join("a.mp4")));
+        assert!(ctx.movie().unwrap().is_none());
+    }
+}
@@ -1,17 +1,22 @@
+use std::collections::HashMap;
 use std::ffi::OsString;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
 use std::string::FromUtf8Error;
 use std::{error, fmt};
 
+use chrono::format::{Item, StrftimeItems};
+use chrono::{TimeZone, Utc};
 use serde::de::Visitor;
 use serde::Deserialize;
 use thiserror::Error;
 
 pub mod context;
+pub mod modifiers;
 pub mod variables;
 
-use context::Context;
+use context::{Context, Value};
+use modifiers::ModifierCall;
 
 /// Template define a simple PathBuf template engine.
 ///
@@ -25,7 +30,164 @@ pub struct Template {
 #[derive(Debug, Clone)]
 enum Token {
     String(String),
-    Variable(String),
+    Variable(Variable),
+}
+
+/// Variable is a variable name followed by modifiers applied to its value.
+///
+/// Built-in variables piped after the name are fallbacks
+/// (`:exif.date|file.md.creation_date|year:`), the first one resolving is
+/// used. The name may be followed by a strftime format (`:date%Y-%m:`), it formats
+/// the date value once modifiers are applied. A literal following a `?`
+/// (`:exif.model?unknown:`) replaces values that are missing or
+/// rendered empty. Optional variables (`:?exif.gps.country:/`) render
+/// nothing instead of failing, along with the path separator following them.
+#[derive(Debug, Clone)]
+struct Variable {
+    optional: bool,
+    name: String,
+    fallbacks: Vec<String>,
+    format: Option<String>,
+    modifiers: Vec<ModifierCall>,
+    default: Option<String>,
+}
+
+impl Variable {
+    fn parse(s: &str, index: usize) -> Result<Self, ParseError> {
+        let (s, optional) = match s.strip_prefix('?') {
+            Some(s) => (s, true),
+            None => (s, false),
+        };
+        let (s, default) = match modifiers::split_top_level(s, '?').first() {
+            Some(head) if head.len() < s.len() => (*head, Some(s[head.len() + 1..].to_owned())),
+            _ => (s, None),
+        };
+        let split_format = |part: &'_ str| match part.split_once('%') {
+            Some((name, format)) => (name.to_owned(), Some(format!("%{}", format))),
+            None => (part.to_owned(), None),
+        };
+
+        let mut parts = modifiers::split_top_level(s, '|').into_iter().peekable();
+        let (name, mut format) = split_format(parts.next().unwrap_or_default());
+        if name.is_empty() {
+            return Err(ParseError::UnamedVariable(index));
+        }
+        // the format follows the last fallback.
+        let mut fallbacks = Vec::new();
+        while let Some((fallback, fallback_format)) = parts
+            .peek()
+            .map(|part| split_format(part))
+            .filter(|(fallback, _)| variables::is_builtin(fallback))
+        {
+            fallbacks.push(fallback);
+            format = fallback_format.or(format);
+            parts.next();
+        }
+        if let Some(format) = &format {
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                return Err(ParseError::InvalidFormat(index, format.to_owned()));
+            }
+        }
+
+        let modifiers = parts
+            .map(ModifierCall::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| ParseError::Modifier(index, err))?;
+
+        Ok(Self {
+            optional,
+            name,
+            fallbacks,
+            format,
+            modifiers,
+            default,
+        })
+    }
+
+    fn render(&self, ctx: &Context) -> Result<OsString, RenderError> {
+        let mut name = &self.name;
+        let mut value = Err(RenderError::UndefinedVariable(name.to_owned()));
+        // defaults only replace missing values, other errors (e.g. I/O
+        // errors) are returned.
+        let mut missing = true;
+        for candidate in std::iter::once(&self.name).chain(&self.fallbacks) {
+            name = candidate;
+            value = match ctx.get(name) {
+                Some(value) => value.render(name, ctx).map_err(|err| {
+                    missing &= context::is_missing(&*err);
+                    RenderError::VariableRender(name.to_owned(), err)
+                }),
+                None => Err(RenderError::UndefinedVariable(name.to_owned())),
+            };
+            if value.is_ok() {
+                break;
+            }
+        }
+        let mut rendered_value = match (value, &self.default) {
+            (Ok(value), _) => value,
+            (Err(_), Some(default)) if missing => return Ok(default.into()),
+            (Err(_), None) if missing && self.optional => return Ok(OsString::new()),
+            (Err(err), _) => return Err(err),
+        };
+
+        for modifier in &self.modifiers {
+            rendered_value = modifier.apply(rendered_value).map_err(|err| {
+                RenderError::ModifierRender(name.to_owned(), modifier.to_string(), err)
+            })?;
+        }
+        // modifiers (e.g. `replace`) may add separators to metadata strings.
+        if ctx.options().safe_variables && variables::is_metadata(ctx, name) {
+            if let Value::String(str) = &rendered_value {
+                rendered_value =
+                    Value::String(sanitize(str).map_err(|value| {
+                        RenderError::PathTraversal(name.to_owned(), value.into())
+                    })?);
+            }
+        }
+        if let Some(format) = &self.format {
+            let date = rendered_value.as_date().ok_or_else(|| {
+                RenderError::NotADate(
+                    name.to_owned(),
+                    format.to_owned(),
+                    rendered_value.to_string(),
+                )
+            })?;
+            rendered_value = Utc
+                .from_utc_datetime(&date)
+                .format_localized(format, ctx.options().locale)
+                .to_string()
+                .into();
+        }
+
+        let rendered_value = rendered_value.into_os_string();
+        match &self.default {
+            Some(default) if rendered_value.is_empty() => Ok(default.into()),
+            _ => Ok(rendered_value),
+        }
+    }
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.optional {
+            f.write_str("?")?;
+        }
+        f.write_str(&self.name)?;
+        for fallback in &self.fallbacks {
+            write!(f, "|{}", fallback)?;
+        }
+        if let Some(format) = &self.format {
+            f.write_str(format)?;
+        }
+        for modifier in &self.modifiers {
+            write!(f, "|{}", modifier)?;
+        }
+        if let Some(default) = &self.default {
+            write!(f, "?{}", default)?;
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Error, Debug, PartialEq, Eq)]
@@ -34,6 +196,18 @@ pub enum ParseError {
     UnamedVariable(usize),
     #[error("unclosed variable (at index {0})")]
     UnclosedVariable(usize),
+    #[error("invalid variable modifier (at index {0}): {1}")]
+    Modifier(usize, #[source] modifiers::ParseError),
+    #[error("invalid date format {1:?} (at index {0})")]
+    InvalidFormat(usize, String),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum AliasError {
+    #[error("alias {0:?} collides with a built-in variable")]
+    Collision(String),
+    #[error("invalid alias {0:?}: {1}")]
+    InvalidTarget(String, #[source] ParseError),
 }
 
 #[derive(Error, Debug)]
@@ -46,35 +220,162 @@ pub enum RenderError {
 
     #[error("failed to render \"{0}\" variable: {1}")]
     VariableRender(String, #[source] Box<dyn error::Error>),
+
+    #[error("failed to apply \"{1}\" modifier on \"{0}\" variable: {2}")]
+    ModifierRender(String, String, #[source] Box<dyn error::Error>),
+
+    #[error("can't format \"{0}\" variable with \"{1}\", expected a date value, got {2:?}")]
+    NotADate(String, String, String),
+
+    #[error("\"{0}\" variable rendered a parent directory component: {1:?}")]
+    PathTraversal(String, OsString),
+}
+
+/// Replaces path separators (`/` and `\`) of a metadata string with `_` so
+/// it stays a single path component. Values containing a `..` component are
+/// returned as error.
+fn sanitize(value: &str) -> Result<String, String> {
+    if value.split(['/', '\\']).any(|component| component == "..") {
+        return Err(value.to_owned());
+    }
+
+    Ok(value.replace(['/', '\\'], "_"))
 }
 
 impl Template {
     pub fn render(&self, ctx: &Context) -> Result<PathBuf, RenderError> {
         let mut result = OsString::default();
+        // whether an optional variable rendered a whole component as nothing,
+        // the separator following it is skipped so the path has no empty
+        // component.
+        let mut skip_separator = false;
 
         for i in 0..self.tokens.len() {
             let tk = &self.tokens[i];
 
             match tk {
-                Token::String(str) => result.push(&str[..]),
-                Token::Variable(name) => {
-                    if let Some(value) = ctx.get(name) {
-                        let rendered_value = match value.render(name, ctx) {
-                            Ok(v) => v,
-                            Err(err) => {
-                                return Err(RenderError::VariableRender(name.to_owned(), err))
-                            }
-                        };
-                        result.push(rendered_value);
-                    } else {
-                        return Err(RenderError::UndefinedVariable(name.to_string()));
-                    }
+                Token::String(str) => {
+                    let str = match str.chars().next() {
+                        Some(c) if skip_separator && std::path::is_separator(c) => {
+                            &str[c.len_utf8()..]
+                        }
+                        _ => &str[..],
+                    };
+                    result.push(str);
+                    skip_separator = false;
+                }
+                Token::Variable(variable) => {
+                    let value = variable.render(ctx)?;
+                    let component_start = result
+                        .as_encoded_bytes()
+                        .last()
+                        .is_none_or(|c| std::path::is_separator(char::from(*c)));
+                    skip_separator = variable.optional && value.is_empty() && component_start;
+                    result.push(value);
                 }
             }
         }
 
         Ok(PathBuf::from(result))
     }
+
+    /// Replaces variables named after an alias with the alias target
+    /// (`y = "date.year"`). Targets may contain modifiers, they're applied
+    /// before the modifiers of the variable. Aliases aren't expanded
+    /// recursively.
+    pub fn expand_aliases(&mut self, aliases: &HashMap<String, String>) -> Result<(), AliasError> {
+        let mut targets = HashMap::with_capacity(aliases.len());
+        for (alias, target) in aliases {
+            if variables::is_builtin(alias) {
+                return Err(AliasError::Collision(alias.to_owned()));
+            }
+
+            let target = Variable::parse(target, 0)
+                .map_err(|err| AliasError::InvalidTarget(alias.to_owned(), err))?;
+            targets.insert(alias.as_str(), target);
+        }
+
+        for tk in self.tokens.iter_mut() {
+            if let Token::Variable(variable) = tk {
+                if let Some(target) = targets.get(variable.name.as_str()) {
+                    let mut modifiers = target.modifiers.clone();
+                    modifiers.append(&mut variable.modifiers);
+                    *variable = Variable {
+                        optional: variable.optional || target.optional,
+                        name: target.name.clone(),
+                        fallbacks: [target.fallbacks.clone(), variable.fallbacks.clone()].concat(),
+                        format: variable.format.take().or_else(|| target.format.clone()),
+                        modifiers,
+                        default: variable.default.take().or_else(|| target.default.clone()),
+                    };
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the template contains the given variable.
+    pub fn uses(&self, name: &str) -> bool {
+        self.tokens.iter().any(|tk| match tk {
+            Token::Variable(variable) => {
+                variable.name == name || variable.fallbacks.iter().any(|f| f == name)
+            }
+            Token::String(_) => false,
+        })
+    }
+
+    /// Returns the template rendering absolute paths below the given
+    /// directory instead (`/dst/:file.name:` becomes `<root>/dst/:file.name:`),
+    /// relative templates are unchanged.
+    pub fn rebase(&self, root: &Path) -> Template {
+        let mut tokens = self.tokens.clone();
+        if let Some(Token::String(prefix)) = tokens.first_mut() {
+            let path = Path::new(prefix.as_str());
+            if path.has_root() {
+                let mut components = path.components();
+                while let Some(Component::Prefix(_) | Component::RootDir) =
+                    components.clone().next()
+                {
+                    components.next();
+                }
+
+                let mut rebased = root
+                    .join(components.as_path())
+                    .to_string_lossy()
+                    .into_owned();
+                if prefix.ends_with(std::path::is_separator)
+                    && !rebased.ends_with(std::path::is_separator)
+                {
+                    rebased.push(std::path::MAIN_SEPARATOR);
+                }
+                *prefix = rebased;
+            }
+        }
+
+        Template { tokens }
+    }
+
+    /// Returns the longest directory path shared by every rendered path, that
+    /// is the directory part of the template before the first variable.
+    pub fn root(&self) -> Option<PathBuf> {
+        let prefix = match self.tokens.first() {
+            Some(Token::String(str)) => str,
+            _ => return None,
+        };
+
+        let root = if self.tokens.len() > 1 && prefix.ends_with(std::path::MAIN_SEPARATOR) {
+            Path::new(prefix)
+        } else {
+            Path::new(prefix).parent()?
+        };
+
+        if root.as_os_str().is_empty() {
+            None
+        } else {
+            Some(root.to_owned())
+        }
+    }
 }
 
 impl FromStr for Template {
@@ -103,7 +404,7 @@ impl FromStr for Template {
                         return Err(ParseError::UnamedVariable(i));
                     }
 
-                    tokens.push(Token::Variable(s[start_var..i].to_string()));
+                    tokens.push(Token::Variable(Variable::parse(&s[start_var..i], i)?));
                     string_start_index = Some(i + 1);
                     variable_start_index = None;
                 }
@@ -126,6 +427,19 @@ impl FromStr for Template {
     }
 }
 
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for tk in &self.tokens {
+            match tk {
+                Token::String(str) => f.write_str(str)?,
+                Token::Variable(variable) => write!(f, ":{}:", variable)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl<'de> Deserialize<'de> for Template {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -161,11 +475,19 @@ impl<'de> Deserialize<'de> for Template {
 mod tests {
     use thiserror::Error;
 
-    use crate::template::context::TemplateValue;
+    use chrono::NaiveDate;
+
+    use crate::template::context::{TemplateValue, Value};
+    use crate::template::modifiers::ParseError::UnknownModifier;
 
-    use super::context::Context;
-    use super::{ParseError, RenderError, Template};
-    use std::{path::PathBuf, str::FromStr};
+    use super::context::{Context, Options};
+    use super::{AliasError, ParseError, RenderError, Template};
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+        str::FromStr,
+        sync::Arc,
+    };
 
     #[test]
     fn string_without_variable() {
@@ -215,6 +537,322 @@ mod tests {
         assert_eq!(str, PathBuf::from("19/constant_prefix08/2022"));
     }
 
+    #[test]
+    fn root() {
+        let root = |tpl: &str| Template::from_str(tpl).unwrap().root();
+
+        assert_eq!(
+            root("/dst/:date.year:/:file.name:"),
+            Some(PathBuf::from("/dst/"))
+        );
+        assert_eq!(root("/dst/photos-:date.year:"), Some(PathBuf::from("/dst")));
+        assert_eq!(root("/dst/file.jpg"), Some(PathBuf::from("/dst")));
+        assert_eq!(root(":file.path:-copy"), None);
+        assert_eq!(root("photos-:date.year:"), None);
+        assert_eq!(root(""), None);
+    }
+
+    #[test]
+    fn rebase() {
+        let rebase = |tpl: &str| {
+            let tpl = Template::from_str(tpl)
+                .unwrap()
+                .rebase(Path::new("/tmp/scratch"));
+            (tpl.to_string(), tpl.root())
+        };
+
+        assert_eq!(
+            rebase("/dst/:date.year:/:file.name:"),
+            (
+                "/tmp/scratch/dst/:date.year:/:file.name:".to_owned(),
+                Some(PathBuf::from("/tmp/scratch/dst/"))
+            )
+        );
+        assert_eq!(
+            rebase("/:file.name:"),
+            (
+                "/tmp/scratch/:file.name:".to_owned(),
+                Some(PathBuf::from("/tmp/scratch/"))
+            )
+        );
+        assert_eq!(rebase("photos/:file.name:").0, "photos/:file.name:");
+        assert_eq!(rebase(":file.path:-copy").0, ":file.path:-copy");
+    }
+
+    #[test]
+    fn modifiers() {
+        let tpl = Template::from_str(":date|add_hours(-7):/:date|add_hours(-7)|year:").unwrap();
+        assert_eq!(tpl.tokens.len(), 3);
+
+        let mut ctx = Context::default();
+        let date = NaiveDate::from_ymd_opt(2023, 1, 1)
+            .unwrap()
+            .and_hms_opt(3, 0, 0)
+            .unwrap();
+        ctx.insert(&["date"], Box::new(Value::Date(date)));
+
+        let str = tpl.render(&ctx).unwrap();
+        assert_eq!(str, PathBuf::from("2022-12-31/2022"));
+    }
+
+    #[test]
+    fn modifier_errors() {
+        let tpl = Template::from_str(":date|foo:");
+        assert_eq!(
+            tpl.unwrap_err(),
+            ParseError::Modifier(9, UnknownModifier("foo".to_owned()))
+        );
+
+        let tpl = Template::from_str(":name|start_of_week:").unwrap();
+        let mut ctx = Context::default();
+        ctx.insert(&["name"], Box::new("foo"));
+        match tpl.render(&ctx).unwrap_err() {
+            RenderError::ModifierRender(name, modifier, _) => {
+                assert_eq!(name, "name");
+                assert_eq!(modifier, "start_of_week");
+            }
+            err => panic!("expected error of type ModifierRender, got {}", err),
+        }
+    }
+
+    #[test]
+    fn safe_variables() {
+        let render_template = |tpl: &Template, artist: &str, safe_variables| {
+            let options = Options {
+                safe_variables,
+                ..Default::default()
+            };
+            let mut ctx = Context::new(Arc::new(options));
+            ctx.insert(&["exif.artist"], Box::new(artist.to_owned()));
+            ctx.insert(&["file.name"], Box::new("a.jpg"));
+            tpl.render(&ctx)
+        };
+        let tpl = Template::from_str("/dst/:exif.artist:/:file.name:").unwrap();
+        let render = |artist: &str, safe_variables| render_template(&tpl, artist, safe_variables);
+
+        assert_eq!(
+            render("AC/DC", false).unwrap(),
+            PathBuf::from("/dst/AC/DC/a.jpg")
+        );
+        assert_eq!(
+            render("AC/DC\\x", true).unwrap(),
+            PathBuf::from("/dst/AC_DC_x/a.jpg")
+        );
+        assert_eq!(
+            render("/etc", true).unwrap(),
+            PathBuf::from("/dst/_etc/a.jpg")
+        );
+        assert!(matches!(
+            render("../../etc", true),
+            Err(RenderError::PathTraversal(_, _))
+        ));
+        assert!(render("..", false).is_ok());
+        // dots inside a component aren't a traversal.
+        assert!(render("a..b", true).is_ok());
+
+        // values are sanitized once modified.
+        let tpl = Template::from_str("/dst/:exif.artist|replace(-,/)|trim:/:file.name:").unwrap();
+        let render = |artist: &str| render_template(&tpl, artist, true);
+        assert_eq!(render("AC-DC").unwrap(), PathBuf::from("/dst/AC_DC/a.jpg"));
+        assert!(matches!(
+            render(" .. "),
+            Err(RenderError::PathTraversal(_, _))
+        ));
+    }
+
+    #[test]
+    fn safe_variables_paths() {
+        let tpl = Template::from_str("/dst/:date%Y/%m:/:file.relpath:/:file.stem:").unwrap();
+        let options = Options {
+            safe_variables: true,
+            ..Default::default()
+        };
+        let mut ctx = Context::new(Arc::new(options));
+        let date = NaiveDate::from_ymd_opt(2023, 7, 14)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        ctx.insert(&["date"], Box::new(Value::Date(date)));
+        ctx.insert(
+            &["file.relpath"],
+            Box::new(Value::Path("trip/a.jpg".into())),
+        );
+        // only metadata strings are sanitized.
+        ctx.insert(&["file.stem"], Box::new("a\\b"));
+
+        assert_eq!(
+            tpl.render(&ctx).unwrap(),
+            PathBuf::from("/dst/2023/07/trip/a.jpg/a\\b")
+        );
+    }
+
+    #[test]
+    fn aliases() {
+        let aliases = HashMap::from([
+            ("y".to_owned(), "date.year".to_owned()),
+            ("utc".to_owned(), "date|add_hours(-2)".to_owned()),
+        ]);
+
+        let mut tpl = Template::from_str("/dst/:y:/:utc|month:/:file.name:").unwrap();
+        tpl.expand_aliases(&aliases).unwrap();
+        assert_eq!(
+            tpl.to_string(),
+            "/dst/:date.year:/:date|add_hours(-2)|month:/:file.name:"
+        );
+
+        let aliases = HashMap::from([("date.year".to_owned(), "date|year".to_owned())]);
+        assert_eq!(
+            tpl.expand_aliases(&aliases).unwrap_err(),
+            AliasError::Collision("date.year".to_owned())
+        );
+
+        let aliases = HashMap::from([("y".to_owned(), "date|foo".to_owned())]);
+        assert!(matches!(
+            tpl.expand_aliases(&aliases).unwrap_err(),
+            AliasError::InvalidTarget(alias, _) if alias == "y"
+        ));
+    }
+
+    #[test]
+    fn date_format() {
+        let tpl = Template::from_str("/dst/:date%Y-%m:/:date%B %Y|add_days(1):").unwrap();
+        assert_eq!(tpl.to_string(), "/dst/:date%Y-%m:/:date%B %Y|add_days(1):");
+
+        let date = NaiveDate::from_ymd_opt(2023, 1, 31)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let mut ctx = Context::default();
+        ctx.insert(&["date"], Box::new(Value::Date(date)));
+        ctx.insert(&["file.name"], Box::new("a.jpg"));
+        // format is applied once modifiers are.
+        assert_eq!(
+            tpl.render(&ctx).unwrap(),
+            PathBuf::from("/dst/2023-01/February 2023")
+        );
+
+        let tpl = Template::from_str(":file.name%Y:").unwrap();
+        assert!(matches!(
+            tpl.render(&ctx),
+            Err(RenderError::NotADate(name, _, _)) if name == "file.name"
+        ));
+        assert_eq!(
+            Template::from_str("/dst/:date%Q:").unwrap_err(),
+            ParseError::InvalidFormat(12, "%Q".to_owned())
+        );
+    }
+
+    #[test]
+    fn default_value() {
+        let tpl = Template::from_str("/dst/:camera?unknown-camera:/:label|upper?none:/:file.name:")
+            .unwrap();
+        assert_eq!(
+            tpl.to_string(),
+            "/dst/:camera?unknown-camera:/:label|upper?none:/:file.name:"
+        );
+
+        let mut ctx = Context::default();
+        ctx.insert(&["label"], Box::new(""));
+        ctx.insert(&["file.name"], Box::new("a.jpg"));
+        assert_eq!(
+            tpl.render(&ctx).unwrap(),
+            PathBuf::from("/dst/unknown-camera/none/a.jpg")
+        );
+
+        ctx.insert(&["camera"], Box::new("x100"));
+        ctx.insert(&["label"], Box::new("keep"));
+        assert_eq!(
+            tpl.render(&ctx).unwrap(),
+            PathBuf::from("/dst/x100/KEEP/a.jpg")
+        );
+
+        // `?` in modifier arguments doesn't start a default value.
+        let tpl = Template::from_str(":file.name|replace(?,_)?:").unwrap();
+        assert_eq!(tpl.render(&ctx).unwrap(), PathBuf::from("a.jpg"));
+    }
+
+    #[test]
+    fn optional_variable() {
+        let tpl = Template::from_str("/dst/:?country:/:?city:/:file.name:").unwrap();
+        assert_eq!(tpl.to_string(), "/dst/:?country:/:?city:/:file.name:");
+
+        let mut ctx = Context::default();
+        ctx.insert(&["city"], Box::new(""));
+        ctx.insert(&["file.name"], Box::new("a.jpg"));
+        assert_eq!(tpl.render(&ctx).unwrap(), PathBuf::from("/dst/a.jpg"));
+
+        ctx.insert(&["country"], Box::new("France"));
+        assert_eq!(
+            tpl.render(&ctx).unwrap(),
+            PathBuf::from("/dst/France/a.jpg")
+        );
+
+        // optional variables inside a component keep the rest of it.
+        let tpl = Template::from_str("/dst/:file.name:-:?city:.bak").unwrap();
+        assert_eq!(tpl.render(&ctx).unwrap(), PathBuf::from("/dst/a.jpg-.bak"));
+        let tpl = Template::from_str("/dst/IMG-:?city:/:file.name:").unwrap();
+        assert_eq!(tpl.render(&ctx).unwrap(), PathBuf::from("/dst/IMG-/a.jpg"));
+
+        // leading optional variables don't make the path absolute.
+        let tpl = Template::from_str(":?city:/:file.name:").unwrap();
+        assert_eq!(tpl.render(&ctx).unwrap(), PathBuf::from("a.jpg"));
+    }
+
+    #[test]
+    fn fallbacks() {
+        let str = "/dst/:exif.date|file.name.date|file.md.creation_date%Y|add_days(1):/:file.name:";
+        let tpl = Template::from_str(str).unwrap();
+        assert_eq!(tpl.to_string(), str);
+        assert!(tpl.uses("file.name.date"));
+
+        let date = |d| {
+            NaiveDate::from_ymd_opt(2023, 1, d)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap()
+        };
+        let mut ctx = Context::default();
+        ctx.insert(&["file.md.creation_date"], Box::new(Value::Date(date(1))));
+        ctx.insert(&["file.name"], Box::new("a.jpg"));
+        assert_eq!(tpl.render(&ctx).unwrap(), PathBuf::from("/dst/2023/a.jpg"));
+
+        // first resolving variable is used.
+        ctx.insert(&["file.name.date"], Box::new(Value::Date(date(2))));
+        let tpl =
+            Template::from_str(":exif.date|file.name.date|file.md.creation_date|day:").unwrap();
+        assert_eq!(tpl.render(&ctx).unwrap(), PathBuf::from("02"));
+
+        let tpl = Template::from_str(":exif.date|exif.date.year:").unwrap();
+        assert!(matches!(
+            tpl.render(&ctx),
+            Err(RenderError::UndefinedVariable(name)) if name == "exif.date.year"
+        ));
+    }
+
+    #[test]
+    fn display() {
+        let str = ":date.day:/constant_prefix:date.month|add_days(1)|month:/:date.year:";
+        let tpl = Template::from_str(str).unwrap();
+        assert_eq!(tpl.to_string(), str);
+    }
+
+    #[test]
+    fn typed_values() {
+        let tpl = Template::from_str(":date:/:date.month:/:count:").unwrap();
+
+        let mut ctx = Context::default();
+        let date = NaiveDate::from_ymd_opt(2022, 8, 19)
+            .unwrap()
+            .and_hms_opt(13, 37, 0)
+            .unwrap();
+        ctx.insert(&["date"], Box::new(Value::Date(date)));
+        ctx.insert(&["date.month"], Box::new(Value::padded_int(8, 2)));
+        ctx.insert(&["count"], Box::new(Value::int(42)));
+
+        let str = tpl.render(&ctx).unwrap();
+        assert_eq!(str, PathBuf::from("2022-08-19/08/42"));
+    }
+
     #[test]
     fn string_with_unclosed_variable_error() {
         let tpl = Template::from_str(":date.day");
@@ -272,5 +910,23 @@ mod tests {
             }
             _ => panic!("expected error of type VariableRender, got {}", render_err),
         }
+
+        // only missing values are replaced by defaults.
+        for tpl in [":simple.variable?default:", ":?simple.variable:"] {
+            let tpl = Template::from_str(tpl).unwrap();
+            assert!(matches!(
+                tpl.render(&ctx),
+                Err(RenderError::VariableRender(_, _))
+            ));
+        }
+        struct MissingTemplateValue {}
+        impl TemplateValue for MissingTemplateValue {
+            fn render(&self, name: &str, _ctx: &Context) -> crate::template::context::Result {
+                Err(crate::template::context::missing_variable(name.to_owned()))
+            }
+        }
+        ctx.insert(&["simple.variable"], Box::new(MissingTemplateValue {}));
+        let tpl = Template::from_str(":simple.variable?default:").unwrap();
+        assert_eq!(tpl.render(&ctx).unwrap(), PathBuf::from("default"));
     }
 }
@@ -0,0 +1,636 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration, NaiveDateTime};
+use thiserror::Error;
+
+use super::context::Value;
+
+pub type Result = std::result::Result<Value, Box<dyn Error>>;
+
+/// ModifierFn transforms a rendered value using the given arguments.
+pub type ModifierFn = fn(Value, &[String]) -> Result;
+
+/// Modifier defines a function that can be applied to variables in a template
+/// using a pipe: `:date|add_hours(-7):`.
+pub struct Modifier {
+    pub name: &'static str,
+    /// Number of arguments expected by the modifier.
+    pub arity: RangeInclusive<usize>,
+    pub apply: ModifierFn,
+}
+
+/// Registry of all available modifiers.
+static MODIFIERS: &[Modifier] = &[
+    Modifier {
+        name: "add_days",
+        arity: 1..=1,
+        apply: add_days,
+    },
+    Modifier {
+        name: "add_hours",
+        arity: 1..=1,
+        apply: add_hours,
+    },
+    Modifier {
+        name: "add_minutes",
+        arity: 1..=1,
+        apply: add_minutes,
+    },
+    Modifier {
+        name: "start_of_week",
+        arity: 0..=0,
+        apply: start_of_week,
+    },
+    Modifier {
+        name: "start_of_month",
+        arity: 0..=0,
+        apply: start_of_month,
+    },
+    Modifier {
+        name: "year",
+        arity: 0..=0,
+        apply: year,
+    },
+    Modifier {
+        name: "month",
+        arity: 0..=0,
+        apply: month,
+    },
+    Modifier {
+        name: "day",
+        arity: 0..=0,
+        apply: day,
+    },
+    Modifier {
+        name: "roman",
+        arity: 0..=0,
+        apply: roman,
+    },
+    Modifier {
+        name: "roman_lower",
+        arity: 0..=0,
+        apply: roman_lower,
+    },
+    Modifier {
+        name: "alpha",
+        arity: 0..=0,
+        apply: alpha,
+    },
+    Modifier {
+        name: "enum",
+        arity: 1..=usize::MAX,
+        apply: enumeration,
+    },
+    Modifier {
+        name: "lower",
+        arity: 0..=0,
+        apply: lower,
+    },
+    Modifier {
+        name: "upper",
+        arity: 0..=0,
+        apply: upper,
+    },
+    Modifier {
+        name: "pad",
+        arity: 1..=2,
+        apply: pad,
+    },
+    Modifier {
+        name: "replace",
+        arity: 2..=2,
+        apply: replace,
+    },
+    Modifier {
+        name: "trim",
+        arity: 0..=1,
+        apply: trim,
+    },
+];
+
+/// Returns the modifier with the given name, if any.
+pub fn lookup(name: &str) -> Option<&'static Modifier> {
+    MODIFIERS.iter().find(|m| m.name == name)
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("unknown modifier \"{0}\"")]
+    UnknownModifier(String),
+
+    #[error("invalid modifier syntax \"{0}\"")]
+    InvalidSyntax(String),
+
+    #[error("modifier \"{0}\" expects {1} argument(s), got {2}")]
+    WrongArity(String, String, usize),
+}
+
+/// ModifierCall is a parsed modifier along with its arguments.
+#[derive(Clone)]
+pub struct ModifierCall {
+    modifier: &'static Modifier,
+    args: Vec<String>,
+}
+
+impl ModifierCall {
+    pub fn name(&self) -> &'static str {
+        self.modifier.name
+    }
+
+    pub fn apply(&self, value: Value) -> Result {
+        (self.modifier.apply)(value, &self.args)
+    }
+}
+
+impl fmt::Debug for ModifierCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Display for ModifierCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.modifier.name)?;
+        if !self.args.is_empty() {
+            write!(f, "({})", self.args.join(","))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for ModifierCall {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (name, args) = match s.find('(') {
+            Some(start) => {
+                let args = s[start + 1..]
+                    .strip_suffix(')')
+                    .ok_or_else(|| ParseError::InvalidSyntax(s.to_owned()))?;
+                let args: Vec<String> = if args.is_empty() {
+                    Vec::new()
+                } else {
+                    args.split(',').map(|arg| arg.to_owned()).collect()
+                };
+
+                (&s[..start], args)
+            }
+            None => (s, Vec::new()),
+        };
+
+        let modifier = lookup(name).ok_or_else(|| ParseError::UnknownModifier(name.to_owned()))?;
+        if !modifier.arity.contains(&args.len()) {
+            let expected = match (modifier.arity.start(), modifier.arity.end()) {
+                (min, max) if min == max => min.to_string(),
+                (min, &usize::MAX) => format!("at least {}", min),
+                (min, max) => format!("{} to {}", min, max),
+            };
+            return Err(ParseError::WrongArity(
+                name.to_owned(),
+                expected,
+                args.len(),
+            ));
+        }
+
+        Ok(Self { modifier, args })
+    }
+}
+
+/// Splits `s` on every `sep` that isn't enclosed in parenthesis.
+pub(crate) fn split_top_level(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            c if c == sep && depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+
+    parts
+}
+
+#[derive(Error, Debug)]
+enum ModifierError {
+    #[error("expected a {0} value, got {1:?}")]
+    WrongType(&'static str, Value),
+
+    #[error("invalid integer argument {0:?}")]
+    InvalidInt(String),
+
+    #[error("date out of range")]
+    OutOfRange,
+
+    #[error("{0} is out of range ({1})")]
+    IntOutOfRange(i64, String),
+
+    #[error("expected a single character argument, got {0:?}")]
+    InvalidChar(String),
+}
+
+fn date_arg(value: Value) -> std::result::Result<NaiveDateTime, ModifierError> {
+    match value {
+        Value::Date(date) => Ok(date),
+        v => Err(ModifierError::WrongType("date", v)),
+    }
+}
+
+/// Returns the integer value of `value`, strings are parsed.
+fn int_value(value: Value) -> std::result::Result<i64, ModifierError> {
+    match value {
+        Value::Int { value, .. } => Ok(value),
+        Value::String(str) => str
+            .parse()
+            .map_err(|_| ModifierError::WrongType("integer", Value::String(str))),
+        v => Err(ModifierError::WrongType("integer", v)),
+    }
+}
+
+fn int_arg(arg: &str) -> std::result::Result<i64, ModifierError> {
+    arg.trim()
+        .parse()
+        .map_err(|_| ModifierError::InvalidInt(arg.to_owned()))
+}
+
+/// Adds the duration built from the integer argument, durations and dates
+/// out of range are errors.
+fn add_duration(value: Value, arg: &str, duration: fn(i64) -> Option<Duration>) -> Result {
+    let date = date_arg(value)?;
+    let date = duration(int_arg(arg)?)
+        .and_then(|duration| date.checked_add_signed(duration))
+        .ok_or(ModifierError::OutOfRange)?;
+
+    Ok(Value::Date(date))
+}
+
+fn add_days(value: Value, args: &[String]) -> Result {
+    add_duration(value, &args[0], Duration::try_days)
+}
+
+fn add_hours(value: Value, args: &[String]) -> Result {
+    add_duration(value, &args[0], Duration::try_hours)
+}
+
+fn add_minutes(value: Value, args: &[String]) -> Result {
+    add_duration(value, &args[0], Duration::try_minutes)
+}
+
+/// Returns the Monday of the week at midnight.
+fn start_of_week(value: Value, _args: &[String]) -> Result {
+    let date = date_arg(value)?.date();
+    let monday = date - Duration::days(date.weekday().num_days_from_monday().into());
+
+    Ok(Value::Date(monday.and_hms_opt(0, 0, 0).unwrap()))
+}
+
+fn start_of_month(value: Value, _args: &[String]) -> Result {
+    let date = date_arg(value)?.date();
+
+    Ok(Value::Date(
+        date.with_day(1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+    ))
+}
+
+fn year(value: Value, _args: &[String]) -> Result {
+    Ok(Value::padded_int(date_arg(value)?.year().into(), 4))
+}
+
+fn month(value: Value, _args: &[String]) -> Result {
+    Ok(Value::padded_int(date_arg(value)?.month().into(), 2))
+}
+
+fn day(value: Value, _args: &[String]) -> Result {
+    Ok(Value::padded_int(date_arg(value)?.day().into(), 2))
+}
+
+fn to_roman(mut n: i64) -> std::result::Result<String, ModifierError> {
+    const NUMERALS: &[(i64, &str)] = &[
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+
+    if !(1..=3999).contains(&n) {
+        return Err(ModifierError::IntOutOfRange(n, "1 to 3999".to_owned()));
+    }
+
+    let mut roman = String::new();
+    for (value, numeral) in NUMERALS {
+        while n >= *value {
+            roman.push_str(numeral);
+            n -= value;
+        }
+    }
+
+    Ok(roman)
+}
+
+fn roman(value: Value, _args: &[String]) -> Result {
+    Ok(Value::String(to_roman(int_value(value)?)?))
+}
+
+fn roman_lower(value: Value, _args: &[String]) -> Result {
+    Ok(Value::String(to_roman(int_value(value)?)?.to_lowercase()))
+}
+
+/// Spreadsheet column like enumeration: 1 -> a, 26 -> z, 27 -> aa.
+fn alpha(value: Value, _args: &[String]) -> Result {
+    let mut n = int_value(value)?;
+    if n < 1 {
+        return Err(Box::new(ModifierError::IntOutOfRange(
+            n,
+            "at least 1".to_owned(),
+        )));
+    }
+
+    let mut letters = Vec::new();
+    while n > 0 {
+        n -= 1;
+        letters.push(b'a' + (n % 26) as u8);
+        n /= 26;
+    }
+    letters.reverse();
+
+    Ok(Value::String(String::from_utf8(letters).unwrap()))
+}
+
+/// Returns the n-th (starting at 1) argument: `:date.month|enum(jan,feb,...):`.
+fn enumeration(value: Value, args: &[String]) -> Result {
+    let n = int_value(value)?;
+
+    match usize::try_from(n)
+        .ok()
+        .and_then(|n| args.get(n.wrapping_sub(1)))
+    {
+        Some(item) => Ok(Value::String(item.to_owned())),
+        None => Err(Box::new(ModifierError::IntOutOfRange(
+            n,
+            format!("1 to {}", args.len()),
+        ))),
+    }
+}
+
+fn lower(value: Value, _args: &[String]) -> Result {
+    Ok(Value::String(value.to_string().to_lowercase()))
+}
+
+fn upper(value: Value, _args: &[String]) -> Result {
+    Ok(Value::String(value.to_string().to_uppercase()))
+}
+
+/// Largest width of the pad modifier, the longest file name on most
+/// filesystems.
+const MAX_PAD_WIDTH: i64 = 255;
+
+/// Left pads a value to `width` characters with the given character (`0` by
+/// default): `:counter|pad(4):`, `:exif.model|pad(12,_):`.
+fn pad(value: Value, args: &[String]) -> Result {
+    let width = int_arg(&args[0])?;
+    if !(0..=MAX_PAD_WIDTH).contains(&width) {
+        return Err(Box::new(ModifierError::IntOutOfRange(
+            width,
+            format!("0 to {}", MAX_PAD_WIDTH),
+        )));
+    }
+    let width = width as usize;
+    let fill = match args.get(1) {
+        Some(arg) => {
+            let mut chars = arg.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => return Err(Box::new(ModifierError::InvalidChar(arg.to_owned()))),
+            }
+        }
+        None => '0',
+    };
+
+    match value {
+        Value::Int { value, width: w } if fill == '0' => Ok(Value::padded_int(value, width.max(w))),
+        value => {
+            let str = value.to_string();
+            let len = str.chars().count();
+            let mut padded: String = std::iter::repeat_n(fill, width.saturating_sub(len)).collect();
+            padded.push_str(&str);
+
+            Ok(Value::String(padded))
+        }
+    }
+}
+
+/// Replaces every occurrence of the first argument with the second one.
+fn replace(value: Value, args: &[String]) -> Result {
+    Ok(Value::String(value.to_string().replace(&args[0], &args[1])))
+}
+
+/// Removes leading and trailing whitespaces, or the characters of the
+/// argument: `:file.stem|trim(_-):`.
+fn trim(value: Value, args: &[String]) -> Result {
+    let str = value.to_string();
+    let trimmed = match args.first() {
+        Some(chars) => str.trim_matches(|c| chars.contains(c)),
+        None => str.trim(),
+    };
+
+    Ok(Value::String(trimmed.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    use super::{split_top_level, ModifierCall, ParseError};
+    use crate::template::context::Value;
+
+    fn datetime(y: i32, m: u32, d: u32, h: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, 0, 0)
+            .unwrap()
+    }
+
+    fn apply(modifier: &str, value: Value) -> Value {
+        ModifierCall::from_str(modifier)
+            .unwrap()
+            .apply(value)
+            .unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let call = ModifierCall::from_str("add_hours(-7)").unwrap();
+        assert_eq!(call.name(), "add_hours");
+        assert_eq!(call.to_string(), "add_hours(-7)");
+
+        let call = ModifierCall::from_str("start_of_week").unwrap();
+        assert_eq!(call.to_string(), "start_of_week");
+
+        assert_eq!(
+            ModifierCall::from_str("foo").unwrap_err(),
+            ParseError::UnknownModifier("foo".to_owned())
+        );
+        assert_eq!(
+            ModifierCall::from_str("add_hours").unwrap_err(),
+            ParseError::WrongArity("add_hours".to_owned(), "1".to_owned(), 0)
+        );
+        assert_eq!(
+            ModifierCall::from_str("add_hours(1").unwrap_err(),
+            ParseError::InvalidSyntax("add_hours(1".to_owned())
+        );
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(
+            split_top_level("date|add_hours(-7)|year", '|'),
+            vec!["date", "add_hours(-7)", "year"]
+        );
+        assert_eq!(split_top_level("a|f(b|c)", '|'), vec!["a", "f(b|c)"]);
+        assert_eq!(split_top_level("a", '|'), vec!["a"]);
+    }
+
+    #[test]
+    fn add_hours() {
+        let date = Value::Date(datetime(2023, 1, 1, 3));
+        assert_eq!(
+            apply("add_hours(-7)", date),
+            Value::Date(datetime(2022, 12, 31, 20))
+        );
+    }
+
+    #[test]
+    fn add_days() {
+        let date = Value::Date(datetime(2023, 2, 28, 3));
+        assert_eq!(
+            apply("add_days(1)", date),
+            Value::Date(datetime(2023, 3, 1, 3))
+        );
+    }
+
+    #[test]
+    fn add_out_of_range() {
+        let date = Value::Date(datetime(2023, 2, 28, 3));
+        for modifier in [
+            "add_days(9223372036854775807)",
+            "add_hours(-9223372036854775807)",
+            "add_minutes(9223372036854775807)",
+            "add_days(1000000000)",
+        ] {
+            let call = ModifierCall::from_str(modifier).unwrap();
+            assert_eq!(
+                call.apply(date.clone()).unwrap_err().to_string(),
+                "date out of range",
+                "{}",
+                modifier
+            );
+        }
+    }
+
+    #[test]
+    fn start_of_week() {
+        // 2023-07-16 is a sunday
+        let date = Value::Date(datetime(2023, 7, 16, 18));
+        assert_eq!(
+            apply("start_of_week", date),
+            Value::Date(datetime(2023, 7, 10, 0))
+        );
+    }
+
+    #[test]
+    fn date_pieces() {
+        let date = Value::Date(datetime(2023, 7, 6, 18));
+        assert_eq!(apply("year", date.clone()).to_string(), "2023");
+        assert_eq!(apply("month", date.clone()).to_string(), "07");
+        assert_eq!(apply("day", date).to_string(), "06");
+    }
+
+    #[test]
+    fn roman() {
+        assert_eq!(apply("roman", Value::padded_int(7, 2)).to_string(), "VII");
+        assert_eq!(apply("roman", Value::int(1994)).to_string(), "MCMXCIV");
+        assert_eq!(apply("roman_lower", Value::from("12")).to_string(), "xii");
+
+        let call = ModifierCall::from_str("roman").unwrap();
+        assert!(call.apply(Value::int(0)).is_err());
+        assert!(call.apply(Value::int(4000)).is_err());
+    }
+
+    #[test]
+    fn alpha() {
+        assert_eq!(apply("alpha", Value::int(1)).to_string(), "a");
+        assert_eq!(apply("alpha", Value::int(26)).to_string(), "z");
+        assert_eq!(apply("alpha", Value::int(28)).to_string(), "ab");
+    }
+
+    #[test]
+    fn enumeration() {
+        let months =
+            "enum(winter,winter,spring,spring,spring,summer,summer,summer,fall,fall,fall,winter)";
+        assert_eq!(apply(months, Value::padded_int(7, 2)).to_string(), "summer");
+
+        let call = ModifierCall::from_str("enum(a,b)").unwrap();
+        assert!(call.apply(Value::int(3)).is_err());
+        assert!(call.apply(Value::int(0)).is_err());
+        assert_eq!(
+            ModifierCall::from_str("enum").unwrap_err(),
+            ParseError::WrongArity("enum".to_owned(), "at least 1".to_owned(), 0)
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(apply("lower", Value::from("JPG")).to_string(), "jpg");
+        assert_eq!(apply("upper", Value::from("Canon")).to_string(), "CANON");
+        assert_eq!(apply("pad(4)", Value::int(7)).to_string(), "0007");
+        assert_eq!(apply("pad(1)", Value::padded_int(7, 2)).to_string(), "07");
+        assert_eq!(apply("pad(5,_)", Value::from("ab")).to_string(), "___ab");
+        assert_eq!(
+            apply("replace( ,_)", Value::from("Canon EOS R5")).to_string(),
+            "Canon_EOS_R5"
+        );
+        assert_eq!(
+            apply("replace(-,)", Value::from("a-b-c")).to_string(),
+            "abc"
+        );
+        assert_eq!(apply("trim", Value::from(" Nikon  ")).to_string(), "Nikon");
+        assert_eq!(apply("trim(_-)", Value::from("-_IMG_-")).to_string(), "IMG");
+
+        let call = ModifierCall::from_str("pad(4,ab)").unwrap();
+        assert!(call.apply(Value::int(1)).is_err());
+        assert_eq!(apply("pad(255)", Value::int(1)).to_string().len(), 255);
+        for width in ["256", "-1", "18446744073709551615"] {
+            let call = ModifierCall::from_str(&format!("pad({})", width)).unwrap();
+            assert!(call.apply(Value::int(1)).is_err(), "{}", width);
+        }
+        assert!(ModifierCall::from_str("replace(a)").is_err());
+    }
+
+    #[test]
+    fn wrong_type() {
+        let call = ModifierCall::from_str("start_of_month").unwrap();
+        assert!(call.apply(Value::from("foo")).is_err());
+    }
+}
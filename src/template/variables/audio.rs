@@ -0,0 +1,403 @@
+//! Tags of audio files: ID3v2 and ID3v1 tags of MP3 files and Vorbis comments
+//! of FLAC and Ogg (Vorbis, Opus) files.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::result::Result as StdResult;
+
+use crate::template::context::{self, Context, Result, TemplateValue, Value};
+
+/// Tags of an audio file.
+#[derive(Debug, Default, PartialEq)]
+struct AudioTemplateValue {
+    artist: Option<String>,
+    album: Option<String>,
+    title: Option<String>,
+    year: Option<i64>,
+}
+
+impl AudioTemplateValue {
+    /// Sets the tag of the given ID3v2 frame or Vorbis comment field, tags
+    /// already set are kept.
+    fn set(&mut self, key: &str, value: String) {
+        let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        if value.is_empty() {
+            return;
+        }
+
+        match key.to_ascii_uppercase().as_str() {
+            "TPE1" | "ARTIST" => {
+                self.artist.get_or_insert_with(|| value.to_owned());
+            }
+            "TALB" | "ALBUM" => {
+                self.album.get_or_insert_with(|| value.to_owned());
+            }
+            "TIT2" | "TITLE" => {
+                self.title.get_or_insert_with(|| value.to_owned());
+            }
+            // recording time (ID3v2.4), year (ID3v2.3) or date (Vorbis).
+            "TDRC" | "TYER" | "DATE" | "YEAR" if self.year.is_none() => {
+                self.year = value.get(..4).and_then(|year| year.parse().ok());
+            }
+            _ => {}
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl TemplateValue for AudioTemplateValue {
+    fn render(&self, name: &str, _ctx: &Context) -> Result {
+        let value = match name {
+            "audio.artist" => self.artist.clone().map(Value::String),
+            "audio.album" => self.album.clone().map(Value::String),
+            "audio.title" => self.title.clone().map(Value::String),
+            "audio.year" => self.year.map(|year| Value::padded_int(year, 4)),
+            _ => unreachable!("unexpected audio template variable, please report a bug."),
+        };
+
+        value.ok_or_else(|| context::missing_variable(name.to_owned()))
+    }
+}
+
+pub const VARIABLES: &[&str] = &["audio.artist", "audio.album", "audio.title", "audio.year"];
+
+/// Returns the size of an ID3v2 tag or frame, stored on 7 bits per byte.
+fn syncsafe(bytes: [u8; 4]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |size, byte| size << 7 | u64::from(byte & 0x7f))
+}
+
+/// Decodes an ID3v2 text frame, the first byte is the text encoding.
+fn id3_text(data: &[u8]) -> String {
+    let utf16 = |text: &[u8], little_endian: bool| {
+        let units: Vec<u16> = text
+            .chunks_exact(2)
+            .map(|unit| match little_endian {
+                true => u16::from_le_bytes([unit[0], unit[1]]),
+                false => u16::from_be_bytes([unit[0], unit[1]]),
+            })
+            .collect();
+        String::from_utf16_lossy(&units)
+    };
+
+    match data.split_first() {
+        // latin-1.
+        Some((0, text)) => text.iter().map(|b| char::from(*b)).collect(),
+        // UTF-16 with byte order mark.
+        Some((1, [0xff, 0xfe, text @ ..])) => utf16(text, true),
+        Some((1, [0xfe, 0xff, text @ ..])) | Some((2, text)) => utf16(text, false),
+        Some((3, text)) => String::from_utf8_lossy(text).into_owned(),
+        _ => String::new(),
+    }
+}
+
+/// Reads the ID3v2.3 or ID3v2.4 tag of a MP3 file, the tag header is already
+/// read. Frames other than text frames (e.g. cover art) are skipped.
+fn read_id3v2<R: Read + Seek>(
+    reader: &mut R,
+    header: &[u8; 10],
+    tags: &mut AudioTemplateValue,
+) -> io::Result<()> {
+    let version = header[3];
+    if version != 3 && version != 4 {
+        return Ok(());
+    }
+    let end = 10 + syncsafe(header[6..].try_into().unwrap());
+
+    // extended header.
+    if header[5] & 0x40 != 0 {
+        let mut size = [0; 4];
+        reader.read_exact(&mut size)?;
+        match version {
+            3 => reader.seek(SeekFrom::Current(u32::from_be_bytes(size).into()))?,
+            _ => reader.seek(SeekFrom::Current(syncsafe(size) as i64 - 4))?,
+        };
+    }
+
+    while reader.stream_position()? + 10 <= end {
+        let mut frame = [0; 10];
+        reader.read_exact(&mut frame)?;
+        // padding.
+        if frame[0] == 0 {
+            break;
+        }
+        let id = String::from_utf8_lossy(&frame[..4]).into_owned();
+        let size = match version {
+            3 => u32::from_be_bytes(frame[4..8].try_into().unwrap()).into(),
+            _ => syncsafe(frame[4..8].try_into().unwrap()),
+        };
+
+        if id.starts_with('T') {
+            let mut data = Vec::new();
+            reader.take(size).read_to_end(&mut data)?;
+            tags.set(&id, id3_text(&data));
+        } else {
+            reader.seek(SeekFrom::Current(size as i64))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the ID3v1 tag at the end of a MP3 file.
+fn read_id3v1<R: Read + Seek>(reader: &mut R, tags: &mut AudioTemplateValue) -> io::Result<()> {
+    if reader.seek(SeekFrom::End(0))? < 128 {
+        return Ok(());
+    }
+    reader.seek(SeekFrom::End(-128))?;
+    let mut tag = [0; 128];
+    reader.read_exact(&mut tag)?;
+    if &tag[..3] != b"TAG" {
+        return Ok(());
+    }
+
+    let latin1 = |field: &[u8]| field.iter().map(|b| char::from(*b)).collect();
+    tags.set("TIT2", latin1(&tag[3..33]));
+    tags.set("TPE1", latin1(&tag[33..63]));
+    tags.set("TALB", latin1(&tag[63..93]));
+    tags.set("TYER", latin1(&tag[93..97]));
+    Ok(())
+}
+
+/// Parses a Vorbis comment block: vendor string, number of fields and
+/// `KEY=value` fields, lengths are 32 bits little endian.
+fn vorbis_comment(data: &[u8], tags: &mut AudioTemplateValue) {
+    let mut data = data;
+    let u32_le = |data: &mut &[u8]| -> Option<usize> {
+        let value = u32::from_le_bytes(data.get(..4)?.try_into().unwrap());
+        *data = &data[4..];
+        Some(value as usize)
+    };
+    let string = |data: &mut &[u8], len: usize| -> Option<String> {
+        let value = String::from_utf8_lossy(data.get(..len)?).into_owned();
+        *data = &data[len..];
+        Some(value)
+    };
+
+    let vendor = u32_le(&mut data).and_then(|len| string(&mut data, len));
+    let count = match vendor.and_then(|_| u32_le(&mut data)) {
+        Some(count) => count,
+        None => return,
+    };
+    for _ in 0..count {
+        let field = match u32_le(&mut data).and_then(|len| string(&mut data, len)) {
+            Some(field) => field,
+            None => return,
+        };
+        if let Some((key, value)) = field.split_once('=') {
+            tags.set(key, value.to_owned());
+        }
+    }
+}
+
+/// Reads the Vorbis comment of a FLAC file, the `fLaC` marker is already
+/// read.
+fn read_flac<R: Read + Seek>(reader: &mut R, tags: &mut AudioTemplateValue) -> io::Result<()> {
+    loop {
+        let mut header = [0; 4];
+        reader.read_exact(&mut header)?;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+
+        if header[0] & 0x7f == 4 {
+            let mut data = Vec::new();
+            reader.take(len.into()).read_to_end(&mut data)?;
+            vorbis_comment(&data, tags);
+            return Ok(());
+        }
+        // last metadata block.
+        if header[0] & 0x80 != 0 {
+            return Ok(());
+        }
+        reader.seek(SeekFrom::Current(len.into()))?;
+    }
+}
+
+/// Reads the Vorbis comment of an Ogg Vorbis or Opus file, stored in the
+/// second packet of the stream.
+fn read_ogg<R: Read>(reader: &mut R, tags: &mut AudioTemplateValue) -> io::Result<()> {
+    let mut packets: Vec<Vec<u8>> = vec![Vec::new()];
+    while packets.len() < 3 {
+        // capture pattern to page segment count.
+        let mut header = [0; 27];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != b"OggS" {
+            return Ok(());
+        }
+        let mut segments = vec![0; header[26].into()];
+        reader.read_exact(&mut segments)?;
+
+        for len in segments {
+            let packet = packets.last_mut().unwrap();
+            reader.take(len.into()).read_to_end(packet)?;
+            // a segment shorter than 255 bytes ends the packet.
+            if len < 255 {
+                packets.push(Vec::new());
+            }
+        }
+    }
+
+    let comment = &packets[1];
+    if let Some(comment) = comment.strip_prefix(b"\x03vorbis") {
+        vorbis_comment(comment, tags);
+    } else if let Some(comment) = comment.strip_prefix(b"OpusTags") {
+        vorbis_comment(comment, tags);
+    }
+    Ok(())
+}
+
+/// Returns the tags of an audio file, none is returned for other files.
+fn read_tags<R: Read + Seek>(reader: &mut R) -> io::Result<Option<AudioTemplateValue>> {
+    let mut header = [0; 10];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    let mut tags = AudioTemplateValue::default();
+    if &header[..3] == b"ID3" {
+        read_id3v2(reader, &header, &mut tags)?;
+        read_id3v1(reader, &mut tags)?;
+    } else if &header[..4] == b"fLaC" {
+        reader.seek(SeekFrom::Start(4))?;
+        read_flac(reader, &mut tags)?;
+    } else if &header[..4] == b"OggS" {
+        reader.seek(SeekFrom::Start(0))?;
+        read_ogg(reader, &mut tags)?;
+    } else if header[0] == 0xff && header[1] & 0xe0 == 0xe0 {
+        // MPEG audio frame sync, MP3 file without ID3v2 tag.
+        read_id3v1(reader, &mut tags)?;
+    } else {
+        return Ok(None);
+    }
+
+    Ok(Some(tags).filter(|tags| !tags.is_empty()))
+}
+
+pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+    let filepath = ctx.file_path()?;
+
+    // malformed files have no audio variables.
+    match read_tags(&mut BufReader::new(File::open(filepath)?)) {
+        Ok(Some(tags)) => ctx.insert(VARIABLES, Box::new(tags)),
+        Ok(None) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {}
+        Err(err) => return Err(Box::new(err)),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::AudioTemplateValue;
+
+    fn tags(data: Vec<u8>) -> AudioTemplateValue {
+        super::read_tags(&mut Cursor::new(data)).unwrap().unwrap()
+    }
+
+    fn expected(year: i64) -> AudioTemplateValue {
+        AudioTemplateValue {
+            artist: Some("Daft Punk".to_owned()),
+            album: Some("Discovery".to_owned()),
+            title: Some("One More Time".to_owned()),
+            year: Some(year),
+        }
+    }
+
+    /// Returns a Vorbis comment block with the given fields.
+    fn vorbis_comment(fields: &[&str]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for field in [&["photosort"], fields].concat() {
+            if data.len() == 13 {
+                data.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+            }
+            data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            data.extend_from_slice(field.as_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn id3() {
+        let frame = |id: &[u8], text: &[u8]| {
+            let mut frame = id.to_vec();
+            frame.extend_from_slice(&(text.len() as u32).to_be_bytes());
+            frame.extend_from_slice(&[0, 0]);
+            frame.extend_from_slice(text);
+            frame
+        };
+        // UTF-16 with byte order mark.
+        let mut title = vec![1, 0xff, 0xfe];
+        title.extend("One More Time".encode_utf16().flat_map(u16::to_le_bytes));
+        let frames = [
+            frame(b"TPE1", b"\0Daft Punk"),
+            frame(b"APIC", &[0; 32]),
+            frame(b"TALB", b"\x03Discovery\0"),
+            frame(b"TIT2", &title),
+            frame(b"TYER", b"\x002001"),
+        ]
+        .concat();
+        let mut mp3 = b"ID3\x03\0\0\0\0".to_vec();
+        mp3.extend_from_slice(&[(frames.len() >> 7) as u8, frames.len() as u8 & 0x7f]);
+        mp3.extend(frames);
+        mp3.extend_from_slice(&[0; 16]);
+        assert_eq!(tags(mp3), expected(2001));
+
+        // ID3v1 tag only.
+        let field = |text: &str, len: usize| {
+            let mut field = text.as_bytes().to_vec();
+            field.resize(len, 0);
+            field
+        };
+        let mut mp3 = vec![0xff, 0xfb, 0x90, 0x64, 0, 0, 0, 0, 0, 0];
+        mp3.extend_from_slice(b"TAG");
+        mp3.extend(field("One More Time", 30));
+        mp3.extend(field("Daft Punk", 30));
+        mp3.extend(field("Discovery", 30));
+        mp3.extend(field("2001", 35));
+        assert_eq!(tags(mp3), expected(2001));
+    }
+
+    #[test]
+    fn vorbis() {
+        let comment = vorbis_comment(&[
+            "ARTIST=Daft Punk",
+            "album=Discovery",
+            "TITLE=One More Time",
+            "DATE=2001-03-12",
+        ]);
+
+        // STREAMINFO block then last VORBIS_COMMENT block.
+        let mut flac = b"fLaC\0\0\0\x22".to_vec();
+        flac.extend_from_slice(&[0; 0x22]);
+        flac.push(0x84);
+        flac.extend_from_slice(&(comment.len() as u32).to_be_bytes()[1..]);
+        flac.extend_from_slice(&comment);
+        assert_eq!(tags(flac), expected(2001));
+
+        // identification header page then comment header page.
+        let page = |packet: &[u8]| {
+            let mut page = b"OggS".to_vec();
+            page.extend_from_slice(&[0; 22]);
+            page.push(1);
+            page.push(packet.len() as u8);
+            page.extend_from_slice(packet);
+            page
+        };
+        let mut opus_tags = b"OpusTags".to_vec();
+        opus_tags.extend_from_slice(&comment);
+        let ogg = [page(b"OpusHead"), page(&opus_tags)].concat();
+        assert_eq!(tags(ogg), expected(2001));
+
+        let png = b"\x89PNG\r\n\x1a\n\0\0".to_vec();
+        assert!(super::read_tags(&mut Cursor::new(png)).unwrap().is_none());
+    }
+}
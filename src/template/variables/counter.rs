@@ -0,0 +1,25 @@
+use std::error::Error;
+use std::result::Result as StdResult;
+
+use crate::template::context::{Context, Result, TemplateValue, Value};
+
+/// Number of digits counters are zero-padded to.
+const WIDTH: usize = 4;
+
+/// Counter renders the counter of the context, it is incremented by the
+/// sorter until the rendered path is free.
+struct CounterTemplateValue;
+
+impl TemplateValue for CounterTemplateValue {
+    fn render(&self, _name: &str, ctx: &Context) -> Result {
+        Ok(Value::padded_int(ctx.counter(), WIDTH))
+    }
+}
+
+pub const VARIABLES: &[&str] = &["counter"];
+
+pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+    ctx.insert(VARIABLES, Box::new(CounterTemplateValue));
+
+    Ok(())
+}
@@ -1,71 +1,295 @@
 use std::{error::Error, result::Result as StdResult};
 
+use chrono::{Datelike, NaiveDateTime, TimeZone, Timelike, Utc};
 use thiserror::Error;
 
-use crate::template::context::{Context, Result, TemplateValue};
+use crate::template::context::{AdjustError, Context, Result, TemplateValue, Value};
+
+/// Source variables of `date` in preference order, `date.year`, `date.month`
+/// and `date.day` use the same variables suffixed by their part.
+const SOURCES: &[&str] = &[
+    "exif.date",
+    "dji.date",
+    "gopro.date",
+    "video.date",
+    "image.date",
+    "file.name.date",
+    "file.md.creation_date",
+    "file.md.modification_date",
+];
+
+/// Source is a source variable tried to render a composed date variable.
+pub struct Source {
+    pub name: String,
+    pub value: Result,
+}
+
+/// Renders the source variables of the given composed date variable until
+/// one renders, the last source is the one that won if its value is ok.
+/// Sources are the `date_sources` option if set, otherwise `dir.date` is
+/// tried before the file metadata if `dir_date_fallback` is enabled.
+///
+/// Parts other than the year are rendered from the source of `date.year`,
+/// so a date never mixes sources (e.g. the day of a directory name with the
+/// hour of the file modification date).
+pub fn trace(ctx: &Context, name: &str) -> Vec<Source> {
+    let suffix = match name {
+        "date.year" => ".year",
+        "date.month" => ".month",
+        "date.day" => ".day",
+        "date.hour" => ".hour",
+        "date.minute" => ".minute",
+        "date.second" => ".second",
+        _ => "",
+    };
+    if matches!(suffix, "" | ".year") {
+        return trace_sources(ctx, suffix);
+    }
+
+    let mut sources = trace_sources(ctx, ".year");
+    if let Some(source) = sources.last_mut().filter(|source| source.value.is_ok()) {
+        let date = source.name.strip_suffix(".year").unwrap_or(&source.name);
+        let name = format!("{}{}", date, suffix);
+        source.value = render_part(ctx, date, &name, suffix);
+        source.name = name;
+    }
+
+    sources
+}
+
+/// Renders the given part of a source date. Sources without time of day
+/// (e.g. `dir.date`) have the time of their date, midnight.
+fn render_part(ctx: &Context, date: &str, name: &str, suffix: &str) -> Result {
+    let value = ctx
+        .get_or_err(name)
+        .and_then(|value| value.render(name, ctx));
+    if value.is_ok() || !matches!(suffix, ".hour" | ".minute" | ".second") {
+        return value;
+    }
+
+    let Some(date) = ctx
+        .get(date)
+        .and_then(|value| value.render(date, ctx).ok())
+        .and_then(|value| value.as_date())
+    else {
+        return value;
+    };
+    let time = match suffix {
+        ".hour" => date.hour(),
+        ".minute" => date.minute(),
+        _ => date.second(),
+    };
+    Ok(Value::padded_int(time.into(), 2))
+}
+
+/// Renders the source variables suffixed by the given part until one
+/// renders.
+fn trace_sources(ctx: &Context, suffix: &str) -> Vec<Source> {
+    let options = ctx.options();
+    let names: Vec<String> = if options.date_sources.is_empty() {
+        let mut names: Vec<String> = SOURCES.iter().map(|s| format!("{}{}", s, suffix)).collect();
+        if options.dir_date_fallback {
+            names.insert(names.len() - 2, format!("dir.date{}", suffix));
+        }
+        names
+    } else {
+        options
+            .date_sources
+            .iter()
+            .map(|source| format!("{}{}", source.variable(), suffix))
+            .collect()
+    };
+
+    let mut sources = Vec::with_capacity(names.len());
+    for name in names {
+        let value = ctx
+            .get_or_err(&name)
+            .and_then(|value| value.render(&name, ctx));
+        let resolved = value.is_ok();
+        sources.push(Source { name, value });
+        if resolved {
+            break;
+        }
+    }
+
+    sources
+}
 
 #[derive(Default)]
 struct Date {}
 
 impl Date {
-    fn get_one_of(&self, ctx: &Context, keys: &[&str]) -> Result {
+    /// Renders the given composed variable, derived from `date` adjusted by
+    /// the `date_offset` and `timezone` options if any.
+    fn resolve(&self, ctx: &Context, name: &str) -> Result {
+        let Some(date) = self.adjusted(ctx) else {
+            return self.resolve_sources(ctx, name);
+        };
+
+        let date = date?;
+        Ok(match name {
+            "date.year" => Value::padded_int(date.year().into(), 4),
+            "date.month" => Value::padded_int(date.month().into(), 2),
+            "date.day" => Value::padded_int(date.day().into(), 2),
+            "date.hour" => Value::padded_int(date.hour().into(), 2),
+            "date.minute" => Value::padded_int(date.minute().into(), 2),
+            "date.second" => Value::padded_int(date.second().into(), 2),
+            _ => date.into(),
+        })
+    }
+
+    /// Returns `date` adjusted by the `date_offset` and `timezone` options,
+    /// only dates whose source knows their UTC offset are converted to the
+    /// timezone. None if neither option is set, `date` doesn't resolve or
+    /// its source has no time of day (dates of file and directory names
+    /// without time): parts are then resolved from their own sources (e.g. a
+    /// year only directory name).
+    fn adjusted(&self, ctx: &Context) -> Option<StdResult<NaiveDateTime, AdjustError>> {
+        let options = ctx.options();
+        if options.timezone.is_none() && options.date_offset.is_none() {
+            return None;
+        }
+
+        let source = trace(ctx, "date").pop()?;
+        let date = source.value.ok()?.as_date()?;
+        let has_time = match source.name.as_str() {
+            "dir.date" => false,
+            "file.name.date" => ctx
+                .get_or_err("file.name.datetime")
+                .and_then(|value| value.render("file.name.datetime", ctx))
+                .is_ok(),
+            _ => true,
+        };
+        if !has_time {
+            return None;
+        }
+
+        let offset = ctx
+            .get(&source.name)
+            .and_then(|value| value.zoned_date(&source.name, ctx))
+            .map(|date| *date.offset());
+        Some(options.adjust(&date, offset))
+    }
+
+    /// Renders the first source variable of the given composed variable that
+    /// renders, tried sources are logged.
+    fn resolve_sources(&self, ctx: &Context, name: &str) -> Result {
         #[derive(Debug, Error)]
         #[error("failed to get or render any of the following variables: {0:?}")]
         struct GetOneOfErr(Vec<String>);
 
-        for key in keys {
-            match ctx.get(key) {
-                Some(v) => match v.render(key, ctx) {
-                    Ok(rendered_value) => return Ok(rendered_value),
-                    Err(_) => continue,
-                },
-                None => continue,
-            }
+        let mut sources = trace(ctx, name);
+        if sources.last().is_none_or(|source| source.value.is_err()) {
+            return Err(Box::new(GetOneOfErr(
+                sources.into_iter().map(|source| source.name).collect(),
+            )));
         }
 
-        Err(Box::new(GetOneOfErr(
-            Vec::from(keys).iter().map(|k| k.to_string()).collect(),
-        )))
+        let winner = sources.pop().unwrap();
+        if log::log_enabled!(log::Level::Debug) {
+            let failed: Vec<String> = sources
+                .iter()
+                .filter_map(|source| {
+                    let err = source.value.as_ref().err()?;
+                    Some(format!("{}: {}", source.name, err))
+                })
+                .collect();
+            log::debug!(
+                "{:?}: {} resolved by {} (failed: [{}])",
+                ctx.file_path().unwrap_or_default(),
+                name,
+                winner.name,
+                failed.join(", ")
+            );
+        }
+
+        winner.value
     }
 
     fn date(&self, ctx: &Context) -> Result {
-        self.get_one_of(
-            ctx,
-            &["exif.date", "file.name.date", "file.md.creation_date"],
-        )
+        self.resolve(ctx, "date")
     }
 
     fn date_year(&self, ctx: &Context) -> Result {
-        self.get_one_of(
-            ctx,
-            &[
-                "exif.date.year",
-                "file.name.date.year",
-                "file.md.creation_date.year",
-            ],
-        )
+        self.resolve(ctx, "date.year")
     }
 
     fn date_month(&self, ctx: &Context) -> Result {
-        self.get_one_of(
-            ctx,
-            &[
-                "exif.date.month",
-                "file.name.date.month",
-                "file.md.creation_date.month",
-            ],
-        )
+        self.resolve(ctx, "date.month")
     }
 
     fn date_day(&self, ctx: &Context) -> Result {
-        self.get_one_of(
-            ctx,
-            &[
-                "exif.date.day",
-                "file.name.date.day",
-                "file.md.creation_date.day",
-            ],
-        )
+        self.resolve(ctx, "date.day")
+    }
+
+    fn date_hour(&self, ctx: &Context) -> Result {
+        self.resolve(ctx, "date.hour")
+    }
+
+    fn date_minute(&self, ctx: &Context) -> Result {
+        self.resolve(ctx, "date.minute")
+    }
+
+    fn date_second(&self, ctx: &Context) -> Result {
+        self.resolve(ctx, "date.second")
+    }
+}
+
+impl Date {
+    /// Returns the resolved `date` value.
+    fn naive_date(&self, ctx: &Context) -> StdResult<NaiveDateTime, Box<dyn Error>> {
+        #[derive(Debug, Error)]
+        #[error("expected a date value, got {0:?}")]
+        struct NotADateErr(String);
+
+        let value = self.date(ctx)?;
+        Ok(value
+            .as_date()
+            .ok_or_else(|| NotADateErr(value.to_string()))?)
+    }
+
+    /// Renders `date` using the given format and the locale from the
+    /// context options.
+    fn localized(&self, ctx: &Context, fmt: &str) -> Result {
+        let date = Utc.from_utc_datetime(&self.naive_date(ctx)?);
+
+        Ok(date
+            .format_localized(fmt, ctx.options().locale)
+            .to_string()
+            .into())
+    }
+
+    fn date_month_name(&self, ctx: &Context) -> Result {
+        self.localized(ctx, "%B")
+    }
+
+    fn date_weekday_name(&self, ctx: &Context) -> Result {
+        self.localized(ctx, "%A")
+    }
+
+    /// Day of the week, from 1 (Monday) to 7 (Sunday).
+    fn date_weekday(&self, ctx: &Context) -> Result {
+        let weekday = self.naive_date(ctx)?.weekday();
+        Ok(Value::int(weekday.number_from_monday().into()))
+    }
+
+    /// ISO 8601 week number, weeks start on Monday and the first week of a
+    /// year contains its first Thursday.
+    fn date_week(&self, ctx: &Context) -> Result {
+        let week = self.naive_date(ctx)?.iso_week().week();
+        Ok(Value::padded_int(week.into(), 2))
+    }
+
+    /// Year of the ISO 8601 week, which differs from the calendar year for
+    /// days of the first and last week of a year.
+    fn date_week_year(&self, ctx: &Context) -> Result {
+        let year = self.naive_date(ctx)?.iso_week().year();
+        Ok(Value::int(year.into()))
+    }
+
+    fn date_quarter(&self, ctx: &Context) -> Result {
+        let month = self.naive_date(ctx)?.month();
+        Ok(Value::int(((month - 1) / 3 + 1).into()))
     }
 }
 
@@ -76,16 +300,267 @@ impl TemplateValue for Date {
             "date.year" => self.date_year(ctx),
             "date.month" => self.date_month(ctx),
             "date.day" => self.date_day(ctx),
+            "date.hour" => self.date_hour(ctx),
+            "date.minute" => self.date_minute(ctx),
+            "date.second" => self.date_second(ctx),
+            "date.month_name" => self.date_month_name(ctx),
+            "date.weekday_name" => self.date_weekday_name(ctx),
+            "date.weekday" => self.date_weekday(ctx),
+            "date.week" => self.date_week(ctx),
+            "date.week_year" => self.date_week_year(ctx),
+            "date.quarter" => self.date_quarter(ctx),
             _ => unreachable!("unexpected date template variable, please report a bug."),
         }
     }
 }
 
+pub const VARIABLES: &[&str] = &[
+    "date",
+    "date.year",
+    "date.month",
+    "date.day",
+    "date.hour",
+    "date.minute",
+    "date.second",
+    "date.month_name",
+    "date.weekday_name",
+    "date.weekday",
+    "date.week",
+    "date.week_year",
+    "date.quarter",
+];
+
 pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
-    ctx.insert(
-        &["date", "date.year", "date.month", "date.day"],
-        Box::new(Date::default()),
-    );
+    ctx.insert(VARIABLES, Box::new(Date::default()));
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{FixedOffset, NaiveDate, TimeZone};
+
+    use crate::template::context::{
+        self, Context, DateSource, DateTimezone, Options, TemplateValue, Value,
+    };
+
+    #[test]
+    fn localized_names() {
+        let options = Options {
+            locale: context::parse_locale("fr_FR").unwrap(),
+            ..Default::default()
+        };
+        let mut ctx = Context::new(Arc::new(options));
+        let date = NaiveDate::from_ymd_opt(2023, 7, 14)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        ctx.insert(&["exif.date"], Box::new(Value::Date(date)));
+        super::prepare_template_context(&mut ctx).unwrap();
+
+        let render = |name: &str| ctx.get(name).unwrap().render(name, &ctx).unwrap();
+        assert_eq!(render("date.month_name").to_string(), "juillet");
+        assert_eq!(render("date.weekday_name").to_string(), "vendredi");
+    }
+
+    #[test]
+    fn calendar() {
+        let mut ctx = Context::new(Arc::default());
+        // ISO week 1 of 2021 starts on January 4th.
+        let date = NaiveDate::from_ymd_opt(2021, 1, 3)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        ctx.insert(&["exif.date"], Box::new(Value::Date(date)));
+        super::prepare_template_context(&mut ctx).unwrap();
+
+        let render = |name: &str| ctx.get(name).unwrap().render(name, &ctx).unwrap();
+        assert_eq!(render("date.weekday").to_string(), "7");
+        assert_eq!(render("date.week").to_string(), "53");
+        assert_eq!(render("date.week_year").to_string(), "2020");
+        assert_eq!(render("date.quarter").to_string(), "1");
+    }
+
+    #[test]
+    fn dir_date_fallback() {
+        let date_year = |dir_date_fallback| {
+            let options = Options {
+                dir_date_fallback,
+                ..Default::default()
+            };
+            let mut ctx = Context::new(Arc::new(options));
+            ctx.insert(&["dir.date.year"], Box::new(Value::padded_int(2019, 4)));
+            ctx.insert(
+                &["file.md.creation_date.year"],
+                Box::new(Value::padded_int(2024, 4)),
+            );
+            super::prepare_template_context(&mut ctx).unwrap();
+
+            let value = ctx.get("date.year").unwrap();
+            value.render("date.year", &ctx).unwrap().to_string()
+        };
+
+        assert_eq!(date_year(false), "2024");
+        assert_eq!(date_year(true), "2019");
+    }
+
+    #[test]
+    fn single_source() {
+        let options = Options {
+            dir_date_fallback: true,
+            ..Default::default()
+        };
+        let date = NaiveDate::from_ymd_opt(2019, 7, 14)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let render = |dir: &[(&str, Value)], name: &str| {
+            let mut ctx = Context::new(Arc::new(options.clone()));
+            for (source, value) in dir {
+                ctx.insert(&[source], Box::new(value.clone()));
+            }
+            ctx.insert(
+                &["file.md.creation_date.month"],
+                Box::new(Value::padded_int(3, 2)),
+            );
+            ctx.insert(
+                &["file.md.creation_date.hour"],
+                Box::new(Value::padded_int(10, 2)),
+            );
+            super::prepare_template_context(&mut ctx).unwrap();
+            let value = ctx.get(name).unwrap().render(name, &ctx);
+            value.map(|value| value.to_string())
+        };
+
+        // directory dates have no time of day, their time is midnight.
+        let dir = [
+            ("dir.date", Value::Date(date)),
+            ("dir.date.year", Value::padded_int(2019, 4)),
+            ("dir.date.month", Value::padded_int(7, 2)),
+        ];
+        assert_eq!(render(&dir, "date.month").unwrap(), "07");
+        assert_eq!(render(&dir, "date.hour").unwrap(), "00");
+        // the month of a year only directory isn't the creation month.
+        let dir = [("dir.date.year", Value::padded_int(2019, 4))];
+        assert!(render(&dir, "date.month").is_err());
+        assert!(render(&dir, "date.hour").is_err());
+    }
+
+    #[test]
+    fn timezone() {
+        let options = Options {
+            timezone: Some(DateTimezone::Fixed(
+                FixedOffset::east_opt(9 * 3600).unwrap(),
+            )),
+            ..Default::default()
+        };
+        let date = NaiveDate::from_ymd_opt(2023, 7, 14)
+            .unwrap()
+            .and_hms_opt(18, 0, 0)
+            .unwrap();
+        let paris = FixedOffset::east_opt(2 * 3600).unwrap();
+        let render = |source: &str, value: Box<dyn TemplateValue>, name: &str| {
+            let mut ctx = Context::new(Arc::new(options.clone()));
+            ctx.insert(&[source], value);
+            super::prepare_template_context(&mut ctx).unwrap();
+            ctx.get(name).unwrap().render(name, &ctx).unwrap()
+        };
+        let zoned = || Box::new(paris.from_local_datetime(&date).unwrap());
+
+        // 18:00 in Paris is 01:00 the next day in Tokyo.
+        let converted = render("exif.date", zoned(), "date").as_date().unwrap();
+        assert_eq!(converted.to_string(), "2023-07-15 01:00:00");
+        assert_eq!(render("exif.date", zoned(), "date.hour").to_string(), "01");
+        assert_eq!(render("exif.date", zoned(), "date.day").to_string(), "15");
+        // dates without offset (camera time) aren't converted.
+        let floating = render("exif.date", Box::new(Value::Date(date)), "date");
+        assert_eq!(floating.as_date(), Some(date));
+        // file name dates without time aren't converted.
+        let value = render("file.name.date", zoned(), "date");
+        assert_eq!(value.as_date(), Some(date));
+    }
+
+    #[test]
+    fn date_offset() {
+        let options = Options {
+            date_offset: Some("+6h30m".parse().unwrap()),
+            ..Default::default()
+        };
+        let mut ctx = Context::new(Arc::new(options));
+        let date = NaiveDate::from_ymd_opt(2023, 7, 14)
+            .unwrap()
+            .and_hms_opt(18, 0, 0)
+            .unwrap();
+        ctx.insert(&["video.date"], Box::new(Value::Date(date)));
+        super::prepare_template_context(&mut ctx).unwrap();
+
+        let render = |name: &str| ctx.get(name).unwrap().render(name, &ctx).unwrap();
+        assert_eq!(render("date.day").to_string(), "15");
+        assert_eq!(render("date.minute").to_string(), "30");
+    }
+
+    #[test]
+    fn date_sources() {
+        let options = Options {
+            date_sources: vec![DateSource::Filename, DateSource::Exif],
+            ..Default::default()
+        };
+        let mut ctx = Context::new(Arc::new(options));
+        ctx.insert(&["exif.date.year"], Box::new(Value::padded_int(2023, 4)));
+        ctx.insert(
+            &["file.name.date.year"],
+            Box::new(Value::padded_int(2019, 4)),
+        );
+        ctx.insert(
+            &["file.md.modification_date.year"],
+            Box::new(Value::padded_int(2024, 4)),
+        );
+
+        let sources = super::trace(&ctx, "date.year");
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].value.as_ref().unwrap().to_string(), "2019");
+
+        // excluded sources are never tried.
+        let options = Options {
+            date_sources: vec![DateSource::Exif, DateSource::Btime],
+            ..Default::default()
+        };
+        let mut ctx = Context::new(Arc::new(options));
+        ctx.insert(
+            &["file.md.modification_date.year"],
+            Box::new(Value::padded_int(2024, 4)),
+        );
+        let names: Vec<String> = super::trace(&ctx, "date.year")
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(names, ["exif.date.year", "file.md.creation_date.year"]);
+    }
+
+    #[test]
+    fn trace() {
+        let mut ctx = Context::new(Arc::default());
+        ctx.insert(
+            &["file.name.date.year"],
+            Box::new(Value::padded_int(2019, 4)),
+        );
+
+        let sources = super::trace(&ctx, "date.year");
+        let names: Vec<&str> = sources.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "exif.date.year",
+                "dji.date.year",
+                "gopro.date.year",
+                "video.date.year",
+                "image.date.year",
+                "file.name.date.year"
+            ]
+        );
+        assert!(sources[..5].iter().all(|s| s.value.is_err()));
+        assert_eq!(sources[5].value.as_ref().unwrap().to_string(), "2019");
+    }
+}
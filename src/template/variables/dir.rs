@@ -0,0 +1,177 @@
+use std::error::Error;
+use std::path::{Component, Path};
+use std::result::Result as StdResult;
+
+use chrono::NaiveDate;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::encoding;
+use crate::template::context::{self, Context, Result, TemplateValue, Value};
+
+lazy_static! {
+    static ref DIR_DATE_REGEX: Regex = Regex::new(
+        r"(?:^|[^0-9])([0-9]{4})(?:[-_.]?(0[1-9]|1[0-2])(?:[-_.]?(0[1-9]|[12][0-9]|3[01]))?)?(?:[^0-9]|$)"
+    )
+    .unwrap();
+}
+
+/// Years accepted in directory names, other 4 digits numbers are ignored.
+const YEARS: std::ops::RangeInclusive<i32> = 1800..=2199;
+
+/// Date resolved from the ancestor directory names of a file, month and day
+/// are unknown for directories such as `2019` or `2019-07 Holidays`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct DirDate {
+    year: i32,
+    month: Option<u32>,
+    day: Option<u32>,
+}
+
+impl DirDate {
+    fn date(&self) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(self.year, self.month?, self.day?)
+    }
+
+    /// Completes the date with a directory named only by a month or a day
+    /// number, as in `2019/07/14`.
+    fn complete(&mut self, number: u32) {
+        match (self.month, self.day) {
+            (None, _) if (1..=12).contains(&number) => self.month = Some(number),
+            (Some(month), None) if NaiveDate::from_ymd_opt(self.year, month, number).is_some() => {
+                self.day = Some(number)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl TemplateValue for DirDate {
+    fn render(&self, name: &str, _ctx: &Context) -> Result {
+        let value = match name {
+            "dir.date" => self
+                .date()
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap().into()),
+            "dir.date.year" => Some(Value::padded_int(self.year.into(), 4)),
+            "dir.date.month" => self.month.map(|month| Value::padded_int(month.into(), 2)),
+            "dir.date.day" => self.day.map(|day| Value::padded_int(day.into(), 2)),
+            _ => unreachable!("unexpected dir template variable, please report a bug."),
+        };
+
+        value.ok_or_else(|| context::missing_variable(name.to_owned()))
+    }
+}
+
+pub const VARIABLES: &[&str] = &[
+    "dir.date",
+    "dir.date.year",
+    "dir.date.month",
+    "dir.date.day",
+];
+
+/// Parses the first date of a directory name.
+fn name_date(name: &str) -> Option<DirDate> {
+    DIR_DATE_REGEX
+        .captures_iter(name)
+        .filter_map(|captures| {
+            let year = captures[1]
+                .parse()
+                .ok()
+                .filter(|year| YEARS.contains(year))?;
+            let mut date = DirDate {
+                year,
+                ..Default::default()
+            };
+            if let Some(month) = captures.get(2) {
+                date.complete(month.as_str().parse().unwrap());
+            }
+            if let Some(day) = captures.get(3) {
+                date.complete(day.as_str().parse().unwrap());
+            }
+            Some(date)
+        })
+        .next()
+}
+
+/// Resolves the date of the given file from its ancestor directory names.
+/// Directories are walked from the root, dates of nested directories
+/// override the dates of their parents.
+fn dir_date(path: &Path) -> Option<DirDate> {
+    let parent = path.parent()?;
+
+    let mut date: Option<DirDate> = None;
+    for component in parent.components() {
+        let name = match component {
+            Component::Normal(name) => encoding::transliterate(name),
+            _ => continue,
+        };
+
+        if let Some(name_date) = name_date(&name) {
+            date = Some(name_date);
+        } else if let Some(date) = date.as_mut() {
+            let number = name.parse::<u32>().ok().filter(|_| name.len() <= 2);
+            if let Some(number) = number {
+                date.complete(number);
+            }
+        }
+    }
+
+    date
+}
+
+pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+    if let Some(date) = dir_date(&ctx.file_path()?) {
+        ctx.insert(VARIABLES, Box::new(date));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::DirDate;
+
+    #[test]
+    fn dir_date() {
+        let date = |path: &str| super::dir_date(Path::new(path));
+        let ymd = |year, month, day| DirDate { year, month, day };
+
+        assert_eq!(
+            date("/photos/2019-07-14 Beach/img.jpg"),
+            Some(ymd(2019, Some(7), Some(14)))
+        );
+        assert_eq!(
+            date("/photos/2019/07/14/img.jpg"),
+            Some(ymd(2019, Some(7), Some(14)))
+        );
+        assert_eq!(
+            date("/photos/2019/Holidays/img.jpg"),
+            Some(ymd(2019, None, None))
+        );
+        assert_eq!(
+            date("/photos/2019_07 Holidays/raw/img.jpg"),
+            Some(ymd(2019, Some(7), None))
+        );
+        // nested directories are more specific than their parents.
+        assert_eq!(
+            date("/photos/2018/20190714/img.jpg"),
+            Some(ymd(2019, Some(7), Some(14)))
+        );
+        // invalid days are ignored.
+        assert_eq!(
+            date("/photos/2019/02/30/img.jpg"),
+            Some(ymd(2019, Some(2), None))
+        );
+        // file names and numbers that aren't years are ignored.
+        assert_eq!(date("/photos/IMG_12345/2019-07-14.jpg"), None);
+        assert_eq!(date("/photos/0042/img.jpg"), None);
+
+        assert_eq!(
+            date("/photos/2019/07/14/img.jpg").unwrap().date(),
+            chrono::NaiveDate::from_ymd_opt(2019, 7, 14)
+        );
+        assert_eq!(date("/photos/2019/07/img.jpg").unwrap().date(), None);
+    }
+}
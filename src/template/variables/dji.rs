@@ -0,0 +1,164 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::result::Result as StdResult;
+
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+
+use crate::template::context::{self, Context, Result, TemplateValue, Value};
+
+/// Number of bytes searched for the XMP packet, DJI stores it right after
+/// the exif data.
+const XMP_SEARCH_LEN: u64 = 256 * 1024;
+
+lazy_static! {
+    static ref XMP_REGEX: Regex = Regex::new(r"(?s)<x:xmpmeta.*?</x:xmpmeta>").unwrap();
+}
+
+/// Metadata written by DJI drones in the XMP packet of pictures.
+#[derive(Default)]
+struct DjiTemplateValue {
+    date: Option<NaiveDateTime>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    altitude: Option<f64>,
+}
+
+impl TemplateValue for DjiTemplateValue {
+    fn render(&self, name: &str, _ctx: &Context) -> Result {
+        let value = match name {
+            "dji.date" => self.date.map(Value::from),
+            "dji.date.year" => self
+                .date
+                .map(|date| Value::padded_int(date.year().into(), 4)),
+            "dji.date.month" => self
+                .date
+                .map(|date| Value::padded_int(date.month().into(), 2)),
+            "dji.date.day" => self
+                .date
+                .map(|date| Value::padded_int(date.day().into(), 2)),
+            "dji.date.hour" => self
+                .date
+                .map(|date| Value::padded_int(date.hour().into(), 2)),
+            "dji.date.minute" => self
+                .date
+                .map(|date| Value::padded_int(date.minute().into(), 2)),
+            "dji.date.second" => self
+                .date
+                .map(|date| Value::padded_int(date.second().into(), 2)),
+            "dji.gps.latitude" => self.latitude.map(|v| v.to_string().into()),
+            "dji.gps.longitude" => self.longitude.map(|v| v.to_string().into()),
+            "dji.gps.altitude" => self.altitude.map(|v| v.to_string().into()),
+            _ => unreachable!("unexpected dji template variable, please report a bug."),
+        };
+
+        value.ok_or_else(|| context::missing_variable(name.to_owned()))
+    }
+}
+
+pub const VARIABLES: &[&str] = &[
+    "dji.date",
+    "dji.date.year",
+    "dji.date.month",
+    "dji.date.day",
+    "dji.date.hour",
+    "dji.date.minute",
+    "dji.date.second",
+    "dji.gps.latitude",
+    "dji.gps.longitude",
+    "dji.gps.altitude",
+];
+
+/// Returns the value of the given XMP property, stored either as an
+/// attribute (`name="value"`) or as an element (`<name>value</name>`).
+fn property<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    for (prefix, end) in [(format!("{}=\"", name), '"'), (format!("<{}>", name), '<')] {
+        if let Some(start) = xmp.find(&prefix) {
+            let value = &xmp[start + prefix.len()..];
+            return value.find(end).map(|end| value[..end].trim());
+        }
+    }
+
+    None
+}
+
+/// Parses the DJI metadata of an XMP packet, none is returned if it wasn't
+/// written by a DJI drone.
+fn parse_xmp(xmp: &str) -> Option<DjiTemplateValue> {
+    if !xmp.contains("drone-dji:") {
+        return None;
+    }
+
+    let number = |name| property(xmp, name)?.parse::<f64>().ok();
+    // dates are local time, fractional seconds and offset are ignored.
+    let date = property(xmp, "xmp:CreateDate")
+        .and_then(|date| date.get(..19))
+        .and_then(|date| NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S").ok());
+
+    Some(DjiTemplateValue {
+        date,
+        latitude: number("drone-dji:GpsLatitude"),
+        longitude: number("drone-dji:GpsLongitude"),
+        altitude: number("drone-dji:AbsoluteAltitude"),
+    })
+}
+
+pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+    let filepath = ctx.file_path()?;
+
+    let mut file = File::open(filepath)?;
+    let mut head = Vec::new();
+    (&mut file).take(4).read_to_end(&mut head)?;
+
+    // only JPEG and TIFF based (DNG) pictures are searched.
+    if !(head.starts_with(b"\xff\xd8") || head.starts_with(b"II*\0") || head.starts_with(b"MM\0*"))
+    {
+        return Ok(());
+    }
+    file.take(XMP_SEARCH_LEN - 4).read_to_end(&mut head)?;
+
+    let value = XMP_REGEX
+        .find(&head)
+        .and_then(|xmp| std::str::from_utf8(xmp.as_bytes()).ok())
+        .and_then(parse_xmp);
+    if let Some(value) = value {
+        ctx.insert(VARIABLES, Box::new(value));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parse_xmp() {
+        let xmp = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="DJI Meta Data"
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:drone-dji="http://www.dji.com/drone-dji/1.0/"
+   xmp:CreateDate="2023-07-14T18:42:07.52"
+   drone-dji:GpsLatitude="+45.832622"
+   drone-dji:GpsLongitude="6.865175"
+   drone-dji:AbsoluteAltitude="+4808.47">
+   <drone-dji:RelativeAltitude>+120.30</drone-dji:RelativeAltitude>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+        let value = super::parse_xmp(xmp).unwrap();
+        assert_eq!(value.date.unwrap().to_string(), "2023-07-14 18:42:07");
+        assert_eq!(value.latitude, Some(45.832622));
+        assert_eq!(value.longitude, Some(6.865175));
+        assert_eq!(value.altitude, Some(4808.47));
+        assert_eq!(
+            super::property(xmp, "drone-dji:RelativeAltitude"),
+            Some("+120.30")
+        );
+
+        // XMP packets of other vendors are ignored.
+        assert!(super::parse_xmp(r#"<x:xmpmeta xmp:CreateDate="2023-07-14T18:42:07"/>"#).is_none());
+    }
+}
@@ -0,0 +1,12 @@
+use std::error::Error;
+use std::result::Result as StdResult;
+
+use crate::template::context::Context;
+
+pub const VARIABLES: &[&str] = &["date.event"];
+
+/// `date.event` depends on dates of other files, the sorter adds it (see
+/// [`crate::event`]).
+pub fn prepare_template_context(_ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+    Ok(())
+}
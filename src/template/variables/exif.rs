@@ -1,11 +1,20 @@
+use std::collections::HashMap;
 use std::error::Error;
-use std::path::PathBuf;
+use std::io::{Read, Seek, SeekFrom};
 use std::result::Result as StdResult;
+use std::str::FromStr;
 
-use exif::{DateTime, Exif, In, Reader, Tag, Value};
+use chrono::{Datelike, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use exif::{
+    Context as TagContext, DateTime, Exif, Field, In, Rational, Reader, Tag, Value as ExifValue,
+};
+use lazy_static::lazy_static;
 use thiserror::Error;
 
-use crate::template::context::{Context, Result, TemplateValue};
+use crate::template::context::{self, Context, Result, TemplateValue, Value};
+use crate::thumbnail;
+
+use super::geocode;
 
 struct ExifTemplateValue {
     exif: Exif,
@@ -17,10 +26,23 @@ enum ExifError {
     MissingField(String),
 
     #[error("expected field of type \"{0}\", got \"{1:?}\"")]
-    WrongType(String, Value),
+    WrongType(String, ExifValue),
 
     #[error("failed to parse exif datetime")]
     ParseDateTime(#[from] exif::Error),
+
+    #[error("invalid exif datetime {0}")]
+    InvalidDateTime(DateTime),
+}
+
+impl ExifError {
+    /// Boxes the error, missing fields are missing values.
+    fn boxed(self) -> Box<dyn Error> {
+        match self {
+            ExifError::MissingField(_) => context::missing_value(self.to_string()),
+            err => Box::new(err),
+        }
+    }
 }
 
 impl ExifTemplateValue {
@@ -28,60 +50,425 @@ impl ExifTemplateValue {
         Self { exif }
     }
 
-    fn datetime(&self) -> StdResult<DateTime, ExifError> {
-        let ascii = match self.exif.get_field(Tag::DateTime, In::PRIMARY) {
+    fn datetime(&self, tag: Tag) -> StdResult<DateTime, ExifError> {
+        let ascii = match self.exif.get_field(tag, In::PRIMARY) {
             Some(f) => match &f.value {
-                Value::Ascii(ascii) => ascii
+                ExifValue::Ascii(ascii) => ascii
                     .iter()
                     .flatten()
                     .map(|v| v.to_owned())
                     .collect::<Vec<u8>>(),
                 &_ => return Err(ExifError::WrongType("ascii".to_owned(), f.value.to_owned())),
             },
-            None => return Err(ExifError::MissingField(Tag::DateTime.to_string())),
+            None => return Err(ExifError::MissingField(tag.to_string())),
         };
 
         Ok(DateTime::from_ascii(ascii.as_slice())?)
     }
 
-    fn date(&self) -> Result {
-        let date = self.datetime()?;
-        // RFC3339
-        Ok(format!("{:04}-{:02}-{:02}", date.year, date.month, date.day).into())
+    fn tag_datetime(&self, tag: Tag) -> StdResult<NaiveDateTime, ExifError> {
+        let date = self.datetime(tag)?;
+
+        NaiveDate::from_ymd_opt(date.year.into(), date.month.into(), date.day.into())
+            .and_then(|d| d.and_hms_opt(date.hour.into(), date.minute.into(), date.second.into()))
+            .ok_or(ExifError::InvalidDateTime(date))
+    }
+
+    /// Returns the offset of the given datetime tag (`OffsetTime*` tags), if
+    /// any.
+    fn tag_offset(&self, tag: Tag) -> Option<FixedOffset> {
+        let offset_tag = match tag {
+            Tag::DateTimeOriginal => Tag::OffsetTimeOriginal,
+            Tag::DateTimeDigitized => Tag::OffsetTimeDigitized,
+            _ => Tag::OffsetTime,
+        };
+        let offset = std::str::from_utf8(ascii(&self.exif, offset_tag)?).ok()?;
+
+        offset.trim_end_matches('\0').parse().ok()
+    }
+
+    /// Returns the first valid datetime of the tags preferred by the
+    /// `exif_date` option, the error of the preferred tag is returned if none
+    /// is valid.
+    ///
+    /// Exif dates are local camera time, returned with the offset of their
+    /// tag or the one inferred from GPS coordinates if known. They're never
+    /// converted, `date` variables convert them to the `timezone` option.
+    ///
+    /// Without GPS timestamp, the camera clock is assumed to be set to the
+    /// local time of the GPS coordinates, times around DST transitions are
+    /// resolved with the `dst_policy` option.
+    fn offset_datetime(
+        &self,
+        ctx: &Context,
+    ) -> StdResult<(NaiveDateTime, Option<FixedOffset>), Box<dyn Error>> {
+        #[cfg(feature = "gps-timezone")]
+        if ctx.options().infer_timezone {
+            if let Some(date) = timezone::gps_local_datetime(&self.exif) {
+                return Ok((date.naive_local(), Some(*date.offset())));
+            }
+        }
+
+        let [preferred, fallbacks @ ..] = ctx.options().exif_date.tags();
+        let (tag, date) = match self.tag_datetime(preferred) {
+            Ok(date) => (preferred, date),
+            Err(err) => fallbacks
+                .into_iter()
+                .find_map(|tag| Some((tag, self.tag_datetime(tag).ok()?)))
+                .ok_or_else(|| err.boxed())?,
+        };
+
+        let offset = self.tag_offset(tag);
+        #[cfg(feature = "gps-timezone")]
+        if offset.is_none() && ctx.options().infer_timezone {
+            if let Some(tz) = timezone::gps_timezone(&self.exif) {
+                let date = ctx.options().localize(&tz, &date)?.fixed_offset();
+                return Ok((date.naive_local(), Some(*date.offset())));
+            }
+        }
+
+        Ok((date, offset))
     }
 
-    fn date_year(&self) -> Result {
-        let date = self.datetime()?;
-        Ok(format!("{:04}", date.year).into())
+    fn naive_datetime(&self, ctx: &Context) -> StdResult<NaiveDateTime, Box<dyn Error>> {
+        Ok(self.offset_datetime(ctx)?.0)
     }
 
-    fn date_month(&self) -> Result {
-        let date = self.datetime()?;
-        Ok(format!("{:02}", date.month).into())
+    fn date(&self, ctx: &Context) -> Result {
+        Ok(self.naive_datetime(ctx)?.into())
     }
 
-    fn date_day(&self) -> Result {
-        let date = self.datetime()?;
-        Ok(format!("{:02}", date.day).into())
+    fn date_tag(&self, tag: Tag) -> Result {
+        Ok(self.tag_datetime(tag).map_err(ExifError::boxed)?.into())
+    }
+
+    fn date_year(&self, ctx: &Context) -> Result {
+        let date = self.naive_datetime(ctx)?;
+        Ok(Value::padded_int(date.year().into(), 4))
+    }
+
+    fn date_month(&self, ctx: &Context) -> Result {
+        let date = self.naive_datetime(ctx)?;
+        Ok(Value::padded_int(date.month().into(), 2))
+    }
+
+    fn date_day(&self, ctx: &Context) -> Result {
+        let date = self.naive_datetime(ctx)?;
+        Ok(Value::padded_int(date.day().into(), 2))
+    }
+
+    fn date_hour(&self, ctx: &Context) -> Result {
+        let date = self.naive_datetime(ctx)?;
+        Ok(Value::padded_int(date.hour().into(), 2))
+    }
+
+    fn date_minute(&self, ctx: &Context) -> Result {
+        let date = self.naive_datetime(ctx)?;
+        Ok(Value::padded_int(date.minute().into(), 2))
+    }
+
+    fn date_second(&self, ctx: &Context) -> Result {
+        let date = self.naive_datetime(ctx)?;
+        Ok(Value::padded_int(date.second().into(), 2))
+    }
+
+    /// Returns the GPS coordinate in decimal degrees, negative for south and
+    /// west.
+    fn gps(&self, name: &str, coordinate: fn(&Exif) -> Option<f64>) -> Result {
+        coordinate(&self.exif)
+            .map(|degrees| Value::String(format!("{:.6}", degrees)))
+            .ok_or_else(|| context::missing_variable(name.to_owned()))
+    }
+
+    /// Returns the country or city of the GPS coordinates.
+    fn place(&self, name: &str, place: fn(f64, f64) -> Option<&'static str>) -> Result {
+        latitude(&self.exif)
+            .zip(longitude(&self.exif))
+            .and_then(|(lat, lon)| place(lat, lon))
+            .map(|place| Value::String(place.to_owned()))
+            .ok_or_else(|| context::missing_variable(name.to_owned()))
+    }
+
+    fn rational(&self, tag: Tag) -> Option<f64> {
+        match &self.exif.get_field(tag, In::PRIMARY)?.value {
+            ExifValue::Rational(r) if r.first()?.denom != 0 => Some(r[0].to_f64()),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the given ascii tag (e.g. camera make and model).
+    fn text(&self, name: &str, tag: Tag) -> Result {
+        self.exif
+            .get_field(tag, In::PRIMARY)
+            .map(field_string)
+            .filter(|text| !text.is_empty())
+            .map(Value::String)
+            .ok_or_else(|| context::missing_variable(name.to_owned()))
+    }
+
+    fn iso(&self, name: &str) -> Result {
+        self.exif
+            .get_field(Tag::PhotographicSensitivity, In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0))
+            .map(|iso| Value::int(iso.into()))
+            .ok_or_else(|| context::missing_variable(name.to_owned()))
+    }
+
+    /// Returns the value of the given rational tag with at most one decimal
+    /// (e.g. `2.8` for f/2.8, `35` for 35 mm).
+    fn decimal(&self, name: &str, tag: Tag) -> Result {
+        self.rational(tag)
+            .map(|value| Value::String(((value * 10.0).round() / 10.0).to_string()))
+            .ok_or_else(|| context::missing_variable(name.to_owned()))
+    }
+
+    /// Returns the exposure time in seconds, fractions of a second are written
+    /// `1_250` (1/250 s) so they don't create directories.
+    fn shutter(&self, name: &str) -> Result {
+        let seconds = self
+            .rational(Tag::ExposureTime)
+            .filter(|seconds| *seconds > 0.0)
+            .ok_or_else(|| context::missing_variable(name.to_owned()))?;
+
+        let denominator = 1.0 / seconds;
+        if seconds < 1.0 && (denominator - denominator.round()).abs() < 0.05 {
+            Ok(Value::String(format!("1_{}", denominator.round())))
+        } else {
+            Ok(Value::String(((seconds * 10.0).round() / 10.0).to_string()))
+        }
+    }
+
+    /// Returns "true" if the file embeds a JPEG thumbnail, "false" otherwise.
+    fn thumbnail(&self) -> Result {
+        Ok(Value::String(
+            thumbnail::embedded(&self.exif).is_some().to_string(),
+        ))
     }
 }
 
 impl TemplateValue for ExifTemplateValue {
-    fn render(&self, name: &str, _ctx: &Context) -> Result {
+    fn render(&self, name: &str, ctx: &Context) -> Result {
         match name {
-            "exif.date" => self.date(),
-            "exif.date.year" => self.date_year(),
-            "exif.date.month" => self.date_month(),
-            "exif.date.day" => self.date_day(),
+            "exif.date" => self.date(ctx),
+            "exif.date.year" => self.date_year(ctx),
+            "exif.date.month" => self.date_month(ctx),
+            "exif.date.day" => self.date_day(ctx),
+            "exif.date.hour" => self.date_hour(ctx),
+            "exif.date.minute" => self.date_minute(ctx),
+            "exif.date.second" => self.date_second(ctx),
+            "exif.date.original" => self.date_tag(Tag::DateTimeOriginal),
+            "exif.date.digitized" => self.date_tag(Tag::DateTimeDigitized),
+            "exif.thumbnail" => self.thumbnail(),
+            "exif.make" => self.text(name, Tag::Make),
+            "exif.model" => self.text(name, Tag::Model),
+            "exif.iso" => self.iso(name),
+            "exif.aperture" => self.decimal(name, Tag::FNumber),
+            "exif.focal_length" => self.decimal(name, Tag::FocalLength),
+            "exif.shutter" => self.shutter(name),
+            "exif.gps.lat" => self.gps(name, latitude),
+            "exif.gps.lon" => self.gps(name, longitude),
+            "exif.gps.country" => self.place(name, geocode::country),
+            "exif.gps.city" => self.place(name, geocode::city),
             _ => unreachable!("unexpected exif template variable, please report a bug."),
         }
     }
+
+    fn zoned_date(&self, name: &str, ctx: &Context) -> Option<chrono::DateTime<FixedOffset>> {
+        if name != "exif.date" {
+            return None;
+        }
+
+        let (date, offset) = self.offset_datetime(ctx).ok()?;
+        offset?.from_local_datetime(&date).single()
+    }
+}
+
+pub const VARIABLES: &[&str] = &[
+    "exif.date",
+    "exif.date.year",
+    "exif.date.month",
+    "exif.date.day",
+    "exif.date.hour",
+    "exif.date.minute",
+    "exif.date.second",
+    "exif.date.original",
+    "exif.date.digitized",
+    "exif.thumbnail",
+    "exif.make",
+    "exif.model",
+    "exif.iso",
+    "exif.aperture",
+    "exif.focal_length",
+    "exif.shutter",
+    "exif.gps.lat",
+    "exif.gps.lon",
+    "exif.gps.country",
+    "exif.gps.city",
+];
+
+fn rationals(exif: &Exif, tag: Tag) -> Option<&[Rational]> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        ExifValue::Rational(r) if r.len() == 3 => Some(r),
+        _ => None,
+    }
+}
+
+fn ascii(exif: &Exif, tag: Tag) -> Option<&[u8]> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        ExifValue::Ascii(ascii) => ascii.first().map(|v| v.as_slice()),
+        _ => None,
+    }
+}
+
+/// Returns the coordinate in decimal degrees, negative if the reference
+/// is `negative_ref` (south or west).
+fn coordinate(exif: &Exif, tag: Tag, ref_tag: Tag, negative_ref: u8) -> Option<f64> {
+    let dms = rationals(exif, tag)?;
+    let degrees = to_degrees(dms)?;
+
+    match ascii(exif, ref_tag)?.first() {
+        Some(r) if *r == negative_ref => Some(-degrees),
+        Some(_) => Some(degrees),
+        None => None,
+    }
+}
+
+fn latitude(exif: &Exif) -> Option<f64> {
+    coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S')
+}
+
+fn longitude(exif: &Exif) -> Option<f64> {
+    coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W')
+}
+
+/// Converts degrees, minutes and seconds to decimal degrees.
+fn to_degrees(dms: &[Rational]) -> Option<f64> {
+    if dms.iter().any(|r| r.denom == 0) {
+        return None;
+    }
+
+    Some(dms[0].to_f64() + dms[1].to_f64() / 60.0 + dms[2].to_f64() / 3600.0)
+}
+
+/// Groups of [`ExifTag`] names, after exif-py naming.
+const TAG_GROUPS: &[(&str, TagContext, In)] = &[
+    ("Image", TagContext::Tiff, In::PRIMARY),
+    ("Exif", TagContext::Exif, In::PRIMARY),
+    ("GPS", TagContext::Gps, In::PRIMARY),
+    ("Interop", TagContext::Interop, In::PRIMARY),
+    ("Thumbnail", TagContext::Tiff, In::THUMBNAIL),
+];
+
+lazy_static! {
+    /// Numbers of known tags by context and name.
+    static ref TAG_NUMBERS: HashMap<(TagContext, String), u16> = TAG_GROUPS
+        .iter()
+        .take(4)
+        .flat_map(|(_, context, _)| (0..=u16::MAX).map(|number| Tag(*context, number)))
+        .filter_map(|tag| {
+            let name = tag.to_string();
+            // unknown tags are displayed as "Tag(Tiff, 42)".
+            (!name.starts_with("Tag(")).then(|| ((tag.context(), name), tag.number()))
+        })
+        .collect();
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ExifTagError {
+    #[error("invalid exif tag {0:?}, expected <group>.<name> (e.g. Image.Artist)")]
+    InvalidFormat(String),
+    #[error("unknown exif tag group {0:?}, expected Image, Exif, GPS, Interop or Thumbnail")]
+    UnknownGroup(String),
+    #[error("unknown exif tag {0:?}")]
+    UnknownTag(String),
+}
+
+/// ExifTag is an exif tag mapped to a user defined variable, it is named
+/// after its group and name (`Image.Artist`) or number (`Exif.0xa431`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExifTag {
+    tag: Tag,
+    ifd: In,
+}
+
+impl FromStr for ExifTag {
+    type Err = ExifTagError;
+
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        let (group, name) = s
+            .split_once('.')
+            .ok_or_else(|| ExifTagError::InvalidFormat(s.to_owned()))?;
+        let (_, context, ifd) = TAG_GROUPS
+            .iter()
+            .find(|(g, _, _)| *g == group)
+            .ok_or_else(|| ExifTagError::UnknownGroup(group.to_owned()))?;
+
+        let number = match name.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16).ok(),
+            None => TAG_NUMBERS.get(&(*context, name.to_owned())).copied(),
+        };
+
+        match number {
+            Some(number) => Ok(Self {
+                tag: Tag(*context, number),
+                ifd: *ifd,
+            }),
+            None => Err(ExifTagError::UnknownTag(s.to_owned())),
+        }
+    }
+}
+
+/// Renders the value of the given field as a string, ascii values are
+/// rendered without quotes.
+fn field_string(field: &Field) -> String {
+    match &field.value {
+        ExifValue::Ascii(ascii) => ascii
+            .iter()
+            .map(|v| String::from_utf8_lossy(v).trim().to_owned())
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => field.display_value().to_string(),
+    }
+}
+
+/// Adds the variables of the `[exif_tags]` config table present in the given
+/// exif data.
+fn insert_tag_variables(ctx: &mut Context, exif: &Exif) {
+    let values: Vec<_> = ctx
+        .options()
+        .exif_tags
+        .iter()
+        .filter_map(|(variable, tag)| {
+            let field = exif.get_field(tag.tag, tag.ifd)?;
+            Some((variable.clone(), field_string(field)))
+        })
+        .collect();
+
+    for (variable, value) in values {
+        ctx.insert(&[&variable], Box::new(Value::String(value)));
+    }
+}
+
+/// Reads exif data of camera RAW files that aren't TIFF files (e.g. CR3).
+#[cfg(feature = "raw")]
+fn raw_exif<R: Read + Seek>(
+    ctx: &Context,
+    reader: &mut R,
+) -> StdResult<Option<Exif>, Box<dyn Error>> {
+    Ok(super::raw::read_exif(reader, ctx.movie()?)?)
+}
+
+#[cfg(not(feature = "raw"))]
+fn raw_exif<R: Read + Seek>(
+    _ctx: &Context,
+    _reader: &mut R,
+) -> StdResult<Option<Exif>, Box<dyn Error>> {
+    Ok(None)
 }
 
 pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
     // get filepath private variables
-    let filepath = ctx.get(":file.path").unwrap().render("", ctx)?;
-    let filepath = PathBuf::from(filepath);
+    let filepath = ctx.file_path()?;
 
     let file = std::fs::File::open(filepath)?;
     let mut reader = std::io::BufReader::new(&file);
@@ -90,20 +477,510 @@ pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Erro
         Ok(exif) => exif,
         Err(err) => match err {
             exif::Error::Io(err) => return Err(Box::new(err)),
-            _ => return Ok(()),
+            _ => match raw_exif(ctx, &mut reader)? {
+                Some(exif) => exif,
+                None => {
+                    reader.seek(SeekFrom::Start(0))?;
+                    if let Some(date) = salvage::datetime(&mut reader)? {
+                        ctx.insert(&VARIABLES[..7], Box::new(salvage::SalvagedDate(date)));
+                    }
+                    return Ok(());
+                }
+            },
         },
     };
+    insert_tag_variables(ctx, &exif);
     let template_value = Box::new(ExifTemplateValue::new(exif));
 
-    ctx.insert(
-        &[
-            "exif.date",
-            "exif.date.year",
-            "exif.date.month",
-            "exif.date.day",
-        ],
-        template_value,
-    );
+    ctx.insert(VARIABLES, template_value);
 
     Ok(())
 }
+
+/// Date salvaging of damaged exif data.
+///
+/// Exif data corrupted in the middle of an IFD can't be parsed at all, dates
+/// are then searched in the raw bytes of the file header. Exif datetimes are
+/// NUL terminated ASCII strings (`2023:07:14 18:42:07`), the earliest one is
+/// used as DateTimeOriginal is never later than DateTime (modification date).
+mod salvage {
+    use std::io::{self, Read};
+
+    use chrono::{Datelike, NaiveDateTime, Timelike};
+    use lazy_static::lazy_static;
+    use regex::bytes::Regex;
+
+    use crate::template::context::{Context, Result, TemplateValue, Value};
+
+    /// Number of bytes searched for dates, exif data of JPEG files is limited
+    /// to a 64 KiB segment.
+    const SEARCH_LEN: u64 = 128 * 1024;
+
+    lazy_static! {
+        static ref DATETIME_REGEX: Regex =
+            Regex::new(r"[0-9]{4}:[0-9]{2}:[0-9]{2} [0-9]{2}:[0-9]{2}:[0-9]{2}\x00").unwrap();
+    }
+
+    /// Date salvaged from damaged exif data, only `exif.date` variables are
+    /// rendered.
+    pub struct SalvagedDate(pub NaiveDateTime);
+
+    impl TemplateValue for SalvagedDate {
+        fn render(&self, name: &str, _ctx: &Context) -> Result {
+            let date = self.0;
+            match name {
+                "exif.date" => Ok(date.into()),
+                "exif.date.year" => Ok(Value::padded_int(date.year().into(), 4)),
+                "exif.date.month" => Ok(Value::padded_int(date.month().into(), 2)),
+                "exif.date.day" => Ok(Value::padded_int(date.day().into(), 2)),
+                "exif.date.hour" => Ok(Value::padded_int(date.hour().into(), 2)),
+                "exif.date.minute" => Ok(Value::padded_int(date.minute().into(), 2)),
+                "exif.date.second" => Ok(Value::padded_int(date.second().into(), 2)),
+                _ => unreachable!("unexpected exif template variable, please report a bug."),
+            }
+        }
+    }
+
+    /// Returns the earliest exif datetime found in the header of the given
+    /// file, none is returned if the file has no exif header.
+    pub fn datetime<R: Read>(reader: R) -> io::Result<Option<NaiveDateTime>> {
+        let mut head = Vec::new();
+        reader.take(SEARCH_LEN).read_to_end(&mut head)?;
+
+        let is_tiff = head.starts_with(b"II*\0") || head.starts_with(b"MM\0*");
+        let has_exif = head.windows(6).any(|window| window == b"Exif\0\0");
+        if !(is_tiff || has_exif) {
+            return Ok(None);
+        }
+
+        Ok(DATETIME_REGEX
+            .find_iter(&head)
+            .filter_map(|m| std::str::from_utf8(&m.as_bytes()[..19]).ok())
+            .filter_map(|date| NaiveDateTime::parse_from_str(date, "%Y:%m:%d %H:%M:%S").ok())
+            .min())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        #[test]
+        fn datetime() {
+            let mut jpeg = b"\xff\xd8\xff\xe1\x00\x40Exif\x00\x00MM\x00*\xde\xad".to_vec();
+            jpeg.extend_from_slice(b"2023:07:14 18:45:00\x002023:07:14 18:42:07\x00");
+            // truncated and invalid dates are ignored.
+            jpeg.extend_from_slice(b"2023:07:01 10:00:002023:13:01 10:00:00\x00");
+            let date = super::datetime(&jpeg[..]).unwrap().unwrap();
+            assert_eq!(date.to_string(), "2023-07-14 18:42:07");
+
+            let text = b"2023:07:14 18:42:07\x00";
+            assert_eq!(super::datetime(&text[..]).unwrap(), None);
+        }
+    }
+}
+
+/// Timezone inference from GPS coordinates.
+///
+/// Exif DateTime is the camera clock which is often left to the home timezone
+/// while travelling. GPS timestamp is in UTC, when the exif doesn't contain an
+/// offset, it is converted to the timezone of the GPS coordinates.
+#[cfg(feature = "gps-timezone")]
+mod timezone {
+    use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+    use exif::{Exif, In, Tag};
+
+    use super::{ascii, geocode, latitude, longitude, rationals};
+
+    /// Returns the GPS date converted to local time of the GPS coordinates.
+    pub fn gps_local_datetime(exif: &Exif) -> Option<DateTime<FixedOffset>> {
+        let has_offset = [Tag::OffsetTimeOriginal, Tag::OffsetTime]
+            .iter()
+            .any(|tag| exif.get_field(*tag, In::PRIMARY).is_some());
+        if has_offset {
+            return None;
+        }
+
+        let (lat, lon) = (latitude(exif)?, longitude(exif)?);
+
+        local_datetime(lat, lon, gps_utc_datetime(exif)?)
+    }
+
+    /// Returns the timezone of the GPS coordinates.
+    pub fn gps_timezone(exif: &Exif) -> Option<chrono_tz::Tz> {
+        geocode::timezone_name(latitude(exif)?, longitude(exif)?)
+            .parse()
+            .ok()
+    }
+
+    /// Converts the given UTC datetime to local time at the given coordinates.
+    pub fn local_datetime(lat: f64, lon: f64, utc: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+        let tz: chrono_tz::Tz = geocode::timezone_name(lat, lon).parse().ok()?;
+        Some(tz.from_utc_datetime(&utc).fixed_offset())
+    }
+
+    fn gps_utc_datetime(exif: &Exif) -> Option<NaiveDateTime> {
+        let date = std::str::from_utf8(ascii(exif, Tag::GPSDateStamp)?).ok()?;
+        let date = NaiveDate::parse_from_str(date, "%Y:%m:%d").ok()?;
+
+        let hms = rationals(exif, Tag::GPSTimeStamp)?;
+        if hms.iter().any(|r| r.denom == 0) {
+            return None;
+        }
+        let time = NaiveTime::from_hms_opt(
+            hms[0].to_f64() as u32,
+            hms[1].to_f64() as u32,
+            hms[2].to_f64() as u32,
+        )?;
+
+        Some(date.and_time(time))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use chrono::NaiveDate;
+
+        #[test]
+        fn local_datetime() {
+            let utc = NaiveDate::from_ymd_opt(2023, 7, 14)
+                .unwrap()
+                .and_hms_opt(23, 30, 0)
+                .unwrap();
+
+            // Paris, UTC+2 in summer.
+            let local = super::local_datetime(48.8584, 2.2945, utc).unwrap();
+            assert_eq!(local.to_string(), "2023-07-15 01:30:00 +02:00");
+
+            // New York, UTC-4 in summer.
+            let local = super::local_datetime(40.6892, -74.0445, utc).unwrap();
+            assert_eq!(local.to_string(), "2023-07-14 19:30:00 -04:00");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use exif::{Field, In, Rational, Reader, Tag, Value};
+
+    use chrono::{Duration, NaiveDate};
+
+    use super::{ExifTag, ExifTagError, ExifTemplateValue};
+    use crate::template::context::{self, Context, DateTimezone, ExifDate, Options, TemplateValue};
+
+    fn exif(fields: &[Field]) -> exif::Exif {
+        let mut writer = exif::experimental::Writer::new();
+        for field in fields {
+            writer.push_field(field);
+        }
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+
+        Reader::new().read_raw(tiff.into_inner()).unwrap()
+    }
+
+    fn field(tag: Tag, value: Value) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value,
+        }
+    }
+
+    #[test]
+    fn to_degrees() {
+        let dms = [
+            Rational::from((48, 1)),
+            Rational::from((51, 1)),
+            Rational::from((2430, 100)),
+        ];
+        let degrees = super::to_degrees(&dms).unwrap();
+        assert!((degrees - 48.85675).abs() < 1e-6);
+
+        let dms = [Rational::from((48, 0)); 3];
+        assert_eq!(super::to_degrees(&dms), None);
+    }
+
+    #[test]
+    fn date_offset() {
+        let ascii = |s: &str| Value::Ascii(vec![s.as_bytes().to_vec()]);
+        let exif = || {
+            exif(&[
+                field(Tag::DateTimeOriginal, ascii("2023:07:14 18:42:07")),
+                field(Tag::OffsetTimeOriginal, ascii("+09:00")),
+            ])
+        };
+        let render = |timezone, name: &str| {
+            let options = Options {
+                timezone,
+                ..Default::default()
+            };
+            let mut ctx = Context::new(Arc::new(options));
+            ctx.insert(super::VARIABLES, Box::new(ExifTemplateValue::new(exif())));
+            crate::template::variables::date::prepare_template_context(&mut ctx).unwrap();
+            let value = ctx.get(name).unwrap();
+            (
+                value.render(name, &ctx).unwrap(),
+                value.zoned_date(name, &ctx),
+            )
+        };
+
+        let date = NaiveDate::from_ymd_opt(2023, 7, 14)
+            .unwrap()
+            .and_hms_opt(18, 42, 7)
+            .unwrap();
+        let (value, zoned) = render(None, "exif.date");
+        assert_eq!(value.as_date(), Some(date));
+        assert_eq!(zoned.unwrap().to_rfc3339(), "2023-07-14T18:42:07+09:00");
+
+        // exif variables are camera time, date variables are converted.
+        let utc = Some(DateTimezone::Utc);
+        let (value, _) = render(utc, "exif.date");
+        assert_eq!(value.as_date(), Some(date));
+        let (value, _) = render(utc, "date");
+        assert_eq!(value.as_date(), Some(date - Duration::hours(9)));
+    }
+
+    #[cfg(feature = "gps-timezone")]
+    #[test]
+    fn dst_policy() {
+        use crate::template::context::DstPolicy;
+
+        let ascii = |s: &str| Value::Ascii(vec![s.as_bytes().to_vec()]);
+        let dms = |d, m, s| {
+            Value::Rational(vec![
+                Rational::from((d, 1)),
+                Rational::from((m, 1)),
+                Rational::from((s, 100)),
+            ])
+        };
+        // Paris, without GPS timestamp.
+        let render = |dst_policy, date: &str| {
+            let options = Options {
+                infer_timezone: true,
+                dst_policy,
+                ..Default::default()
+            };
+            let ctx = Context::new(Arc::new(options));
+            let value = ExifTemplateValue::new(exif(&[
+                field(Tag::DateTimeOriginal, ascii(date)),
+                field(Tag::GPSLatitudeRef, ascii("N")),
+                field(Tag::GPSLatitude, dms(48, 51, 3024)),
+                field(Tag::GPSLongitudeRef, ascii("E")),
+                field(Tag::GPSLongitude, dms(2, 17, 4020)),
+            ]));
+            value
+                .offset_datetime(&ctx)
+                .map(|(date, offset)| format!("{} {}", date, offset.unwrap()))
+                .map_err(|err| err.to_string())
+        };
+
+        assert_eq!(
+            render(DstPolicy::Earliest, "2023:07:14 18:42:07").unwrap(),
+            "2023-07-14 18:42:07 +02:00"
+        );
+        // clocks turned back from 03:00 to 02:00.
+        let ambiguous = "2023:10:29 02:30:00";
+        assert_eq!(
+            render(DstPolicy::Earliest, ambiguous).unwrap(),
+            "2023-10-29 02:30:00 +02:00"
+        );
+        assert_eq!(
+            render(DstPolicy::Latest, ambiguous).unwrap(),
+            "2023-10-29 02:30:00 +01:00"
+        );
+        assert!(render(DstPolicy::Error, ambiguous).is_err());
+        // clocks turned forward from 02:00 to 03:00.
+        let nonexistent = "2023:03:26 02:30:00";
+        assert_eq!(
+            render(DstPolicy::Earliest, nonexistent).unwrap(),
+            "2023-03-26 03:30:00 +02:00"
+        );
+        assert!(render(DstPolicy::Error, nonexistent).is_err());
+    }
+
+    #[test]
+    fn date_preference() {
+        let ascii = |s: &str| Value::Ascii(vec![s.as_bytes().to_vec()]);
+        let dates = || {
+            exif(&[
+                field(Tag::DateTime, ascii("2023:08:01 09:00:00")),
+                field(Tag::DateTimeOriginal, ascii("2023:07:14 18:42:07")),
+            ])
+        };
+        let render = |exif_date, name| {
+            let options = Options {
+                exif_date,
+                ..Default::default()
+            };
+            let ctx = Context::new(Arc::new(options));
+            let value = ExifTemplateValue::new(dates());
+            value.render(name, &ctx).map(|v| v.to_string())
+        };
+
+        assert_eq!(
+            render(ExifDate::Original, "exif.date").unwrap(),
+            "2023-07-14"
+        );
+        assert_eq!(
+            render(ExifDate::Modified, "exif.date").unwrap(),
+            "2023-08-01"
+        );
+        assert_eq!(
+            render(ExifDate::Modified, "exif.date.original").unwrap(),
+            "2023-07-14"
+        );
+        assert!(render(ExifDate::Original, "exif.date.digitized").is_err());
+
+        // missing preferred tag fallbacks to the others.
+        let value =
+            ExifTemplateValue::new(exif(&[field(Tag::DateTime, ascii("2023:08:01 09:00:00"))]));
+        let date = value.render("exif.date.day", &Context::default()).unwrap();
+        assert_eq!(date.to_string(), "01");
+    }
+
+    #[test]
+    fn time_of_day() {
+        let ascii = |s: &str| Value::Ascii(vec![s.as_bytes().to_vec()]);
+        let value = ExifTemplateValue::new(exif(&[field(
+            Tag::DateTimeOriginal,
+            ascii("2023:07:14 08:04:09"),
+        )]));
+        let mut ctx = Context::default();
+        ctx.insert(&super::VARIABLES[..7], Box::new(value));
+        super::super::date::prepare_template_context(&mut ctx).unwrap();
+
+        let render = |name: &str| ctx.get(name).unwrap().render(name, &ctx).unwrap();
+        assert_eq!(render("exif.date.hour").to_string(), "08");
+        assert_eq!(render("date.hour").to_string(), "08");
+        assert_eq!(render("date.minute").to_string(), "04");
+        assert_eq!(render("date.second").to_string(), "09");
+    }
+
+    #[test]
+    fn exposure() {
+        let rational = |num, denom| Value::Rational(vec![Rational::from((num, denom))]);
+        let value = ExifTemplateValue::new(exif(&[
+            field(Tag::PhotographicSensitivity, Value::Short(vec![400])),
+            field(Tag::FNumber, rational(28, 10)),
+            field(Tag::FocalLength, rational(350, 10)),
+            field(Tag::ExposureTime, rational(10, 2500)),
+        ]));
+        let ctx = Context::default();
+
+        let render = |name| value.render(name, &ctx).unwrap().to_string();
+        assert_eq!(render("exif.iso"), "400");
+        assert_eq!(render("exif.aperture"), "2.8");
+        assert_eq!(render("exif.focal_length"), "35");
+        assert_eq!(render("exif.shutter"), "1_250");
+
+        let shutter = |num, denom| {
+            let value =
+                ExifTemplateValue::new(exif(&[field(Tag::ExposureTime, rational(num, denom))]));
+            value.render("exif.shutter", &ctx).unwrap().to_string()
+        };
+        assert_eq!(shutter(5, 2), "2.5");
+        assert_eq!(shutter(3, 10), "0.3");
+    }
+
+    #[test]
+    fn camera() {
+        let ascii = |s: &str| Value::Ascii(vec![s.as_bytes().to_vec()]);
+        let value = ExifTemplateValue::new(exif(&[
+            field(Tag::Make, ascii("NIKON CORPORATION")),
+            field(Tag::Model, ascii("NIKON Z 6 ")),
+        ]));
+        let ctx = Context::default();
+
+        let render = |name| value.render(name, &ctx).unwrap().to_string();
+        assert_eq!(render("exif.make"), "NIKON CORPORATION");
+        assert_eq!(render("exif.model"), "NIKON Z 6");
+    }
+
+    #[test]
+    fn gps() {
+        let dms = |d, m, s| {
+            Value::Rational(vec![
+                Rational::from((d, 1)),
+                Rational::from((m, 1)),
+                Rational::from((s, 100)),
+            ])
+        };
+        let value = ExifTemplateValue::new(exif(&[
+            field(Tag::GPSLatitudeRef, Value::Ascii(vec![b"S".to_vec()])),
+            field(Tag::GPSLatitude, dms(33, 51, 3456)),
+            field(Tag::GPSLongitudeRef, Value::Ascii(vec![b"E".to_vec()])),
+            field(Tag::GPSLongitude, dms(151, 12, 5400)),
+        ]));
+        let ctx = Context::default();
+
+        let render = |name| value.render(name, &ctx).unwrap().to_string();
+        assert_eq!(render("exif.gps.lat"), "-33.859600");
+        assert_eq!(render("exif.gps.lon"), "151.215000");
+        assert_eq!(render("exif.gps.country"), "Australia");
+        assert_eq!(render("exif.gps.city"), "Sydney");
+
+        // coordinates without reference are ignored.
+        let value = ExifTemplateValue::new(exif(&[field(Tag::GPSLatitude, dms(33, 51, 3456))]));
+        assert!(value.render("exif.gps.lat", &ctx).is_err());
+    }
+
+    #[test]
+    fn exif_tag() {
+        let tag = |s: &str| s.parse::<ExifTag>();
+
+        assert_eq!(
+            tag("Image.Artist").unwrap(),
+            ExifTag {
+                tag: Tag::Artist,
+                ifd: In::PRIMARY
+            }
+        );
+        assert_eq!(tag("Exif.0xa431").unwrap().tag, Tag::BodySerialNumber);
+        assert_eq!(tag("Thumbnail.Artist").unwrap().ifd, In::THUMBNAIL);
+        assert_eq!(
+            tag("Artist").unwrap_err(),
+            ExifTagError::InvalidFormat("Artist".to_owned())
+        );
+        assert_eq!(
+            tag("Maker.Artist").unwrap_err(),
+            ExifTagError::UnknownGroup("Maker".to_owned())
+        );
+        // Artist is a TIFF tag.
+        assert_eq!(
+            tag("Exif.Artist").unwrap_err(),
+            ExifTagError::UnknownTag("Exif.Artist".to_owned())
+        );
+    }
+
+    #[test]
+    fn tag_variables() {
+        let exif = exif(&[
+            field(Tag::Artist, Value::Ascii(vec![b"Jane Doe".to_vec()])),
+            field(Tag::ISOSpeed, Value::Long(vec![400])),
+        ]);
+
+        let tags = HashMap::from([
+            ("Image.Artist".to_owned(), "exif.artist".to_owned()),
+            ("Exif.ISOSpeed".to_owned(), "exif.iso_speed".to_owned()),
+            ("Image.Copyright".to_owned(), "exif.copyright".to_owned()),
+        ]);
+        let options = Options {
+            exif_tags: context::parse_exif_tags(tags).unwrap(),
+            ..Default::default()
+        };
+        let mut ctx = Context::new(Arc::new(options));
+        super::insert_tag_variables(&mut ctx, &exif);
+
+        let render = |name: &str| ctx.get(name).unwrap().render(name, &ctx).unwrap();
+        assert_eq!(render("exif.artist").to_string(), "Jane Doe");
+        assert_eq!(render("exif.iso_speed").to_string(), "400");
+        assert!(ctx.get("exif.copyright").is_none());
+
+        let tags = HashMap::from([("Image.Artist".to_owned(), "exif.date".to_owned())]);
+        assert!(matches!(
+            context::parse_exif_tags(tags),
+            Err(context::ExifTagsError::Collision(_))
+        ));
+    }
+}
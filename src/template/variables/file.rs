@@ -1,13 +1,15 @@
 use std::error::Error;
-use std::path::PathBuf;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use std::result;
 
-use chrono::NaiveDate;
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
 use lazy_static::lazy_static;
 use regex::Regex;
 use thiserror::Error;
 
-use crate::template::context::{Context, Result, TemplateValue};
+use crate::encoding::{self, NonUtf8Policy};
+use crate::template::context::{self, Context, Result, TemplateValue, Value};
 
 #[derive(Default)]
 struct FileTemplateValue;
@@ -15,33 +17,96 @@ struct FileTemplateValue;
 lazy_static! {
     static ref DATE_REGEX: Regex =
         Regex::new("[0-9]{4}(-|_)?(0[1-9]|1[0-2])(-|_)?([0-2][1-9]|3[0-1])").unwrap();
+    /// Time following the date of a file name (`IMG_20230812_183422.jpg`).
+    static ref TIME_REGEX: Regex =
+        Regex::new("^( at |[-_ T.])?([01][0-9]|2[0-3])[-_.:]?([0-5][0-9])[-_.:]?([0-5][0-9])").unwrap();
+    /// Unix timestamp in seconds or milliseconds since 2001 (`1694959200123.jpg`).
+    static ref EPOCH_REGEX: Regex = Regex::new("(^|[^0-9])(1[0-9]{12}|1[0-9]{9})([^0-9]|$)").unwrap();
+    /// Naming conventions of apps and devices, in matching order.
+    static ref SOURCE_REGEXES: [(Regex, &'static str); 4] = [
+        // IMG-20230812-WA0003.jpg
+        (
+            Regex::new("^(IMG|VID|AUD|PTT|STK|DOC)-[0-9]{8}-WA[0-9]+").unwrap(),
+            "whatsapp"
+        ),
+        // photo_2023-08-12_18-34-22.jpg
+        (
+            Regex::new("^(photo|video|file)_[0-9]{4}-[0-9]{2}-[0-9]{2}_[0-9]{2}-[0-9]{2}-[0-9]{2}")
+                .unwrap(),
+            "telegram"
+        ),
+        // Screenshot_2023-08-12-18-34-22.png, Screen Shot 2023-08-12 at 18.34.22.png
+        (Regex::new("(?i)^screen ?shot").unwrap(), "screenshot"),
+        // PXL_20230812_183422.jpg, IMG_0001.JPG, DSC_0001.NEF
+        (
+            Regex::new("^(IMG|VID|PXL|MVIMG|DSC|DSCN|DSCF|GOPR|GX|DJI)_?[0-9]").unwrap(),
+            "camera"
+        ),
+    ];
+}
+
+/// Returns the app or device that named the given file, if its name follows
+/// a known convention.
+fn name_source(filename: &str) -> Option<&'static str> {
+    SOURCE_REGEXES
+        .iter()
+        .find(|(regex, _)| regex.is_match(filename))
+        .map(|(_, source)| *source)
+}
+
+/// Returns the local date of the first Unix timestamp of the given file name,
+/// timestamps before 2001 (fewer digits) or in the future are ignored.
+fn epoch_datetime(filename: &str) -> Option<NaiveDateTime> {
+    let digits = EPOCH_REGEX.captures(filename)?.get(2)?.as_str();
+    let timestamp: i64 = digits.parse().ok()?;
+    let datetime = match digits.len() {
+        13 => DateTime::from_timestamp_millis(timestamp)?,
+        _ => DateTime::from_timestamp(timestamp, 0)?,
+    };
+    if datetime > chrono::Utc::now() {
+        return None;
+    }
+
+    Some(datetime.with_timezone(&Local).naive_local())
 }
 
 #[derive(Error, Debug)]
 enum FileNameDateError {
     #[error("date not found")]
     DateNotFound,
-    #[error("not a valid UTF-8 string")]
-    NotUTF8String,
+    #[error("time not found")]
+    TimeNotFound,
     #[error("failed to parse date: {0}")]
     ParseError(#[from] chrono::ParseError),
 }
 
+impl FileNameDateError {
+    /// Boxes the error, names without date are missing values.
+    fn boxed(self) -> Box<dyn Error> {
+        match self {
+            FileNameDateError::DateNotFound | FileNameDateError::TimeNotFound => {
+                context::missing_value(self.to_string())
+            }
+            err => Box::new(err),
+        }
+    }
+}
+
 impl FileTemplateValue {
     fn filepath(&self, ctx: &Context) -> Result {
         ctx.get_or_err(":file.path")?.render("", ctx)
     }
 
     fn filepathbuf(&self, ctx: &Context) -> PathBuf {
-        PathBuf::from(self.filepath(ctx).unwrap())
+        ctx.file_path().unwrap()
     }
 
     fn filename(&self, ctx: &Context) -> Result {
         let filepath = self.filepathbuf(ctx);
 
         match filepath.file_name() {
-            Some(fname) => Ok(fname.to_owned()),
-            None => Ok("".to_owned().into()),
+            Some(fname) => Ok(name_value(fname, ctx)),
+            None => Ok("".into()),
         }
     }
 
@@ -49,9 +114,9 @@ impl FileTemplateValue {
         let filepath = self.filepathbuf(ctx);
 
         if let Some(fstem) = filepath.file_stem() {
-            Ok(fstem.to_owned())
+            Ok(name_value(fstem, ctx))
         } else {
-            Ok("".to_owned().into())
+            Ok("".into())
         }
     }
 
@@ -60,46 +125,180 @@ impl FileTemplateValue {
 
         // file extension
         if let Some(fext) = filepath.extension() {
-            Ok(fext.to_owned())
+            Ok(name_value(fext, ctx))
         } else {
-            Ok("".to_owned().into())
+            Ok("".into())
         }
     }
 
-    fn filename_naivedate(&self, ctx: &Context) -> result::Result<NaiveDate, FileNameDateError> {
-        let filename = self.filepathbuf(ctx);
-        let filename = match filename.to_str() {
-            Some(f) => f,
-            None => return Err(FileNameDateError::NotUTF8String),
-        };
+    fn file_dir(&self, ctx: &Context) -> Result {
+        let filepath = self.filepathbuf(ctx);
+        let dir = filepath.parent().unwrap_or(Path::new(""));
+        Ok(Value::Path(dir.to_owned()))
+    }
+
+    /// Returns the name of the nth ancestor directory of the file, 1 for its
+    /// parent directory.
+    fn ancestor_name(&self, ctx: &Context, name: &str, n: usize) -> Result {
+        let filepath = self.filepathbuf(ctx);
+        match filepath.ancestors().nth(n).and_then(Path::file_name) {
+            Some(dir_name) => Ok(name_value(dir_name, ctx)),
+            None => Err(context::missing_variable(name.to_owned())),
+        }
+    }
 
-        match DATE_REGEX.find(filename) {
-            Some(date_match) => {
-                let date_str = date_match.as_str().replace(&['-', '_'][..], "");
-                Ok(NaiveDate::parse_from_str(&date_str, "%Y%m%d")?)
+    /// Returns the date of the file name and the time following it, if any.
+    fn filename_timestamp(
+        &self,
+        ctx: &Context,
+    ) -> result::Result<(NaiveDate, Option<NaiveTime>), FileNameDateError> {
+        let filepath = self.filepathbuf(ctx);
+        let filename = encoding::transliterate(filepath.file_name().unwrap_or_default());
+
+        let options = ctx.options();
+        if let Some(timestamp) = options
+            .name_dates
+            .iter()
+            .find_map(|name_date| name_date.parse(&filename))
+        {
+            return Ok(timestamp);
+        }
+        if options.name_dates_only {
+            return Err(FileNameDateError::DateNotFound);
+        }
+
+        // dates win over timestamps, unless they're digits of the timestamp.
+        let epoch = EPOCH_REGEX
+            .captures(&filename)
+            .and_then(|captures| captures.get(2))
+            .map(|digits| digits.range());
+        let date_match = DATE_REGEX.find_iter(&filename).find(|date_match| {
+            epoch.as_ref().is_none_or(|epoch| {
+                date_match.end() <= epoch.start || date_match.start() >= epoch.end
+            })
+        });
+        if date_match.is_none() {
+            if let Some(datetime) = epoch_datetime(&filename) {
+                return Ok((datetime.date(), Some(datetime.time())));
             }
-            None => Err(FileNameDateError::DateNotFound),
+        }
+
+        // dates of the closest parent directory are used unless dir.date is a
+        // source of date.
+        let dirs: Vec<_> = match options.dir_date_fallback {
+            true => Vec::new(),
+            false => filepath
+                .ancestors()
+                .skip(1)
+                .filter_map(Path::file_name)
+                .map(encoding::transliterate)
+                .collect(),
+        };
+        let (haystack, date_match) = match date_match.or_else(|| DATE_REGEX.find(&filename)) {
+            Some(date_match) => (&*filename, date_match),
+            // digits of longer numbers (identifiers, counters) aren't dates.
+            None => dirs
+                .iter()
+                .find_map(|dir| {
+                    let date_match = DATE_REGEX.find_iter(dir).find(|date_match| {
+                        !dir[..date_match.start()].ends_with(|c: char| c.is_ascii_digit())
+                            && !dir[date_match.end()..].starts_with(|c: char| c.is_ascii_digit())
+                    })?;
+                    Some((&**dir, date_match))
+                })
+                .ok_or(FileNameDateError::DateNotFound)?,
+        };
+        let date_str = date_match.as_str().replace(&['-', '_'][..], "");
+        let date = NaiveDate::parse_from_str(&date_str, "%Y%m%d")?;
+
+        let time = TIME_REGEX
+            .captures(&haystack[date_match.end()..])
+            .and_then(|captures| {
+                let part = |i: usize| captures[i].parse().ok();
+                NaiveTime::from_hms_opt(part(2)?, part(3)?, part(4)?)
+            });
+
+        Ok((date, time))
+    }
+
+    fn filename_naivedate(&self, ctx: &Context) -> result::Result<NaiveDate, FileNameDateError> {
+        Ok(self.filename_timestamp(ctx)?.0)
+    }
+
+    fn filename_naivedatetime(
+        &self,
+        ctx: &Context,
+    ) -> result::Result<NaiveDateTime, FileNameDateError> {
+        match self.filename_timestamp(ctx)? {
+            (date, Some(time)) => Ok(date.and_time(time)),
+            (_, None) => Err(FileNameDateError::TimeNotFound),
         }
     }
 
+    /// Date of the file name, at midnight unless a time follows it.
     fn filename_date(&self, ctx: &Context) -> Result {
-        let date = self.filename_naivedate(ctx).map_err(Box::new)?;
-        Ok(date.format("%Y-%m-%d").to_string().into())
+        let (date, time) = self
+            .filename_timestamp(ctx)
+            .map_err(FileNameDateError::boxed)?;
+        Ok(date.and_time(time.unwrap_or_default()).into())
+    }
+
+    fn filename_datetime(&self, ctx: &Context) -> Result {
+        let datetime = self
+            .filename_naivedatetime(ctx)
+            .map_err(FileNameDateError::boxed)?;
+        Ok(datetime.into())
     }
 
     fn filename_date_year(&self, ctx: &Context) -> Result {
-        let date = self.filename_naivedate(ctx).map_err(Box::new)?;
-        Ok(date.format("%Y").to_string().into())
+        let date = self
+            .filename_naivedate(ctx)
+            .map_err(FileNameDateError::boxed)?;
+        Ok(Value::padded_int(date.year().into(), 4))
     }
 
     fn filename_date_month(&self, ctx: &Context) -> Result {
-        let date = self.filename_naivedate(ctx).map_err(Box::new)?;
-        Ok(date.format("%m").to_string().into())
+        let date = self
+            .filename_naivedate(ctx)
+            .map_err(FileNameDateError::boxed)?;
+        Ok(Value::padded_int(date.month().into(), 2))
     }
 
     fn filename_date_day(&self, ctx: &Context) -> Result {
-        let date = self.filename_naivedate(ctx).map_err(Box::new)?;
-        Ok(date.format("%d").to_string().into())
+        let date = self
+            .filename_naivedate(ctx)
+            .map_err(FileNameDateError::boxed)?;
+        Ok(Value::padded_int(date.day().into(), 2))
+    }
+
+    fn filename_date_hour(&self, ctx: &Context) -> Result {
+        let datetime = self
+            .filename_naivedatetime(ctx)
+            .map_err(FileNameDateError::boxed)?;
+        Ok(Value::padded_int(datetime.hour().into(), 2))
+    }
+
+    fn filename_date_minute(&self, ctx: &Context) -> Result {
+        let datetime = self
+            .filename_naivedatetime(ctx)
+            .map_err(FileNameDateError::boxed)?;
+        Ok(Value::padded_int(datetime.minute().into(), 2))
+    }
+
+    fn filename_date_second(&self, ctx: &Context) -> Result {
+        let datetime = self
+            .filename_naivedatetime(ctx)
+            .map_err(FileNameDateError::boxed)?;
+        Ok(Value::padded_int(datetime.second().into(), 2))
+    }
+}
+
+/// Returns the value of a name, names that aren't valid UTF-8 are kept as is
+/// unless they're transliterated.
+fn name_value(name: &OsStr, ctx: &Context) -> Value {
+    match (name.to_str(), ctx.options().non_utf8) {
+        (None, NonUtf8Policy::Transliterate) => encoding::transliterate(name).into_owned().into(),
+        _ => name.to_owned().into(),
     }
 }
 
@@ -110,114 +309,941 @@ impl TemplateValue for FileTemplateValue {
             "file.name" => self.filename(ctx),
             "file.stem" => self.filestem(ctx),
             "file.extension" => self.file_extension(ctx),
+            "file.dir" => self.file_dir(ctx),
+            "file.dir.name" => self.ancestor_name(ctx, name, 1),
+            "file.dir.parent" => self.ancestor_name(ctx, name, 2),
             "file.name.date" => self.filename_date(ctx),
             "file.name.date.year" => self.filename_date_year(ctx),
             "file.name.date.month" => self.filename_date_month(ctx),
             "file.name.date.day" => self.filename_date_day(ctx),
+            "file.name.date.hour" => self.filename_date_hour(ctx),
+            "file.name.date.minute" => self.filename_date_minute(ctx),
+            "file.name.date.second" => self.filename_date_second(ctx),
+            "file.name.datetime" => self.filename_datetime(ctx),
             _ => unreachable!("unexpected file template variable, please report a bug."),
         }
     }
 }
 
+pub const VARIABLES: &[&str] = &[
+    "file.path",
+    "file.name",
+    "file.stem",
+    "file.extension",
+    "file.dir",
+    "file.dir.name",
+    "file.dir.parent",
+    "file.name.date",
+    "file.name.date.year",
+    "file.name.date.month",
+    "file.name.date.day",
+    "file.name.date.hour",
+    "file.name.date.minute",
+    "file.name.date.second",
+    "file.name.datetime",
+];
+
 pub fn prepare_template_context(ctx: &mut Context) -> result::Result<(), Box<dyn Error>> {
-    ctx.insert(
-        &[
-            "file.path",
-            "file.name",
-            "file.stem",
-            "file.extension",
-            "file.name.date",
-            "file.name.date.year",
-            "file.name.date.month",
-            "file.name.date.day",
-        ],
-        Box::new(FileTemplateValue::default()),
-    );
-    metadata::prepare_template_context(ctx)?;
+    ctx.insert(VARIABLES, Box::new(FileTemplateValue));
+    insert_regex_captures(ctx);
 
     Ok(())
 }
 
-mod metadata {
+/// `file.name.source` is the app or device that named the file, only files
+/// named by a known app or device have it.
+pub mod name_source {
+    use std::{error::Error, result::Result as StdResult};
+
+    use crate::encoding;
+    use crate::template::context::{Context, Value};
+
+    pub const VARIABLES: &[&str] = &["file.name.source"];
+
+    pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+        let filepath = match ctx.file_path() {
+            Ok(filepath) => filepath,
+            Err(_) => return Ok(()),
+        };
+        let name = encoding::transliterate(filepath.file_name().unwrap_or_default());
+        if let Some(source) = super::name_source(&name) {
+            ctx.insert(VARIABLES, Box::new(Value::String(source.to_owned())));
+        }
+
+        Ok(())
+    }
+}
+
+/// Adds a `file.re.<name>` variable for each named group of the file name
+/// regex that matched the file name. The bytes of file names that aren't
+/// valid UTF-8 are matched, unless they're transliterated.
+fn insert_regex_captures(ctx: &mut Context) {
+    let regex = match &ctx.options().file_regex {
+        Some(regex) => regex.clone(),
+        None => return,
+    };
+    let filepath = match ctx.file_path() {
+        Ok(filepath) => filepath,
+        Err(_) => return,
+    };
+    let name = filepath.file_name().unwrap_or_default();
+
+    let values: Vec<(String, Value)> = match (name.to_str(), ctx.options().non_utf8) {
+        (None, NonUtf8Policy::Keep) => {
+            let regex = match regex::bytes::Regex::new(regex.as_str()) {
+                Ok(regex) => regex,
+                Err(_) => return,
+            };
+            let captures = match regex.captures(name.as_encoded_bytes()) {
+                Some(captures) => captures,
+                None => return,
+            };
+            regex
+                .capture_names()
+                .flatten()
+                .filter_map(|group| {
+                    let value = encoding::os_string(captures.name(group)?.as_bytes());
+                    Some((group.to_owned(), value.into()))
+                })
+                .collect()
+        }
+        _ => {
+            let name = encoding::transliterate(name);
+            let captures = match regex.captures(&name) {
+                Some(captures) => captures,
+                None => return,
+            };
+            regex
+                .capture_names()
+                .flatten()
+                .filter_map(|group| {
+                    let value = captures.name(group)?.as_str().to_owned();
+                    Some((group.to_owned(), Value::String(value)))
+                })
+                .collect()
+        }
+    };
+
+    for (group, value) in values {
+        ctx.insert(&[&format!("file.re.{}", group)], Box::new(value));
+    }
+}
+
+pub mod metadata {
+    use std::time::SystemTime;
     use std::{error::Error, fs, io, result::Result as StdResult};
 
-    use chrono::{DateTime, Local};
+    use chrono::{DateTime, Datelike, FixedOffset, Local, Timelike};
     use thiserror::Error;
 
-    use crate::template::context::{Context, Result, TemplateValue};
+    use crate::permissions;
+    use crate::template::context::{Context, Result, TemplateValue, Value};
 
     #[derive(Error, Debug)]
     enum MetadataError {
         #[error("failed to read metadata: {0}")]
         Read(#[from] io::Error),
+        #[cfg(not(unix))]
+        #[error("owners and modes are only supported on unix")]
+        Unsupported,
     }
 
     #[derive(Default)]
     struct FileMetadataTemplateValue {}
 
     impl FileMetadataTemplateValue {
-        fn creation_datetime(&self, ctx: &Context) -> StdResult<DateTime<Local>, Box<dyn Error>> {
-            let filepath = ctx.get_or_err(":file.path")?.render("", ctx)?;
+        /// Returns the given time of the file, e.g. its creation time.
+        fn datetime(
+            &self,
+            ctx: &Context,
+            time: fn(&fs::Metadata) -> io::Result<SystemTime>,
+        ) -> StdResult<DateTime<Local>, Box<dyn Error>> {
+            let filepath = ctx.file_path()?;
 
             let md = fs::metadata(filepath).map_err(|e| Box::new(MetadataError::Read(e)))?;
-            let systime = md.created()?;
+            let systime = time(&md)?;
 
             Ok(DateTime::from(systime))
         }
 
-        fn creation_date(&self, ctx: &Context) -> Result {
-            let date = self.creation_datetime(ctx)?;
-            Ok(date.format("%Y-%m-%d").to_string().into())
+        /// Renders the given part of a date, an empty part renders the whole
+        /// date.
+        fn date_part(&self, date: DateTime<Local>, part: &str) -> Value {
+            match part {
+                "" => date.naive_local().into(),
+                ".year" => Value::padded_int(date.year().into(), 4),
+                ".month" => Value::padded_int(date.month().into(), 2),
+                ".day" => Value::padded_int(date.day().into(), 2),
+                ".hour" => Value::padded_int(date.hour().into(), 2),
+                ".minute" => Value::padded_int(date.minute().into(), 2),
+                ".second" => Value::padded_int(date.second().into(), 2),
+                &_ => {
+                    unreachable!("unexpected file metadata template variable, please report a bug.")
+                }
+            }
         }
+    }
 
-        fn creation_date_year(&self, ctx: &Context) -> Result {
-            let date = self.creation_datetime(ctx)?;
-            Ok(date.format("%Y").to_string().into())
-        }
+    impl FileMetadataTemplateValue {
+        /// Renders the owner, group or permission bits of the file. Owner
+        /// and group are rendered by name, or by id if they have none.
+        #[cfg(unix)]
+        fn ownership(&self, ctx: &Context, name: &str) -> Result {
+            use std::os::unix::fs::MetadataExt;
 
-        fn creation_date_month(&self, ctx: &Context) -> Result {
-            let date = self.creation_datetime(ctx)?;
-            Ok(date.format("%m").to_string().into())
+            let md =
+                fs::metadata(ctx.file_path()?).map_err(|e| Box::new(MetadataError::Read(e)))?;
+            Ok(match name {
+                "file.md.owner" => permissions::user_name(md.uid())
+                    .unwrap_or_else(|| md.uid().to_string())
+                    .into(),
+                "file.md.group" => permissions::group_name(md.gid())
+                    .unwrap_or_else(|| md.gid().to_string())
+                    .into(),
+                _ => format!("{:04o}", md.mode() & 0o7777).into(),
+            })
         }
 
-        fn creation_date_day(&self, ctx: &Context) -> Result {
-            let date = self.creation_datetime(ctx)?;
-            Ok(date.format("%d").to_string().into())
+        #[cfg(not(unix))]
+        fn ownership(&self, _ctx: &Context, _name: &str) -> Result {
+            Err(Box::new(MetadataError::Unsupported))
         }
     }
 
     impl TemplateValue for FileMetadataTemplateValue {
         fn render(&self, name: &str, ctx: &Context) -> Result {
+            if matches!(name, "file.md.owner" | "file.md.group" | "file.md.mode") {
+                self.ownership(ctx, name)
+            } else if let Some(part) = name.strip_prefix("file.md.creation_date") {
+                let date = self.datetime(ctx, fs::Metadata::created)?;
+                Ok(self.date_part(date, part))
+            } else if let Some(part) = name.strip_prefix("file.md.modification_date") {
+                let date = self.datetime(ctx, fs::Metadata::modified)?;
+                Ok(self.date_part(date, part))
+            } else {
+                unreachable!("unexpected file metadata template variable, please report a bug.")
+            }
+        }
+
+        fn zoned_date(&self, name: &str, ctx: &Context) -> Option<DateTime<FixedOffset>> {
+            let time = match name {
+                "file.md.creation_date" => fs::Metadata::created,
+                "file.md.modification_date" => fs::Metadata::modified,
+                _ => return None,
+            };
+            Some(self.datetime(ctx, time).ok()?.fixed_offset())
+        }
+    }
+
+    pub const VARIABLES: &[&str] = &[
+        "file.md.creation_date",
+        "file.md.creation_date.year",
+        "file.md.creation_date.month",
+        "file.md.creation_date.day",
+        "file.md.creation_date.hour",
+        "file.md.creation_date.minute",
+        "file.md.creation_date.second",
+        "file.md.modification_date",
+        "file.md.modification_date.year",
+        "file.md.modification_date.month",
+        "file.md.modification_date.day",
+        "file.md.modification_date.hour",
+        "file.md.modification_date.minute",
+        "file.md.modification_date.second",
+        "file.md.owner",
+        "file.md.group",
+        "file.md.mode",
+    ];
+
+    pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+        ctx.insert(VARIABLES, Box::new(FileMetadataTemplateValue::default()));
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::sync::Arc;
+        use std::time::{Duration, SystemTime};
+        use std::{env, fs};
+
+        use uuid::Uuid;
+
+        use crate::template::context::{self, Context};
+
+        #[test]
+        fn modification_date() {
+            let path = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+            fs::write(&path, "content").unwrap();
+            // 2019-07-14 12:00:00 UTC, days don't depend on the local timezone.
+            let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_563_105_600);
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+
+            let mut ctx = Context::new(Arc::default());
+            context::prepare_template_context(&mut ctx, &path).unwrap();
+            let render = |name: &str| ctx.get(name).unwrap().render(name, &ctx).unwrap();
+
+            assert_eq!(render("file.md.modification_date.year").to_string(), "2019");
+            assert_eq!(render("file.md.modification_date.month").to_string(), "07");
+
+            fs::remove_file(path).unwrap();
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn ownership() {
+            use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+            let path = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+            fs::write(&path, "content").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+            let md = fs::metadata(&path).unwrap();
+
+            let mut ctx = Context::new(Arc::default());
+            context::prepare_template_context(&mut ctx, &path).unwrap();
+            let render = |name: &str| ctx.get(name).unwrap().render(name, &ctx).unwrap();
+
+            assert_eq!(render("file.md.mode").to_string(), "0640");
+            let owner = crate::permissions::user_name(md.uid()).unwrap_or(md.uid().to_string());
+            assert_eq!(render("file.md.owner").to_string(), owner);
+            let group = crate::permissions::group_name(md.gid()).unwrap_or(md.gid().to_string());
+            assert_eq!(render("file.md.group").to_string(), group);
+
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
+
+pub mod hash {
+    use std::cell::OnceCell;
+    use std::{error::Error, result::Result as StdResult};
+
+    use crate::index::{self, Hash};
+    use crate::template::context::{Context, Result, TemplateValue, Value};
+
+    /// Number of hex digits of short hashes.
+    const SHORT_LEN: usize = 8;
+
+    /// Blake3 hash of the file content, it is computed on first render and
+    /// cached for the lifetime of the context.
+    #[derive(Default)]
+    struct FileHashTemplateValue {
+        hash: OnceCell<Hash>,
+    }
+
+    impl FileHashTemplateValue {
+        fn hash(&self, ctx: &Context) -> StdResult<&Hash, Box<dyn Error>> {
+            if let Some(hash) = self.hash.get() {
+                return Ok(hash);
+            }
+
+            let hash = index::hash_file(&ctx.file_path()?)?;
+            Ok(self.hash.get_or_init(|| hash))
+        }
+    }
+
+    impl TemplateValue for FileHashTemplateValue {
+        fn render(&self, name: &str, ctx: &Context) -> Result {
+            let hex = self.hash(ctx)?.to_hex();
             match name {
-                "file.md.creation_date" => self.creation_date(ctx),
-                "file.md.creation_date.year" => self.creation_date_year(ctx),
-                "file.md.creation_date.month" => self.creation_date_month(ctx),
-                "file.md.creation_date.day" => self.creation_date_day(ctx),
-                &_ => {
-                    unreachable!("unexpected file metadata template variable, please report a bug.")
+                "file.hash" => Ok(Value::String(hex.to_string())),
+                "file.hash.short" => Ok(Value::String(hex[..SHORT_LEN].to_owned())),
+                &_ => unreachable!("unexpected file hash template variable, please report a bug."),
+            }
+        }
+    }
+
+    pub const VARIABLES: &[&str] = &["file.hash", "file.hash.short"];
+
+    pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+        ctx.insert(VARIABLES, Box::new(FileHashTemplateValue::default()));
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::sync::Arc;
+        use std::{env, fs};
+
+        use uuid::Uuid;
+
+        use crate::template::context::{self, Context};
+
+        #[test]
+        fn hash_cached() {
+            let path = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+            fs::write(&path, "content").unwrap();
+
+            let mut ctx = Context::new(Arc::default());
+            context::prepare_template_context(&mut ctx, &path).unwrap();
+            let render = |name: &str| ctx.get(name).unwrap().render(name, &ctx).unwrap();
+
+            let hash = blake3::hash(b"content").to_hex().to_string();
+            assert_eq!(render("file.hash").to_string(), hash);
+
+            // hash is computed once per context.
+            fs::write(&path, "modified").unwrap();
+            assert_eq!(render("file.hash.short").to_string(), hash[..8]);
+
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
+
+pub mod size {
+    use std::{error::Error, fs, result::Result as StdResult};
+
+    use crate::template::context::{Context, Result, TemplateValue, Value};
+
+    /// Decimal size units, as shown by file managers.
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB", "PB"];
+
+    /// Returns the given size with the largest unit it reaches, with one
+    /// decimal below 10 (e.g. `4.2MB`, `42MB`).
+    pub(super) fn human(size: u64) -> String {
+        let mut value = size as f64;
+        let mut unit = 0;
+        while value >= 1000.0 && unit < UNITS.len() - 1 {
+            value /= 1000.0;
+            unit += 1;
+        }
+
+        if unit == 0 || value >= 10.0 {
+            format!("{}{}", value.floor(), UNITS[unit])
+        } else {
+            format!("{:.1}{}", (value * 10.0).floor() / 10.0, UNITS[unit])
+        }
+    }
+
+    /// Returns the power of ten the given size reaches, with its unit (e.g.
+    /// `1MB` for sizes from 1MB to 10MB excluded), sizes below 1KB are in the
+    /// `0B` bucket.
+    pub(super) fn bucket(size: u64) -> String {
+        if size < 1000 {
+            return "0B".to_owned();
+        }
+
+        let exponent = size.ilog10() as usize;
+        let unit = (exponent / 3).min(UNITS.len() - 1);
+        format!("{}{}", 10u64.pow((exponent - unit * 3) as u32), UNITS[unit])
+    }
+
+    /// Size of the file in bytes.
+    #[derive(Default)]
+    struct FileSizeTemplateValue {}
+
+    impl TemplateValue for FileSizeTemplateValue {
+        fn render(&self, name: &str, ctx: &Context) -> Result {
+            let size = fs::metadata(ctx.file_path()?)?.len();
+            match name {
+                "file.size" => Ok(Value::int(size as i64)),
+                "file.size.kb" => Ok(Value::int((size / 1000) as i64)),
+                "file.size.mb" => Ok(Value::int((size / 1_000_000) as i64)),
+                "file.size.human" => Ok(Value::String(human(size))),
+                "file.size.bucket" => Ok(Value::String(bucket(size))),
+                &_ => unreachable!("unexpected file size template variable, please report a bug."),
+            }
+        }
+    }
+
+    pub const VARIABLES: &[&str] = &[
+        "file.size",
+        "file.size.kb",
+        "file.size.mb",
+        "file.size.human",
+        "file.size.bucket",
+    ];
+
+    pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+        ctx.insert(VARIABLES, Box::new(FileSizeTemplateValue::default()));
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        #[test]
+        fn human() {
+            assert_eq!(super::human(512), "512B");
+            assert_eq!(super::human(4_280_000), "4.2MB");
+            assert_eq!(super::human(42_000_000), "42MB");
+            assert_eq!(super::human(999_999), "999KB");
+            assert_eq!(super::human(3_000_000_000), "3.0GB");
+
+            assert_eq!(super::bucket(512), "0B");
+            assert_eq!(super::bucket(4_280_000), "1MB");
+            assert_eq!(super::bucket(42_000_000), "10MB");
+            assert_eq!(super::bucket(1_500_000_000), "1GB");
+        }
+    }
+}
+
+pub mod mime {
+    use std::cell::OnceCell;
+    use std::fs::File;
+    use std::io::Read;
+    use std::{error::Error, result::Result as StdResult};
+
+    use crate::template::context::{Context, Result, TemplateValue, Value};
+
+    /// Number of bytes sniffed to detect the MIME type.
+    const SNIFF_LEN: u64 = 512;
+
+    /// MIME types of the `ftyp` brands of ISO base media files.
+    const BRANDS: &[(&[u8; 4], &str)] = &[
+        (b"heic", "image/heic"),
+        (b"heix", "image/heic"),
+        (b"mif1", "image/heif"),
+        (b"msf1", "image/heif"),
+        (b"avif", "image/avif"),
+        (b"crx ", "image/x-canon-cr3"),
+        (b"qt  ", "video/quicktime"),
+        (b"M4A ", "audio/mp4"),
+        (b"3gp4", "video/3gpp"),
+        (b"3gp5", "video/3gpp"),
+        (b"3g2a", "video/3gpp2"),
+    ];
+
+    /// Returns the MIME type of a file starting with the given bytes.
+    pub(super) fn sniff(data: &[u8]) -> &'static str {
+        let at =
+            |offset: usize, magic: &[u8]| data.get(offset..offset + magic.len()) == Some(magic);
+
+        match data {
+            [0xff, 0xd8, 0xff, ..] => "image/jpeg",
+            [0x89, b'P', b'N', b'G', ..] => "image/png",
+            [b'G', b'I', b'F', b'8', ..] => "image/gif",
+            [b'B', b'M', ..] => "image/bmp",
+            // CR2, NEF, ARW and DNG files are TIFF files.
+            [b'I', b'I', 42, 0, ..] | [b'M', b'M', 0, 42, ..] => "image/tiff",
+            [b'I', b'I', b'R', b'O' | b'S', ..] | [b'M', b'M', b'O', b'R', ..] => {
+                "image/x-olympus-orf"
+            }
+            [b'I', b'I', b'U', 0, ..] => "image/x-panasonic-rw2",
+            _ if at(0, b"RIFF") && at(8, b"WEBP") => "image/webp",
+            _ if at(0, b"RIFF") && at(8, b"AVI ") => "video/x-msvideo",
+            _ if at(0, b"RIFF") && at(8, b"WAVE") => "audio/wav",
+            _ if at(4, b"ftyp") => BRANDS
+                .iter()
+                .find(|(brand, _)| at(8, *brand))
+                .map_or("video/mp4", |(_, mime)| mime),
+            // old QuickTime files have no ftyp box.
+            _ if at(4, b"moov") || at(4, b"mdat") || at(4, b"wide") => "video/quicktime",
+            [0x1a, 0x45, 0xdf, 0xa3, ..] => {
+                if data.windows(4).any(|w| w == b"webm") {
+                    "video/webm"
+                } else {
+                    "video/x-matroska"
                 }
             }
+            [b'I', b'D', b'3', ..] | [0xff, 0xe0..=0xff, ..] => "audio/mpeg",
+            [b'f', b'L', b'a', b'C', ..] => "audio/flac",
+            [b'O', b'g', b'g', b'S', ..] => "audio/ogg",
+            [b'%', b'P', b'D', b'F', ..] => "application/pdf",
+            [b'{', b'\\', b'r', b't', b'f', ..] => "application/rtf",
+            // legacy Microsoft Office documents.
+            [0xd0, 0xcf, 0x11, 0xe0, ..] => "application/x-ole-storage",
+            [b'P', b'K', 3, 4, ..] => "application/zip",
+            [] => "application/octet-stream",
+            _ if is_text(data) => "text/plain",
+            _ => "application/octet-stream",
+        }
+    }
+
+    /// Returns true if the given bytes are UTF-8 text, a multi-byte
+    /// character may be cut at the end of sniffed bytes.
+    fn is_text(data: &[u8]) -> bool {
+        if data.contains(&0) {
+            return false;
+        }
+        match std::str::from_utf8(data) {
+            Ok(_) => true,
+            Err(err) => err.error_len().is_none(),
         }
     }
 
+    /// Returns the coarse type of the given MIME type.
+    pub(super) fn file_type(mime: &str) -> &'static str {
+        match mime.split_once('/') {
+            Some(("image", _)) => "image",
+            Some(("video", _)) => "video",
+            Some(("audio", _)) => "audio",
+            Some(("text", _)) => "document",
+            Some((_, "pdf" | "rtf" | "x-ole-storage")) => "document",
+            _ => "other",
+        }
+    }
+
+    /// MIME type sniffed from the first bytes of the file, it is detected on
+    /// first render and cached for the lifetime of the context.
+    #[derive(Default)]
+    struct FileMimeTemplateValue {
+        mime: OnceCell<&'static str>,
+    }
+
+    impl FileMimeTemplateValue {
+        fn mime(&self, ctx: &Context) -> StdResult<&'static str, Box<dyn Error>> {
+            if let Some(mime) = self.mime.get() {
+                return Ok(mime);
+            }
+
+            let mut data = Vec::new();
+            File::open(ctx.file_path()?)?
+                .take(SNIFF_LEN)
+                .read_to_end(&mut data)?;
+            Ok(self.mime.get_or_init(|| sniff(&data)))
+        }
+    }
+
+    impl TemplateValue for FileMimeTemplateValue {
+        fn render(&self, name: &str, ctx: &Context) -> Result {
+            let mime = self.mime(ctx)?;
+            match name {
+                "file.mime" => Ok(Value::from(mime)),
+                "file.type" => Ok(Value::from(file_type(mime))),
+                &_ => unreachable!("unexpected file mime template variable, please report a bug."),
+            }
+        }
+    }
+
+    pub const VARIABLES: &[&str] = &["file.mime", "file.type"];
+
     pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
-        ctx.insert(
-            &[
-                "file.md.creation_date",
-                "file.md.creation_date",
-                "file.md.creation_date",
-                "file.md.creation_date",
-            ],
-            Box::new(FileMetadataTemplateValue::default()),
-        );
+        ctx.insert(VARIABLES, Box::new(FileMimeTemplateValue::default()));
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        #[test]
+        fn sniff() {
+            let cases: &[(&[u8], &str, &str)] = &[
+                (b"\xff\xd8\xff\xe1", "image/jpeg", "image"),
+                (b"RIFF\0\0\0\0WEBPVP8 ", "image/webp", "image"),
+                (b"\0\0\0\x18ftypheic", "image/heic", "image"),
+                (b"\0\0\0\x18ftypisom", "video/mp4", "video"),
+                (b"\0\0\0\x08wide", "video/quicktime", "video"),
+                (b"ID3\x04\0", "audio/mpeg", "audio"),
+                (b"fLaC\0\0\0\x22", "audio/flac", "audio"),
+                (b"%PDF-1.7", "application/pdf", "document"),
+                ("notes \u{e9}t\u{e9}".as_bytes(), "text/plain", "document"),
+                // truncated multi-byte character.
+                (&"\u{e9}".as_bytes()[..1], "text/plain", "document"),
+                (b"PK\x03\x04", "application/zip", "other"),
+                (b"\0\x01\x02", "application/octet-stream", "other"),
+            ];
+
+            for (data, mime, file_type) in cases {
+                assert_eq!(super::sniff(data), *mime);
+                assert_eq!(super::file_type(mime), *file_type);
+            }
+        }
+    }
+}
+
+/// `file.relpath` is the path of the file relative to the source directory
+/// it was found in. Only the sorter knows sources and overrides it, files
+/// sorted without a source fail to render it.
+pub mod relpath {
+    use std::{error::Error, result::Result as StdResult};
+
+    use thiserror::Error;
+
+    use crate::template::context::{Context, Result, TemplateValue};
+
+    #[derive(Error, Debug)]
+    #[error("file isn't sorted from a source directory")]
+    struct NoSourceErr;
+
+    struct FileRelPathTemplateValue;
+
+    impl TemplateValue for FileRelPathTemplateValue {
+        fn render(&self, _name: &str, _ctx: &Context) -> Result {
+            Err(Box::new(NoSourceErr))
+        }
+    }
+
+    pub const VARIABLES: &[&str] = &["file.relpath"];
+
+    pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+        ctx.insert(VARIABLES, Box::new(FileRelPathTemplateValue));
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+
+    use regex::Regex;
+
+    use crate::template::context::{self, Context, Options};
+    use crate::template::Template;
+
+    use chrono::NaiveDateTime;
+
     use super::DATE_REGEX;
 
+    #[test]
+    fn epoch_timestamps() {
+        let local = |timestamp| {
+            chrono::DateTime::from_timestamp(timestamp, 0)
+                .unwrap()
+                .with_timezone(&chrono::Local)
+                .naive_local()
+        };
+
+        assert_eq!(
+            super::epoch_datetime("1694959200123.jpg"),
+            Some(local(1694959200) + chrono::Duration::milliseconds(123))
+        );
+        assert_eq!(
+            super::epoch_datetime("signal-1694959200.jpg"),
+            Some(local(1694959200))
+        );
+        // 14 digits timestamps are dates, far future timestamps are ignored.
+        assert_eq!(super::epoch_datetime("20230812183422.jpg"), None);
+        assert_eq!(super::epoch_datetime("9999999999.jpg"), None);
+        assert_eq!(super::epoch_datetime("IMG_20230812_183422.jpg"), None);
+        // timestamps before 2001 are counters.
+        assert_eq!(super::epoch_datetime("0000000123.jpg"), None);
+        assert_eq!(super::epoch_datetime("0001694959200.jpg"), None);
+    }
+
+    #[test]
+    fn directory_dates() {
+        let tree = crate::test_utils::TempTree::new()
+            .with_file("2019-07-14 Beach/img001.jpg", "")
+            .with_file("2019-07-14 Beach/IMG_20190715.jpg", "")
+            .with_file("1234201907141/img001.jpg", "");
+        let render = |name: &str, dir_date_fallback| {
+            let options = Options {
+                dir_date_fallback,
+                ..Options::default()
+            };
+            let mut ctx = Context::new(Arc::new(options));
+            context::prepare_template_context(&mut ctx, &tree.join(name)).unwrap();
+            ctx.get("file.name.date")?
+                .render("file.name.date", &ctx)
+                .ok()?
+                .as_date()
+        };
+
+        let date = |date: &str| date.parse::<NaiveDateTime>().ok();
+        assert_eq!(
+            render("2019-07-14 Beach/img001.jpg", false),
+            date("2019-07-14T00:00:00")
+        );
+        // dates of file names win.
+        assert_eq!(
+            render("2019-07-14 Beach/IMG_20190715.jpg", false),
+            date("2019-07-15T00:00:00")
+        );
+        assert_eq!(render("1234201907141/img001.jpg", false), None);
+        // directory dates are resolved by dir.date.
+        assert_eq!(render("2019-07-14 Beach/img001.jpg", true), None);
+    }
+
+    #[test]
+    fn name_conventions() {
+        let dir = crate::test_utils::TempTree::new();
+        for (name, source, date) in [
+            (
+                "IMG-20230812-WA0003.jpg",
+                Some("whatsapp"),
+                "2023-08-12T00:00:00",
+            ),
+            (
+                "photo_2023-08-12_18-34-22.jpg",
+                Some("telegram"),
+                "2023-08-12T18:34:22",
+            ),
+            (
+                "Screenshot_2023-08-12-18-34-22.png",
+                Some("screenshot"),
+                "2023-08-12T18:34:22",
+            ),
+            (
+                "Screen Shot 2023-08-12 at 18.34.22.png",
+                Some("screenshot"),
+                "2023-08-12T18:34:22",
+            ),
+            (
+                "PXL_20230812_183422.jpg",
+                Some("camera"),
+                "2023-08-12T18:34:22",
+            ),
+            ("holidays-2023-08-12.jpg", None, "2023-08-12T00:00:00"),
+            // dates win over timestamps.
+            (
+                "IMG_20230812_1694959200.jpg",
+                Some("camera"),
+                "2023-08-12T00:00:00",
+            ),
+        ] {
+            let path = dir.join(name);
+            std::fs::write(&path, "").unwrap();
+            let mut ctx = Context::new(Arc::default());
+            context::prepare_template_context(&mut ctx, &path).unwrap();
+
+            let render = |variable: &str| ctx.get(variable)?.render(variable, &ctx).ok();
+            assert_eq!(
+                render("file.name.source").map(|value| value.to_string()),
+                source.map(str::to_owned),
+                "{}",
+                name
+            );
+            assert_eq!(
+                render("file.name.date").and_then(|value| value.as_date()),
+                date.parse().ok(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn parent_dirs() {
+        let dir = crate::test_utils::TempTree::new().with_file("DCIM/100CANON/IMG_0001.jpg", "");
+        let path = dir.join("DCIM/100CANON/IMG_0001.jpg");
+        let mut ctx = Context::new(Arc::default());
+        context::prepare_template_context(&mut ctx, &path).unwrap();
+
+        let render = |variable: &str| ctx.get(variable)?.render(variable, &ctx).ok();
+        assert_eq!(
+            render("file.dir").map(|value| value.into_os_string()),
+            Some(dir.join("DCIM/100CANON").into_os_string())
+        );
+        assert_eq!(render("file.dir.name").unwrap().to_string(), "100CANON");
+        assert_eq!(render("file.dir.parent").unwrap().to_string(), "DCIM");
+
+        let mut ctx = Context::new(Arc::default());
+        ctx.insert(
+            &[":file.path"],
+            Box::new(context::Value::Path("/a.jpg".into())),
+        );
+        super::prepare_template_context(&mut ctx).unwrap();
+        let render = |variable: &str| ctx.get(variable)?.render(variable, &ctx).ok();
+        assert!(render("file.dir.name").is_none());
+    }
+
+    #[test]
+    fn name_dates() {
+        let dir = crate::test_utils::TempTree::new()
+            .with_file("vacances 14.07.2023.jpg", "")
+            .with_file("2023-07-14 12h30 plage.jpg", "")
+            .with_file("IMG_20230812.jpg", "");
+        let options: Options = toml::from_str(
+            r#"
+            name_dates_only = true
+
+            [[name_dates]]
+            regex = '(?P<date>\d{2}\.\d{2}\.\d{4})'
+            format = "%d.%m.%Y"
+
+            [[name_dates]]
+            regex = '^\d{4}-\d{2}-\d{2} \d{2}h\d{2}'
+            format = "%Y-%m-%d %Hh%M"
+            "#,
+        )
+        .unwrap();
+        let options = Arc::new(options);
+        let date = |name: &str| {
+            let mut ctx = Context::new(options.clone());
+            context::prepare_template_context(&mut ctx, &dir.join(name)).unwrap();
+            ctx.get("file.name.date")?
+                .render("file.name.date", &ctx)
+                .ok()?
+                .as_date()
+        };
+
+        assert_eq!(
+            date("vacances 14.07.2023.jpg"),
+            "2023-07-14T00:00:00".parse().ok()
+        );
+        assert_eq!(
+            date("2023-07-14 12h30 plage.jpg"),
+            "2023-07-14T12:30:00".parse().ok()
+        );
+        // built-in patterns are disabled.
+        assert_eq!(date("IMG_20230812.jpg"), None);
+
+        let options = toml::from_str::<Options>(
+            r#"
+            [[name_dates]]
+            regex = '\d+'
+            format = "%Y%Q"
+            "#,
+        );
+        assert!(options
+            .unwrap_err()
+            .to_string()
+            .contains("invalid date format \"%Y%Q\""));
+    }
+
+    #[test]
+    fn filename_time() {
+        let dir = crate::test_utils::TempTree::new()
+            .with_file("IMG_20230812_183422.jpg", "")
+            .with_file("picture-2022-11-01-0000.jpg", "");
+        let render = |name: &str, variable: &str| {
+            let mut ctx = Context::new(Arc::default());
+            context::prepare_template_context(&mut ctx, &dir.join(name)).unwrap();
+            ctx.get(variable).unwrap().render(variable, &ctx).ok()
+        };
+        let datetime = |date: &str| date.parse::<NaiveDateTime>().ok();
+
+        let name = "IMG_20230812_183422.jpg";
+        assert_eq!(
+            render(name, "file.name.datetime").and_then(|value| value.as_date()),
+            datetime("2023-08-12T18:34:22")
+        );
+        assert_eq!(
+            render(name, "file.name.date").and_then(|value| value.as_date()),
+            datetime("2023-08-12T18:34:22")
+        );
+        for (variable, value) in [
+            ("file.name.date.hour", "18"),
+            ("file.name.date.minute", "34"),
+            ("file.name.date.second", "22"),
+            ("date.hour", "18"),
+        ] {
+            assert_eq!(render(name, variable).unwrap().to_string(), value);
+        }
+
+        // sequence numbers aren't times.
+        let name = "picture-2022-11-01-0000.jpg";
+        assert!(render(name, "file.name.datetime").is_none());
+        assert_eq!(
+            render(name, "file.name.date").and_then(|value| value.as_date()),
+            datetime("2022-11-01T00:00:00")
+        );
+    }
+
+    #[test]
+    fn regex_captures() {
+        let dir = crate::test_utils::TempTree::new().with_file("event-rome_0421.jpg", "");
+        let options = Options {
+            file_regex: Some(
+                Regex::new(r"^(?P<event>[a-z]+)-(?P<place>[a-z]+)(_(?P<seq>x))?").unwrap(),
+            ),
+            ..Default::default()
+        };
+        let mut ctx = Context::new(Arc::new(options));
+        context::prepare_template_context(&mut ctx, &dir.join("event-rome_0421.jpg")).unwrap();
+
+        let template: Template = ":file.re.place:/:file.re.event:.jpg".parse().unwrap();
+        assert_eq!(
+            template.render(&ctx).unwrap().to_string_lossy(),
+            "rome/event.jpg"
+        );
+        // groups that didn't participate in the match aren't defined.
+        assert!(ctx.get("file.re.seq").is_none());
+    }
+
     #[test]
     fn test_date_year_regex() {
         assert_eq!(
@@ -307,6 +1333,9 @@ mod test {
                 .as_str(),
             "20221231"
         );
-        assert_eq!(DATE_REGEX.find("picture-202212310000").unwrap().as_str(), "20221231")
+        assert_eq!(
+            DATE_REGEX.find("picture-202212310000").unwrap().as_str(),
+            "20221231"
+        )
     }
 }
@@ -0,0 +1,175 @@
+//! Offline reverse geocoding against an embedded list of capitals and major
+//! cities (`cities.csv`).
+//!
+//! With the `gps-timezone` feature, countries are resolved from timezone
+//! boundaries instead: they follow national borders and the country of every
+//! timezone is embedded (`zones.csv`, from the tz database `zone.tab`). The
+//! nearest city may be across a border otherwise.
+
+#[cfg(feature = "gps-timezone")]
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+#[cfg(feature = "gps-timezone")]
+use tzf_rs::DefaultFinder;
+
+/// Maximum distance to the nearest city for a location to be in that city.
+const CITY_RADIUS_KM: f64 = 50.0;
+
+/// Maximum distance to the nearest city for a location to be in the country
+/// of that city, farther locations (oceans, deserts) have no country.
+#[cfg(not(feature = "gps-timezone"))]
+const COUNTRY_RADIUS_KM: f64 = 500.0;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+pub struct City {
+    pub name: &'static str,
+    pub country: &'static str,
+    latitude: f64,
+    longitude: f64,
+}
+
+lazy_static! {
+    static ref CITIES: Vec<City> = include_str!("cities.csv")
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let mut fields = line.split(',');
+            let mut next = || fields.next().expect("malformed cities.csv");
+            City {
+                name: next(),
+                country: next(),
+                latitude: next().parse().expect("malformed cities.csv"),
+                longitude: next().parse().expect("malformed cities.csv"),
+            }
+        })
+        .collect();
+}
+
+#[cfg(feature = "gps-timezone")]
+lazy_static! {
+    static ref FINDER: DefaultFinder = DefaultFinder::new();
+    static ref ZONES: HashMap<&'static str, &'static str> = include_str!("zones.csv")
+        .lines()
+        .skip(1)
+        .map(|line| line.split_once(',').expect("malformed zones.csv"))
+        .collect();
+}
+
+/// Returns the name of the timezone of the given coordinates, `Etc/GMT*`
+/// zones at sea.
+#[cfg(feature = "gps-timezone")]
+pub fn timezone_name(latitude: f64, longitude: f64) -> &'static str {
+    FINDER.get_tz_name(longitude, latitude)
+}
+
+/// Returns the great-circle distance between two coordinates in kilometers.
+fn distance_km((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (lon2 - lon1).to_radians();
+
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Returns the nearest city of the given coordinates and its distance.
+fn nearest(latitude: f64, longitude: f64) -> (&'static City, f64) {
+    CITIES
+        .iter()
+        .map(|city| {
+            let distance = distance_km((latitude, longitude), (city.latitude, city.longitude));
+            (city, distance)
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .expect("cities.csv is empty")
+}
+
+/// Returns the city containing the given coordinates, if any.
+pub fn city(latitude: f64, longitude: f64) -> Option<&'static str> {
+    let (city, distance) = nearest(latitude, longitude);
+    (distance <= CITY_RADIUS_KM).then_some(city.name)
+}
+
+/// Timezones whose boundaries span several countries, countries in them are
+/// those of the nearest city.
+#[cfg(feature = "gps-timezone")]
+const SHARED_ZONES: &[(&str, &[&str])] = &[("Asia/Bangkok", &["Thailand", "Vietnam"])];
+
+/// Returns the country containing the given coordinates, if any.
+#[cfg(feature = "gps-timezone")]
+pub fn country(latitude: f64, longitude: f64) -> Option<&'static str> {
+    let zone = timezone_name(latitude, longitude);
+    match SHARED_ZONES.iter().find(|(name, _)| *name == zone) {
+        Some((_, countries)) => CITIES
+            .iter()
+            .filter(|city| countries.contains(&city.country))
+            .min_by(|a, b| {
+                let distance = |city: &City| {
+                    distance_km((latitude, longitude), (city.latitude, city.longitude))
+                };
+                distance(a).total_cmp(&distance(b))
+            })
+            .map(|city| city.country),
+        None => ZONES.get(zone).copied(),
+    }
+}
+
+/// Returns the country of the nearest city of the given coordinates, if
+/// any.
+#[cfg(not(feature = "gps-timezone"))]
+pub fn country(latitude: f64, longitude: f64) -> Option<&'static str> {
+    let (city, distance) = nearest(latitude, longitude);
+    (distance <= COUNTRY_RADIUS_KM).then_some(city.country)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn reverse_geocode() {
+        // Eiffel Tower.
+        assert_eq!(super::city(48.8584, 2.2945), Some("Paris"));
+        assert_eq!(super::country(48.8584, 2.2945), Some("France"));
+
+        // Mont Saint-Michel, far from any city.
+        assert_eq!(super::city(48.6361, -1.5115), None);
+        assert_eq!(super::country(48.6361, -1.5115), Some("France"));
+
+        // Opera House, southern and eastern hemispheres.
+        assert_eq!(super::city(-33.8568, 151.2153), Some("Sydney"));
+
+        // Middle of the Pacific ocean.
+        assert_eq!(super::country(0.0, -140.0), None);
+
+        // Every line of the dataset is well formed.
+        assert!(super::CITIES.len() > 200);
+    }
+
+    #[cfg(feature = "gps-timezone")]
+    #[test]
+    fn border_countries() {
+        // Annemasse, next to Geneva across the French border.
+        assert_eq!(super::city(46.1934, 6.2342), Some("Geneva"));
+        assert_eq!(super::country(46.1934, 6.2342), Some("France"));
+        // Kyiv, Monaco, Nice and the Vatican.
+        assert_eq!(super::country(50.4501, 30.5234), Some("Ukraine"));
+        assert_eq!(super::country(43.7384, 7.4246), Some("Monaco"));
+        assert_eq!(super::country(43.7034, 7.2663), Some("France"));
+        assert_eq!(super::country(41.9029, 12.4534), Some("Vatican City"));
+        // Hanoi, in the timezone of Bangkok.
+        assert_eq!(super::country(21.0285, 105.8542), Some("Vietnam"));
+        // sea.
+        assert_eq!(super::country(0.0, -140.0), None);
+
+        // countries of cities are countries of their timezone.
+        for city in super::CITIES.iter() {
+            assert_eq!(
+                super::country(city.latitude, city.longitude),
+                Some(city.country),
+                "{}",
+                city.name
+            );
+        }
+    }
+}
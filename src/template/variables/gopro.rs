@@ -0,0 +1,264 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::result::Result as StdResult;
+
+use chrono::{Datelike, NaiveDateTime, Timelike};
+
+use crate::template::context::{Context, Result, TemplateValue, Value};
+
+use super::mp4::{children, creation_time, find, read_payload, Movie, Mp4Box};
+
+/// Maximum number of bytes of the first GPMF sample searched for the GPS
+/// time.
+const GPMF_SAMPLE_LEN: u64 = 64 * 1024;
+
+/// Capture date of GoPro videos.
+struct GoProTemplateValue {
+    date: NaiveDateTime,
+}
+
+impl TemplateValue for GoProTemplateValue {
+    fn render(&self, name: &str, _ctx: &Context) -> Result {
+        match name {
+            "gopro.date" => Ok(self.date.into()),
+            "gopro.date.year" => Ok(Value::padded_int(self.date.year().into(), 4)),
+            "gopro.date.month" => Ok(Value::padded_int(self.date.month().into(), 2)),
+            "gopro.date.day" => Ok(Value::padded_int(self.date.day().into(), 2)),
+            "gopro.date.hour" => Ok(Value::padded_int(self.date.hour().into(), 2)),
+            "gopro.date.minute" => Ok(Value::padded_int(self.date.minute().into(), 2)),
+            "gopro.date.second" => Ok(Value::padded_int(self.date.second().into(), 2)),
+            _ => unreachable!("unexpected gopro template variable, please report a bug."),
+        }
+    }
+}
+
+pub const VARIABLES: &[&str] = &[
+    "gopro.date",
+    "gopro.date.year",
+    "gopro.date.month",
+    "gopro.date.day",
+    "gopro.date.hour",
+    "gopro.date.minute",
+    "gopro.date.second",
+];
+
+/// Returns the offset of the first sample of the GPMF metadata track (`gpmd`
+/// sample description) of the given movie.
+fn gpmf_sample_offset<R: Read + Seek>(reader: &mut R, moov: &[Mp4Box]) -> io::Result<Option<u64>> {
+    for trak in moov.iter().filter(|b| &b.kind == b"trak") {
+        let stbl = match find(&children(reader, trak)?, b"mdia")
+            .map(|mdia| children(reader, &mdia))
+            .transpose()?
+            .and_then(|mdia| find(&mdia, b"minf"))
+            .map(|minf| children(reader, &minf))
+            .transpose()?
+            .and_then(|minf| find(&minf, b"stbl"))
+        {
+            Some(stbl) => children(reader, &stbl)?,
+            None => continue,
+        };
+
+        // version, flags, entry count, size of the first entry then format.
+        let is_gpmd = match find(&stbl, b"stsd") {
+            Some(stsd) => read_payload(reader, &stsd, 16)?.get(12..16) == Some(b"gpmd"),
+            None => false,
+        };
+        if !is_gpmd {
+            continue;
+        }
+
+        // version, flags and entry count precede offsets.
+        if let Some(stco) = find(&stbl, b"stco") {
+            let payload = read_payload(reader, &stco, 12)?;
+            return Ok(payload
+                .get(8..12)
+                .map(|offset| u32::from_be_bytes(offset.try_into().unwrap()).into()));
+        }
+        if let Some(co64) = find(&stbl, b"co64") {
+            let payload = read_payload(reader, &co64, 16)?;
+            return Ok(payload
+                .get(8..16)
+                .map(|offset| u64::from_be_bytes(offset.try_into().unwrap())));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns the first GPS time (`GPSU`, UTC) of GPMF data. GPMF is a list of
+/// key, type, struct size, repeat and payload entries, entries of type 0
+/// contain nested entries.
+fn gpsu(mut data: &[u8]) -> Option<NaiveDateTime> {
+    while data.len() >= 8 {
+        let key = &data[0..4];
+        let kind = data[4];
+        let len = usize::from(data[5]) * usize::from(u16::from_be_bytes([data[6], data[7]]));
+        let payload = data.get(8..8 + len)?;
+
+        if key == b"GPSU" && kind == b'U' {
+            let date = std::str::from_utf8(payload.get(..12)?).ok()?;
+            return NaiveDateTime::parse_from_str(date, "%y%m%d%H%M%S").ok();
+        }
+        if kind == 0 {
+            if let Some(date) = gpsu(payload) {
+                return Some(date);
+            }
+        }
+
+        // payloads are 32 bits aligned.
+        data = data.get(8 + len.div_ceil(4) * 4..)?;
+    }
+
+    None
+}
+
+/// Returns the capture date of a GoPro video, none is returned if the file
+/// isn't a GoPro MP4 file. The camera clock is used unless it wasn't set, GPS
+/// time is used instead.
+fn capture_date<R: Read + Seek>(
+    reader: &mut R,
+    movie: &Movie,
+) -> io::Result<Option<NaiveDateTime>> {
+    if !movie.has_ftyp() {
+        return Ok(None);
+    }
+    let moov = &movie.moov;
+
+    // GoPro cameras store their settings in a GPMF box of user data.
+    let is_gopro = match find(moov, b"udta") {
+        Some(udta) => find(&children(reader, &udta)?, b"GPMF").is_some(),
+        None => false,
+    };
+    if !is_gopro {
+        return Ok(None);
+    }
+
+    let created = match find(moov, b"mvhd") {
+        Some(mvhd) => creation_time(reader, &mvhd)?,
+        None => None,
+    };
+    // clock of new or reset cameras starts in 2016 or earlier.
+    if let Some(date) = created.filter(|date| date.year() > 2016) {
+        return Ok(Some(date));
+    }
+
+    let gps_date = match gpmf_sample_offset(reader, moov)? {
+        Some(offset) => {
+            reader.seek(SeekFrom::Start(offset))?;
+            let mut sample = Vec::new();
+            reader.take(GPMF_SAMPLE_LEN).read_to_end(&mut sample)?;
+            gpsu(&sample)
+        }
+        None => None,
+    };
+
+    Ok(gps_date.or(created))
+}
+
+pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+    let movie = match ctx.movie()? {
+        Some(movie) => movie,
+        // not an MP4 file.
+        None => return Ok(()),
+    };
+
+    // malformed files have no GoPro variables.
+    let mut file = File::open(ctx.file_path()?)?;
+    match capture_date(&mut file, movie) {
+        Ok(Some(date)) => ctx.insert(VARIABLES, Box::new(GoProTemplateValue { date })),
+        Ok(None) => {}
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {}
+        Err(err) => return Err(Box::new(err)),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut b = (payload.len() as u32 + 8).to_be_bytes().to_vec();
+        b.extend_from_slice(kind);
+        b.extend_from_slice(payload);
+        b
+    }
+
+    /// Returns a GoPro MP4 file with the given movie header creation time and
+    /// a GPMF sample containing the given GPS time.
+    fn gopro_mp4(creation_time: u32, gpsu: &[u8; 16]) -> Vec<u8> {
+        let mut mvhd = vec![0; 4];
+        mvhd.extend_from_slice(&creation_time.to_be_bytes());
+        let udta = mp4_box(b"udta", &mp4_box(b"GPMF", b"FIRM"));
+
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 16];
+        stsd.extend_from_slice(b"gpmd");
+        stsd.extend_from_slice(&[0; 8]);
+        let ftyp = mp4_box(b"ftyp", b"mp41");
+
+        let mut strm = b"GPSU".to_vec();
+        strm.extend_from_slice(&[b'U', 16, 0, 1]);
+        strm.extend_from_slice(gpsu);
+        let mut devc = b"STRM".to_vec();
+        devc.extend_from_slice(&[0, 4, 0, (strm.len() / 4) as u8]);
+        devc.extend_from_slice(&strm);
+        let mut sample = b"DEVC".to_vec();
+        sample.extend_from_slice(&[0, 4, 0, (devc.len() / 4) as u8]);
+        sample.extend_from_slice(&devc);
+
+        // the sample offset depends on the moov size, it is computed with a
+        // placeholder first.
+        let moov = |offset: u32| {
+            let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            stco.extend_from_slice(&offset.to_be_bytes());
+            let stbl = mp4_box(
+                b"stbl",
+                &[mp4_box(b"stsd", &stsd), mp4_box(b"stco", &stco)].concat(),
+            );
+            let mdia = mp4_box(b"mdia", &mp4_box(b"minf", &stbl));
+            mp4_box(
+                b"moov",
+                &[
+                    mp4_box(b"mvhd", &mvhd),
+                    udta.clone(),
+                    mp4_box(b"trak", &mdia),
+                ]
+                .concat(),
+            )
+        };
+        let offset = (ftyp.len() + moov(0).len() + 8) as u32;
+
+        [ftyp, moov(offset), mp4_box(b"mdat", &sample)].concat()
+    }
+
+    // 2023-07-14 18:42:07 since 1904-01-01.
+    const CREATION_TIME: u32 = 3_772_204_927;
+
+    #[test]
+    fn capture_date() {
+        let date = |mp4: Vec<u8>| {
+            let mut reader = Cursor::new(mp4);
+            let movie = super::super::mp4::read_movie(&mut reader).unwrap()?;
+            super::capture_date(&mut reader, &movie)
+                .unwrap()
+                .map(|d| d.to_string())
+        };
+
+        assert_eq!(
+            date(gopro_mp4(CREATION_TIME, b"230714164207.000")),
+            Some("2023-07-14 18:42:07".to_owned())
+        );
+
+        // GPS time is used when the camera clock wasn't set.
+        assert_eq!(
+            date(gopro_mp4(0, b"230714164207.000")),
+            Some("2023-07-14 16:42:07".to_owned())
+        );
+
+        let mp4 = [mp4_box(b"ftyp", b"mp41"), mp4_box(b"moov", &[])].concat();
+        assert_eq!(date(mp4), None);
+    }
+}
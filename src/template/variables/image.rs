@@ -0,0 +1,665 @@
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::result::Result as StdResult;
+
+use exif::{Exif, In, Reader, Tag, Value as ExifValue};
+
+use crate::template::context::{self, Context, Result, TemplateValue, Value};
+
+/// Image color space and bit depth, read from exif data. Bit depth is read
+/// from the image header if exif data doesn't contain it. Dimensions are read
+/// from the image header, or exif data for other formats (e.g. RAW files),
+/// and swapped for images the exif orientation rotates by 90 degrees.
+#[derive(Default)]
+struct ImageTemplateValue {
+    color_space: Option<&'static str>,
+    bit_depth: Option<u8>,
+    size: Option<(u32, u32)>,
+}
+
+impl ImageTemplateValue {
+    fn orientation(&self) -> Option<&'static str> {
+        let (width, height) = self.size?;
+        Some(match width.cmp(&height) {
+            Ordering::Less => "portrait",
+            Ordering::Equal => "square",
+            Ordering::Greater => "landscape",
+        })
+    }
+}
+
+impl TemplateValue for ImageTemplateValue {
+    fn render(&self, name: &str, _ctx: &Context) -> Result {
+        let value = match name {
+            "image.color_space" => self.color_space.map(|cs| Value::String(cs.to_owned())),
+            "image.bit_depth" => self.bit_depth.map(|depth| Value::int(depth.into())),
+            "image.width" => self.size.map(|(width, _)| Value::int(width.into())),
+            "image.height" => self.size.map(|(_, height)| Value::int(height.into())),
+            "image.orientation" => self.orientation().map(Value::from),
+            _ => unreachable!("unexpected image template variable, please report a bug."),
+        };
+
+        value.ok_or_else(|| context::missing_variable(name.to_owned()))
+    }
+}
+
+pub const VARIABLES: &[&str] = &[
+    "image.color_space",
+    "image.bit_depth",
+    "image.width",
+    "image.height",
+    "image.orientation",
+];
+
+/// Returns the color space of the exif ColorSpace field. Adobe RGB is stored
+/// as uncalibrated with the "R03" interoperability index (DCF), some cameras
+/// use the non-standard value 2 instead.
+fn color_space(exif: &Exif) -> Option<&'static str> {
+    match exif
+        .get_field(Tag::ColorSpace, In::PRIMARY)?
+        .value
+        .get_uint(0)?
+    {
+        1 => Some("sRGB"),
+        2 => Some("AdobeRGB"),
+        0xffff => {
+            let index = exif
+                .get_field(Tag::InteroperabilityIndex, In::PRIMARY)
+                .map(|field| &field.value);
+            match index {
+                Some(ExifValue::Ascii(ascii)) if ascii.first().is_some_and(|v| v == b"R03") => {
+                    Some("AdobeRGB")
+                }
+                _ => Some("Uncalibrated"),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns the bits per sample of the exif BitsPerSample field, it is only
+/// set by TIFF based formats (e.g. RAW files).
+fn exif_bit_depth(exif: &Exif) -> Option<u8> {
+    let depth = exif
+        .get_field(Tag::BitsPerSample, In::PRIMARY)?
+        .value
+        .get_uint(0)?;
+    u8::try_from(depth).ok()
+}
+
+/// Returns the image dimensions of the exif data of TIFF based formats
+/// (ImageWidth and ImageLength) or of other formats (PixelXDimension and
+/// PixelYDimension).
+fn exif_size(exif: &Exif) -> Option<(u32, u32)> {
+    let dimension = |tags: [Tag; 2]| {
+        tags.iter().find_map(|tag| {
+            exif.get_field(*tag, In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+        })
+    };
+
+    let width = dimension([Tag::ImageWidth, Tag::PixelXDimension])?;
+    let height = dimension([Tag::ImageLength, Tag::PixelYDimension])?;
+    Some((width, height))
+}
+
+/// Returns true if the exif orientation rotates the image by 90 degrees.
+fn exif_rotated(exif: &Exif) -> bool {
+    exif.get_field(Tag::Orientation, In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+        .is_some_and(|orientation| (5..=8).contains(&orientation))
+}
+
+/// Returns the bit depth stored in the header of JPEG (sample precision of
+/// the frame) and PNG (IHDR chunk) images.
+fn header_bit_depth<R: BufRead>(reader: &mut R) -> io::Result<Option<u8>> {
+    let mut magic = [0; 2];
+    reader.read_exact(&mut magic)?;
+
+    match magic {
+        [0xff, 0xd8] => jpeg_bit_depth(reader),
+        [0x89, b'P'] => {
+            // 6 remaining signature bytes, IHDR length, type, width and height.
+            let mut header = [0; 23];
+            reader.read_exact(&mut header)?;
+            Ok((&header[10..14] == b"IHDR").then_some(header[22]))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn jpeg_bit_depth<R: BufRead>(reader: &mut R) -> io::Result<Option<u8>> {
+    if !find_jpeg_frame(reader)? {
+        return Ok(None);
+    }
+
+    let mut precision = [0; 1];
+    reader.read_exact(&mut precision)?;
+    Ok(Some(precision[0]))
+}
+
+/// Skips JPEG segments until the frame header, the reader is positioned after
+/// its length. Returns false if the image has no frame header.
+fn find_jpeg_frame<R: BufRead>(reader: &mut R) -> io::Result<bool> {
+    let mut byte = [0; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        if byte[0] != 0xff {
+            return Ok(false);
+        }
+        // markers may be preceded by fill bytes.
+        while byte[0] == 0xff {
+            reader.read_exact(&mut byte)?;
+        }
+
+        let marker = byte[0];
+        match marker {
+            // standalone markers.
+            0x01 | 0xd0..=0xd7 => continue,
+            // start of scan or end of image, no frame header.
+            0xd9 | 0xda => return Ok(false),
+            _ => {}
+        }
+
+        let mut len = [0; 2];
+        reader.read_exact(&mut len)?;
+        let len = u16::from_be_bytes(len);
+
+        // start of frame markers, except DHT, JPG and DAC.
+        if (0xc0..=0xcf).contains(&marker) && ![0xc4, 0xc8, 0xcc].contains(&marker) {
+            return Ok(true);
+        }
+
+        io::copy(
+            &mut reader.take(len.saturating_sub(2).into()),
+            &mut io::sink(),
+        )?;
+    }
+}
+
+/// Returns the width and height stored in the header of JPEG, PNG, GIF, BMP
+/// and WebP images.
+fn header_size<R: BufRead>(reader: &mut R) -> io::Result<Option<(u32, u32)>> {
+    let mut magic = [0; 2];
+    reader.read_exact(&mut magic)?;
+    if magic == [0xff, 0xd8] {
+        if !find_jpeg_frame(reader)? {
+            return Ok(None);
+        }
+        // sample precision, height and width.
+        let mut frame = [0; 5];
+        reader.read_exact(&mut frame)?;
+        let height = u16::from_be_bytes([frame[1], frame[2]]);
+        let width = u16::from_be_bytes([frame[3], frame[4]]);
+        return Ok(Some((width.into(), height.into())));
+    }
+
+    let mut header = magic.to_vec();
+    reader.take(30).read_to_end(&mut header)?;
+    let bytes = |at: usize| -> Option<[u8; 4]> { header.get(at..at + 4)?.try_into().ok() };
+    let u16_le = |at: usize| {
+        let b = header.get(at..at + 2)?;
+        Some(u32::from(u16::from_le_bytes([b[0], b[1]])))
+    };
+    let u32_le = |at: usize| bytes(at).map(u32::from_le_bytes);
+    let u32_be = |at: usize| bytes(at).map(u32::from_be_bytes);
+
+    let (width, height) = match header.as_slice() {
+        // IHDR chunk.
+        [0x89, b'P', b'N', b'G', ..] if header.get(12..16) == Some(b"IHDR") => {
+            (u32_be(16), u32_be(20))
+        }
+        [b'G', b'I', b'F', b'8', ..] => (u16_le(6), u16_le(8)),
+        // height of top-down bitmaps is negative.
+        [b'B', b'M', ..] => (
+            u32_le(18).map(|w| (w as i32).unsigned_abs()),
+            u32_le(22).map(|h| (h as i32).unsigned_abs()),
+        ),
+        _ if header.get(..4) == Some(b"RIFF") && header.get(8..12) == Some(b"WEBP") => {
+            match header.get(12..16) {
+                // lossy: 14 bits dimensions after the frame start code.
+                Some(b"VP8 ") => (
+                    u16_le(26).map(|w| w & 0x3fff),
+                    u16_le(28).map(|h| h & 0x3fff),
+                ),
+                // lossless: 14 bits dimensions minus one after the signature.
+                Some(b"VP8L") => {
+                    let bits = u32_le(21);
+                    (
+                        bits.map(|b| (b & 0x3fff) + 1),
+                        bits.map(|b| (b >> 14 & 0x3fff) + 1),
+                    )
+                }
+                // extended: 24 bits canvas dimensions minus one.
+                Some(b"VP8X") => (
+                    u32_le(24).map(|w| (w & 0xff_ffff) + 1),
+                    u32_le(26).map(|h| (h >> 8) + 1),
+                ),
+                _ => (None, None),
+            }
+        }
+        _ => (None, None),
+    };
+
+    Ok(width.zip(height))
+}
+
+/// Returns none for truncated files, or files that aren't images.
+fn or_none<T>(result: io::Result<Option<T>>) -> io::Result<Option<T>> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        result => result,
+    }
+}
+
+pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+    let filepath = ctx.file_path()?;
+
+    let file = File::open(filepath)?;
+    let mut reader = BufReader::new(&file);
+
+    let mut value = ImageTemplateValue::default();
+    let exif = match Reader::new().read_from_container(&mut reader) {
+        Ok(exif) => Some(exif),
+        Err(exif::Error::Io(err)) => return Err(Box::new(err)),
+        Err(_) => None,
+    };
+    if let Some(exif) = &exif {
+        value.color_space = color_space(exif);
+        value.bit_depth = exif_bit_depth(exif);
+    }
+
+    if value.bit_depth.is_none() {
+        reader.seek(SeekFrom::Start(0))?;
+        value.bit_depth = or_none(header_bit_depth(&mut reader))?;
+    }
+    reader.seek(SeekFrom::Start(0))?;
+    value.size = or_none(header_size(&mut reader))?.or_else(|| exif.as_ref().and_then(exif_size));
+    if exif.as_ref().is_some_and(exif_rotated) {
+        value.size = value.size.map(|(width, height)| (height, width));
+    }
+
+    if value.color_space.is_some() || value.bit_depth.is_some() || value.size.is_some() {
+        ctx.insert(VARIABLES, Box::new(value));
+    }
+
+    Ok(())
+}
+
+/// Creation date embedded in PNG text chunks and PNG/WebP XMP metadata, for
+/// screenshots and exported graphics without exif data.
+pub mod date {
+    use std::error::Error;
+    use std::fs::File;
+    use std::io::{self, BufReader, Read, Seek, SeekFrom};
+    use std::result::Result as StdResult;
+
+    use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Timelike};
+    use lazy_static::lazy_static;
+    use regex::Regex;
+
+    use crate::template::context::{Context, Result, TemplateValue, Value};
+
+    /// Keyword of PNG text chunks containing XMP metadata.
+    const XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+    /// Keywords of PNG text chunks containing the creation date: the
+    /// standard one and the one written by ImageMagick.
+    const DATE_KEYWORDS: &[&[u8]] = &[b"Creation Time", b"date:create"];
+
+    lazy_static! {
+        /// XMP properties containing the creation date in preference order,
+        /// as attributes or elements.
+        static ref XMP_DATES: Vec<Regex> = ["exif:DateTimeOriginal", "photoshop:DateCreated", "xmp:CreateDate"]
+            .iter()
+            .map(|property| {
+                Regex::new(&format!(r#"{}(?:="([^"]+)"|>([^<]+)<)"#, property)).unwrap()
+            })
+            .collect();
+    }
+
+    struct ImageDateTemplateValue {
+        date: NaiveDateTime,
+    }
+
+    impl TemplateValue for ImageDateTemplateValue {
+        fn render(&self, name: &str, _ctx: &Context) -> Result {
+            match name {
+                "image.date" => Ok(self.date.into()),
+                "image.date.year" => Ok(Value::padded_int(self.date.year().into(), 4)),
+                "image.date.month" => Ok(Value::padded_int(self.date.month().into(), 2)),
+                "image.date.day" => Ok(Value::padded_int(self.date.day().into(), 2)),
+                "image.date.hour" => Ok(Value::padded_int(self.date.hour().into(), 2)),
+                "image.date.minute" => Ok(Value::padded_int(self.date.minute().into(), 2)),
+                "image.date.second" => Ok(Value::padded_int(self.date.second().into(), 2)),
+                _ => unreachable!("unexpected image date template variable, please report a bug."),
+            }
+        }
+    }
+
+    pub const VARIABLES: &[&str] = &[
+        "image.date",
+        "image.date.year",
+        "image.date.month",
+        "image.date.day",
+        "image.date.hour",
+        "image.date.minute",
+        "image.date.second",
+    ];
+
+    /// Parses a creation date: RFC 3339/ISO 8601 (XMP), RFC 1123 (PNG
+    /// `Creation Time`) or exif format. Dates with an offset keep their local
+    /// time, like exif dates.
+    pub(super) fn parse_date(date: &str) -> Option<NaiveDateTime> {
+        let date = date.trim();
+        if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+            return Some(date.naive_local());
+        }
+        if let Ok(date) = DateTime::parse_from_rfc2822(date) {
+            return Some(date.naive_local());
+        }
+
+        const FORMATS: &[&str] = &[
+            "%Y-%m-%dT%H:%M:%S%.f%:z",
+            "%Y-%m-%dT%H:%M%:z",
+            "%Y-%m-%dT%H:%M:%S%.f",
+            "%Y-%m-%dT%H:%M",
+            "%Y:%m:%d %H:%M:%S",
+            "%Y-%m-%d %H:%M:%S",
+        ];
+        for format in FORMATS {
+            if let Ok(date) = DateTime::parse_from_str(date, format) {
+                return Some(date.naive_local());
+            }
+            if let Ok(date) = NaiveDateTime::parse_from_str(date, format) {
+                return Some(date);
+            }
+        }
+
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+    }
+
+    /// Returns the creation date of XMP metadata.
+    pub(super) fn xmp_date(xmp: &str) -> Option<NaiveDateTime> {
+        XMP_DATES.iter().find_map(|regex| {
+            let captures = regex.captures(xmp)?;
+            let date = captures.get(1).or_else(|| captures.get(2))?;
+            parse_date(date.as_str())
+        })
+    }
+
+    /// Returns the date of a PNG text chunk (`tEXt` or uncompressed `iTXt`).
+    fn text_chunk_date(kind: &[u8; 4], data: &[u8]) -> Option<NaiveDateTime> {
+        let (keyword, text) = data.split_at(data.iter().position(|b| *b == 0)?);
+        let text = match kind {
+            // latin-1 text, dates are ASCII.
+            b"tEXt" => &text[1..],
+            // compression flag and method, language and translated keyword.
+            b"iTXt" => {
+                if text.get(1) != Some(&0) {
+                    return None;
+                }
+                let mut text = text.get(3..)?;
+                for _ in 0..2 {
+                    text = &text[text.iter().position(|b| *b == 0)? + 1..];
+                }
+                text
+            }
+            _ => return None,
+        };
+        let text = String::from_utf8_lossy(text);
+
+        if keyword == XMP_KEYWORD {
+            xmp_date(&text)
+        } else if DATE_KEYWORDS.contains(&keyword) {
+            parse_date(&text)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the creation date of a PNG file, the chunk signature is
+    /// already read.
+    pub(super) fn png_date<R: Read + Seek>(reader: &mut R) -> io::Result<Option<NaiveDateTime>> {
+        loop {
+            let mut header = [0; 8];
+            reader.read_exact(&mut header)?;
+            let len = u32::from_be_bytes(header[..4].try_into().unwrap());
+            let kind: [u8; 4] = header[4..].try_into().unwrap();
+
+            match &kind {
+                b"IEND" => return Ok(None),
+                b"tEXt" | b"iTXt" => {
+                    let mut data = Vec::new();
+                    reader.take(len.into()).read_to_end(&mut data)?;
+                    if let Some(date) = text_chunk_date(&kind, &data) {
+                        return Ok(Some(date));
+                    }
+                    reader.seek(SeekFrom::Current(4))?;
+                }
+                // data and CRC.
+                _ => {
+                    reader.seek(SeekFrom::Current(i64::from(len) + 4))?;
+                }
+            }
+        }
+    }
+
+    /// Returns the creation date of a WebP file, the RIFF header is already
+    /// read.
+    pub(super) fn webp_date<R: Read + Seek>(reader: &mut R) -> io::Result<Option<NaiveDateTime>> {
+        loop {
+            let mut header = [0; 8];
+            match reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(err),
+            }
+            let len = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+            if &header[..4] == b"XMP " {
+                let mut xmp = Vec::new();
+                reader.take(len.into()).read_to_end(&mut xmp)?;
+                return Ok(xmp_date(&String::from_utf8_lossy(&xmp)));
+            }
+            // chunks are padded to an even size, a chunk too large to be
+            // padded can't fit in the file.
+            let Some(padded) = len.checked_add(len & 1) else {
+                return Ok(None);
+            };
+            reader.seek(SeekFrom::Current(i64::from(padded)))?;
+        }
+    }
+
+    pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+        let filepath = ctx.file_path()?;
+
+        let mut reader = BufReader::new(File::open(filepath)?);
+        let mut header = [0; 12];
+        let date = match reader.read_exact(&mut header) {
+            Ok(()) if header[..8] == *b"\x89PNG\r\n\x1a\n" => {
+                reader.seek(SeekFrom::Start(8))?;
+                png_date(&mut reader)
+            }
+            Ok(()) if &header[..4] == b"RIFF" && &header[8..] == b"WEBP" => webp_date(&mut reader),
+            // not a PNG nor WebP file.
+            _ => return Ok(()),
+        };
+
+        // truncated files have no image date.
+        match date {
+            Ok(Some(date)) => ctx.insert(VARIABLES, Box::new(ImageDateTemplateValue { date })),
+            Ok(None) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {}
+            Err(err) => return Err(Box::new(err)),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use exif::{Field, In, Reader, Tag, Value};
+
+    fn exif(fields: &[Field]) -> exif::Exif {
+        let mut writer = exif::experimental::Writer::new();
+        for field in fields {
+            writer.push_field(field);
+        }
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+
+        Reader::new().read_raw(tiff.into_inner()).unwrap()
+    }
+
+    fn field(tag: Tag, value: Value) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value,
+        }
+    }
+
+    #[test]
+    fn color_space() {
+        let srgb = exif(&[field(Tag::ColorSpace, Value::Short(vec![1]))]);
+        assert_eq!(super::color_space(&srgb), Some("sRGB"));
+
+        let adobe_rgb = exif(&[
+            field(Tag::ColorSpace, Value::Short(vec![0xffff])),
+            field(
+                Tag::InteroperabilityIndex,
+                Value::Ascii(vec![b"R03".to_vec()]),
+            ),
+        ]);
+        assert_eq!(super::color_space(&adobe_rgb), Some("AdobeRGB"));
+
+        let uncalibrated = exif(&[field(Tag::ColorSpace, Value::Short(vec![0xffff]))]);
+        assert_eq!(super::color_space(&uncalibrated), Some("Uncalibrated"));
+
+        let none = exif(&[field(
+            Tag::Software,
+            Value::Ascii(vec![b"photosort".to_vec()]),
+        )]);
+        assert_eq!(super::color_space(&none), None);
+    }
+
+    #[test]
+    fn header_bit_depth() {
+        let depth = |bytes: &[u8]| super::header_bit_depth(&mut Cursor::new(bytes));
+
+        // SOI, APP0 with 2 bytes of payload and a 12 bits baseline frame.
+        let jpeg = [
+            0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x00, 0x00, 0xff, 0xc0, 0x00, 0x0b, 0x0c,
+        ];
+        assert_eq!(depth(&jpeg).unwrap(), Some(12));
+
+        let mut png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1, 16]);
+        assert_eq!(depth(&png).unwrap(), Some(16));
+
+        assert_eq!(depth(b"text file").unwrap(), None);
+        assert_eq!(
+            depth(&jpeg[..6]).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn header_size() {
+        let size = |bytes: &[u8]| super::header_size(&mut Cursor::new(bytes)).unwrap();
+
+        // SOI, APP0 with 2 bytes of payload and a 8 bits 640x480 frame.
+        let jpeg = [
+            0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x00, 0x00, 0xff, 0xc0, 0x00, 0x11, 0x08, 0x01,
+            0xe0, 0x02, 0x80,
+        ];
+        assert_eq!(size(&jpeg), Some((640, 480)));
+
+        let mut png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 0x0f, 0xa0, 0, 0, 0x0b, 0xb8, 8]);
+        assert_eq!(size(&png), Some((4000, 3000)));
+
+        assert_eq!(size(b"GIF89a\x40\x01\xc8\x00"), Some((320, 200)));
+
+        // lossless WebP of 1080x1920.
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8L\0\0\0\0\x2f".to_vec();
+        webp.extend_from_slice(&(1079u32 | 1919 << 14).to_le_bytes());
+        assert_eq!(size(&webp), Some((1080, 1920)));
+
+        assert_eq!(size(b"text file"), None);
+    }
+
+    #[test]
+    fn orientation() {
+        let value = |size| super::ImageTemplateValue {
+            size,
+            ..Default::default()
+        };
+        assert_eq!(value(Some((1080, 1920))).orientation(), Some("portrait"));
+        assert_eq!(value(Some((1920, 1080))).orientation(), Some("landscape"));
+        assert_eq!(value(Some((512, 512))).orientation(), Some("square"));
+        assert_eq!(value(None).orientation(), None);
+
+        let rotated = exif(&[field(Tag::Orientation, Value::Short(vec![6]))]);
+        assert!(super::exif_rotated(&rotated));
+    }
+
+    #[test]
+    fn embedded_date() {
+        let date = |d: Option<chrono::NaiveDateTime>| d.map(|d| d.to_string());
+        let expected = Some("2023-07-14 18:42:07".to_owned());
+
+        let chunk = |kind: &[u8], data: &[u8]| {
+            let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+            chunk.extend_from_slice(kind);
+            chunk.extend_from_slice(data);
+            chunk.extend_from_slice(&[0; 4]);
+            chunk
+        };
+        let png = [
+            chunk(b"IHDR", &[0; 13]),
+            chunk(b"tEXt", b"Software\0photosort"),
+            chunk(b"tEXt", b"Creation Time\0Fri, 14 Jul 2023 18:42:07 +0200"),
+            chunk(b"IEND", &[]),
+        ]
+        .concat();
+        let png_date = super::date::png_date(&mut Cursor::new(png)).unwrap();
+        assert_eq!(date(png_date), expected);
+
+        let xmp = br#"<rdf:Description xmp:CreateDate="2023-07-14T18:42:07+02:00"/>"#;
+        let mut itxt = b"XML:com.adobe.xmp\0\0\0\0\0".to_vec();
+        itxt.extend_from_slice(xmp);
+        let png = [chunk(b"iTXt", &itxt), chunk(b"IEND", &[])].concat();
+        let png_date = super::date::png_date(&mut Cursor::new(png)).unwrap();
+        assert_eq!(date(png_date), expected);
+
+        let mut webp = b"VP8 \x01\0\0\0\0\0XMP ".to_vec();
+        let xmp = b"<photoshop:DateCreated>2023-07-14T18:42:07</photoshop:DateCreated>";
+        webp.extend_from_slice(&(xmp.len() as u32).to_le_bytes());
+        webp.extend_from_slice(xmp);
+        let webp_date = super::date::webp_date(&mut Cursor::new(webp)).unwrap();
+        assert_eq!(date(webp_date), expected);
+
+        // chunk sizes can't overflow.
+        let webp = b"VP8 \xff\xff\xff\xff".to_vec();
+        assert_eq!(
+            super::date::webp_date(&mut Cursor::new(webp)).unwrap(),
+            None
+        );
+
+        assert_eq!(
+            super::date::parse_date("2023-07-14"),
+            chrono::NaiveDate::from_ymd_opt(2023, 7, 14)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+        );
+    }
+}
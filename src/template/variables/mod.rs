@@ -1,20 +1,234 @@
 use std::error::Error;
 
-use crate::template::context::Context;
+use crate::template::context::{self, Context};
 
+mod audio;
+mod counter;
+mod date;
+mod dir;
+mod dji;
+mod event;
 mod exif;
 mod file;
-mod date;
+mod geocode;
+mod gopro;
+mod image;
+pub(crate) mod mp4;
+mod pair;
+#[cfg(feature = "raw")]
+mod raw;
+mod scan;
+mod tags;
+mod video;
+
+pub use self::date::Source;
+pub use self::exif::{ExifTag, ExifTagError};
+
+/// Provider define a set of built-in variables and how to add them to a
+/// template context.
+pub struct Provider {
+    pub name: &'static str,
+    pub variables: &'static [&'static str],
+    prepare: fn(&mut Context) -> Result<(), Box<dyn Error>>,
+}
+
+/// Registry of all variable providers, in preparation order: composed
+/// variables (date) must come after their sources.
+pub static PROVIDERS: &[Provider] = &[
+    Provider {
+        name: "file",
+        variables: file::VARIABLES,
+        prepare: file::prepare_template_context,
+    },
+    Provider {
+        name: "file.name.source",
+        variables: file::name_source::VARIABLES,
+        prepare: file::name_source::prepare_template_context,
+    },
+    Provider {
+        name: "file.md",
+        variables: file::metadata::VARIABLES,
+        prepare: file::metadata::prepare_template_context,
+    },
+    Provider {
+        name: "file.hash",
+        variables: file::hash::VARIABLES,
+        prepare: file::hash::prepare_template_context,
+    },
+    Provider {
+        name: "file.size",
+        variables: file::size::VARIABLES,
+        prepare: file::size::prepare_template_context,
+    },
+    Provider {
+        name: "file.mime",
+        variables: file::mime::VARIABLES,
+        prepare: file::mime::prepare_template_context,
+    },
+    Provider {
+        name: "file.relpath",
+        variables: file::relpath::VARIABLES,
+        prepare: file::relpath::prepare_template_context,
+    },
+    Provider {
+        name: "exif",
+        variables: exif::VARIABLES,
+        prepare: exif::prepare_template_context,
+    },
+    Provider {
+        name: "image",
+        variables: image::VARIABLES,
+        prepare: image::prepare_template_context,
+    },
+    Provider {
+        name: "image.date",
+        variables: image::date::VARIABLES,
+        prepare: image::date::prepare_template_context,
+    },
+    Provider {
+        name: "dji",
+        variables: dji::VARIABLES,
+        prepare: dji::prepare_template_context,
+    },
+    Provider {
+        name: "gopro",
+        variables: gopro::VARIABLES,
+        prepare: gopro::prepare_template_context,
+    },
+    Provider {
+        name: "video",
+        variables: video::VARIABLES,
+        prepare: video::prepare_template_context,
+    },
+    Provider {
+        name: "audio",
+        variables: audio::VARIABLES,
+        prepare: audio::prepare_template_context,
+    },
+    Provider {
+        name: "dir",
+        variables: dir::VARIABLES,
+        prepare: dir::prepare_template_context,
+    },
+    Provider {
+        name: "tags",
+        variables: tags::VARIABLES,
+        prepare: tags::prepare_template_context,
+    },
+    Provider {
+        name: "scan",
+        variables: scan::VARIABLES,
+        prepare: scan::prepare_template_context,
+    },
+    Provider {
+        name: "pair",
+        variables: pair::VARIABLES,
+        prepare: pair::prepare_template_context,
+    },
+    Provider {
+        name: "date",
+        variables: date::VARIABLES,
+        prepare: date::prepare_template_context,
+    },
+    Provider {
+        name: "event",
+        variables: event::VARIABLES,
+        prepare: event::prepare_template_context,
+    },
+    Provider {
+        name: "counter",
+        variables: counter::VARIABLES,
+        prepare: counter::prepare_template_context,
+    },
+];
+
+/// Providers of variables read from file contents, whose strings may contain
+/// any character.
+const METADATA_PROVIDERS: &[&str] = &["exif", "image", "dji", "gopro", "video", "audio", "tags"];
+
+/// Returns whether the given variable renders metadata read from file
+/// contents (exif strings, tags, ...), including `[exif_tags]` variables.
+pub fn is_metadata(ctx: &Context, name: &str) -> bool {
+    ctx.options().exif_tags.contains_key(name)
+        || METADATA_PROVIDERS.iter().any(|provider| {
+            name.strip_prefix(provider)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        })
+}
+
+/// Returns whether the given name is a built-in variable.
+pub fn is_builtin(name: &str) -> bool {
+    PROVIDERS.iter().any(|p| p.variables.contains(&name))
+}
 
-/// Prepares the given template context by adding variables from the following
-/// modules:
-/// - file
-/// - exif
-/// - date
+/// Resolved is the rendered value of a built-in variable.
+pub struct Resolved {
+    pub provider: &'static str,
+    pub name: &'static str,
+    pub value: context::Result,
+    /// Source variables tried to render composed variables (`date.*`), in
+    /// order, empty for other variables.
+    pub sources: Vec<Source>,
+}
+
+/// Renders every built-in variable of the given context. Variables missing
+/// from the context (e.g. file without exif data) are resolved to an error.
+pub fn resolve_all(ctx: &Context) -> Vec<Resolved> {
+    PROVIDERS
+        .iter()
+        .flat_map(|provider| {
+            provider.variables.iter().map(|name| Resolved {
+                provider: provider.name,
+                name,
+                value: ctx.get_or_err(name).and_then(|v| v.render(name, ctx)),
+                sources: match provider.name {
+                    "date" => date::trace(ctx, name),
+                    _ => Vec::new(),
+                },
+            })
+        })
+        .collect()
+}
+
+/// Prepares the given template context by adding variables of every
+/// [`PROVIDERS`].
 pub fn prepare_template_context(ctx: &mut Context) -> Result<(), Box<dyn Error>> {
-    file::prepare_template_context(ctx)?;
-    exif::prepare_template_context(ctx)?;
-    date::prepare_template_context(ctx)?;
+    for provider in PROVIDERS {
+        (provider.prepare)(ctx)?;
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::template::context::{self, Context};
+
+    #[test]
+    fn resolve_all() {
+        let mut ctx = Context::new(Arc::default());
+        context::prepare_template_context(&mut ctx, file!().as_ref()).unwrap();
+
+        let resolved = super::resolve_all(&ctx);
+        assert_eq!(
+            resolved.len(),
+            super::PROVIDERS
+                .iter()
+                .map(|p| p.variables.len())
+                .sum::<usize>()
+        );
+
+        let value = |name: &str| {
+            let r = resolved.iter().find(|r| r.name == name).unwrap();
+            r.value
+                .as_ref()
+                .map(|v| v.to_string())
+                .map_err(|e| e.to_string())
+        };
+        assert_eq!(value("file.name"), Ok("mod.rs".to_owned()));
+        // source files don't contain exif data.
+        assert!(value("exif.date").is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! Parsing of MP4 (ISO base media) and QuickTime containers, shared by video
+//! variable providers.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+/// Top level boxes an MP4 or QuickTime file may start with, old QuickTime
+/// files have no `ftyp` box.
+const FIRST_BOXES: &[&[u8; 4]] = &[b"ftyp", b"moov", b"mdat", b"wide", b"free", b"skip"];
+
+/// Box of an MP4 (ISO base media) file.
+#[derive(Debug, Clone, Copy)]
+pub struct Mp4Box {
+    pub kind: [u8; 4],
+    /// Offset of the box payload.
+    pub offset: u64,
+    /// Size of the box payload.
+    pub len: u64,
+}
+
+/// Top level and movie boxes of an MP4/MOV file, they're read once per
+/// rendering context and shared by video variable providers.
+#[derive(Debug)]
+pub struct Movie {
+    pub boxes: Vec<Mp4Box>,
+    /// Children of the `moov` box.
+    pub moov: Vec<Mp4Box>,
+}
+
+impl Movie {
+    /// Returns whether the file starts with a `ftyp` box, QuickTime files
+    /// may not.
+    pub fn has_ftyp(&self) -> bool {
+        self.boxes.first().is_some_and(|b| &b.kind == b"ftyp")
+    }
+}
+
+/// Returns the boxes of an MP4/MOV file, none is returned if the file isn't
+/// one or has no movie box.
+pub fn read_movie<R: Read + Seek>(reader: &mut R) -> io::Result<Option<Movie>> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = [0; 8];
+    match reader.read_exact(&mut header) {
+        Ok(()) if FIRST_BOXES.iter().any(|kind| &header[4..8] == *kind) => {}
+        Ok(()) => return Ok(None),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    let end = reader.seek(SeekFrom::End(0))?;
+    let boxes = read_boxes(reader, 0, end)?;
+    let moov = match find(&boxes, b"moov") {
+        Some(moov) => children(reader, &moov)?,
+        None => return Ok(None),
+    };
+
+    Ok(Some(Movie { boxes, moov }))
+}
+
+/// Returns the boxes stored between `offset` and `end`.
+pub fn read_boxes<R: Read + Seek>(
+    reader: &mut R,
+    mut offset: u64,
+    end: u64,
+) -> io::Result<Vec<Mp4Box>> {
+    let mut boxes = Vec::new();
+    while offset
+        .checked_add(8)
+        .is_some_and(|header_end| header_end <= end)
+    {
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        let kind = [header[4], header[5], header[6], header[7]];
+
+        let (header_len, size) =
+            match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+                // box extends to the end of its parent.
+                0 => (8, end - offset),
+                1 => {
+                    let mut size = [0; 8];
+                    reader.read_exact(&mut size)?;
+                    (16, u64::from_be_bytes(size))
+                }
+                size => (8, size.into()),
+            };
+        // boxes always advance the offset as their size includes their header.
+        let next = match offset.checked_add(size) {
+            Some(next) if size >= header_len && next <= end => next,
+            _ => return Err(io::ErrorKind::InvalidData.into()),
+        };
+
+        boxes.push(Mp4Box {
+            kind,
+            offset: offset + header_len,
+            len: size - header_len,
+        });
+        offset = next;
+    }
+
+    Ok(boxes)
+}
+
+/// Returns the children of the given box.
+pub fn children<R: Read + Seek>(reader: &mut R, parent: &Mp4Box) -> io::Result<Vec<Mp4Box>> {
+    let end = parent
+        .offset
+        .checked_add(parent.len)
+        .ok_or(io::ErrorKind::InvalidData)?;
+    read_boxes(reader, parent.offset, end)
+}
+
+pub fn find(boxes: &[Mp4Box], kind: &[u8; 4]) -> Option<Mp4Box> {
+    boxes.iter().find(|b| &b.kind == kind).copied()
+}
+
+pub fn read_payload<R: Read + Seek>(
+    reader: &mut R,
+    b: &Mp4Box,
+    max_len: u64,
+) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(b.offset))?;
+    let mut payload = Vec::new();
+    reader.take(b.len.min(max_len)).read_to_end(&mut payload)?;
+    Ok(payload)
+}
+
+/// Returns the creation time of the movie header, in UTC according to the
+/// specification but many cameras store the local time of their clock.
+pub fn creation_time<R: Read + Seek>(
+    reader: &mut R,
+    mvhd: &Mp4Box,
+) -> io::Result<Option<NaiveDateTime>> {
+    let payload = read_payload(reader, mvhd, 12)?;
+    let seconds = match payload.first() {
+        Some(0) if payload.len() >= 8 => {
+            u32::from_be_bytes(payload[4..8].try_into().unwrap()).into()
+        }
+        Some(1) if payload.len() >= 12 => u64::from_be_bytes(payload[4..12].try_into().unwrap()),
+        _ => return Ok(None),
+    };
+
+    // seconds since 1904-01-01.
+    let epoch = NaiveDate::from_ymd_opt(1904, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    Ok(i64::try_from(seconds)
+        .ok()
+        .and_then(Duration::try_seconds)
+        .and_then(|duration| epoch.checked_add_signed(duration)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    fn boxes(file: &[u8]) -> io::Result<Vec<super::Mp4Box>> {
+        super::read_boxes(&mut Cursor::new(file), 0, file.len() as u64)
+    }
+
+    #[test]
+    fn read_boxes() {
+        let mut file = 16_u32.to_be_bytes().to_vec();
+        file.extend_from_slice(b"ftypisom");
+        file.extend_from_slice(&[0; 4]);
+        file.extend_from_slice(&0_u32.to_be_bytes());
+        file.extend_from_slice(b"mdat");
+        file.extend_from_slice(&[0; 8]);
+
+        let boxes = boxes(&file).unwrap();
+        assert_eq!(boxes.len(), 2);
+        assert_eq!(&boxes[0].kind, b"ftyp");
+        assert_eq!((boxes[0].offset, boxes[0].len), (8, 8));
+        // size 0 extends to the end of the file.
+        assert_eq!(&boxes[1].kind, b"mdat");
+        assert_eq!((boxes[1].offset, boxes[1].len), (24, 8));
+    }
+
+    #[test]
+    fn malformed_box_sizes() {
+        let large = |size: u64| {
+            let mut file = 1_u32.to_be_bytes().to_vec();
+            file.extend_from_slice(b"free");
+            file.extend_from_slice(&size.to_be_bytes());
+            file.extend_from_slice(&[0; 16]);
+            boxes(&file).map_err(|err| err.kind())
+        };
+
+        // sizes wrapping the offset around.
+        assert_eq!(large(u64::MAX).unwrap_err(), io::ErrorKind::InvalidData);
+        assert_eq!(large(u64::MAX - 7).unwrap_err(), io::ErrorKind::InvalidData);
+        // sizes that don't advance the offset.
+        assert_eq!(large(0).unwrap_err(), io::ErrorKind::InvalidData);
+        assert_eq!(large(8).unwrap_err(), io::ErrorKind::InvalidData);
+        assert_eq!(large(32).unwrap().len(), 1);
+
+        // headers past the largest offset.
+        assert!(
+            super::read_boxes(&mut Cursor::new(vec![0; 8]), u64::MAX - 4, u64::MAX)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn creation_time() {
+        let mvhd = |seconds: u64| {
+            let mut payload = vec![1, 0, 0, 0];
+            payload.extend_from_slice(&seconds.to_be_bytes());
+            let b = super::Mp4Box {
+                kind: *b"mvhd",
+                offset: 0,
+                len: payload.len() as u64,
+            };
+            super::creation_time(&mut Cursor::new(payload), &b).unwrap()
+        };
+
+        assert_eq!(
+            mvhd(3_772_204_927).unwrap().to_string(),
+            "2023-07-14 18:42:07"
+        );
+        // out of range creation times.
+        assert_eq!(mvhd(i64::MAX as u64), None);
+        assert_eq!(mvhd(u64::MAX), None);
+    }
+}
@@ -0,0 +1,19 @@
+use std::error::Error;
+use std::result::Result as StdResult;
+
+use crate::pair;
+use crate::template::context::{Context, Value};
+
+pub const VARIABLES: &[&str] = &["pair.role"];
+
+/// Adds the role (`raw` or `jpeg`) of files belonging to a RAW+JPEG pair.
+pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+    let path = ctx.file_path()?;
+    if pair::partner(&path).is_some() {
+        if let Some(role) = pair::Role::of(&path) {
+            ctx.insert(VARIABLES, Box::new(Value::String(role.to_string())));
+        }
+    }
+
+    Ok(())
+}
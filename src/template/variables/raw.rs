@@ -0,0 +1,198 @@
+//! Exif data of camera RAW formats that kamadak-exif doesn't read. CR2, NEF,
+//! ARW and DNG files are TIFF files read as is, Olympus ORF and Panasonic RW2
+//! files are TIFF files with another magic number and Canon CR3 files are
+//! ISO base media files storing exif IFDs in boxes.
+
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+
+use exif::experimental::Writer;
+use exif::{Context as TagContext, Exif, Field, In, Reader, Tag, Value as ExifValue};
+
+use super::mp4::{find, read_boxes, read_payload, Movie};
+
+/// Magic numbers of ORF (`IIRO`, `IIRS`, `MMOR`) and RW2 (`IIU\0`) files.
+const TIFF_MAGICS: &[&[u8; 4]] = &[b"IIRO", b"IIRS", b"MMOR", b"IIU\0"];
+
+/// User type of the `uuid` box containing Canon metadata of CR3 files.
+const CANON_UUID: [u8; 16] = [
+    0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48,
+];
+
+/// Boxes of the Canon metadata box containing a TIFF file whose first IFD is
+/// the IFD of the given context.
+const CANON_IFDS: &[(&[u8; 4], TagContext)] = &[
+    (b"CMT1", TagContext::Tiff),
+    (b"CMT2", TagContext::Exif),
+    (b"CMT4", TagContext::Gps),
+];
+
+/// Returns the exif data of an ORF, RW2 or CR3 file, none is returned for
+/// other files. The boxes of CR3 files are given by `movie`.
+pub fn read_exif<R: Read + Seek>(
+    reader: &mut R,
+    movie: Option<&Movie>,
+) -> io::Result<Option<Exif>> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = [0; 8];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    if TIFF_MAGICS.iter().any(|magic| header[..4] == magic[..]) {
+        let mut data = Vec::new();
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_to_end(&mut data)?;
+        // standard TIFF magic number.
+        data[2..4].copy_from_slice(if header[0] == b'I' {
+            &[42, 0]
+        } else {
+            &[0, 42]
+        });
+        return Ok(Reader::new().read_raw(data).ok());
+    }
+
+    if let Some(movie) = movie.filter(|movie| movie.has_ftyp()) {
+        // malformed files have no exif data.
+        return match cr3_exif(reader, movie) {
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => Ok(None),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            result => result,
+        };
+    }
+
+    Ok(None)
+}
+
+/// Returns the exif data of a CR3 file, IFDs of the Canon metadata box are
+/// merged into a single TIFF file.
+fn cr3_exif<R: Read + Seek>(reader: &mut R, movie: &Movie) -> io::Result<Option<Exif>> {
+    let mut canon = None;
+    for uuid in movie.moov.iter().filter(|b| &b.kind == b"uuid") {
+        if read_payload(reader, uuid, 16)? == CANON_UUID {
+            canon = Some(*uuid);
+            break;
+        }
+    }
+    let canon = match canon {
+        Some(canon) if canon.len >= 16 => canon,
+        _ => return Ok(None),
+    };
+    let boxes = read_boxes(reader, canon.offset + 16, canon.offset + canon.len)?;
+
+    let mut little_endian = true;
+    let mut fields = Vec::new();
+    for (kind, context) in CANON_IFDS {
+        let ifd = match find(&boxes, kind) {
+            Some(ifd) => read_payload(reader, &ifd, ifd.len)?,
+            None => continue,
+        };
+        let exif = match Reader::new().read_raw(ifd) {
+            Ok(exif) => exif,
+            Err(_) => continue,
+        };
+
+        little_endian = exif.little_endian();
+        fields.extend(
+            exif.fields()
+                .filter(|f| f.ifd_num == In::PRIMARY)
+                // unknown types can't be written.
+                .filter(|f| !matches!(f.value, ExifValue::Unknown(..)))
+                .map(|f| Field {
+                    tag: Tag(*context, f.tag.number()),
+                    ifd_num: In::PRIMARY,
+                    value: f.value.clone(),
+                }),
+        );
+    }
+
+    let mut writer = Writer::new();
+    for field in &fields {
+        writer.push_field(field);
+    }
+    let mut tiff = Cursor::new(Vec::new());
+    if writer.write(&mut tiff, little_endian).is_err() {
+        return Ok(None);
+    }
+
+    Ok(Reader::new().read_raw(tiff.into_inner()).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use exif::experimental::Writer;
+    use exif::{Field, In, Tag, Value};
+
+    use super::super::mp4::read_movie;
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut b = (payload.len() as u32 + 8).to_be_bytes().to_vec();
+        b.extend_from_slice(kind);
+        b.extend_from_slice(payload);
+        b
+    }
+
+    /// Returns a TIFF file containing the given field.
+    fn tiff(tag: Tag, value: &str) -> Vec<u8> {
+        let field = Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![value.as_bytes().to_vec()]),
+        };
+        let mut writer = Writer::new();
+        writer.push_field(&field);
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, true).unwrap();
+        tiff.into_inner()
+    }
+
+    fn read_exif(data: Vec<u8>) -> Option<exif::Exif> {
+        let mut reader = Cursor::new(data);
+        let movie = read_movie(&mut reader).unwrap();
+        super::read_exif(&mut reader, movie.as_ref()).unwrap()
+    }
+
+    fn read(data: Vec<u8>) -> exif::Exif {
+        read_exif(data).unwrap()
+    }
+
+    #[test]
+    fn cr3() {
+        // CMT2 IFD is the exif IFD, its tags are stored in the first IFD.
+        let model = tiff(Tag::Model, "Canon EOS R5");
+        let date = tiff(
+            Tag(exif::Context::Tiff, Tag::DateTimeOriginal.number()),
+            "2023:07:14 18:42:07",
+        );
+        let mut canon = super::CANON_UUID.to_vec();
+        canon.extend([mp4_box(b"CMT1", &model), mp4_box(b"CMT2", &date)].concat());
+        let cr3 = [
+            mp4_box(b"ftyp", b"crx "),
+            mp4_box(b"moov", &mp4_box(b"uuid", &canon)),
+        ]
+        .concat();
+
+        let exif = read(cr3);
+        let field = |tag| exif.get_field(tag, In::PRIMARY).unwrap().display_value();
+        assert_eq!(field(Tag::Model).to_string(), "\"Canon EOS R5\"");
+        assert_eq!(
+            field(Tag::DateTimeOriginal).to_string(),
+            "2023-07-14 18:42:07"
+        );
+    }
+
+    #[test]
+    fn orf() {
+        let mut orf = tiff(Tag::Model, "E-M1");
+        orf[..4].copy_from_slice(b"IIRO");
+
+        let exif = read(orf);
+        assert!(exif.get_field(Tag::Model, In::PRIMARY).is_some());
+
+        let mp4 = [mp4_box(b"ftyp", b"mp41"), mp4_box(b"mdat", &[])].concat();
+        assert!(read_exif(mp4).is_none());
+    }
+}
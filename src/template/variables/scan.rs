@@ -0,0 +1,180 @@
+use std::error::Error;
+use std::path::Path;
+use std::result::Result as StdResult;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::encoding;
+use crate::template::context::{Context, Result, ScanOptions, TemplateValue, Value};
+
+lazy_static! {
+    static ref TOKEN_REGEX: Regex = Regex::new(r"[0-9]+|\p{L}+").unwrap();
+}
+
+/// Month and season names making an adjacent year more likely to be a date.
+const DATE_WORDS: &[&str] = &[
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+    "jan",
+    "feb",
+    "mar",
+    "apr",
+    "jun",
+    "jul",
+    "aug",
+    "sep",
+    "sept",
+    "oct",
+    "nov",
+    "dec",
+    "spring",
+    "summer",
+    "autumn",
+    "fall",
+    "winter",
+    "christmas",
+    "xmas",
+];
+
+/// Year guessed from the file and directory names of a scanned photo.
+struct ScanTemplateValue {
+    year: i32,
+}
+
+impl TemplateValue for ScanTemplateValue {
+    fn render(&self, name: &str, _ctx: &Context) -> Result {
+        match name {
+            "scan.year" => Ok(Value::padded_int(self.year.into(), 4)),
+            _ => unreachable!("unexpected scan template variable, please report a bug."),
+        }
+    }
+}
+
+pub const VARIABLES: &[&str] = &["scan.year"];
+
+/// Returns the most confident year found in the given name and its
+/// confidence.
+fn name_year(name: &str, options: &ScanOptions) -> Option<(i32, u8)> {
+    let tokens: Vec<String> = TOKEN_REGEX
+        .find_iter(name)
+        .map(|m| m.as_str().to_lowercase())
+        .collect();
+    let is_date_word = |i: Option<usize>| {
+        i.and_then(|i| tokens.get(i))
+            .is_some_and(|token| DATE_WORDS.contains(&token.as_str()))
+    };
+
+    let mut best: Option<(i32, u8)> = None;
+    for (i, token) in tokens.iter().enumerate() {
+        let number = match token.parse::<i32>() {
+            Ok(number) => number,
+            Err(_) => continue,
+        };
+        let is_decade = tokens.get(i + 1).is_some_and(|next| next == "s") && number % 10 == 0;
+
+        let candidate = match token.len() {
+            4 if is_decade => (number, 1),
+            4 if is_date_word(i.checked_sub(1)) || is_date_word(Some(i + 1)) => (number, 3),
+            4 => (number, 2),
+            2 if is_decade => (1900 + number, 1),
+            _ => continue,
+        };
+        if candidate.0 < options.min_year || candidate.0 > options.max_year {
+            continue;
+        }
+        if best.is_none_or(|(_, confidence)| candidate.1 > confidence) {
+            best = Some(candidate);
+        }
+    }
+
+    best
+}
+
+/// Returns the most confident year found in the file stem and parent
+/// directory names of the given path.
+fn guess_year(path: &Path, options: &ScanOptions) -> Option<i32> {
+    let stem = path.file_stem().into_iter();
+    let dirs = path
+        .ancestors()
+        .skip(1)
+        .take(options.depth)
+        .filter_map(|dir| dir.file_name());
+
+    let mut best: Option<(i32, u8)> = None;
+    for name in stem.chain(dirs) {
+        if let Some(candidate) = name_year(&encoding::transliterate(name), options) {
+            if best.is_none_or(|(_, confidence)| candidate.1 > confidence) {
+                best = Some(candidate);
+            }
+        }
+    }
+
+    best.filter(|(_, confidence)| *confidence >= options.min_confidence)
+        .map(|(year, _)| year)
+}
+
+pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+    let options = match &ctx.options().scan {
+        Some(options) => options,
+        None => return Ok(()),
+    };
+
+    if let Some(year) = guess_year(&ctx.file_path()?, options) {
+        ctx.insert(VARIABLES, Box::new(ScanTemplateValue { year }));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::template::context::ScanOptions;
+
+    #[test]
+    fn guess_year() {
+        let options = ScanOptions {
+            max_year: 2005,
+            ..Default::default()
+        };
+        let year = |path: &str| super::guess_year(Path::new(path), &options);
+
+        assert_eq!(year("/scans/Summer 1992/img001.tif"), Some(1992));
+        assert_eq!(year("/scans/album/1987.tif"), Some(1987));
+        // month names win over lone years.
+        assert_eq!(year("/scans/1990-1995/xmas1993_04.tif"), Some(1993));
+        // file name wins over directories.
+        assert_eq!(year("/scans/1985/1987 beach.tif"), Some(1987));
+        // years out of range are ignored.
+        assert_eq!(year("/scans/2016/img_1234.tif"), None);
+        // decades aren't confident enough by default.
+        assert_eq!(year("/scans/80s/img.tif"), None);
+        // directories deeper than depth are ignored.
+        assert_eq!(year("/scans/1987/a/b/img.tif"), None);
+
+        let options = ScanOptions {
+            min_confidence: 1,
+            ..options
+        };
+        assert_eq!(
+            super::guess_year(Path::new("/scans/1980s/img.tif"), &options),
+            Some(1980)
+        );
+        assert_eq!(
+            super::guess_year(Path::new("/scans/80s/img.tif"), &options),
+            Some(1980)
+        );
+    }
+}
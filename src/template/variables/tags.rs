@@ -0,0 +1,33 @@
+//! User tags of files (macOS Finder tags, Linux `user.xdg.tags`).
+
+use std::error::Error;
+use std::result::Result as StdResult;
+
+use crate::tags;
+use crate::template::context::{Context, Result, TemplateValue, Value};
+
+/// Tags of a file, in order.
+struct TagsTemplateValue {
+    tags: Vec<String>,
+}
+
+impl TemplateValue for TagsTemplateValue {
+    fn render(&self, name: &str, _ctx: &Context) -> Result {
+        match name {
+            "tags" => Ok(Value::String(self.tags.join(","))),
+            "tags.first" => Ok(Value::from(self.tags[0].as_str())),
+            _ => unreachable!("unexpected tags template variable, please report a bug."),
+        }
+    }
+}
+
+pub const VARIABLES: &[&str] = &["tags", "tags.first"];
+
+pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+    let tags = tags::read(&ctx.file_path()?)?;
+    if !tags.is_empty() {
+        ctx.insert(VARIABLES, Box::new(TagsTemplateValue { tags }));
+    }
+
+    Ok(())
+}
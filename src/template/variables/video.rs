@@ -0,0 +1,128 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::result::Result as StdResult;
+
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveDateTime, TimeZone, Timelike, Utc};
+
+use crate::template::context::{Context, Result, TemplateValue, Value};
+
+use super::mp4::{creation_time, find, Movie};
+
+/// Creation date of MP4/MOV videos.
+struct VideoTemplateValue {
+    date: DateTime<Local>,
+}
+
+impl TemplateValue for VideoTemplateValue {
+    fn render(&self, name: &str, _ctx: &Context) -> Result {
+        match name {
+            "video.date" => Ok(self.date.naive_local().into()),
+            "video.date.year" => Ok(Value::padded_int(self.date.year().into(), 4)),
+            "video.date.month" => Ok(Value::padded_int(self.date.month().into(), 2)),
+            "video.date.day" => Ok(Value::padded_int(self.date.day().into(), 2)),
+            "video.date.hour" => Ok(Value::padded_int(self.date.hour().into(), 2)),
+            "video.date.minute" => Ok(Value::padded_int(self.date.minute().into(), 2)),
+            "video.date.second" => Ok(Value::padded_int(self.date.second().into(), 2)),
+            _ => unreachable!("unexpected video template variable, please report a bug."),
+        }
+    }
+
+    fn zoned_date(&self, name: &str, _ctx: &Context) -> Option<DateTime<FixedOffset>> {
+        (name == "video.date").then(|| self.date.fixed_offset())
+    }
+}
+
+pub const VARIABLES: &[&str] = &[
+    "video.date",
+    "video.date.year",
+    "video.date.month",
+    "video.date.day",
+    "video.date.hour",
+    "video.date.minute",
+    "video.date.second",
+];
+
+/// Returns the creation date (UTC) of the movie header of a MP4/MOV file,
+/// none is returned if the file has no movie header or its creation time
+/// isn't set.
+fn creation_date<R: Read + Seek>(
+    reader: &mut R,
+    movie: &Movie,
+) -> io::Result<Option<NaiveDateTime>> {
+    let created = match find(&movie.moov, b"mvhd") {
+        Some(mvhd) => creation_time(reader, &mvhd)?,
+        None => None,
+    };
+    // unset creation time is 1904-01-01.
+    Ok(created.filter(|date| date.year() > 1904))
+}
+
+pub fn prepare_template_context(ctx: &mut Context) -> StdResult<(), Box<dyn Error>> {
+    let movie = match ctx.movie()? {
+        Some(movie) => movie,
+        // not an MP4/MOV file.
+        None => return Ok(()),
+    };
+
+    // malformed files have no video variables.
+    let mut file = File::open(ctx.file_path()?)?;
+    match creation_date(&mut file, movie) {
+        Ok(Some(date)) => {
+            // creation time is UTC, other dates are local.
+            let date = Utc.from_utc_datetime(&date).with_timezone(&Local);
+            ctx.insert(VARIABLES, Box::new(VideoTemplateValue { date }))
+        }
+        Ok(None) => {}
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {}
+        Err(err) => return Err(Box::new(err)),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut b = (payload.len() as u32 + 8).to_be_bytes().to_vec();
+        b.extend_from_slice(kind);
+        b.extend_from_slice(payload);
+        b
+    }
+
+    /// Returns a MOV file with a version 1 (64 bits) movie header.
+    fn mov(creation_time: u64) -> Vec<u8> {
+        let mut mvhd = vec![1, 0, 0, 0];
+        mvhd.extend_from_slice(&creation_time.to_be_bytes());
+        [
+            mp4_box(b"ftyp", b"qt  "),
+            mp4_box(b"wide", &[]),
+            mp4_box(b"moov", &mp4_box(b"mvhd", &mvhd)),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn creation_date() {
+        let date = |mov: Vec<u8>| {
+            let mut reader = Cursor::new(mov);
+            let movie = super::super::mp4::read_movie(&mut reader).unwrap()?;
+            super::creation_date(&mut reader, &movie)
+                .unwrap()
+                .map(|d| d.to_string())
+        };
+
+        // 2023-07-14 18:42:07 since 1904-01-01.
+        assert_eq!(
+            date(mov(3_772_204_927)),
+            Some("2023-07-14 18:42:07".to_owned())
+        );
+        assert_eq!(date(mov(0)), None);
+
+        let mp4 = [mp4_box(b"ftyp", b"mp41"), mp4_box(b"mdat", &[])].concat();
+        assert_eq!(date(mp4), None);
+    }
+}
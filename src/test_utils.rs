@@ -0,0 +1,191 @@
+//! Fixtures for tests of photosort and of applications embedding it, enabled
+//! with the `test-utils` feature.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::NaiveDateTime;
+use exif::{Field, In, Tag, Value};
+
+/// TempTree is a temporary directory of source files, removed when dropped.
+#[derive(Debug)]
+pub struct TempTree {
+    root: PathBuf,
+}
+
+impl Default for TempTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TempTree {
+    /// Creates an empty directory with a unique name in the temporary
+    /// directory.
+    pub fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let root = env::temp_dir().join(format!(
+            "photosort-{}-{}-{}",
+            process::id(),
+            nanos,
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&root).expect("failed to create temporary directory");
+
+        Self { root }
+    }
+
+    /// Adds a file with the given content, parent directories are created.
+    pub fn with_file(self, path: impl AsRef<Path>, content: impl AsRef<[u8]>) -> Self {
+        let path = self.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("failed to create parent directory");
+        }
+        fs::write(&path, content).expect("failed to write file");
+        self
+    }
+
+    /// Adds an empty directory (e.g. the destination root).
+    pub fn with_dir(self, path: impl AsRef<Path>) -> Self {
+        fs::create_dir_all(self.join(path)).expect("failed to create directory");
+        self
+    }
+
+    /// Adds a JPEG file whose exif DateTimeOriginal is the given date.
+    pub fn with_jpeg(self, path: impl AsRef<Path>, date: NaiveDateTime) -> Self {
+        self.with_file(path, jpeg_with_date(date))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the given path relative to the tree root.
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// Returns the sorted paths, relative to the tree root, of files under the
+    /// given directory of the tree.
+    pub fn files(&self, dir: impl AsRef<Path>) -> Vec<PathBuf> {
+        let dir = self.join(dir);
+        let mut files = Vec::new();
+        if dir.exists() {
+            crate::plan::walk_files(&dir, &mut |path: io::Result<PathBuf>| {
+                let path = path.expect("failed to read directory");
+                files.push(path.strip_prefix(&self.root).unwrap().to_owned())
+            });
+        }
+        files.sort();
+        files
+    }
+
+    /// Panics unless files under the given directory of the tree are exactly
+    /// the expected ones (paths relative to the tree root).
+    #[track_caller]
+    pub fn assert_files(&self, dir: impl AsRef<Path>, expected: &[&str]) {
+        let mut expected: Vec<PathBuf> = expected.iter().map(PathBuf::from).collect();
+        expected.sort();
+        assert_eq!(self.files(dir), expected, "unexpected destination layout");
+    }
+}
+
+impl Drop for TempTree {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Returns a minimal JPEG file whose exif data contains the given fields and
+/// thumbnail, if any.
+pub fn jpeg(fields: &[Field], thumbnail: Option<&[u8]>) -> Vec<u8> {
+    let mut writer = exif::experimental::Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+    if let Some(thumbnail) = thumbnail {
+        writer.set_jpeg(thumbnail, In::THUMBNAIL);
+    }
+    let mut tiff = io::Cursor::new(Vec::new());
+    writer
+        .write(&mut tiff, false)
+        .expect("failed to write exif data");
+    let tiff = tiff.into_inner();
+
+    let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+    jpeg.extend_from_slice(&(tiff.len() as u16 + 8).to_be_bytes());
+    jpeg.extend_from_slice(b"Exif\0\0");
+    jpeg.extend_from_slice(&tiff);
+    jpeg.extend_from_slice(&[0xff, 0xd9]);
+    jpeg
+}
+
+/// Returns a minimal JPEG file whose exif DateTimeOriginal is the given date.
+pub fn jpeg_with_date(date: NaiveDateTime) -> Vec<u8> {
+    let date = Field {
+        tag: Tag::DateTimeOriginal,
+        ifd_num: In::PRIMARY,
+        value: Value::Ascii(vec![date
+            .format("%Y:%m:%d %H:%M:%S")
+            .to_string()
+            .into_bytes()]),
+    };
+    jpeg(&[date], None)
+}
+
+/// Returns a minimal JPEG file whose exif data embeds the given thumbnail.
+pub fn jpeg_with_thumbnail(thumbnail: &[u8]) -> Vec<u8> {
+    let software = Field {
+        tag: Tag::Software,
+        ifd_num: In::PRIMARY,
+        value: Value::Ascii(vec![b"photosort".to_vec()]),
+    };
+    jpeg(&[software], Some(thumbnail))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::NaiveDate;
+
+    use crate::replicator::CopyReplicator;
+    use crate::sort::{Config, Sorter};
+    use crate::template::Template;
+
+    #[test]
+    fn sorted_tree() {
+        let date = |d| {
+            NaiveDate::from_ymd_opt(2023, 7, d)
+                .unwrap()
+                .and_hms_opt(18, 42, 7)
+                .unwrap()
+        };
+        let tree = super::TempTree::new()
+            .with_jpeg("src/a.jpg", date(14))
+            .with_jpeg("src/trip/b.jpg", date(15))
+            .with_file("src/notes.txt", "notes");
+
+        let template = format!("{}/dst/:exif.date:/:file.name:", tree.root().display());
+        let sorter = Sorter::new(Config::new(
+            Template::from_str(&template).unwrap(),
+            Box::new(CopyReplicator::default()),
+            false,
+        ));
+        for file in tree.files("src") {
+            let _ = sorter.sort_file(&tree.join(file));
+        }
+
+        tree.assert_files("dst", &["dst/2023-07-14/a.jpg", "dst/2023-07-15/b.jpg"]);
+    }
+}
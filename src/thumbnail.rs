@@ -0,0 +1,80 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use exif::{Exif, In, Reader, Tag};
+
+/// Returns the JPEG thumbnail embedded in the given exif data, if any.
+pub fn embedded(exif: &Exif) -> Option<&[u8]> {
+    let uint = |tag| exif.get_field(tag, In::THUMBNAIL)?.value.get_uint(0);
+    let offset = uint(Tag::JPEGInterchangeFormat)? as usize;
+    let len = uint(Tag::JPEGInterchangeFormatLength)? as usize;
+
+    exif.buf()
+        .get(offset..offset.checked_add(len)?)
+        .filter(|thumbnail| !thumbnail.is_empty())
+}
+
+/// Reads the exif thumbnail of the given file, none is returned if the file
+/// has no exif data or no thumbnail.
+pub fn read(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let exif = match Reader::new().read_from_container(&mut reader) {
+        Ok(exif) => exif,
+        Err(exif::Error::Io(err)) => return Err(err),
+        Err(_) => return Ok(None),
+    };
+
+    Ok(embedded(&exif).map(|thumbnail| thumbnail.to_vec()))
+}
+
+/// Extracts the exif thumbnail of the given file to `thumbnail_path`, parent
+/// directories are created if needed. Returns false if the file has no
+/// thumbnail.
+pub fn extract(path: &Path, thumbnail_path: &Path) -> io::Result<bool> {
+    let thumbnail = match read(path)? {
+        Some(thumbnail) => thumbnail,
+        None => return Ok(false),
+    };
+
+    if let Some(parent) = thumbnail_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(thumbnail_path, thumbnail)?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    use crate::test_utils;
+
+    #[test]
+    fn extract() {
+        let dir = env::temp_dir().join(format!("{}", Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+
+        let thumbnail = b"\xff\xd8thumbnail\xff\xd9";
+        let path = dir.join("photo.jpg");
+        fs::write(&path, test_utils::jpeg_with_thumbnail(thumbnail)).unwrap();
+
+        assert_eq!(super::read(&path).unwrap().as_deref(), Some(&thumbnail[..]));
+
+        let thumbnail_path = dir.join("thumbs/photo.jpg");
+        assert!(super::extract(&path, &thumbnail_path).unwrap());
+        assert_eq!(fs::read(&thumbnail_path).unwrap(), thumbnail);
+
+        // files without exif data have no thumbnail.
+        let path = dir.join("notes.txt");
+        fs::write(&path, "notes").unwrap();
+        assert_eq!(super::read(&path).unwrap(), None);
+        assert!(!super::extract(&path, &dir.join("thumbs/notes.jpg")).unwrap());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
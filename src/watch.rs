@@ -0,0 +1,812 @@
+//! Sorting of files as they are added to sources.
+//!
+//! Events come from an [`EventSource`] and time from a [`Clock`], so watch
+//! behavior (drain timeouts, destination retries) can be tested with
+//! `MockEventSource` and `VirtualClock` (`test-utils` feature) without
+//! waiting in real time.
+
+#[cfg(any(test, feature = "test-utils"))]
+use std::{cell::Cell, rc::Rc};
+use std::{
+    cmp::Reverse,
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use notify::{
+    event::{AccessKind, AccessMode, CreateKind, ModifyKind, RenameMode},
+    Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use regex::Regex;
+use thiserror::Error;
+
+use crate::backoff::Backoff;
+use crate::encoding;
+use crate::plan::walk_files;
+use crate::sort::{SortError, SortResult, Sorter};
+
+/// Maximum number of events received at once, they're handled by priority
+/// (see [`Sorter::priority`]).
+const MAX_BURST: usize = 1024;
+
+#[derive(Error, Debug)]
+pub enum WatcherError {
+    #[error("failed to create filesystem watcher: {0}")]
+    CreatingWatcher(#[source] notify::Error),
+
+    #[error("failed to add source {0:?} to watch list: {1}")]
+    Watch(PathBuf, #[source] notify::Error),
+
+    #[error("destination is unavailable: {0}")]
+    Destination(#[source] SortError),
+
+    #[error("filesystem watcher stopped unexpectedly")]
+    Disconnected,
+}
+
+/// Drain mode options, watcher exits once sources are drained instead of
+/// running forever.
+#[derive(Debug, Clone, Copy)]
+pub struct DrainOptions {
+    /// Exit after no event was received for this duration.
+    pub idle_timeout: Duration,
+    /// Exit after this duration even if events are still received.
+    pub max_duration: Option<Duration>,
+}
+
+/// Clock is the time source of the watcher.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// SystemClock is the monotonic clock of the system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// VirtualClock is a clock that only moves forward when advanced, clones
+/// share the same time.
+#[cfg(any(test, feature = "test-utils"))]
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    now: Rc<Cell<Instant>>,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self {
+            now: Rc::new(Cell::new(Instant::now())),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl VirtualClock {
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+}
+
+/// EventSource produces filesystem events of watched directories.
+pub trait EventSource {
+    /// Starts watching the given directory recursively.
+    fn watch(&mut self, path: &Path) -> notify::Result<()>;
+
+    /// Waits for the next event, up to the given timeout if any.
+    fn recv(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<notify::Result<Event>, RecvTimeoutError>;
+}
+
+/// NotifySource is the event source of the watcher suitable for the platform.
+pub struct NotifySource {
+    watcher: RecommendedWatcher,
+    rx: Receiver<notify::Result<Event>>,
+}
+
+impl NotifySource {
+    pub fn new() -> Result<Self, WatcherError> {
+        log::debug!("creating watcher suitable for this platform");
+        let (tx, rx) = mpsc::channel();
+        let watcher = notify::recommended_watcher(tx).map_err(WatcherError::CreatingWatcher)?;
+        log::debug!("watcher successfully created");
+
+        Ok(Self { watcher, rx })
+    }
+}
+
+impl EventSource for NotifySource {
+    fn watch(&mut self, path: &Path) -> notify::Result<()> {
+        self.watcher.watch(path, RecursiveMode::Recursive)
+    }
+
+    fn recv(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<notify::Result<Event>, RecvTimeoutError> {
+        match timeout {
+            Some(timeout) => self.rx.recv_timeout(timeout),
+            None => self.rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        }
+    }
+}
+
+/// MockEventSource replays events at given times of a virtual clock, waiting
+/// advances the clock instead of sleeping. It is disconnected once all events
+/// are received and no timeout is given.
+#[cfg(any(test, feature = "test-utils"))]
+#[derive(Debug)]
+pub struct MockEventSource {
+    clock: VirtualClock,
+    events: VecDeque<(Instant, notify::Result<Event>)>,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl MockEventSource {
+    pub fn new(clock: VirtualClock) -> Self {
+        Self {
+            clock,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Adds an event received after the given delay since the previous event
+    /// (or the creation of the source).
+    pub fn with_event(mut self, delay: Duration, event: notify::Result<Event>) -> Self {
+        let previous = self
+            .events
+            .back()
+            .map(|(at, _)| *at)
+            .unwrap_or_else(|| self.clock.now());
+        self.events.push_back((previous + delay, event));
+        self
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl EventSource for MockEventSource {
+    fn watch(&mut self, _path: &Path) -> notify::Result<()> {
+        Ok(())
+    }
+
+    fn recv(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<notify::Result<Event>, RecvTimeoutError> {
+        let now = self.clock.now();
+        let deadline = timeout.map(|timeout| now + timeout);
+        match self.events.front() {
+            Some((at, _)) if deadline.is_none_or(|deadline| *at <= deadline) => {
+                self.clock.advance(at.saturating_duration_since(now));
+                Ok(self.events.pop_front().unwrap().1)
+            }
+            _ => match timeout {
+                Some(timeout) => {
+                    self.clock.advance(timeout);
+                    Err(RecvTimeoutError::Timeout)
+                }
+                None => Err(RecvTimeoutError::Disconnected),
+            },
+        }
+    }
+}
+
+/// EventWatcher sorts files of sources as events are received.
+pub struct EventWatcher {
+    sorter: Sorter,
+    sources: Vec<PathBuf>,
+    ignore_regex: Option<Regex>,
+    drain: Option<DrainOptions>,
+    wait_for_mount: bool,
+    create_root: bool,
+}
+
+impl EventWatcher {
+    pub fn new(sorter: Sorter, sources: Vec<PathBuf>) -> Self {
+        Self {
+            sorter,
+            sources,
+            ignore_regex: None,
+            drain: None,
+            wait_for_mount: false,
+            create_root: false,
+        }
+    }
+
+    /// Ignore files that match the given regular expression.
+    pub fn with_ignore_regex(mut self, ignore_regex: Option<Regex>) -> Self {
+        self.ignore_regex = ignore_regex;
+        self
+    }
+
+    /// Exit once sources are drained instead of running forever.
+    pub fn with_drain(mut self, drain: Option<DrainOptions>) -> Self {
+        self.drain = drain;
+        self
+    }
+
+    /// Queue events until the destination is mounted instead of failing if it
+    /// isn't mounted on start.
+    pub fn with_wait_for_mount(mut self, wait_for_mount: bool) -> Self {
+        self.wait_for_mount = wait_for_mount;
+        self
+    }
+
+    /// Create the destination root directory if it doesn't exist instead of
+    /// queuing events until it is mounted.
+    pub fn with_create_root(mut self, create_root: bool) -> Self {
+        self.create_root = create_root;
+        self
+    }
+
+    /// Watches sources with the platform watcher.
+    pub fn start<F>(self, result_handler: F) -> Result<(), WatcherError>
+    where
+        F: Fn(Result<EventHandlerResult, EventHandlerError>),
+    {
+        let mut source = NotifySource::new()?;
+        self.run(&mut source, &SystemClock, result_handler)
+    }
+
+    /// Watches sources with the given event source and clock.
+    pub fn run<F>(
+        self,
+        events: &mut dyn EventSource,
+        clock: &dyn Clock,
+        result_handler: F,
+    ) -> Result<(), WatcherError>
+    where
+        F: Fn(Result<EventHandlerResult, EventHandlerError>),
+    {
+        let Self {
+            sorter,
+            sources,
+            ignore_regex,
+            drain,
+            wait_for_mount,
+            create_root,
+        } = self;
+
+        let start = clock.now();
+        let filter = EventFilter::new(ignore_regex);
+        if !wait_for_mount {
+            sorter.check_mount().map_err(WatcherError::Destination)?;
+        }
+        let mut gate = DestinationGate::new(sorter.destination_root(), create_root, clock);
+        let handler = EventHandler::new(filter, sorter, sources.clone());
+        let sorter = &handler.sorter;
+
+        log::debug!("adding sources to watcher watch list");
+        for src in &sources {
+            log::debug!("adding {:?} to watch list", src);
+            events
+                .watch(src)
+                .map_err(|err| WatcherError::Watch(src.to_owned(), err))?;
+        }
+        log::debug!("sources successfully added to watcher watch list");
+
+        let handle_events = |mut events: Vec<notify::Result<Event>>| {
+            // stable sort, events of a file keep their order.
+            events.sort_by_cached_key(|event| {
+                Reverse(
+                    event
+                        .as_ref()
+                        .ok()
+                        .and_then(|event| event.paths.first())
+                        .map_or(0, |path| sorter.priority(path)),
+                )
+            });
+            for event in events {
+                result_handler(handler.handle_event(event));
+            }
+        };
+
+        if drain.is_some() {
+            // Files added before the watcher started are handled as if they were
+            // just created.
+            log::debug!("draining pending files");
+            for src in &sources {
+                walk_files(src, &mut |result| {
+                    let event = result
+                        .map(|path| Event::new(EventKind::Create(CreateKind::File)).add_path(path))
+                        .map_err(notify::Error::io);
+                    handle_events(gate.submit(sorter, event));
+                });
+            }
+        }
+
+        let deadline = drain.and_then(|d| d.max_duration).map(|d| start + d);
+        let mut last_activity = clock.now();
+        loop {
+            let idle = clock.now().saturating_duration_since(last_activity);
+            let mut timeout = drain.map(|d| d.idle_timeout.saturating_sub(idle));
+            if let Some(retry) = gate.retry_timeout() {
+                timeout = Some(timeout.map_or(retry, |t| t.min(retry)));
+            }
+            if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(clock.now());
+                timeout = Some(timeout.map_or(remaining, |t| t.min(remaining)));
+            }
+
+            match events.recv(timeout) {
+                Ok(event) => {
+                    last_activity = clock.now();
+                    // events already waiting are handled along with this one.
+                    let mut ready = gate.submit(sorter, event);
+                    for _ in 1..MAX_BURST {
+                        match events.recv(Some(Duration::ZERO)) {
+                            Ok(event) => ready.extend(gate.submit(sorter, event)),
+                            Err(RecvTimeoutError::Timeout) => break,
+                            Err(RecvTimeoutError::Disconnected) => {
+                                return Err(WatcherError::Disconnected)
+                            }
+                        }
+                    }
+                    handle_events(ready);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if gate.is_degraded() {
+                        // Sources aren't idle while events are queued.
+                        last_activity = clock.now();
+                        handle_events(gate.retry(sorter));
+                    } else if let Some(drain) = drain {
+                        if clock.now().saturating_duration_since(last_activity)
+                            >= drain.idle_timeout
+                        {
+                            break;
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(WatcherError::Disconnected),
+            }
+
+            if deadline.is_some_and(|d| d <= clock.now()) {
+                log::info!("maximum drain duration reached");
+                break;
+            }
+        }
+
+        if gate.is_degraded() {
+            log::warn!(
+                "destination is still unavailable, {} queued events were not handled",
+                gate.queue.len()
+            );
+        }
+        log::debug!("sources drained");
+
+        Ok(())
+    }
+}
+
+/// DestinationGate queues events while the destination root directory is
+/// unavailable (e.g. unmounted network share) or its pinned filesystem isn't
+/// mounted and periodically checks if it is back using an exponential backoff
+/// with jitter. A missing root is unavailable (e.g. removable drive
+/// unplugged) unless it is allowed to be created.
+struct DestinationGate<'a> {
+    root: Option<PathBuf>,
+    create_root: bool,
+    clock: &'a dyn Clock,
+    queue: VecDeque<notify::Result<Event>>,
+    backoff: Backoff,
+    next_check: Option<Instant>,
+}
+
+impl<'a> DestinationGate<'a> {
+    fn new(root: Option<PathBuf>, create_root: bool, clock: &'a dyn Clock) -> Self {
+        Self {
+            root,
+            create_root,
+            clock,
+            queue: VecDeque::new(),
+            backoff: Backoff::new(Duration::from_secs(1), Duration::from_secs(5 * 60)),
+            next_check: None,
+        }
+    }
+
+    fn is_available(&self, sorter: &Sorter) -> bool {
+        if let Err(err) = sorter.check_mount() {
+            log::debug!("{}", err);
+            return false;
+        }
+
+        match &self.root {
+            Some(root) if root.is_dir() => true,
+            // missing roots are created by the sorter.
+            Some(root) => self.create_root && matches!(root.try_exists(), Ok(false)),
+            None => true,
+        }
+    }
+
+    fn is_degraded(&self) -> bool {
+        self.next_check.is_some()
+    }
+
+    /// Returns events ready to be handled.
+    fn submit(
+        &mut self,
+        sorter: &Sorter,
+        event: notify::Result<Event>,
+    ) -> Vec<notify::Result<Event>> {
+        if self.is_degraded() {
+            self.queue.push_back(event);
+            return self.retry(sorter);
+        }
+
+        if self.is_available(sorter) {
+            return vec![event];
+        }
+
+        log::warn!(
+            "destination {:?} is unavailable, events are queued until it is back",
+            self.root.as_deref().unwrap_or_else(|| Path::new(""))
+        );
+        self.queue.push_back(event);
+        self.backoff.reset();
+        self.next_check = Some(self.clock.now() + self.backoff.next_delay());
+
+        Vec::new()
+    }
+
+    /// Duration until next availability check, if destination is unavailable.
+    fn retry_timeout(&self) -> Option<Duration> {
+        self.next_check
+            .map(|next_check| next_check.saturating_duration_since(self.clock.now()))
+    }
+
+    /// Checks if destination is available again if it's time to and returns
+    /// queued events if so.
+    fn retry(&mut self, sorter: &Sorter) -> Vec<notify::Result<Event>> {
+        let next_check = match self.next_check {
+            Some(next_check) => next_check,
+            None => return Vec::new(),
+        };
+        if next_check > self.clock.now() {
+            return Vec::new();
+        }
+
+        if !self.is_available(sorter) {
+            let delay = self.backoff.next_delay();
+            log::debug!("destination still unavailable, next check in {:?}", delay);
+            self.next_check = Some(self.clock.now() + delay);
+            return Vec::new();
+        }
+
+        log::info!(
+            "destination {:?} is available again, handling {} queued events",
+            self.root.as_deref().unwrap_or_else(|| Path::new("")),
+            self.queue.len()
+        );
+        self.next_check = None;
+        self.queue.drain(..).collect()
+    }
+}
+
+pub struct EventHandler {
+    event_filter: EventFilter,
+    sorter: Sorter,
+    sources: Vec<PathBuf>,
+}
+
+pub enum EventHandlerResult {
+    Ignored(Event),
+    Sort(PathBuf, Result<SortResult, SortError>),
+    Filtered(FilterReason),
+}
+
+#[derive(Debug, Error)]
+pub enum EventHandlerError {
+    #[error("failed to retrieve event: {0}")]
+    RetrieveEvent(notify::Error),
+}
+
+impl EventHandler {
+    pub fn new(event_filter: EventFilter, sorter: Sorter, sources: Vec<PathBuf>) -> Self {
+        Self {
+            event_filter,
+            sorter,
+            sources,
+        }
+    }
+
+    fn handle_event(
+        &self,
+        event: notify::Result<Event>,
+    ) -> Result<EventHandlerResult, EventHandlerError> {
+        let mut event = match event {
+            Ok(e) => e,
+            Err(err) => return Err(EventHandlerError::RetrieveEvent(err)),
+        };
+
+        let src_path = match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                // If file is renamed and its previous name was filtered
+                if let Err(FilterReason::MatchIgnoreRegex(_)) = self.event_filter.filter(&event) {
+                    // remove the old name from the paths vector
+                    event.paths.remove(0);
+                }
+
+                &event.paths[0]
+            }
+            EventKind::Access(AccessKind::Close(AccessMode::Write))
+            | EventKind::Create(CreateKind::File) => &event.paths[0],
+            _ => return Ok(EventHandlerResult::Ignored(event)),
+        };
+
+        log::debug!("handling event: {:?}", event);
+        if let Err(filter_reason) = self.event_filter.filter(&event) {
+            return Ok(EventHandlerResult::Filtered(filter_reason));
+        }
+
+        let source = self.sources.iter().find(|src| src_path.starts_with(src));
+        let sort_result = self
+            .sorter
+            .sort_source_file(src_path, source.map(PathBuf::as_path));
+        log::debug!("event handled: {:?}", event);
+        Ok(EventHandlerResult::Sort(src_path.to_owned(), sort_result))
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum FilterReason {
+    #[error("missing file path in event: {0:?}")]
+    MissingEventPath(Event),
+    #[error("{0:?} matched ignore regex")]
+    MatchIgnoreRegex(PathBuf),
+}
+
+pub struct EventFilter {
+    ignore_regex: Option<Regex>,
+}
+
+impl EventFilter {
+    pub fn new(ignore_regex: Option<Regex>) -> Self {
+        Self { ignore_regex }
+    }
+
+    pub fn filter(&self, event: &Event) -> Result<(), FilterReason> {
+        let path = match event.paths.first() {
+            Some(p) => p,
+            None => return Err(FilterReason::MissingEventPath(event.clone())),
+        };
+
+        if let Some(ignore_regex) = &self.ignore_regex {
+            if encoding::is_match(ignore_regex, path.as_os_str()) {
+                return Err(FilterReason::MatchIgnoreRegex(event.paths[0].to_owned()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+    use std::sync::mpsc::RecvTimeoutError;
+    use std::time::{Duration, Instant};
+
+    use notify::event::CreateKind;
+    use notify::{Event, EventKind};
+    use regex::Regex;
+
+    use crate::replicator::CopyReplicator;
+    use crate::sort::{Config, Rule, Sorter};
+    use crate::template::Template;
+    use crate::test_utils::TempTree;
+
+    use super::{
+        Clock, DrainOptions, EventHandlerResult, EventSource, EventWatcher, MockEventSource,
+        VirtualClock,
+    };
+
+    fn created(path: PathBuf) -> notify::Result<Event> {
+        Ok(Event::new(EventKind::Create(CreateKind::File)).add_path(path))
+    }
+
+    fn watcher(tree: &TempTree, idle_timeout: u64, max_duration: Option<u64>) -> EventWatcher {
+        let template = format!("{}/dst/:file.name:", tree.root().display());
+        let sorter = Sorter::new(Config::new(
+            Template::from_str(&template).unwrap(),
+            Box::new(CopyReplicator::default()),
+            false,
+        ));
+
+        EventWatcher::new(sorter, vec![tree.join("src")]).with_drain(Some(DrainOptions {
+            idle_timeout: Duration::from_secs(idle_timeout),
+            max_duration: max_duration.map(Duration::from_secs),
+        }))
+    }
+
+    /// Runs the watcher and returns the sorted files.
+    fn run(
+        watcher: EventWatcher,
+        events: &mut impl EventSource,
+        clock: &VirtualClock,
+    ) -> Vec<PathBuf> {
+        let sorted = RefCell::new(Vec::new());
+        watcher
+            .run(events, clock, |result| {
+                if let Ok(EventHandlerResult::Sort(path, Ok(_))) = result {
+                    sorted.borrow_mut().push(path);
+                }
+            })
+            .unwrap();
+        sorted.into_inner()
+    }
+
+    #[test]
+    fn drain_on_virtual_clock() {
+        let tree = TempTree::new()
+            .with_file("src/a.jpg", "a")
+            .with_file("src/b.jpg", "b")
+            .with_dir("dst");
+        let clock = VirtualClock::default();
+        let start = clock.now();
+        let mut events = MockEventSource::new(clock.clone())
+            .with_event(Duration::from_secs(30), created(tree.join("src/b.jpg")));
+
+        // pending files are sorted, then watcher exits once idle for 60s.
+        let sorted = run(watcher(&tree, 60, None), &mut events, &clock);
+        assert_eq!(
+            sorted,
+            [
+                tree.join("src/a.jpg"),
+                tree.join("src/b.jpg"),
+                tree.join("src/b.jpg")
+            ]
+        );
+        assert_eq!(clock.now() - start, Duration::from_secs(90));
+        tree.assert_files("dst", &["dst/a.jpg", "dst/b.jpg"]);
+    }
+
+    #[test]
+    fn missing_destination() {
+        let tree = TempTree::new().with_file("src/a.jpg", "a");
+        let clock = VirtualClock::default();
+        let events = || {
+            MockEventSource::new(clock.clone())
+                .with_event(Duration::from_secs(10), created(tree.join("src/a.jpg")))
+        };
+
+        // missing root may be an unplugged drive, events are queued.
+        let sorted = run(watcher(&tree, 60, Some(120)), &mut events(), &clock);
+        assert!(sorted.is_empty());
+        assert!(!tree.join("dst").exists());
+
+        let watcher = watcher(&tree, 60, None).with_create_root(true);
+        let sorted = run(watcher, &mut events(), &clock);
+        assert_eq!(sorted.len(), 2);
+        tree.assert_files("dst", &["dst/a.jpg"]);
+    }
+
+    /// Remounted replaces the destination file by a directory once the clock
+    /// reaches the given instant, as if it was mounted.
+    struct Remounted {
+        events: MockEventSource,
+        clock: VirtualClock,
+        at: Instant,
+        root: PathBuf,
+    }
+
+    impl EventSource for Remounted {
+        fn watch(&mut self, path: &Path) -> notify::Result<()> {
+            self.events.watch(path)
+        }
+
+        fn recv(
+            &mut self,
+            timeout: Option<Duration>,
+        ) -> Result<notify::Result<Event>, RecvTimeoutError> {
+            if self.clock.now() >= self.at && self.root.is_file() {
+                fs::remove_file(&self.root).unwrap();
+                fs::create_dir(&self.root).unwrap();
+            }
+            self.events.recv(timeout)
+        }
+    }
+
+    #[test]
+    fn drain_queued_events() {
+        let tree = TempTree::new()
+            .with_file("src/a.jpg", "a")
+            .with_file("src/b.jpg", "b")
+            .with_file("dst", "");
+        let clock = VirtualClock::default();
+        let start = clock.now();
+        let mut events = Remounted {
+            events: MockEventSource::new(clock.clone())
+                .with_event(Duration::from_secs(10), created(tree.join("src/b.jpg"))),
+            clock: clock.clone(),
+            at: start + Duration::from_secs(20),
+            root: tree.join("dst"),
+        };
+
+        // events are queued until destination is available, then flushed
+        // before watcher exits once idle.
+        let sorted = run(watcher(&tree, 60, None), &mut events, &clock);
+        assert_eq!(sorted.len(), 3);
+        assert!(clock.now() - start >= Duration::from_secs(80));
+        tree.assert_files("dst", &["dst/a.jpg", "dst/b.jpg"]);
+    }
+
+    #[test]
+    fn max_duration() {
+        let tree = TempTree::new().with_file("src/a.jpg", "a").with_dir("dst");
+        let clock = VirtualClock::default();
+        let start = clock.now();
+        let mut events = MockEventSource::new(clock.clone());
+        for _ in 0..10 {
+            events = events.with_event(Duration::from_secs(10), created(tree.join("src/a.jpg")));
+        }
+
+        // sources are never idle, watcher exits after 35s.
+        let sorted = run(watcher(&tree, 60, Some(35)), &mut events, &clock);
+        assert_eq!(sorted.len(), 4);
+        assert!(sorted
+            .iter()
+            .all(|path| path == Path::new(&tree.join("src/a.jpg"))));
+        assert_eq!(clock.now() - start, Duration::from_secs(35));
+    }
+
+    #[test]
+    fn burst_priority() {
+        let tree = TempTree::new()
+            .with_file("card/a.mp4", "a")
+            .with_file("card/b.jpg", "b")
+            .with_file("card/c.mp4", "c")
+            .with_dir("src")
+            .with_dir("dst");
+        let clock = VirtualClock::default();
+        let mut events = MockEventSource::new(clock.clone())
+            .with_event(Duration::from_secs(1), created(tree.join("card/a.mp4")))
+            .with_event(Duration::ZERO, created(tree.join("card/b.jpg")))
+            .with_event(Duration::ZERO, created(tree.join("card/c.mp4")));
+
+        let template = format!("{}/dst/:file.name:", tree.root().display());
+        let rules = vec![Rule::new(Regex::new(r"\.jpg$").unwrap(), None).with_priority(1)];
+        let sorter = Sorter::new(
+            Config::new(
+                Template::from_str(&template).unwrap(),
+                Box::new(CopyReplicator::default()),
+                false,
+            )
+            .with_rules(rules),
+        );
+        let watcher =
+            EventWatcher::new(sorter, vec![tree.join("src")]).with_drain(Some(DrainOptions {
+                idle_timeout: Duration::from_secs(60),
+                max_duration: None,
+            }));
+
+        // events received at once are handled by priority.
+        let sorted = run(watcher, &mut events, &clock);
+        assert_eq!(
+            sorted,
+            [
+                tree.join("card/b.jpg"),
+                tree.join("card/a.mp4"),
+                tree.join("card/c.mp4")
+            ]
+        );
+    }
+}